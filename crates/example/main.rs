@@ -96,7 +96,8 @@ impl ExampleScheme {
 }
 
 impl Scheme for ExampleScheme {
-    fn open(&mut self, path: &str, flags: usize) -> Result<usize> {
+    #[allow(unused_variables)]
+    fn open(&mut self, path: &str, flags: usize, uid: usize, gid: usize) -> Result<usize> {
         println!("open {:X} = {}, {:X}", path.as_ptr() as usize, path, flags);
         let id = self.next_id as usize;
         self.next_id += 1;
@@ -108,13 +109,13 @@ impl Scheme for ExampleScheme {
     }
 
     #[allow(unused_variables)]
-    fn unlink(&mut self, path: &str) -> Result<usize> {
+    fn unlink(&mut self, path: &str, uid: usize, gid: usize) -> Result<usize> {
         println!("unlink {}", path);
         Err(Error::new(ENOENT))
     }
 
     #[allow(unused_variables)]
-    fn mkdir(&mut self, path: &str, mode: usize) -> Result<usize> {
+    fn mkdir(&mut self, path: &str, mode: usize, uid: usize, gid: usize) -> Result<usize> {
         println!("mkdir {}, {:X}", path, mode);
         Err(Error::new(ENOENT))
     }
@@ -3,13 +3,84 @@
 
 use std::env;
 use std::fs::File;
-use std::io::{stdin, stdout, Result, Read, Write};
-use std::process::{Command, ExitStatus};
+use std::io::{stdin, stdout, Error, ErrorKind, Result, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{self, Command, ExitStatus};
 
-fn login(user: &str) -> Result<ExitStatus> {
-    let shell = "/bin/sh";
-    let home = "/home/";
-    env::set_current_dir(home)?;
+use sha256::sha256;
+
+mod sha256;
+
+/// The number of times a password is hashed, to slow down guessing it from its hash
+const ROUNDS: usize = 10000;
+
+/// An entry of `/etc/passwd`, which has lines in the form of
+/// `user;password;uid;gid;name;home;shell`
+///
+/// The password is stored as `salt$hash`, where the hash is made by `hash`, and is written in
+/// hexadecimal. It is empty if no password is needed, and a password that starts with `!` locks
+/// the account, so that nobody can log in to it. A line can be made with `login --hash <salt>`.
+struct Passwd<'a> {
+    user: &'a str,
+    password: &'a str,
+    uid: u32,
+    gid: u32,
+    home: &'a str,
+    shell: &'a str,
+}
+
+impl<'a> Passwd<'a> {
+    fn parse(line: &'a str) -> Option<Passwd<'a>> {
+        let parts: Vec<&str> = line.split(';').collect();
+        if parts.len() < 7 {
+            return None;
+        }
+
+        match (parts[2].parse::<u32>(), parts[3].parse::<u32>()) {
+            (Ok(uid), Ok(gid)) => Some(Passwd {
+                user: parts[0],
+                password: parts[1],
+                uid: uid,
+                gid: gid,
+                home: parts[5],
+                shell: parts[6],
+            }),
+            _ => None
+        }
+    }
+}
+
+/// Hash a password with a salt, as hexadecimal
+fn hash(salt: &str, password: &str) -> String {
+    let mut data = salt.as_bytes().to_vec();
+    data.extend_from_slice(password.as_bytes());
+
+    let mut digest = sha256(&data);
+    for _ in 1..ROUNDS {
+        let mut round = digest.to_vec();
+        round.extend_from_slice(&data);
+        digest = sha256(&round);
+    }
+
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Check a password against a stored `salt$hash`, comparing every byte of the hash so that the
+/// time taken does not tell how much of it matched
+fn verify(stored: &str, password: &str) -> bool {
+    let mut parts = stored.splitn(2, '$');
+    match (parts.next(), parts.next()) {
+        (Some(salt), Some(expected)) => {
+            let actual = hash(salt, password);
+            actual.len() == expected.len()
+                && actual.bytes().zip(expected.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+        },
+        _ => false
+    }
+}
+
+fn login(passwd: &Passwd) -> Result<ExitStatus> {
+    env::set_current_dir(passwd.home)?;
 
     if let Ok(mut motd) = File::open("/etc/motd") {
         let mut motd_string = String::new();
@@ -18,14 +89,36 @@ fn login(user: &str) -> Result<ExitStatus> {
         }
     }
 
-    Command::new("/bin/sh")
-            .env("HOME", home)
-            .env("SHELL", shell)
-            .env("USER", user)
+    Command::new(passwd.shell)
+            .env("HOME", passwd.home)
+            .env("SHELL", passwd.shell)
+            .env("USER", passwd.user)
+            .uid(passwd.uid)
+            .gid(passwd.gid)
             .spawn()?.wait()
 }
 
+fn read_line() -> String {
+    let mut line = String::new();
+    stdin().read_line(&mut line).unwrap();
+    line.trim().to_string()
+}
+
 fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() > 1 {
+        if args.len() == 3 && args[1] == "--hash" && ! args[2].is_empty() && ! args[2].contains(|c| c == '$' || c == ';') {
+            print!("password: ");
+            stdout().flush().unwrap();
+
+            println!("{}${}", args[2], hash(&args[2], &read_line()));
+            return;
+        } else {
+            println!("usage: {} [--hash <salt>]", args[0]);
+            process::exit(1);
+        }
+    }
+
     loop {
         if let Ok(mut issue) = File::open("/etc/issue") {
             let mut issue_string = String::new();
@@ -37,12 +130,41 @@ fn main() {
         print!("redox login: ");
         stdout().flush().unwrap();
 
-        let mut user = String::new();
-        stdin().read_line(&mut user).unwrap();
+        let user = read_line();
+        if user.is_empty() {
+            continue;
+        }
+
+        let mut passwd_string = String::new();
+        if let Err(err) = File::open("/etc/passwd").and_then(|mut file| file.read_to_string(&mut passwd_string)) {
+            println!("login: failed to read /etc/passwd: {}", err);
+            continue;
+        }
+
+        let result = match passwd_string.lines().filter_map(Passwd::parse).find(|passwd| passwd.user == user) {
+            Some(passwd) => {
+                let authorized = if passwd.password.starts_with('!') {
+                    false
+                } else if passwd.password.is_empty() {
+                    true
+                } else {
+                    print!("password: ");
+                    stdout().flush().unwrap();
+
+                    verify(passwd.password, &read_line())
+                };
+
+                if authorized {
+                    login(&passwd)
+                } else {
+                    Err(Error::new(ErrorKind::PermissionDenied, "incorrect password"))
+                }
+            },
+            None => Err(Error::new(ErrorKind::NotFound, "unknown user"))
+        };
 
-        match login(&user) {
-            Ok(_exit_status) => (),
-            Err(err) => println!("login: failed to login as {}: {}", user, err)
+        if let Err(err) = result {
+            println!("login: failed to login as {}: {}", user, err);
         }
     }
 }
@@ -4,6 +4,11 @@ use core::{mem, slice, str};
 use super::error::*;
 use super::syscall::*;
 
+/// The `a` of a packet that cancels the request with the ID in `b`, because a signal interrupted
+/// its caller. The ID of the packet itself is 0. Memory passed with the request stays mapped until
+/// the reply to this packet, so it is sent once the server is done with the request.
+pub const SCHEME_CANCEL: usize = !0;
+
 #[derive(Copy, Clone, Debug, Default)]
 #[repr(packed)]
 pub struct Packet {
@@ -11,7 +16,14 @@ pub struct Packet {
     pub a: usize,
     pub b: usize,
     pub c: usize,
-    pub d: usize
+    pub d: usize,
+    /// The effective user ID of the caller, so that the scheme can check permissions
+    pub uid: usize,
+    /// The effective group ID of the caller
+    pub gid: usize,
+    /// The file status flags of the resource, such as `O_NONBLOCK`, for calls on an open resource.
+    /// A scheme should return `EAGAIN` instead of blocking while `O_NONBLOCK` is set.
+    pub flags: usize
 }
 
 impl Deref for Packet {
@@ -31,13 +43,51 @@ impl DerefMut for Packet {
     }
 }
 
+/// Check that the user `uid` in the group `gid` may access a file with the permission bits of
+/// `mode`, owned by `owner_uid` and `owner_gid`, with the access mode of the open `flags`
+///
+/// Root can access everything.
+pub fn check_access(mode: u16, owner_uid: u16, owner_gid: u16, uid: usize, gid: usize, flags: usize) -> Result<()> {
+    if uid == 0 {
+        return Ok(());
+    }
+
+    let perm = if owner_uid as usize == uid {
+        (mode >> 6) & 7
+    } else if owner_gid as usize == gid {
+        (mode >> 3) & 7
+    } else {
+        mode & 7
+    };
+
+    let needed = match flags & O_ACCMODE {
+        O_RDONLY => 4,
+        O_WRONLY => 2,
+        _ => 6,
+    };
+
+    if perm & needed == needed {
+        Ok(())
+    } else {
+        Err(Error::new(EACCES))
+    }
+}
+
 pub trait Scheme {
     fn handle(&mut self, packet: &mut Packet) {
         packet.a = Error::mux(match packet.a {
-            SYS_OPEN => self.open(unsafe { str::from_utf8_unchecked(slice::from_raw_parts(packet.b as *const u8, packet.c)) }, packet.d),
-            SYS_MKDIR => self.mkdir(unsafe { str::from_utf8_unchecked(slice::from_raw_parts(packet.b as *const u8, packet.c)) }, packet.d),
-            SYS_RMDIR => self.rmdir(unsafe { str::from_utf8_unchecked(slice::from_raw_parts(packet.b as *const u8, packet.c)) }),
-            SYS_UNLINK => self.unlink(unsafe { str::from_utf8_unchecked(slice::from_raw_parts(packet.b as *const u8, packet.c)) }),
+            SYS_OPEN => self.open(unsafe { str::from_utf8_unchecked(slice::from_raw_parts(packet.b as *const u8, packet.c)) }, packet.d, packet.uid, packet.gid),
+            SYS_MKDIR => self.mkdir(unsafe { str::from_utf8_unchecked(slice::from_raw_parts(packet.b as *const u8, packet.c)) }, packet.d, packet.uid, packet.gid),
+            SYS_RMDIR => self.rmdir(unsafe { str::from_utf8_unchecked(slice::from_raw_parts(packet.b as *const u8, packet.c)) }, packet.uid, packet.gid),
+            SYS_UNLINK => self.unlink(unsafe { str::from_utf8_unchecked(slice::from_raw_parts(packet.b as *const u8, packet.c)) }, packet.uid, packet.gid),
+            SYS_RENAME => {
+                let (old, new) = unsafe { str::from_utf8_unchecked(slice::from_raw_parts(packet.b as *const u8, packet.d)) }.split_at(packet.c);
+                self.rename(old, new, packet.uid, packet.gid)
+            },
+            SYS_LINK => {
+                let (old, new) = unsafe { str::from_utf8_unchecked(slice::from_raw_parts(packet.b as *const u8, packet.d)) }.split_at(packet.c);
+                self.link(old, new, packet.uid, packet.gid)
+            },
 
             SYS_DUP => self.dup(packet.b),
             SYS_READ => self.read(packet.b, unsafe { slice::from_raw_parts_mut(packet.c as *mut u8, packet.d) }),
@@ -47,26 +97,34 @@ pub trait Scheme {
             SYS_FSTAT => self.fstat(packet.b, unsafe { &mut *(packet.c as *mut Stat) }),
             SYS_FSYNC => self.fsync(packet.b),
             SYS_FTRUNCATE => self.ftruncate(packet.b, packet.c),
+            SYS_MMAP => self.mmap(packet.b, packet.c, packet.d),
+            SYS_POLL => self.poll(packet.b, packet.c),
             SYS_CLOSE => self.close(packet.b),
 
+            SCHEME_CANCEL => self.cancel(packet.b),
+
             _ => Err(Error::new(ENOSYS))
         });
     }
 
     /* Scheme operations */
 
+    /// Open `path` for the user `uid` in the group `gid`, the effective IDs of the caller
+    ///
+    /// The scheme checks permissions, such as with `check_access`, before it creates or truncates
+    /// anything. The same goes for the other operations that take the IDs of the caller.
     #[allow(unused_variables)]
-    fn open(&mut self, path: &str, flags: usize) -> Result<usize> {
+    fn open(&mut self, path: &str, flags: usize, uid: usize, gid: usize) -> Result<usize> {
         Err(Error::new(ENOENT))
     }
 
     #[allow(unused_variables)]
-    fn mkdir(&mut self, path: &str, mode: usize) -> Result<usize> {
+    fn mkdir(&mut self, path: &str, mode: usize, uid: usize, gid: usize) -> Result<usize> {
         Err(Error::new(ENOENT))
     }
 
     #[allow(unused_variables)]
-    fn rmdir(&mut self, path: &str) -> Result<usize> {
+    fn rmdir(&mut self, path: &str, uid: usize, gid: usize) -> Result<usize> {
         Err(Error::new(ENOENT))
     }
 
@@ -76,10 +134,22 @@ pub trait Scheme {
     }
 
     #[allow(unused_variables)]
-    fn unlink(&mut self, path: &str) -> Result<usize> {
+    fn unlink(&mut self, path: &str, uid: usize, gid: usize) -> Result<usize> {
         Err(Error::new(ENOENT))
     }
 
+    /// Rename `old` to `new`, both are URLs on this scheme
+    #[allow(unused_variables)]
+    fn rename(&mut self, old: &str, new: &str, uid: usize, gid: usize) -> Result<usize> {
+        Err(Error::new(ENOSYS))
+    }
+
+    /// Create a hard link `new` to `old`, both are URLs on this scheme
+    #[allow(unused_variables)]
+    fn link(&mut self, old: &str, new: &str, uid: usize, gid: usize) -> Result<usize> {
+        Err(Error::new(ENOSYS))
+    }
+
     /* Resource operations */
     #[allow(unused_variables)]
     fn dup(&mut self, old_id: usize) -> Result<usize> {
@@ -121,8 +191,33 @@ pub trait Scheme {
         Err(Error::new(EBADF))
     }
 
+    /// Return the address, in the scheme's memory, of `size` bytes at `offset` in the resource
+    ///
+    /// The memory must stay valid until the resource is closed.
+    #[allow(unused_variables)]
+    fn mmap(&mut self, id: usize, offset: usize, size: usize) -> Result<usize> {
+        Err(Error::new(ENODEV))
+    }
+
+    /// Return which of the requested `events` are ready on the resource
+    ///
+    /// The kernel does not wait for the answer, so a scheme may also hold the request and answer
+    /// once one of the events is ready, instead of answering at once with none. Held requests
+    /// should be answered when the resource is closed.
+    #[allow(unused_variables)]
+    fn poll(&mut self, id: usize, events: usize) -> Result<usize> {
+        Ok(events & (POLLIN | POLLOUT))
+    }
+
     #[allow(unused_variables)]
     fn close(&mut self, id: usize) -> Result<usize> {
         Err(Error::new(EBADF))
     }
+
+    /// Stop working on the request with the packet ID `packet_id`, if it is deferred. Its caller
+    /// no longer waits, so a reply to it is ignored, apart from files it opened being closed.
+    #[allow(unused_variables)]
+    fn cancel(&mut self, packet_id: usize) -> Result<usize> {
+        Ok(0)
+    }
 }
@@ -1,4 +1,4 @@
-use syscall::arch::{syscall0, syscall1, syscall2, syscall3, syscall5};
+use syscall::arch::{sigreturn, syscall0, syscall1, syscall2, syscall3, syscall4, syscall5};
 use error::Result;
 
 pub const SYS_BRK: usize = 45;
@@ -22,32 +22,107 @@ pub const SYS_CLOCK_GETTIME: usize = 265;
     pub const CLOCK_REALTIME: usize = 1;
     pub const CLOCK_MONOTONIC: usize = 4;
 pub const SYS_DUP: usize = 41;
+pub const SYS_DUP2: usize = 63;
 pub const SYS_EXECVE: usize = 11;
+    pub const AT_NULL: usize = 0;
+    pub const AT_PHDR: usize = 3;
+    pub const AT_PHENT: usize = 4;
+    pub const AT_PHNUM: usize = 5;
+    pub const AT_PAGESZ: usize = 6;
+    pub const AT_BASE: usize = 7;
+    pub const AT_ENTRY: usize = 9;
 pub const SYS_EXIT: usize = 1;
+pub const SYS_FCNTL: usize = 55;
+    pub const F_GETFD: usize = 1;
+    pub const F_SETFD: usize = 2;
+    pub const F_GETFL: usize = 3;
+    pub const F_SETFL: usize = 4;
+    pub const FD_CLOEXEC: usize = 1;
 pub const SYS_FPATH: usize = 928;
 pub const SYS_FSTAT: usize = 28;
     pub const MODE_DIR: u16 = 0x4000;
     pub const MODE_FILE: u16 = 0x8000;
     pub const MODE_ALL: u16 = MODE_DIR | MODE_FILE;
+    pub const MODE_PERM: u16 = 0o777;
 pub const SYS_FSYNC: usize = 118;
 pub const SYS_FTRUNCATE: usize = 93;
 pub const SYS_FUTEX: usize = 240;
     pub const FUTEX_WAIT: usize = 0;
     pub const FUTEX_WAKE: usize = 1;
     pub const FUTEX_REQUEUE: usize = 2;
+pub const SYS_GETEGID: usize = 50;
+pub const SYS_GETEUID: usize = 49;
+pub const SYS_GETGID: usize = 47;
+pub const SYS_GETPGID: usize = 132;
 pub const SYS_GETPID: usize = 20;
+pub const SYS_GETPRIORITY: usize = 96;
+    pub const PRIO_PROCESS: usize = 0;
+    pub const PRIO_PGRP: usize = 1;
+    pub const PRIO_USER: usize = 2;
+pub const SYS_GETRLIMIT: usize = 76;
+    pub const RLIMIT_CPU: usize = 0;
+    pub const RLIMIT_NPROC: usize = 6;
+    pub const RLIMIT_NOFILE: usize = 7;
+    pub const RLIMIT_AS: usize = 9;
+    pub const RLIMIT_NLIMITS: usize = 10;
+    pub const RLIM_INFINITY: usize = !0;
+pub const SYS_GETUID: usize = 24;
 pub const SYS_IOPL: usize = 110;
+pub const SYS_KILL: usize = 37;
+    pub const SIGHUP: usize = 1;
+    pub const SIGINT: usize = 2;
+    pub const SIGQUIT: usize = 3;
+    pub const SIGILL: usize = 4;
+    pub const SIGTRAP: usize = 5;
+    pub const SIGABRT: usize = 6;
+    pub const SIGBUS: usize = 7;
+    pub const SIGFPE: usize = 8;
+    pub const SIGKILL: usize = 9;
+    pub const SIGUSR1: usize = 10;
+    pub const SIGSEGV: usize = 11;
+    pub const SIGUSR2: usize = 12;
+    pub const SIGPIPE: usize = 13;
+    pub const SIGALRM: usize = 14;
+    pub const SIGTERM: usize = 15;
+    pub const SIGSTKFLT: usize = 16;
+    pub const SIGCHLD: usize = 17;
+    pub const SIGCONT: usize = 18;
+    pub const SIGSTOP: usize = 19;
+    pub const SIGTSTP: usize = 20;
+    pub const SIGTTIN: usize = 21;
+    pub const SIGTTOU: usize = 22;
+    pub const SIGURG: usize = 23;
+    pub const SIGXCPU: usize = 24;
+    pub const SIGXFSZ: usize = 25;
+    pub const SIGVTALRM: usize = 26;
+    pub const SIGPROF: usize = 27;
+    pub const SIGWINCH: usize = 28;
+    pub const SIGIO: usize = 29;
+    pub const SIGPWR: usize = 30;
+    pub const SIGSYS: usize = 31;
+    pub const NSIG: usize = 32;
 pub const SYS_LINK: usize = 9;
 pub const SYS_LSEEK: usize = 19;
     pub const SEEK_SET: usize = 0;
     pub const SEEK_CUR: usize = 1;
     pub const SEEK_END: usize = 2;
 pub const SYS_MKDIR: usize = 39;
+pub const SYS_MMAP: usize = 90;
+    pub const PROT_NONE: usize = 0;
+    pub const PROT_READ: usize = 1;
+    pub const PROT_WRITE: usize = 2;
+    pub const PROT_EXEC: usize = 4;
+    pub const MAP_SHARED: usize = 1;
+    pub const MAP_PRIVATE: usize = 2;
+    pub const MAP_ANONYMOUS: usize = 0x20;
+pub const SYS_MPROTECT: usize = 125;
+pub const SYS_MUNMAP: usize = 91;
 pub const SYS_NANOSLEEP: usize = 162;
 pub const SYS_OPEN: usize = 5;
     pub const O_RDONLY: usize = 0;
     pub const O_WRONLY: usize = 1;
     pub const O_RDWR: usize = 2;
+    pub const O_ACCMODE: usize = 3;
     pub const O_NONBLOCK: usize = 4;
     pub const O_APPEND: usize = 8;
     pub const O_SHLOCK: usize = 0x10;
@@ -57,11 +132,38 @@ pub const SYS_OPEN: usize = 5;
     pub const O_CREAT: usize = 0x200;
     pub const O_TRUNC: usize = 0x400;
     pub const O_EXCL: usize = 0x800;
+    pub const O_CLOEXEC: usize = 0x100000;
 pub const SYS_PIPE2: usize = 331;
+pub const SYS_POLL: usize = 168;
+    pub const POLLIN: usize = 1;
+    pub const POLLOUT: usize = 4;
+    pub const POLLERR: usize = 8;
+    pub const POLLHUP: usize = 0x10;
+    pub const POLLNVAL: usize = 0x20;
 pub const SYS_READ: usize = 3;
+pub const SYS_RENAME: usize = 38;
 pub const SYS_RMDIR: usize = 84;
+pub const SYS_SIGACTION: usize = 67;
+    pub const SIG_DFL: usize = 0;
+    pub const SIG_IGN: usize = 1;
+    pub const SA_NODEFER: u32 = 0x40000000;
+    pub const SA_RESETHAND: u32 = 0x80000000;
+pub const SYS_SETGID: usize = 46;
+pub const SYS_SETPGID: usize = 57;
+pub const SYS_SETPRIORITY: usize = 97;
+pub const SYS_SETRLIMIT: usize = 75;
+pub const SYS_SETSID: usize = 66;
+pub const SYS_SETUID: usize = 23;
+pub const SYS_SIGPROCMASK: usize = 126;
+    pub const SIG_BLOCK: usize = 0;
+    pub const SIG_UNBLOCK: usize = 1;
+    pub const SIG_SETMASK: usize = 2;
+pub const SYS_SIGRETURN: usize = 119;
 pub const SYS_UNLINK: usize = 10;
 pub const SYS_WAITPID: usize = 7;
+    pub const WNOHANG: usize = 1;
+    pub const WUNTRACED: usize = 2;
+    pub const WCONTINUED: usize = 8;
 pub const SYS_WRITE: usize = 4;
 pub const SYS_YIELD: usize = 158;
 
@@ -81,6 +183,48 @@ pub struct Stat {
     pub st_ctime: u32
 }
 
+/// A file descriptor to watch with `sys_poll`
+///
+/// `events` are the requested `POLL*` events, and `revents` is set to those that are ready.
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(packed)]
+pub struct PollFd {
+    pub fd: usize,
+    pub events: usize,
+    pub revents: usize,
+}
+
+/// A signal disposition, as used by `sys_sigaction`
+///
+/// Signal masks are bit sets, where bit `n` stands for signal `n`.
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(packed)]
+pub struct SigAction {
+    pub sa_handler: usize,
+    pub sa_mask: u32,
+    pub sa_flags: u32,
+    pub sa_restorer: usize,
+}
+
+/// A resource limit, as used by `sys_getrlimit` and `sys_setrlimit`
+///
+/// `rlim_cur` is the soft limit that is enforced, and `rlim_max` is the ceiling for the soft limit.
+#[derive(Copy, Clone, Debug)]
+#[repr(packed)]
+pub struct RLimit {
+    pub rlim_cur: usize,
+    pub rlim_max: usize,
+}
+
+impl Default for RLimit {
+    fn default() -> RLimit {
+        RLimit {
+            rlim_cur: RLIM_INFINITY,
+            rlim_max: RLIM_INFINITY,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, Default)]
 #[repr(packed)]
 pub struct TimeSpec {
@@ -112,14 +256,24 @@ pub fn sys_dup(fd: usize) -> Result<usize> {
     unsafe { syscall1(SYS_DUP, fd) }
 }
 
-pub unsafe fn sys_execve(path: *const u8, args: *const *const u8) -> Result<usize> {
-    syscall2(SYS_EXECVE, path as usize, args as usize)
+pub fn sys_dup2(fd: usize, new_fd: usize) -> Result<usize> {
+    unsafe { syscall2(SYS_DUP2, fd, new_fd) }
+}
+
+/// Execute a program, with the null terminated `NAME=VALUE` strings of `env_vars` as its
+/// environment, or the environment of the current process if `env_vars` is null
+pub unsafe fn sys_execve(path: *const u8, args: *const *const u8, env_vars: *const *const u8) -> Result<usize> {
+    syscall3(SYS_EXECVE, path as usize, args as usize, env_vars as usize)
 }
 
 pub fn sys_exit(status: usize) -> Result<usize> {
     unsafe { syscall1(SYS_EXIT, status) }
 }
 
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> Result<usize> {
+    unsafe { syscall3(SYS_FCNTL, fd, cmd, arg) }
+}
+
 pub fn sys_fpath(fd: usize, buf: &mut [u8]) -> Result<usize> {
     unsafe { syscall3(SYS_FPATH, fd, buf.as_mut_ptr() as usize, buf.len()) }
 }
@@ -140,16 +294,50 @@ pub unsafe fn sys_futex(addr: *mut i32, op: usize, val: i32, val2: usize, addr2:
     syscall5(SYS_FUTEX, addr as usize, op, (val as isize) as usize, val2, addr2 as usize)
 }
 
+pub fn sys_getegid() -> Result<usize> {
+    unsafe { syscall0(SYS_GETEGID) }
+}
+
+pub fn sys_geteuid() -> Result<usize> {
+    unsafe { syscall0(SYS_GETEUID) }
+}
+
+pub fn sys_getgid() -> Result<usize> {
+    unsafe { syscall0(SYS_GETGID) }
+}
+
+pub fn sys_getpgid(pid: usize) -> Result<usize> {
+    unsafe { syscall1(SYS_GETPGID, pid) }
+}
+
 pub fn sys_getpid() -> Result<usize> {
     unsafe { syscall0(SYS_GETPID) }
 }
 
+/// Get the highest priority, which is the lowest nice value, of the selected contexts
+pub fn sys_getpriority(which: usize, who: usize) -> Result<isize> {
+    // The kernel returns 20 - nice, so that the result is never negative
+    unsafe { syscall2(SYS_GETPRIORITY, which, who) }.map(|prio| 20 - prio as isize)
+}
+
+pub fn sys_getrlimit(resource: usize, rlim: &mut RLimit) -> Result<usize> {
+    unsafe { syscall2(SYS_GETRLIMIT, resource, rlim as *mut RLimit as usize) }
+}
+
+pub fn sys_getuid() -> Result<usize> {
+    unsafe { syscall0(SYS_GETUID) }
+}
+
 pub unsafe fn sys_iopl(level: usize) -> Result<usize> {
     syscall1(SYS_IOPL, level)
 }
 
-pub unsafe fn sys_link(old: *const u8, new: *const u8) -> Result<usize> {
-    syscall2(SYS_LINK, old as usize, new as usize)
+pub fn sys_kill(pid: usize, sig: usize) -> Result<usize> {
+    unsafe { syscall2(SYS_KILL, pid, sig) }
+}
+
+pub fn sys_link(old: &str, new: &str) -> Result<usize> {
+    unsafe { syscall4(SYS_LINK, old.as_ptr() as usize, old.len(), new.as_ptr() as usize, new.len()) }
 }
 
 pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> Result<usize> {
@@ -160,6 +348,24 @@ pub fn sys_mkdir(path: &str, mode: usize) -> Result<usize> {
     unsafe { syscall3(SYS_MKDIR, path.as_ptr() as usize, path.len(), mode) }
 }
 
+/// Map `size` bytes of a file, starting at the page aligned `offset`, into memory
+///
+/// With `MAP_ANONYMOUS`, `fd` and `offset` are ignored and zeroed memory is mapped instead.
+/// Returns the address of the mapping.
+pub unsafe fn sys_mmap(fd: usize, offset: usize, size: usize, prot: usize, flags: usize) -> Result<usize> {
+    syscall5(SYS_MMAP, fd, offset, size, prot, flags)
+}
+
+/// Change the protection of a mapping created by `sys_mmap`
+pub unsafe fn sys_mprotect(addr: usize, size: usize, prot: usize) -> Result<usize> {
+    syscall3(SYS_MPROTECT, addr, size, prot)
+}
+
+/// Remove a mapping created by `sys_mmap`
+pub unsafe fn sys_munmap(addr: usize, size: usize) -> Result<usize> {
+    syscall2(SYS_MUNMAP, addr, size)
+}
+
 pub fn sys_nanosleep(req: &TimeSpec, rem: &mut TimeSpec) -> Result<usize> {
     unsafe { syscall2(SYS_NANOSLEEP, req as *const TimeSpec as usize, rem as *mut TimeSpec as usize) }
 }
@@ -172,22 +378,131 @@ pub fn sys_pipe2(fds: &mut [usize; 2], flags: usize) -> Result<usize> {
     unsafe { syscall2(SYS_PIPE2, fds.as_ptr() as usize, flags) }
 }
 
+pub fn sys_poll(fds: &mut [PollFd], timeout: isize) -> Result<usize> {
+    unsafe { syscall3(SYS_POLL, fds.as_mut_ptr() as usize, fds.len(), timeout as usize) }
+}
+
 pub fn sys_read(fd: usize, buf: &mut [u8]) -> Result<usize> {
     unsafe { syscall3(SYS_READ, fd, buf.as_mut_ptr() as usize, buf.len()) }
 }
 
+pub fn sys_rename(old: &str, new: &str) -> Result<usize> {
+    unsafe { syscall4(SYS_RENAME, old.as_ptr() as usize, old.len(), new.as_ptr() as usize, new.len()) }
+}
+
 pub fn sys_rmdir(path: &str) -> Result<usize> {
     unsafe { syscall2(SYS_RMDIR, path.as_ptr() as usize, path.len()) }
 }
 
+pub fn sys_setgid(gid: usize) -> Result<usize> {
+    unsafe { syscall1(SYS_SETGID, gid) }
+}
+
+pub fn sys_setpgid(pid: usize, pgid: usize) -> Result<usize> {
+    unsafe { syscall2(SYS_SETPGID, pid, pgid) }
+}
+
+pub fn sys_setpriority(which: usize, who: usize, nice: isize) -> Result<usize> {
+    unsafe { syscall3(SYS_SETPRIORITY, which, who, nice as usize) }
+}
+
+pub fn sys_setrlimit(resource: usize, rlim: &RLimit) -> Result<usize> {
+    unsafe { syscall2(SYS_SETRLIMIT, resource, rlim as *const RLimit as usize) }
+}
+
+pub fn sys_setsid() -> Result<usize> {
+    unsafe { syscall0(SYS_SETSID) }
+}
+
+pub fn sys_setuid(uid: usize) -> Result<usize> {
+    unsafe { syscall1(SYS_SETUID, uid) }
+}
+
+/// Set the disposition of a signal, returning the previous one in `oldact`
+///
+/// If a handler is given without a restorer, the default `sigreturn` trampoline is used.
+pub fn sys_sigaction(sig: usize, act: Option<&SigAction>, oldact: Option<&mut SigAction>) -> Result<usize> {
+    let mut act_copy = SigAction::default();
+    let act_ptr = if let Some(act) = act {
+        act_copy = *act;
+        if act_copy.sa_handler > SIG_IGN && act_copy.sa_restorer == 0 {
+            act_copy.sa_restorer = sigreturn as usize;
+        }
+        &act_copy as *const SigAction as usize
+    } else {
+        0
+    };
+    let oldact_ptr = oldact.map_or(0, |oldact| oldact as *mut SigAction as usize);
+    unsafe { syscall3(SYS_SIGACTION, sig, act_ptr, oldact_ptr) }
+}
+
+pub fn sys_sigprocmask(how: usize, set: Option<&u32>, oldset: Option<&mut u32>) -> Result<usize> {
+    let set_ptr = set.map_or(0, |set| set as *const u32 as usize);
+    let oldset_ptr = oldset.map_or(0, |oldset| oldset as *mut u32 as usize);
+    unsafe { syscall3(SYS_SIGPROCMASK, how, set_ptr, oldset_ptr) }
+}
+
 pub fn sys_unlink(path: &str) -> Result<usize> {
     unsafe { syscall2(SYS_UNLINK, path.as_ptr() as usize, path.len()) }
 }
 
+/// Wait for a child to change state, returning its PID
+///
+/// `pid` is interpreted as signed: a positive value waits for that child, `-1` for any child, `0`
+/// for any child in the caller's process group, and any other negative value for any child in the
+/// process group `-pid`. With `WNOHANG`, 0 is returned if no child has changed state yet.
 pub fn sys_waitpid(pid: usize, status: &mut usize, options: usize) -> Result<usize> {
     unsafe { syscall3(SYS_WAITPID, pid, status as *mut usize as usize, options) }
 }
 
+/// The wait status of a context that exited with `code`
+pub fn wexited(code: usize) -> usize {
+    (code & 0xFF) << 8
+}
+
+/// The wait status of a context that was terminated by `sig`
+pub fn wsignaled(sig: usize) -> usize {
+    sig & 0x7F
+}
+
+/// The wait status of a context that was stopped by `sig`
+pub fn wstopped(sig: usize) -> usize {
+    (sig & 0xFF) << 8 | 0x7F
+}
+
+/// The wait status of a context that was continued
+pub fn wcontinued() -> usize {
+    0xFFFF
+}
+
+pub fn wifexited(status: usize) -> bool {
+    status & 0x7F == 0
+}
+
+pub fn wexitstatus(status: usize) -> usize {
+    (status >> 8) & 0xFF
+}
+
+pub fn wifsignaled(status: usize) -> bool {
+    status & 0x7F != 0 && status & 0x7F != 0x7F
+}
+
+pub fn wtermsig(status: usize) -> usize {
+    status & 0x7F
+}
+
+pub fn wifstopped(status: usize) -> bool {
+    status & 0xFF == 0x7F
+}
+
+pub fn wstopsig(status: usize) -> usize {
+    (status >> 8) & 0xFF
+}
+
+pub fn wifcontinued(status: usize) -> bool {
+    status == 0xFFFF
+}
+
 pub fn sys_write(fd: usize, buf: &[u8]) -> Result<usize> {
     unsafe { syscall3(SYS_WRITE, fd, buf.as_ptr() as usize, buf.len()) }
 }
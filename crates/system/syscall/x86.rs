@@ -1,4 +1,5 @@
 use error::{Error, Result};
+use syscall::unix::SYS_SIGRETURN;
 
 pub unsafe fn syscall0(mut a: usize) -> Result<usize> {
     asm!("int 0x80"
@@ -59,3 +60,18 @@ pub unsafe fn syscall5(mut a: usize, b: usize, c: usize, d: usize, e: usize, f:
 
     Error::demux(a)
 }
+
+/// Return from a signal handler
+///
+/// This is the default `sa_restorer`, which the kernel places as the return address of every
+/// signal handler frame. It must not push or pop, as the kernel finds the frame through the stack
+/// pointer.
+#[naked]
+pub unsafe extern "C" fn sigreturn() -> ! {
+    asm!("int 0x80"
+        :
+        : "{eax}"(SYS_SIGRETURN)
+        : "memory"
+        : "intel", "volatile");
+    loop {}
+}
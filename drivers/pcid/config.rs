@@ -0,0 +1,133 @@
+use std::fs::File;
+use std::io::Read;
+
+use pci::PciHeader;
+
+/// The devices that a driver is for
+#[derive(Debug)]
+pub enum DriverMatch {
+    /// A vendor, and a device of that vendor or any device if None
+    Id(u16, Option<u16>),
+    /// A class, and optionally a subclass and an interface
+    Class(u8, Option<u8>, Option<u8>),
+}
+
+impl DriverMatch {
+    /// Parse `VVVV:DDDD`, `VVVV:*`, `CC`, `CC.SS` or `CC.SS.II`, in hexadecimal
+    fn parse(s: &str) -> Option<DriverMatch> {
+        if let Some(colon) = s.find(':') {
+            let vendor = match u16::from_str_radix(&s[..colon], 16) {
+                Ok(vendor) => vendor,
+                Err(_) => return None,
+            };
+            let device = match &s[colon + 1..] {
+                "*" => None,
+                device => match u16::from_str_radix(device, 16) {
+                    Ok(device) => Some(device),
+                    Err(_) => return None,
+                },
+            };
+            Some(DriverMatch::Id(vendor, device))
+        } else {
+            let mut codes = Vec::new();
+            for code in s.split('.') {
+                match u8::from_str_radix(code, 16) {
+                    Ok(code) => codes.push(code),
+                    Err(_) => return None,
+                }
+            }
+            match codes.len() {
+                1 => Some(DriverMatch::Class(codes[0], None, None)),
+                2 => Some(DriverMatch::Class(codes[0], Some(codes[1]), None)),
+                3 => Some(DriverMatch::Class(codes[0], Some(codes[1]), Some(codes[2]))),
+                _ => None,
+            }
+        }
+    }
+
+    pub fn matches(&self, header: &PciHeader) -> bool {
+        match *self {
+            DriverMatch::Id(vendor, device) => {
+                header.vendor_id == vendor && device.map_or(true, |device| header.device_id == device)
+            },
+            DriverMatch::Class(class, subclass, interface) => {
+                header.class == class
+                    && subclass.map_or(true, |subclass| header.subclass == subclass)
+                    && interface.map_or(true, |interface| header.interface == interface)
+            },
+        }
+    }
+}
+
+/// A driver, with the devices it is for and the command that starts it
+#[derive(Debug)]
+pub struct DriverConfig {
+    pub devices: DriverMatch,
+    pub command: Vec<String>,
+}
+
+/// The drivers from the configuration file
+///
+/// Every line that is not empty or a comment has the devices to match, then the driver and its
+/// arguments, separated by spaces:
+///
+/// ```text
+/// # Intel 82540EM
+/// 8086:100E e1000d $BAR0 $IRQ
+/// # Any AHCI controller
+/// 01.06.01 ahcid $BAR5 $IRQ
+/// ```
+///
+/// The first line that matches a device starts its driver.
+#[derive(Debug, Default)]
+pub struct Config {
+    pub drivers: Vec<DriverConfig>,
+}
+
+impl Config {
+    pub fn parse(string: &str) -> Config {
+        let mut config = Config::default();
+
+        for (i, line_untrimmed) in string.lines().enumerate() {
+            let line = line_untrimmed.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut args = line.split(' ').filter(|arg| ! arg.is_empty());
+            let devices = match args.next().and_then(DriverMatch::parse) {
+                Some(devices) => devices,
+                None => {
+                    println!("pcid: line {}: invalid device match '{}'", i + 1, line);
+                    continue;
+                }
+            };
+
+            let command: Vec<String> = args.map(|arg| arg.to_string()).collect();
+            if command.is_empty() {
+                println!("pcid: line {}: no driver for '{}'", i + 1, line);
+                continue;
+            }
+
+            config.drivers.push(DriverConfig {
+                devices: devices,
+                command: command,
+            });
+        }
+
+        config
+    }
+
+    /// Read the configuration at `path`
+    pub fn open(path: &str) -> Result<Config, String> {
+        let mut string = String::new();
+        let mut file = try!(File::open(path).map_err(|err| format!("failed to open {}: {}", path, err)));
+        try!(file.read_to_string(&mut string).map_err(|err| format!("failed to read {}: {}", path, err)));
+        Ok(Config::parse(&string))
+    }
+
+    /// The first driver for a device
+    pub fn driver(&self, header: &PciHeader) -> Option<&DriverConfig> {
+        self.drivers.iter().find(|driver| driver.devices.matches(header))
+    }
+}
@@ -1,62 +1,164 @@
 extern crate system;
 
+use std::env;
+use std::process::{self, Command};
+
 use system::syscall::sys_iopl;
 
-use pci::{Pci, PciBar, PciClass};
+use config::{Config, DriverConfig};
+use pci::{Pci, PciBar, PciBus, PciClass, PciHeader};
 
+mod config;
 mod pci;
 
-fn enumerate_pci() {
-    println!("PCI BS/DV/FN VEND:DEVI CL.SC.IN.RV");
+/// The configuration that is read if none is given as an argument
+const DEFAULT_CONFIG: &'static str = "/etc/pcid.rc";
 
-    let pci = Pci::new();
-    for bus in pci.buses() {
-        for dev in bus.devs() {
-            for func in dev.funcs() {
-                if let Some(header) = func.header() {
-                    println!("PCI {:>02X}/{:>02X}/{:>02X} {:>04X}:{:>04X} {:>02X}.{:>02X}.{:>02X}.{:>02X} {:?}",
-                            bus.num, dev.num, func.num,
-                            header.vendor_id, header.device_id,
-                            header.class, header.subclass, header.interface, header.revision,
-                            PciClass::from(header.class));
-
-                    for i in 0..header.bars.len() {
-                        match PciBar::from(header.bars[i]) {
-                            PciBar::None => (),
-                            PciBar::Memory(address) => println!("    BAR {} {:>08X}", i, address),
-                            PciBar::Port(address) => println!("    BAR {} {:>04X}", i, address)
-                        }
+/// Check if the kernel has its own driver for a device, in which case its BARs are not sized, as
+/// the device may be in use
+fn kernel_driven(header: &PciHeader) -> bool {
+    match (PciClass::from(header.class), header.subclass, header.interface) {
+        // IDE, AHCI, USB host controllers and the display, which has the framebuffer
+        (PciClass::Storage, 0x01, _) | (PciClass::Storage, 0x06, 0x01) |
+        (PciClass::SerialBus, 0x03, _) | (PciClass::Display, _, _) => true,
+        _ => match (header.vendor_id, header.device_id) {
+            // RTL8139, e1000, AC'97 and Intel HD Audio
+            (0x10EC, 0x8139) | (0x8086, 0x100E) | (0x8086, 0x2415) | (0x8086, 0x24C5) |
+            (0x8086, 0x2668) => true,
+            _ => false
+        }
+    }
+}
+
+/// Start the driver for a device
+///
+/// The driver gets the location, IDs, BARs and IRQ of the device in environment variables that
+/// start with `PCI_`, such as `PCI_BAR0`. Arguments that name one of them with a `$` and without
+/// the prefix, such as `$BAR0`, are replaced with its value. BARs and their sizes, such as
+/// `PCI_BAR0_SIZE`, are in hexadecimal, and the IRQ is in decimal, as it is opened in the `irq:`
+/// scheme.
+fn spawn_driver(driver: &DriverConfig, bus: u8, dev: u8, func: u8, header: &PciHeader, sizes: &[u64]) {
+    let mut vars = vec![
+        ("BUS", format!("{:>02X}", bus)),
+        ("DEV", format!("{:>02X}", dev)),
+        ("FUNC", format!("{:>02X}", func)),
+        ("VENDOR", format!("{:>04X}", header.vendor_id)),
+        ("DEVICE", format!("{:>04X}", header.device_id)),
+        ("CLASS", format!("{:>02X}", header.class)),
+        ("SUBCLASS", format!("{:>02X}", header.subclass)),
+        ("INTERFACE", format!("{:>02X}", header.interface)),
+        ("IRQ", format!("{}", header.interrupt_line)),
+    ];
+
+    const BAR_NAMES: [&'static str; 6] = ["BAR0", "BAR1", "BAR2", "BAR3", "BAR4", "BAR5"];
+    const BAR_SIZE_NAMES: [&'static str; 6] = ["BAR0_SIZE", "BAR1_SIZE", "BAR2_SIZE", "BAR3_SIZE", "BAR4_SIZE", "BAR5_SIZE"];
+    for (i, bar) in PciBar::parse(header.bars()).into_iter().enumerate() {
+        match bar {
+            PciBar::None => continue,
+            PciBar::Memory(address) => vars.push((BAR_NAMES[i], format!("{:>08X}", address))),
+            PciBar::Port(address) => vars.push((BAR_NAMES[i], format!("{:>04X}", address))),
+        }
+        vars.push((BAR_SIZE_NAMES[i], format!("{:X}", sizes[i])));
+    }
+
+    let mut command = Command::new(&driver.command[0]);
+    for arg in driver.command[1..].iter() {
+        let value = if arg.starts_with('$') {
+            vars.iter().find(|&&(name, _)| name == &arg[1..]).map(|&(_, ref value)| value.as_str())
+        } else {
+            None
+        };
+        command.arg(value.unwrap_or(arg.as_str()));
+    }
+    for &(name, ref value) in vars.iter() {
+        command.env(&format!("PCI_{}", name), value);
+    }
+
+    match command.spawn() {
+        Ok(_) => println!("    + {}", driver.command.join(" ")),
+        Err(err) => println!("pcid: failed to execute '{}': {}", driver.command.join(" "), err),
+    }
+}
+
+/// Print the devices on a bus and start their drivers, then scan the buses behind its bridges
+fn scan_bus(pci: &Pci, num: u8, config: &Config, scanned: &mut [bool; 256]) {
+    if scanned[num as usize] {
+        return;
+    }
+    scanned[num as usize] = true;
+
+    let bus = PciBus {
+        pci: pci,
+        num: num
+    };
+    for dev in bus.devs() {
+        for func in dev.funcs() {
+            if let Some(header) = func.header() {
+                println!("PCI {:>02X}/{:>02X}/{:>02X} {:>04X}:{:>04X} {:>02X}.{:>02X}.{:>02X}.{:>02X} {:?}",
+                        bus.num, dev.num, func.num,
+                        header.vendor_id, header.device_id,
+                        header.class, header.subclass, header.interface, header.revision,
+                        PciClass::from(header.class));
+
+                let bars = header.bars();
+                let sizes = if kernel_driven(&header) {
+                    vec![0; bars.len()]
+                } else {
+                    func.bar_sizes(&header)
+                };
+                for (i, bar) in PciBar::parse(bars).into_iter().enumerate() {
+                    match bar {
+                        PciBar::None => (),
+                        PciBar::Memory(address) => println!("    BAR {} {:>08X} {:X}{}{}", i, address, sizes[i],
+                                                            if PciBar::wide(bars[i]) { " 64-bit" } else { "" },
+                                                            if PciBar::prefetchable(bars[i]) { " prefetchable" } else { "" }),
+                        PciBar::Port(address) => println!("    BAR {} {:>04X} {:X}", i, address, sizes[i])
                     }
+                }
+
+                for (capability, offset) in func.capabilities(&header) {
+                    println!("    CAP {:>02X} {:?}", offset, capability);
+                }
 
-                    match PciClass::from(header.class) {
-                        PciClass::Storage => match header.subclass {
-                            0x01 => {
-                                println!("    + IDE");
+                match PciClass::from(header.class) {
+                    PciClass::Storage => match header.subclass {
+                        0x01 => {
+                            println!("    + IDE");
+                        },
+                        0x06 => {
+                            println!("    + SATA");
+                        },
+                        _ => ()
+                    },
+                    PciClass::SerialBus => match header.subclass {
+                        0x03 => match header.interface {
+                            0x00 => {
+                                println!("    + UHCI");
                             },
-                            0x06 => {
-                                println!("    + SATA");
+                            0x10 => {
+                                println!("    + OHCI");
                             },
-                            _ => ()
-                        },
-                        PciClass::SerialBus => match header.subclass {
-                            0x03 => match header.interface {
-                                0x00 => {
-                                    println!("    + UHCI");
-                                },
-                                0x10 => {
-                                    println!("    + OHCI");
-                                },
-                                0x20 => {
-                                    println!("    + EHCI");
-                                },
-                                0x30 => {
-                                    println!("    + XHCI");
-                                },
-                                _ => ()
+                            0x20 => {
+                                println!("    + EHCI");
+                            },
+                            0x30 => {
+                                println!("    + XHCI");
                             },
                             _ => ()
                         },
                         _ => ()
+                    },
+                    _ => ()
+                }
+
+                if let Some(driver) = config.driver(&header) {
+                    spawn_driver(driver, bus.num, dev.num, func.num, &header, &sizes);
+                }
+
+                if let Some((secondary, subordinate)) = header.bridge_buses() {
+                    println!("    BUS {:>02X}-{:>02X}", secondary, subordinate);
+                    if secondary > num {
+                        scan_bus(pci, secondary, config, scanned);
                     }
                 }
             }
@@ -64,8 +166,36 @@ fn enumerate_pci() {
     }
 }
 
+fn enumerate_pci(config: &Config) {
+    println!("PCI BS/DV/FN VEND:DEVI CL.SC.IN.RV");
+
+    // If the host bridge at 00/00/00 has more than one function, each function is the host
+    // bridge of the bus with its number
+    let pci = Pci::new();
+    let host_bridges = if unsafe { pci.read(0, 0, 0, 0x0C) } & 0x800000 == 0x800000 { 8 } else { 1 };
+
+    let mut scanned = [false; 256];
+    for func in 0..host_bridges {
+        if unsafe { pci.read(0, 0, func, 0) } != 0xFFFFFFFF {
+            scan_bus(&pci, func, config, &mut scanned);
+        }
+    }
+}
+
 fn main() {
-    unsafe { sys_iopl(3).unwrap() };
+    let path = env::args().nth(1).unwrap_or(DEFAULT_CONFIG.to_string());
+    let config = match Config::open(&path) {
+        Ok(config) => config,
+        Err(err) => {
+            println!("pcid: {}", err);
+            Config::default()
+        }
+    };
+
+    if let Err(err) = unsafe { sys_iopl(3) } {
+        println!("pcid: failed to get I/O permission: {}", err);
+        process::exit(1);
+    }
 
-    enumerate_pci();
+    enumerate_pci(&config);
 }
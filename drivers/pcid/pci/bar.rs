@@ -1,18 +1,47 @@
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub enum PciBar {
     None,
-    Memory(u32),
+    Memory(u64),
     Port(u16)
 }
 
-impl From<u32> for PciBar {
-    fn from(bar: u32) -> Self {
-        if bar & 0xFFFFFFFC == 0 {
-            PciBar::None
-        } else if bar & 1 == 0 {
-            PciBar::Memory(bar & 0xFFFFFFF0)
-        } else {
-            PciBar::Port((bar & 0xFFFC) as u16)
+impl PciBar {
+    /// Decode the BARs of a header
+    ///
+    /// A 64 bit memory BAR takes two registers, so the second one is decoded as None.
+    pub fn parse(bars: &[u32]) -> Vec<PciBar> {
+        let mut parsed = Vec::new();
+
+        let mut i = 0;
+        while i < bars.len() {
+            let bar = bars[i];
+            if bar & 1 == 1 {
+                let address = (bar & 0xFFFC) as u16;
+                parsed.push(if address == 0 { PciBar::None } else { PciBar::Port(address) });
+            } else {
+                let mut address = (bar & 0xFFFFFFF0) as u64;
+                if PciBar::wide(bar) && i + 1 < bars.len() {
+                    address |= (bars[i + 1] as u64) << 32;
+                }
+                parsed.push(if address == 0 { PciBar::None } else { PciBar::Memory(address) });
+                if PciBar::wide(bar) && i + 1 < bars.len() {
+                    parsed.push(PciBar::None);
+                    i += 1;
+                }
+            }
+            i += 1;
         }
+
+        parsed
+    }
+
+    /// Is `bar` the first register of a 64 bit memory BAR
+    pub fn wide(bar: u32) -> bool {
+        bar & 0b111 == 0b100
+    }
+
+    /// Is `bar` a prefetchable memory BAR
+    pub fn prefetchable(bar: u32) -> bool {
+        bar & 0b1001 == 0b1000
     }
 }
@@ -0,0 +1,20 @@
+#[derive(Clone, Copy, Debug)]
+pub enum PciCapability {
+    PowerManagement,
+    Msi,
+    PciExpress,
+    MsiX,
+    Other(u8)
+}
+
+impl From<u8> for PciCapability {
+    fn from(id: u8) -> PciCapability {
+        match id {
+            0x01 => PciCapability::PowerManagement,
+            0x05 => PciCapability::Msi,
+            0x10 => PciCapability::PciExpress,
+            0x11 => PciCapability::MsiX,
+            id => PciCapability::Other(id)
+        }
+    }
+}
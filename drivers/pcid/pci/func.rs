@@ -1,6 +1,12 @@
 use std::ops::DerefMut;
 
-use super::{PciDev, PciHeader};
+use super::{PciBar, PciCapability, PciDev, PciHeader};
+
+/// The bit of the status register that is set if the function has a list of capabilities
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+
+/// The most capabilities that are read, in case the list loops
+const MAX_CAPABILITIES: usize = 48;
 
 pub struct PciFunc<'pci> {
     pub dev: &'pci PciDev<'pci>,
@@ -24,7 +30,82 @@ impl<'pci> PciFunc<'pci> {
         }
     }
 
+    /// The sizes of the BARs of a header, in bytes, or 0 if a BAR is not used
+    ///
+    /// Each BAR is sized by writing ones to it and reading back which bits stuck. Memory and IO
+    /// decoding is disabled while doing so, so the device does not answer at the wrong address.
+    /// CardBus bridges are skipped, as their header only has one BAR, followed by other registers.
+    pub fn bar_sizes(&self, header: &PciHeader) -> Vec<u64> {
+        let bars = header.bars();
+        let mut sizes = vec![0; bars.len()];
+
+        if header.header_type() == 2 {
+            return sizes;
+        }
+
+        unsafe {
+            let command = self.read(0x04) & 0xFFFF;
+            self.write(0x04, command & !0b11);
+
+            let mut i = 0;
+            while i < bars.len() {
+                let offset = 0x10 + (i as u8) * 4;
+                let bar = bars[i];
+                if bar & 1 == 1 {
+                    self.write(offset, 0xFFFFFFFF);
+                    let mask = self.read(offset) | 0xFFFF0000;
+                    self.write(offset, bar);
+                    if mask & 0xFFFC != 0 {
+                        sizes[i] = (!(mask & 0xFFFFFFFC)).wrapping_add(1) as u64;
+                    }
+                } else if PciBar::wide(bar) && i + 1 < bars.len() {
+                    self.write(offset, 0xFFFFFFFF);
+                    self.write(offset + 4, 0xFFFFFFFF);
+                    let mask = (self.read(offset) & 0xFFFFFFF0) as u64 | (self.read(offset + 4) as u64) << 32;
+                    self.write(offset, bar);
+                    self.write(offset + 4, bars[i + 1]);
+                    if mask != 0 {
+                        sizes[i] = (!mask).wrapping_add(1);
+                    }
+                    i += 1;
+                } else {
+                    self.write(offset, 0xFFFFFFFF);
+                    let mask = self.read(offset) & 0xFFFFFFF0;
+                    self.write(offset, bar);
+                    if mask != 0 {
+                        sizes[i] = (!mask).wrapping_add(1) as u64;
+                    }
+                }
+                i += 1;
+            }
+
+            self.write(0x04, command);
+        }
+
+        sizes
+    }
+
+    /// The capabilities of a header, with their offsets in the configuration space
+    pub fn capabilities(&self, header: &PciHeader) -> Vec<(PciCapability, u8)> {
+        let mut capabilities = Vec::new();
+
+        if header.status & STATUS_CAPABILITIES == STATUS_CAPABILITIES {
+            let mut offset = header.capabilities & 0xFC;
+            while offset != 0 && capabilities.len() < MAX_CAPABILITIES {
+                let dword = unsafe { self.read(offset) };
+                capabilities.push((PciCapability::from(dword as u8), offset));
+                offset = (dword >> 8) as u8 & 0xFC;
+            }
+        }
+
+        capabilities
+    }
+
     pub unsafe fn read(&self, offset: u8) -> u32 {
         self.dev.read(self.num, offset)
     }
+
+    pub unsafe fn write(&self, offset: u8, value: u32) {
+        self.dev.write(self.num, offset, value)
+    }
 }
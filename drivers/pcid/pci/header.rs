@@ -29,6 +29,31 @@ pub struct PciHeader {
     pub max_latency: u8
 }
 
+impl PciHeader {
+    /// The layout of the header, 0 for a device and 1 for a PCI to PCI bridge
+    pub fn header_type(&self) -> u8 {
+        self.header_type & 0x7F
+    }
+
+    /// The BAR registers, of which a bridge only has two
+    pub fn bars(&self) -> &[u32] {
+        if self.header_type() == 1 {
+            &self.bars[..2]
+        } else {
+            &self.bars
+        }
+    }
+
+    /// The secondary and subordinate bus numbers of a bridge
+    pub fn bridge_buses(&self) -> Option<(u8, u8)> {
+        if self.header_type() == 1 {
+            Some(((self.bars[2] >> 8) as u8, (self.bars[2] >> 16) as u8))
+        } else {
+            None
+        }
+    }
+}
+
 impl Deref for PciHeader {
     type Target = [u32];
     fn deref(&self) -> &[u32] {
@@ -6,6 +6,7 @@ use std::cell::RefCell;
 
 pub use self::bar::PciBar;
 pub use self::bus::{PciBus, PciBusIter};
+pub use self::cap::PciCapability;
 pub use self::class::PciClass;
 pub use self::dev::{PciDev, PciDevIter};
 pub use self::func::PciFunc;
@@ -13,6 +14,7 @@ pub use self::header::PciHeader;
 
 mod bar;
 mod bus;
+mod cap;
 mod class;
 mod dev;
 mod func;
@@ -20,14 +22,14 @@ mod header;
 
 pub struct Pci {
     addr: RefCell<Pio<u32>>,
-    data: Pio<u32>
+    data: RefCell<Pio<u32>>
 }
 
 impl Pci {
     pub fn new() -> Self {
         Pci {
             addr: RefCell::new(Pio::new(0xCF8)),
-            data: Pio::new(0xCFC)
+            data: RefCell::new(Pio::new(0xCFC))
         }
     }
 
@@ -35,10 +37,18 @@ impl Pci {
         PciIter::new(self)
     }
 
+    fn address(bus: u8, dev: u8, func: u8, offset: u8) -> u32 {
+        0x80000000 | ((bus as u32) << 16) | ((dev as u32) << 11) | ((func as u32) << 8) | ((offset as u32) & 0xFC)
+    }
+
     pub unsafe fn read(&self, bus: u8, dev: u8, func: u8, offset: u8) -> u32 {
-        let address = 0x80000000 | ((bus as u32) << 16) | ((dev as u32) << 11) | ((func as u32) << 8) | ((offset as u32) & 0xFC);
-        self.addr.borrow_mut().write(address);
-        self.data.read()
+        self.addr.borrow_mut().write(Pci::address(bus, dev, func, offset));
+        self.data.borrow().read()
+    }
+
+    pub unsafe fn write(&self, bus: u8, dev: u8, func: u8, offset: u8, value: u32) {
+        self.addr.borrow_mut().write(Pci::address(bus, dev, func, offset));
+        self.data.borrow_mut().write(value);
     }
 }
 
@@ -1,5 +1,7 @@
 extern crate system;
 
+use std::fs::File;
+
 use system::syscall::sys_iopl;
 
 use power::reset;
@@ -7,9 +9,12 @@ use power::reset;
 mod power;
 
 fn main() {
-    unsafe { sys_iopl(3).unwrap() };
-
     println!("Performing reset");
 
+    // The kernel resets with the ACPI reset register, and does not return if it can
+    let _ = File::create("acpi:reset");
+
+    unsafe { sys_iopl(3).unwrap() };
+
     reset();
 }
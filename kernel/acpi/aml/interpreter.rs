@@ -0,0 +1,990 @@
+use alloc::boxed::Box;
+
+use collections::{String, Vec};
+
+use core::cmp::{self, Ordering};
+
+use arch::apic;
+
+use system::error::{Error, Result, EINVAL, ELOOP, ENOENT, ENOSYS};
+
+use super::namespace::{absolute, join, Namespace};
+use super::parser::*;
+use super::value::{AmlValue, BufferField, FieldKind, FieldUnit, Method, OperationRegion};
+
+/// The deepest that methods may call each other
+const MAX_DEPTH: usize = 32;
+
+/// The most iterations of a while loop, so that firmware waiting on hardware cannot hang the
+/// kernel
+const MAX_ITERATIONS: usize = 0x100000;
+
+/// The state of a method call, or of loading a table
+pub struct Frame {
+    pub scope: String,
+    pub args: Vec<AmlValue>,
+    pub locals: Vec<AmlValue>,
+    /// The objects created by the method, which are removed when it returns
+    pub created: Vec<String>,
+    pub depth: usize,
+}
+
+impl Frame {
+    pub fn new(scope: String, mut args: Vec<AmlValue>, depth: usize) -> Frame {
+        while args.len() < 7 {
+            args.push(AmlValue::Uninitialized);
+        }
+
+        Frame {
+            scope: scope,
+            args: args,
+            locals: vec![AmlValue::Uninitialized; 8],
+            created: Vec::new(),
+            depth: depth,
+        }
+    }
+}
+
+/// How a term list finished
+pub enum Flow {
+    Next,
+    Return(AmlValue),
+    Break,
+    Continue,
+}
+
+/// Where a value is stored
+enum Target {
+    Null,
+    Debug,
+    Local(usize),
+    Arg(usize),
+    Name(String),
+    Index(Box<Target>, usize),
+    Deref(Box<Target>),
+}
+
+/// Call the method at `path`
+pub fn call(namespace: &mut Namespace, path: &str, args: Vec<AmlValue>, depth: usize) -> Result<AmlValue> {
+    if depth >= MAX_DEPTH {
+        syslog_debug!("AML: {} is nested too deep", path);
+        return Err(Error::new(ELOOP));
+    }
+
+    let method = match namespace.get(path) {
+        Some(&AmlValue::Method(method)) => method,
+        _ => return Err(Error::new(EINVAL)),
+    };
+
+    if path == "\\_OSI" {
+        return Ok(AmlValue::Integer(osi(namespace, args.get(0))));
+    }
+
+    let mut frame = Frame::new(path.into(), args, depth + 1);
+    let result = execute(namespace, &mut frame, method.code, 0, method.code.len());
+
+    for path in frame.created.iter() {
+        namespace.remove(path);
+    }
+
+    match result? {
+        Flow::Return(value) => Ok(value),
+        _ => Ok(AmlValue::Integer(0)),
+    }
+}
+
+/// The interfaces that `\_OSI` reports, which are the versions of Windows, as firmware is
+/// tested with those
+fn osi(namespace: &Namespace, interface: Option<&AmlValue>) -> u64 {
+    match interface {
+        Some(&AmlValue::String(ref interface)) if interface.starts_with("Windows") => namespace.ones(),
+        _ => 0,
+    }
+}
+
+/// Execute the term list from `start` to `end`
+pub fn execute(namespace: &mut Namespace, frame: &mut Frame, code: &'static [u8], start: usize, end: usize) -> Result<Flow> {
+    let mut i = start;
+    while i < end {
+        match execute_term(namespace, frame, code, &mut i)? {
+            Flow::Next => (),
+            flow => return Ok(flow),
+        }
+    }
+    Ok(Flow::Next)
+}
+
+/// Create an object, which is removed when the current method returns
+fn define(namespace: &mut Namespace, frame: &mut Frame, path: String, value: AmlValue) {
+    if frame.depth > 0 {
+        frame.created.push(path.clone());
+    }
+    namespace.insert(path, value);
+}
+
+/// Execute a term list in the scope at `path`
+fn execute_scope(namespace: &mut Namespace, frame: &mut Frame, code: &'static [u8], path: String, start: usize, end: usize) -> Result<Flow> {
+    let scope = frame.scope.clone();
+    frame.scope = path;
+    let result = execute(namespace, frame, code, start, end);
+    frame.scope = scope;
+    result
+}
+
+fn execute_term(namespace: &mut Namespace, frame: &mut Frame, code: &'static [u8], i: &mut usize) -> Result<Flow> {
+    let op = peek(code, *i)?;
+    match op {
+        NAME_OP => {
+            *i += 1;
+            let name = parse_name_string(code, i)?;
+            let value = evaluate(namespace, frame, code, i)?;
+            let path = absolute(&frame.scope, &name);
+            define(namespace, frame, path, value);
+        },
+        ALIAS_OP => {
+            *i += 1;
+            let source = parse_name_string(code, i)?;
+            let alias = parse_name_string(code, i)?;
+            if let Some(source) = namespace.resolve(&frame.scope, &source) {
+                if let Some(value) = namespace.get(&source).cloned() {
+                    let path = absolute(&frame.scope, &alias);
+                    define(namespace, frame, path, value);
+                }
+            }
+        },
+        SCOPE_OP => {
+            *i += 1;
+            let end = parse_end(code, i)?;
+            let name = parse_name_string(code, i)?;
+            let path = match namespace.resolve(&frame.scope, &name) {
+                Some(path) => path,
+                None => {
+                    // Tables may add to scopes of tables that are loaded later
+                    let path = absolute(&frame.scope, &name);
+                    namespace.insert(path.clone(), AmlValue::Scope);
+                    path
+                }
+            };
+            let flow = execute_scope(namespace, frame, code, path, *i, end)?;
+            *i = end;
+            return Ok(flow);
+        },
+        METHOD_OP => {
+            *i += 1;
+            let end = parse_end(code, i)?;
+            let name = parse_name_string(code, i)?;
+            let flags = next(code, i)?;
+            let path = absolute(&frame.scope, &name);
+            define(namespace, frame, path, AmlValue::Method(Method {
+                code: &code[*i..end],
+                args: flags & 0b111,
+            }));
+            *i = end;
+        },
+        EXTERNAL_OP => {
+            *i += 1;
+            parse_name_string(code, i)?;
+            // The object type and argument count
+            *i += 2;
+        },
+        IF_OP => {
+            *i += 1;
+            let end = parse_end(code, i)?;
+            let predicate = evaluate_integer(namespace, frame, code, i)?;
+
+            let flow = if predicate != 0 {
+                execute(namespace, frame, code, *i, end)?
+            } else {
+                Flow::Next
+            };
+            *i = end;
+
+            if peek(code, *i).ok() == Some(ELSE_OP) {
+                *i += 1;
+                let end = parse_end(code, i)?;
+                let flow = if predicate == 0 {
+                    execute(namespace, frame, code, *i, end)?
+                } else {
+                    Flow::Next
+                };
+                *i = end;
+                return Ok(flow);
+            }
+
+            return Ok(flow);
+        },
+        ELSE_OP => {
+            *i += 1;
+            *i = parse_end(code, i)?;
+        },
+        WHILE_OP => {
+            *i += 1;
+            let end = parse_end(code, i)?;
+            let predicate = *i;
+
+            for _ in 0..MAX_ITERATIONS {
+                *i = predicate;
+                if evaluate_integer(namespace, frame, code, i)? == 0 {
+                    break;
+                }
+
+                match execute(namespace, frame, code, *i, end)? {
+                    Flow::Break => break,
+                    Flow::Return(value) => {
+                        *i = end;
+                        return Ok(Flow::Return(value));
+                    },
+                    _ => (),
+                }
+            }
+
+            *i = end;
+        },
+        RETURN_OP => {
+            *i += 1;
+            let value = evaluate(namespace, frame, code, i)?;
+            return Ok(Flow::Return(value));
+        },
+        BREAK_OP => {
+            *i += 1;
+            return Ok(Flow::Break);
+        },
+        CONTINUE_OP => {
+            *i += 1;
+            return Ok(Flow::Continue);
+        },
+        NOOP_OP | BREAKPOINT_OP => *i += 1,
+        NOTIFY_OP => {
+            *i += 1;
+            parse_target(namespace, frame, code, i)?;
+            let value = evaluate_integer(namespace, frame, code, i)?;
+            syslog_debug!("AML: notify {:X}", value);
+        },
+        EXT_OP_PREFIX => match peek(code, *i + 1)? {
+            MUTEX_OP => {
+                *i += 2;
+                let name = parse_name_string(code, i)?;
+                // The sync level
+                *i += 1;
+                let path = absolute(&frame.scope, &name);
+                define(namespace, frame, path, AmlValue::Mutex);
+            },
+            EVENT_OP => {
+                *i += 2;
+                let name = parse_name_string(code, i)?;
+                let path = absolute(&frame.scope, &name);
+                define(namespace, frame, path, AmlValue::Event);
+            },
+            OP_REGION_OP => {
+                *i += 2;
+                let name = parse_name_string(code, i)?;
+                let space = next(code, i)?;
+                let offset = evaluate_integer(namespace, frame, code, i)?;
+                let length = evaluate_integer(namespace, frame, code, i)?;
+                let path = absolute(&frame.scope, &name);
+                let region = OperationRegion {
+                    space: space,
+                    offset: offset,
+                    length: length,
+                    scope: frame.scope.clone(),
+                };
+                define(namespace, frame, path, AmlValue::OperationRegion(region));
+            },
+            FIELD_OP => {
+                *i += 2;
+                let end = parse_end(code, i)?;
+                let name = parse_name_string(code, i)?;
+                let region = match namespace.resolve(&frame.scope, &name) {
+                    Some(region) => region,
+                    None => absolute(&frame.scope, &name),
+                };
+                let flags = next(code, i)?;
+                define_fields(namespace, frame, code, i, end, FieldKind::Region(region), flags)?;
+            },
+            INDEX_FIELD_OP => {
+                *i += 2;
+                let end = parse_end(code, i)?;
+                let index = parse_name_string(code, i)?;
+                let data = parse_name_string(code, i)?;
+                let kind = FieldKind::Index {
+                    index: absolute(&frame.scope, &index),
+                    data: absolute(&frame.scope, &data),
+                };
+                let flags = next(code, i)?;
+                define_fields(namespace, frame, code, i, end, kind, flags)?;
+            },
+            DEVICE_OP | THERMAL_ZONE_OP => {
+                let ext_op = code[*i + 1];
+                *i += 2;
+                let end = parse_end(code, i)?;
+                let name = parse_name_string(code, i)?;
+                let path = absolute(&frame.scope, &name);
+                define(namespace, frame, path.clone(), if ext_op == DEVICE_OP {
+                    AmlValue::Device
+                } else {
+                    AmlValue::ThermalZone
+                });
+                execute_scope(namespace, frame, code, path, *i, end)?;
+                *i = end;
+            },
+            PROCESSOR_OP => {
+                *i += 2;
+                let end = parse_end(code, i)?;
+                let name = parse_name_string(code, i)?;
+                // The processor ID, and the address and length of the processor block
+                *i += 6;
+                let path = absolute(&frame.scope, &name);
+                define(namespace, frame, path.clone(), AmlValue::Processor);
+                execute_scope(namespace, frame, code, path, *i, end)?;
+                *i = end;
+            },
+            POWER_RES_OP => {
+                *i += 2;
+                let end = parse_end(code, i)?;
+                let name = parse_name_string(code, i)?;
+                // The system level and resource order
+                *i += 3;
+                let path = absolute(&frame.scope, &name);
+                define(namespace, frame, path.clone(), AmlValue::PowerResource);
+                execute_scope(namespace, frame, code, path, *i, end)?;
+                *i = end;
+            },
+            SLEEP_OP => {
+                *i += 2;
+                let millis = evaluate_integer(namespace, frame, code, i)?;
+                apic::delay(cmp::min(millis, 1000) as usize * 1000);
+            },
+            STALL_OP => {
+                *i += 2;
+                let micros = evaluate_integer(namespace, frame, code, i)?;
+                apic::delay(cmp::min(micros, 100) as usize);
+            },
+            SIGNAL_OP | RESET_OP | RELEASE_OP => {
+                *i += 2;
+                parse_target(namespace, frame, code, i)?;
+            },
+            FATAL_OP => {
+                *i += 2;
+                let kind = next(code, i)?;
+                let fatal_code = parse_num::<u32>(code, i);
+                let arg = evaluate_integer(namespace, frame, code, i)?;
+                syslog_info!("AML: fatal error {:X}, {:X}, {:X}", kind, fatal_code, arg);
+                return Err(Error::new(EINVAL));
+            },
+            _ => {
+                evaluate(namespace, frame, code, i)?;
+            },
+        },
+        _ => {
+            evaluate(namespace, frame, code, i)?;
+        },
+    }
+
+    Ok(Flow::Next)
+}
+
+/// Define the field units of a field list, ending at `end`
+fn define_fields(namespace: &mut Namespace, frame: &mut Frame, code: &'static [u8], i: &mut usize, end: usize, kind: FieldKind, mut flags: u8) -> Result<()> {
+    let mut offset = 0;
+    while *i < end {
+        match peek(code, *i)? {
+            // Reserved field
+            0x00 => {
+                *i += 1;
+                offset += parse_length(code, i) as u64;
+            },
+            // Access field
+            0x01 => {
+                *i += 1;
+                let access_type = next(code, i)?;
+                *i += 1;
+                flags = flags & 0xF0 | access_type & 0x0F;
+            },
+            // Extended access field
+            0x03 => {
+                *i += 1;
+                let access_type = next(code, i)?;
+                *i += 2;
+                flags = flags & 0xF0 | access_type & 0x0F;
+            },
+            0x02 => {
+                syslog_debug!("AML: connect fields are not supported");
+                return Err(Error::new(ENOSYS));
+            },
+            _ => {
+                let name = parse_name_seg(code, i)?;
+                let length = parse_length(code, i) as u64;
+                let path = join(&frame.scope, &name);
+                define(namespace, frame, path, AmlValue::FieldUnit(FieldUnit {
+                    kind: kind.clone(),
+                    offset: offset,
+                    length: length,
+                    flags: flags,
+                }));
+                offset += length;
+            },
+        }
+    }
+
+    *i = end;
+    Ok(())
+}
+
+fn evaluate_integer(namespace: &mut Namespace, frame: &mut Frame, code: &'static [u8], i: &mut usize) -> Result<u64> {
+    evaluate(namespace, frame, code, i)?.to_integer()
+}
+
+fn boolean(namespace: &Namespace, value: bool) -> AmlValue {
+    AmlValue::Integer(if value {
+        namespace.ones()
+    } else {
+        0
+    })
+}
+
+/// Evaluate two integer operands and a target, storing and returning the result of `f`
+fn binary<F: Fn(u64, u64) -> Result<u64>>(namespace: &mut Namespace, frame: &mut Frame, code: &'static [u8], i: &mut usize, f: F) -> Result<AmlValue> {
+    let a = evaluate_integer(namespace, frame, code, i)?;
+    let b = evaluate_integer(namespace, frame, code, i)?;
+    let target = parse_target(namespace, frame, code, i)?;
+    let value = AmlValue::Integer(namespace.truncate(f(a, b)?));
+    store(namespace, frame, &target, value.clone())?;
+    Ok(value)
+}
+
+/// Compare integers, or the bytes of strings and buffers
+fn compare(a: &AmlValue, b: &AmlValue) -> Result<Ordering> {
+    match *a {
+        AmlValue::Integer(a) => Ok(a.cmp(&b.to_integer()?)),
+        _ => Ok(a.to_bytes()?.cmp(&b.to_bytes()?)),
+    }
+}
+
+/// Evaluate a term that has a value
+pub fn evaluate(namespace: &mut Namespace, frame: &mut Frame, code: &'static [u8], i: &mut usize) -> Result<AmlValue> {
+    let op = next(code, i)?;
+    Ok(match op {
+        ZERO_OP => AmlValue::Integer(0),
+        ONE_OP => AmlValue::Integer(1),
+        ONES_OP => AmlValue::Integer(namespace.ones()),
+        BYTE_PREFIX => AmlValue::Integer(parse_num::<u8>(code, i) as u64),
+        WORD_PREFIX => AmlValue::Integer(parse_num::<u16>(code, i) as u64),
+        DWORD_PREFIX => AmlValue::Integer(parse_num::<u32>(code, i) as u64),
+        QWORD_PREFIX => AmlValue::Integer(parse_num::<u64>(code, i)),
+        STRING_PREFIX => AmlValue::String(parse_string(code, i)?),
+        BUFFER_OP => {
+            let end = parse_end(code, i)?;
+            let size = evaluate_integer(namespace, frame, code, i)? as usize;
+            let mut bytes = code[*i..end].to_vec();
+            bytes.resize(cmp::max(size, end - *i), 0);
+            *i = end;
+            AmlValue::Buffer(bytes)
+        },
+        PACKAGE_OP | VAR_PACKAGE_OP => {
+            let end = parse_end(code, i)?;
+            let count = if op == PACKAGE_OP {
+                next(code, i)? as usize
+            } else {
+                evaluate_integer(namespace, frame, code, i)? as usize
+            };
+
+            let mut elements = Vec::new();
+            while *i < end {
+                if is_name_start(peek(code, *i)?) {
+                    // Names in packages are references, which may not be defined yet
+                    let name = parse_name_string(code, i)?;
+                    elements.push(AmlValue::Reference(match namespace.resolve(&frame.scope, &name) {
+                        Some(path) => path,
+                        None => absolute(&frame.scope, &name),
+                    }));
+                } else {
+                    elements.push(evaluate(namespace, frame, code, i)?);
+                }
+            }
+            while elements.len() < count {
+                elements.push(AmlValue::Uninitialized);
+            }
+
+            *i = end;
+            AmlValue::Package(elements)
+        },
+        LOCAL0_OP ... LOCAL7_OP => frame.locals[(op - LOCAL0_OP) as usize].clone(),
+        ARG0_OP ... ARG6_OP => match frame.args[(op - ARG0_OP) as usize].clone() {
+            AmlValue::Reference(path) => namespace.value_of(&path)?,
+            value => value,
+        },
+        STORE_OP | COPY_OBJECT_OP => {
+            let value = evaluate(namespace, frame, code, i)?;
+            let target = parse_target(namespace, frame, code, i)?;
+            store(namespace, frame, &target, value.clone())?;
+            value
+        },
+        ADD_OP => binary(namespace, frame, code, i, |a, b| Ok(a.wrapping_add(b)))?,
+        SUBTRACT_OP => binary(namespace, frame, code, i, |a, b| Ok(a.wrapping_sub(b)))?,
+        MULTIPLY_OP => binary(namespace, frame, code, i, |a, b| Ok(a.wrapping_mul(b)))?,
+        SHIFT_LEFT_OP => binary(namespace, frame, code, i, |a, b| Ok(if b < 64 { a << b } else { 0 }))?,
+        SHIFT_RIGHT_OP => binary(namespace, frame, code, i, |a, b| Ok(if b < 64 { a >> b } else { 0 }))?,
+        AND_OP => binary(namespace, frame, code, i, |a, b| Ok(a & b))?,
+        NAND_OP => binary(namespace, frame, code, i, |a, b| Ok(!(a & b)))?,
+        OR_OP => binary(namespace, frame, code, i, |a, b| Ok(a | b))?,
+        NOR_OP => binary(namespace, frame, code, i, |a, b| Ok(!(a | b)))?,
+        XOR_OP => binary(namespace, frame, code, i, |a, b| Ok(a ^ b))?,
+        MOD_OP => binary(namespace, frame, code, i, |a, b| if b == 0 {
+            Err(Error::new(EINVAL))
+        } else {
+            Ok(a % b)
+        })?,
+        DIVIDE_OP => {
+            let a = evaluate_integer(namespace, frame, code, i)?;
+            let b = evaluate_integer(namespace, frame, code, i)?;
+            let remainder = parse_target(namespace, frame, code, i)?;
+            let quotient = parse_target(namespace, frame, code, i)?;
+            if b == 0 {
+                return Err(Error::new(EINVAL));
+            }
+            store(namespace, frame, &remainder, AmlValue::Integer(a % b))?;
+            store(namespace, frame, &quotient, AmlValue::Integer(a / b))?;
+            AmlValue::Integer(a / b)
+        },
+        NOT_OP | FIND_SET_LEFT_BIT_OP | FIND_SET_RIGHT_BIT_OP => {
+            let a = evaluate_integer(namespace, frame, code, i)?;
+            let target = parse_target(namespace, frame, code, i)?;
+            let value = AmlValue::Integer(match op {
+                NOT_OP => namespace.truncate(!a),
+                FIND_SET_LEFT_BIT_OP => if a == 0 { 0 } else { 64 - a.leading_zeros() as u64 },
+                _ => if a == 0 { 0 } else { a.trailing_zeros() as u64 + 1 },
+            });
+            store(namespace, frame, &target, value.clone())?;
+            value
+        },
+        INCREMENT_OP | DECREMENT_OP => {
+            let target = parse_target(namespace, frame, code, i)?;
+            let a = read_target(namespace, frame, &target)?.to_integer()?;
+            let value = AmlValue::Integer(namespace.truncate(if op == INCREMENT_OP {
+                a.wrapping_add(1)
+            } else {
+                a.wrapping_sub(1)
+            }));
+            store(namespace, frame, &target, value.clone())?;
+            value
+        },
+        LAND_OP | LOR_OP => {
+            let a = evaluate_integer(namespace, frame, code, i)?;
+            let b = evaluate_integer(namespace, frame, code, i)?;
+            boolean(namespace, if op == LAND_OP {
+                a != 0 && b != 0
+            } else {
+                a != 0 || b != 0
+            })
+        },
+        LNOT_OP => match peek(code, *i)? {
+            // LNotEqual, LLessEqual and LGreaterEqual are LNot of the opposite comparison
+            LEQUAL_OP | LGREATER_OP | LLESS_OP => {
+                let compare_op = next(code, i)?;
+                let a = evaluate(namespace, frame, code, i)?;
+                let b = evaluate(namespace, frame, code, i)?;
+                let ordering = compare(&a, &b)?;
+                boolean(namespace, match compare_op {
+                    LEQUAL_OP => ordering != Ordering::Equal,
+                    LGREATER_OP => ordering != Ordering::Greater,
+                    _ => ordering != Ordering::Less,
+                })
+            },
+            _ => {
+                let a = evaluate_integer(namespace, frame, code, i)?;
+                boolean(namespace, a == 0)
+            },
+        },
+        LEQUAL_OP | LGREATER_OP | LLESS_OP => {
+            let a = evaluate(namespace, frame, code, i)?;
+            let b = evaluate(namespace, frame, code, i)?;
+            let ordering = compare(&a, &b)?;
+            boolean(namespace, match op {
+                LEQUAL_OP => ordering == Ordering::Equal,
+                LGREATER_OP => ordering == Ordering::Greater,
+                _ => ordering == Ordering::Less,
+            })
+        },
+        CONCAT_OP => {
+            let a = evaluate(namespace, frame, code, i)?;
+            let b = evaluate(namespace, frame, code, i)?;
+            let target = parse_target(namespace, frame, code, i)?;
+            let value = match a {
+                AmlValue::String(ref a) => {
+                    let mut string = a.clone();
+                    match b {
+                        AmlValue::String(ref b) => string.push_str(b),
+                        _ => string.push_str(&format!("{:X}", b.to_integer()?)),
+                    }
+                    AmlValue::String(string)
+                },
+                _ => {
+                    let mut bytes = a.to_bytes()?;
+                    bytes.extend_from_slice(&b.to_bytes()?);
+                    AmlValue::Buffer(bytes)
+                },
+            };
+            store(namespace, frame, &target, value.clone())?;
+            value
+        },
+        CONCAT_RES_OP => {
+            let mut a = evaluate(namespace, frame, code, i)?.to_bytes()?;
+            let b = evaluate(namespace, frame, code, i)?.to_bytes()?;
+            let target = parse_target(namespace, frame, code, i)?;
+            // The end tag of the first template is dropped, and the second one ends the result
+            if a.len() >= 2 && a[a.len() - 2] == 0x79 {
+                let len = a.len() - 2;
+                a.truncate(len);
+            }
+            a.extend_from_slice(&b);
+            let value = AmlValue::Buffer(a);
+            store(namespace, frame, &target, value.clone())?;
+            value
+        },
+        SIZE_OF_OP => {
+            let target = parse_target(namespace, frame, code, i)?;
+            AmlValue::Integer(match read_target(namespace, frame, &target)? {
+                AmlValue::String(string) => string.len() as u64,
+                AmlValue::Buffer(bytes) => bytes.len() as u64,
+                AmlValue::Package(elements) => elements.len() as u64,
+                _ => return Err(Error::new(EINVAL)),
+            })
+        },
+        INDEX_OP => {
+            let source = evaluate(namespace, frame, code, i)?;
+            let index = evaluate_integer(namespace, frame, code, i)? as usize;
+            let target = parse_target(namespace, frame, code, i)?;
+            let value = index_of(&source, index)?;
+            store(namespace, frame, &target, value.clone())?;
+            value
+        },
+        DEREF_OF_OP => match evaluate(namespace, frame, code, i)? {
+            AmlValue::Reference(path) => namespace.value_of(&path)?,
+            AmlValue::String(name) => match namespace.resolve(&frame.scope, &name) {
+                Some(path) => namespace.value_of(&path)?,
+                None => return Err(Error::new(ENOENT)),
+            },
+            value => value,
+        },
+        REF_OF_OP => match parse_target(namespace, frame, code, i)? {
+            Target::Name(path) => AmlValue::Reference(path),
+            target => read_target(namespace, frame, &target)?,
+        },
+        OBJECT_TYPE_OP => {
+            let target = parse_target(namespace, frame, code, i)?;
+            let object_type = match target {
+                Target::Name(ref path) => namespace.get(path).map(|value| value.object_type()),
+                _ => None,
+            };
+            AmlValue::Integer(match object_type {
+                Some(object_type) => object_type,
+                None => read_target(namespace, frame, &target)?.object_type(),
+            })
+        },
+        TO_INTEGER_OP | TO_BUFFER_OP | TO_HEX_STRING_OP => {
+            let a = evaluate(namespace, frame, code, i)?;
+            let target = parse_target(namespace, frame, code, i)?;
+            let value = match op {
+                TO_INTEGER_OP => AmlValue::Integer(a.to_integer()?),
+                TO_BUFFER_OP => AmlValue::Buffer(a.to_buffer()?),
+                _ => match a {
+                    AmlValue::Integer(a) => AmlValue::String(format!("0x{:X}", a)),
+                    AmlValue::String(a) => AmlValue::String(a),
+                    a => {
+                        let mut hex = String::new();
+                        for b in a.to_bytes()?.iter() {
+                            if ! hex.is_empty() {
+                                hex.push(',');
+                            }
+                            hex.push_str(&format!("0x{:02X}", b));
+                        }
+                        AmlValue::String(hex)
+                    },
+                },
+            };
+            store(namespace, frame, &target, value.clone())?;
+            value
+        },
+        TO_STRING_OP => {
+            let bytes = evaluate(namespace, frame, code, i)?.to_bytes()?;
+            let length = evaluate_integer(namespace, frame, code, i)? as usize;
+            let target = parse_target(namespace, frame, code, i)?;
+            let value = AmlValue::String(bytes.iter().take(length).take_while(|&&b| b != 0).map(|&b| b as char).collect());
+            store(namespace, frame, &target, value.clone())?;
+            value
+        },
+        MID_OP => {
+            let source = evaluate(namespace, frame, code, i)?;
+            let index = evaluate_integer(namespace, frame, code, i)? as usize;
+            let length = evaluate_integer(namespace, frame, code, i)? as usize;
+            let target = parse_target(namespace, frame, code, i)?;
+            let bytes = source.to_bytes()?;
+            let start = cmp::min(index, bytes.len());
+            let end = cmp::min(start.saturating_add(length), bytes.len());
+            let value = match source {
+                AmlValue::String(_) => AmlValue::String(bytes[start..end].iter().map(|&b| b as char).collect()),
+                _ => AmlValue::Buffer(bytes[start..end].to_vec()),
+            };
+            store(namespace, frame, &target, value.clone())?;
+            value
+        },
+        CREATE_BIT_FIELD_OP | CREATE_BYTE_FIELD_OP | CREATE_WORD_FIELD_OP | CREATE_DWORD_FIELD_OP | CREATE_QWORD_FIELD_OP => {
+            let buffer = buffer_path(namespace, frame, code, i)?;
+            let index = evaluate_integer(namespace, frame, code, i)?;
+            let name = parse_name_string(code, i)?;
+            let (offset, length) = match op {
+                CREATE_BIT_FIELD_OP => (index, 1),
+                CREATE_BYTE_FIELD_OP => (index * 8, 8),
+                CREATE_WORD_FIELD_OP => (index * 8, 16),
+                CREATE_DWORD_FIELD_OP => (index * 8, 32),
+                _ => (index * 8, 64),
+            };
+            let path = absolute(&frame.scope, &name);
+            define(namespace, frame, path, AmlValue::BufferField(BufferField {
+                buffer: buffer,
+                offset: offset,
+                length: length,
+            }));
+            AmlValue::Uninitialized
+        },
+        EXT_OP_PREFIX => {
+            let ext_op = next(code, i)?;
+            match ext_op {
+                COND_REF_OF_OP => {
+                    // The name may not exist, so it is not parsed as a target
+                    let path = if is_name_start(peek(code, *i)?) {
+                        let name = parse_name_string(code, i)?;
+                        namespace.resolve(&frame.scope, &name)
+                    } else {
+                        match parse_target(namespace, frame, code, i)? {
+                            Target::Name(path) => Some(path),
+                            _ => None,
+                        }
+                    };
+                    let target = parse_target(namespace, frame, code, i)?;
+                    match path {
+                        Some(path) => {
+                            store(namespace, frame, &target, AmlValue::Reference(path))?;
+                            boolean(namespace, true)
+                        },
+                        None => boolean(namespace, false),
+                    }
+                },
+                CREATE_FIELD_OP => {
+                    let buffer = buffer_path(namespace, frame, code, i)?;
+                    let offset = evaluate_integer(namespace, frame, code, i)?;
+                    let length = evaluate_integer(namespace, frame, code, i)?;
+                    let name = parse_name_string(code, i)?;
+                    let path = absolute(&frame.scope, &name);
+                    define(namespace, frame, path, AmlValue::BufferField(BufferField {
+                        buffer: buffer,
+                        offset: offset,
+                        length: length,
+                    }));
+                    AmlValue::Uninitialized
+                },
+                ACQUIRE_OP => {
+                    parse_target(namespace, frame, code, i)?;
+                    // The timeout
+                    *i += 2;
+                    // Kernel code runs one at a time, so a mutex is always free
+                    AmlValue::Integer(0)
+                },
+                WAIT_OP => {
+                    parse_target(namespace, frame, code, i)?;
+                    evaluate_integer(namespace, frame, code, i)?;
+                    AmlValue::Integer(0)
+                },
+                REVISION_OP => AmlValue::Integer(2),
+                DEBUG_OP => AmlValue::Uninitialized,
+                TIMER_OP => AmlValue::Integer(0),
+                _ => {
+                    syslog_debug!("AML: unknown extended op: {:02X}", ext_op);
+                    return Err(Error::new(ENOSYS));
+                },
+            }
+        },
+        _ => {
+            *i -= 1;
+            if is_name_start(op) {
+                let name = parse_name_string(code, i)?;
+                let path = match namespace.resolve(&frame.scope, &name) {
+                    Some(path) => path,
+                    None => {
+                        syslog_debug!("AML: {} not found in {}", name, frame.scope);
+                        return Err(Error::new(ENOENT));
+                    }
+                };
+
+                let method = match namespace.get(&path) {
+                    Some(&AmlValue::Method(method)) => Some(method),
+                    _ => None,
+                };
+
+                match method {
+                    Some(method) => {
+                        let mut args = Vec::new();
+                        for _ in 0..method.args {
+                            args.push(evaluate(namespace, frame, code, i)?);
+                        }
+                        call(namespace, &path, args, frame.depth)?
+                    },
+                    None => namespace.value_of(&path)?,
+                }
+            } else {
+                syslog_debug!("AML: unknown op: {:02X}", op);
+                return Err(Error::new(ENOSYS));
+            }
+        },
+    })
+}
+
+/// The path of the named buffer that a buffer field is created in
+fn buffer_path(namespace: &mut Namespace, frame: &mut Frame, code: &'static [u8], i: &mut usize) -> Result<String> {
+    match parse_target(namespace, frame, code, i)? {
+        Target::Name(path) => Ok(path),
+        Target::Arg(arg) => match frame.args[arg] {
+            AmlValue::Reference(ref path) => Ok(path.clone()),
+            _ => Err(Error::new(ENOSYS)),
+        },
+        _ => {
+            syslog_debug!("AML: buffer fields are only supported in named buffers");
+            Err(Error::new(ENOSYS))
+        },
+    }
+}
+
+/// The element at `index` of a package, buffer or string
+fn index_of(source: &AmlValue, index: usize) -> Result<AmlValue> {
+    match *source {
+        AmlValue::Package(ref elements) => match elements.get(index) {
+            Some(element) => Ok(element.clone()),
+            None => Err(Error::new(EINVAL)),
+        },
+        AmlValue::Buffer(ref bytes) => match bytes.get(index) {
+            Some(&b) => Ok(AmlValue::Integer(b as u64)),
+            None => Err(Error::new(EINVAL)),
+        },
+        AmlValue::String(ref string) => match string.as_bytes().get(index) {
+            Some(&b) => Ok(AmlValue::Integer(b as u64)),
+            None => Err(Error::new(EINVAL)),
+        },
+        _ => Err(Error::new(EINVAL)),
+    }
+}
+
+/// Parse a target, or a super name
+fn parse_target(namespace: &mut Namespace, frame: &mut Frame, code: &'static [u8], i: &mut usize) -> Result<Target> {
+    let op = peek(code, *i)?;
+    Ok(match op {
+        ZERO_OP => {
+            *i += 1;
+            Target::Null
+        },
+        LOCAL0_OP ... LOCAL7_OP => {
+            *i += 1;
+            Target::Local((op - LOCAL0_OP) as usize)
+        },
+        ARG0_OP ... ARG6_OP => {
+            *i += 1;
+            Target::Arg((op - ARG0_OP) as usize)
+        },
+        EXT_OP_PREFIX if peek(code, *i + 1)? == DEBUG_OP => {
+            *i += 2;
+            Target::Debug
+        },
+        INDEX_OP => {
+            *i += 1;
+            let source = parse_target(namespace, frame, code, i)?;
+            let index = evaluate_integer(namespace, frame, code, i)? as usize;
+            // The target of the index itself is not used
+            parse_target(namespace, frame, code, i)?;
+            Target::Index(Box::new(source), index)
+        },
+        DEREF_OF_OP => {
+            *i += 1;
+            Target::Deref(Box::new(parse_target(namespace, frame, code, i)?))
+        },
+        _ if is_name_start(op) => {
+            let name = parse_name_string(code, i)?;
+            Target::Name(match namespace.resolve(&frame.scope, &name) {
+                Some(path) => path,
+                None => absolute(&frame.scope, &name),
+            })
+        },
+        _ => {
+            syslog_debug!("AML: unknown target: {:02X}", op);
+            return Err(Error::new(ENOSYS));
+        },
+    })
+}
+
+fn read_target(namespace: &mut Namespace, frame: &mut Frame, target: &Target) -> Result<AmlValue> {
+    match *target {
+        Target::Null | Target::Debug => Ok(AmlValue::Uninitialized),
+        Target::Local(local) => Ok(frame.locals[local].clone()),
+        Target::Arg(arg) => match frame.args[arg].clone() {
+            AmlValue::Reference(path) => namespace.value_of(&path),
+            value => Ok(value),
+        },
+        Target::Name(ref path) => namespace.value_of(path),
+        Target::Index(ref source, index) => index_of(&read_target(namespace, frame, source)?, index),
+        Target::Deref(ref source) => match read_target(namespace, frame, source)? {
+            AmlValue::Reference(path) => namespace.value_of(&path),
+            value => Ok(value),
+        },
+    }
+}
+
+fn store(namespace: &mut Namespace, frame: &mut Frame, target: &Target, value: AmlValue) -> Result<()> {
+    match *target {
+        Target::Null => Ok(()),
+        Target::Debug => {
+            syslog_debug!("AML: debug: {:?}", value);
+            Ok(())
+        },
+        Target::Local(local) => {
+            frame.locals[local] = value;
+            Ok(())
+        },
+        Target::Arg(arg) => {
+            // Arguments that are references store to the referenced object
+            let reference = match frame.args[arg] {
+                AmlValue::Reference(ref path) => Some(path.clone()),
+                _ => None,
+            };
+            match reference {
+                Some(path) => namespace.store(&path, value),
+                None => {
+                    frame.args[arg] = value;
+                    Ok(())
+                },
+            }
+        },
+        Target::Name(ref path) => namespace.store(path, value),
+        Target::Index(ref source, index) => {
+            let container = match read_target(namespace, frame, source)? {
+                AmlValue::Package(mut elements) => {
+                    match elements.get_mut(index) {
+                        Some(element) => *element = value,
+                        None => return Err(Error::new(EINVAL)),
+                    }
+                    AmlValue::Package(elements)
+                },
+                AmlValue::Buffer(mut bytes) => {
+                    match bytes.get_mut(index) {
+                        Some(b) => *b = value.to_integer()? as u8,
+                        None => return Err(Error::new(EINVAL)),
+                    }
+                    AmlValue::Buffer(bytes)
+                },
+                _ => return Err(Error::new(EINVAL)),
+            };
+            store(namespace, frame, source, container)
+        },
+        Target::Deref(ref source) => match read_target(namespace, frame, source)? {
+            AmlValue::Reference(path) => namespace.store(&path, value),
+            _ => Err(Error::new(EINVAL)),
+        },
+    }
+}
@@ -0,0 +1,14 @@
+//! An interpreter for AML (ACPI machine language), the code in the DSDT and SSDTs
+//!
+//! Tables are loaded into a `Namespace` by executing their definitions. Methods are kept as
+//! code, and run when they are evaluated.
+
+pub use self::namespace::{Namespace, PciRoute};
+pub use self::resource::Interrupt;
+pub use self::value::AmlValue;
+
+mod interpreter;
+pub mod namespace;
+mod parser;
+pub mod resource;
+pub mod value;
@@ -0,0 +1,511 @@
+use collections::{BTreeMap, String, Vec};
+
+use core::cmp;
+use core::intrinsics::{volatile_load, volatile_store};
+
+use drivers::io::{Io, Pio};
+use drivers::pci::config::PciConfig;
+
+use system::error::{Error, Result, EINVAL, ENOENT, ENOSYS};
+
+use super::interpreter::{self, Frame};
+use super::resource::{self, Interrupt};
+use super::value::{mask, get_bits, set_bits, AmlValue, FieldKind, FieldUnit, Method, OperationRegion};
+
+/// The address spaces of operation regions
+const SYSTEM_MEMORY: u8 = 0;
+const SYSTEM_IO: u8 = 1;
+const PCI_CONFIG: u8 = 2;
+
+/// The path of a name segment in a scope
+pub fn join(scope: &str, seg: &str) -> String {
+    if scope.ends_with('\\') {
+        format!("{}{}", scope, seg)
+    } else {
+        format!("{}.{}", scope, seg)
+    }
+}
+
+/// The scope containing a path, which is the root for the root
+pub fn parent(path: &str) -> String {
+    match path.rfind('.') {
+        Some(dot) => path[..dot].into(),
+        None => "\\".into(),
+    }
+}
+
+/// The path of a name in a scope, without searching
+pub fn absolute(scope: &str, name: &str) -> String {
+    if name.starts_with('\\') {
+        return name.into();
+    }
+
+    let mut path: String = scope.into();
+    let mut rest = name;
+    while rest.starts_with('^') {
+        path = parent(&path);
+        rest = &rest[1..];
+    }
+
+    if rest.is_empty() {
+        path
+    } else {
+        join(&path, rest)
+    }
+}
+
+/// The interrupt that a pin of the PCI devices in a slot is wired to, from `_PRT`
+#[derive(Clone, Copy, Debug)]
+pub struct PciRoute {
+    pub slot: u8,
+    /// The interrupt pin, from 0 for INTA
+    pub pin: u8,
+    pub interrupt: Interrupt,
+}
+
+/// The ACPI namespace, with the objects of the DSDT and SSDTs
+#[derive(Clone, Debug, Default)]
+pub struct Namespace {
+    objects: BTreeMap<String, AmlValue>,
+    /// Integers are 32 bits wide if the DSDT revision is below 2
+    wide: bool,
+}
+
+impl Namespace {
+    /// Create a namespace with the predefined objects, for a DSDT with `revision`
+    pub fn new(revision: u8) -> Namespace {
+        let mut namespace = Namespace {
+            objects: BTreeMap::new(),
+            wide: revision >= 2,
+        };
+
+        namespace.insert("\\".into(), AmlValue::Scope);
+        for scope in ["\\_GPE", "\\_PR_", "\\_SB_", "\\_SI_", "\\_TZ_"].iter() {
+            namespace.insert((*scope).into(), AmlValue::Scope);
+        }
+        namespace.insert("\\_GL_".into(), AmlValue::Mutex);
+        namespace.insert("\\_OS_".into(), AmlValue::String("Microsoft Windows NT".into()));
+        namespace.insert("\\_REV".into(), AmlValue::Integer(2));
+        // Handled by `interpreter::call`
+        namespace.insert("\\_OSI".into(), AmlValue::Method(Method {
+            code: &[],
+            args: 1,
+        }));
+
+        namespace
+    }
+
+    /// Load the definition blocks of a table
+    pub fn load(&mut self, code: &'static [u8]) -> Result<()> {
+        let mut frame = Frame::new("\\".into(), Vec::new(), 0);
+        interpreter::execute(self, &mut frame, code, 0, code.len()).map(|_| ())
+    }
+
+    pub fn get(&self, path: &str) -> Option<&AmlValue> {
+        self.objects.get(path)
+    }
+
+    pub fn insert(&mut self, path: String, value: AmlValue) {
+        self.objects.insert(path, value);
+    }
+
+    pub fn remove(&mut self, path: &str) {
+        self.objects.remove(path);
+    }
+
+    /// Find a name used in `scope`. A single segment without prefixes is searched for in the
+    /// scope and then its parents.
+    pub fn resolve(&self, scope: &str, name: &str) -> Option<String> {
+        if name.starts_with('\\') || name.starts_with('^') || name.contains('.') {
+            let path = absolute(scope, name);
+            if self.objects.contains_key(&path) {
+                Some(path)
+            } else {
+                None
+            }
+        } else {
+            let mut scope: String = scope.into();
+            loop {
+                let path = join(&scope, name);
+                if self.objects.contains_key(&path) {
+                    return Some(path);
+                }
+                if scope == "\\" {
+                    return None;
+                }
+                scope = parent(&scope);
+            }
+        }
+    }
+
+    /// The all ones integer, which is also true
+    pub fn ones(&self) -> u64 {
+        self.truncate(!0)
+    }
+
+    /// Truncate the result of an operation to the integer width
+    pub fn truncate(&self, value: u64) -> u64 {
+        if self.wide {
+            value
+        } else {
+            value & 0xFFFFFFFF
+        }
+    }
+
+    /// Evaluate the object at `path`, calling it with `args` if it is a method
+    pub fn evaluate(&mut self, path: &str, args: Vec<AmlValue>) -> Result<AmlValue> {
+        if let Some(&AmlValue::Method(_)) = self.get(path) {
+            interpreter::call(self, path, args, 0)
+        } else {
+            self.value_of(path)
+        }
+    }
+
+    /// The value of the object at `path`, reading it if it is a field
+    pub fn value_of(&mut self, path: &str) -> Result<AmlValue> {
+        match self.get(path).cloned() {
+            Some(AmlValue::FieldUnit(field)) => self.read_field(&field),
+            Some(AmlValue::BufferField(field)) => match self.get(&field.buffer) {
+                Some(&AmlValue::Buffer(ref bytes)) => Ok(if field.length <= 64 {
+                    AmlValue::Integer(get_bits(bytes, field.offset, field.length))
+                } else {
+                    let mut data = vec![0; ((field.length + 7) / 8) as usize];
+                    let mut bit = 0;
+                    while bit < field.length {
+                        let count = cmp::min(64, field.length - bit);
+                        set_bits(&mut data, bit, count, get_bits(bytes, field.offset + bit, count));
+                        bit += count;
+                    }
+                    AmlValue::Buffer(data)
+                }),
+                _ => Err(Error::new(EINVAL)),
+            },
+            Some(value) => Ok(value),
+            None => Err(Error::new(ENOENT)),
+        }
+    }
+
+    /// Store a value in the object at `path`, converting it to the type of the object
+    pub fn store(&mut self, path: &str, value: AmlValue) -> Result<()> {
+        match self.get(path).cloned() {
+            Some(AmlValue::FieldUnit(field)) => self.write_field(&field, &value),
+            Some(AmlValue::BufferField(field)) => {
+                let source = value.to_bytes()?;
+                match self.objects.get_mut(&field.buffer) {
+                    Some(&mut AmlValue::Buffer(ref mut bytes)) => {
+                        let mut bit = 0;
+                        while bit < field.length {
+                            let count = cmp::min(64, field.length - bit);
+                            set_bits(bytes, field.offset + bit, count, get_bits(&source, bit, count));
+                            bit += count;
+                        }
+                        Ok(())
+                    },
+                    _ => Err(Error::new(EINVAL)),
+                }
+            },
+            Some(AmlValue::Integer(_)) => {
+                let integer = value.to_integer()?;
+                self.insert(path.into(), AmlValue::Integer(integer));
+                Ok(())
+            },
+            Some(_) => {
+                self.insert(path.into(), value);
+                Ok(())
+            },
+            None => Err(Error::new(ENOENT)),
+        }
+    }
+
+    /// The paths of all devices
+    pub fn devices(&self) -> Vec<String> {
+        self.objects.iter().filter_map(|(path, value)| match *value {
+            AmlValue::Device => Some(path.clone()),
+            _ => None,
+        }).collect()
+    }
+
+    /// Evaluate the `_STA` of a device, which is present, enabled and functioning without one
+    pub fn device_status(&mut self, device: &str) -> Result<u64> {
+        let path = join(device, "_STA");
+        if self.get(&path).is_some() {
+            self.evaluate(&path, Vec::new())?.to_integer()
+        } else {
+            Ok(0xF)
+        }
+    }
+
+    /// Evaluate the `_CRS` of a device, the resource template of its current resources
+    pub fn device_resources(&mut self, device: &str) -> Result<Vec<u8>> {
+        match self.evaluate(&join(device, "_CRS"), Vec::new())? {
+            AmlValue::Buffer(bytes) => Ok(bytes),
+            _ => Err(Error::new(EINVAL)),
+        }
+    }
+
+    /// The interrupt routes of the PCI slots on bus 0, from the `_PRT` of its root bridge
+    ///
+    /// The root bridge is a device in `\_SB` with a `_PRT`, and a `_BBN` of 0 if it has one. A pin
+    /// is wired either to a GSI directly, or to a link device whose `_CRS` has the interrupt. The
+    /// pins of disabled links are left out. Firmware gives other GSIs once `\_PIC` was called
+    /// with 1, to tell it that the APIC is used.
+    pub fn pci_routes(&mut self) -> Result<Vec<PciRoute>> {
+        let mut prt = None;
+        for device in self.devices() {
+            let path = join(&device, "_PRT");
+            if parent(&device) == "\\_SB_" && self.get(&path).is_some() {
+                let bbn = join(&device, "_BBN");
+                let bus = if self.get(&bbn).is_some() {
+                    self.evaluate(&bbn, Vec::new())?.to_integer()?
+                } else {
+                    0
+                };
+                if bus == 0 {
+                    prt = Some(path);
+                    break;
+                }
+            }
+        }
+        let prt = match prt {
+            Some(prt) => prt,
+            None => return Err(Error::new(ENOENT)),
+        };
+
+        let entries = match self.evaluate(&prt, Vec::new())? {
+            AmlValue::Package(entries) => entries,
+            _ => return Err(Error::new(EINVAL)),
+        };
+
+        let mut routes = Vec::new();
+        for entry in entries.iter() {
+            let entry = match *entry {
+                AmlValue::Package(ref entry) if entry.len() == 4 => entry,
+                _ => return Err(Error::new(EINVAL)),
+            };
+
+            // The address has the slot in the upper half, and 0xFFFF for any function
+            let slot = (entry[0].to_integer()? >> 16) as u8;
+            let pin = entry[1].to_integer()? as u8;
+
+            let interrupt = match entry[2] {
+                AmlValue::Reference(ref link) => match self.device_resources(link) {
+                    Ok(resources) => resource::interrupt(&resources),
+                    Err(err) => {
+                        syslog_debug!("AML: {}: _CRS: {}", link, err);
+                        None
+                    }
+                },
+                // Without a link device, the pin is wired to the GSI in the source index, which
+                // is level triggered and active low like any PCI interrupt
+                _ => Some(Interrupt {
+                    number: entry[3].to_integer()? as u32,
+                    level: true,
+                    active_low: true,
+                }),
+            };
+
+            match interrupt {
+                Some(interrupt) if interrupt.number > 0 => routes.push(PciRoute {
+                    slot: slot,
+                    pin: pin,
+                    interrupt: interrupt,
+                }),
+                _ => (),
+            }
+        }
+
+        Ok(routes)
+    }
+
+    fn read_field(&mut self, field: &FieldUnit) -> Result<AmlValue> {
+        let width = field.access_width();
+
+        let mut data = vec![0; ((field.length + 7) / 8) as usize];
+        let mut bit = 0;
+        while bit < field.length {
+            let position = field.offset + bit;
+            let unit = position / width * width;
+            let shift = position - unit;
+            let count = cmp::min(width - shift, field.length - bit);
+
+            let value = self.access_unit(&field.kind, unit / 8, width, None)?;
+            set_bits(&mut data, bit, count, value >> shift & mask(count));
+
+            bit += count;
+        }
+
+        if field.length <= 64 {
+            Ok(AmlValue::Integer(get_bits(&data, 0, field.length)))
+        } else {
+            Ok(AmlValue::Buffer(data))
+        }
+    }
+
+    fn write_field(&mut self, field: &FieldUnit, value: &AmlValue) -> Result<()> {
+        let width = field.access_width();
+        let source = value.to_bytes()?;
+
+        let mut bit = 0;
+        while bit < field.length {
+            let position = field.offset + bit;
+            let unit = position / width * width;
+            let shift = position - unit;
+            let count = cmp::min(width - shift, field.length - bit);
+
+            let mut value = if count == width {
+                0
+            } else {
+                match field.update_rule() {
+                    0 => self.access_unit(&field.kind, unit / 8, width, None)?,
+                    1 => !0,
+                    _ => 0,
+                }
+            };
+            value &= !(mask(count) << shift);
+            value |= (get_bits(&source, bit, count) & mask(count)) << shift;
+
+            self.access_unit(&field.kind, unit / 8, width, Some(value))?;
+
+            bit += count;
+        }
+
+        Ok(())
+    }
+
+    /// Read a unit of a field at a byte `offset`, or write it if there is a value
+    fn access_unit(&mut self, kind: &FieldKind, offset: u64, width: u64, value: Option<u64>) -> Result<u64> {
+        match *kind {
+            FieldKind::Region(ref path) => {
+                let region = match self.get(path) {
+                    Some(&AmlValue::OperationRegion(ref region)) => region.clone(),
+                    _ => return Err(Error::new(EINVAL)),
+                };
+
+                if offset + width / 8 > region.length {
+                    return Err(Error::new(EINVAL));
+                }
+
+                self.access_region(&region, region.offset + offset, width, value)
+            },
+            FieldKind::Index { ref index, ref data } => {
+                self.store(index, AmlValue::Integer(offset))?;
+                match value {
+                    Some(value) => {
+                        self.store(data, AmlValue::Integer(value))?;
+                        Ok(value)
+                    },
+                    None => self.value_of(data)?.to_integer(),
+                }
+            },
+        }
+    }
+
+    fn access_region(&mut self, region: &OperationRegion, address: u64, width: u64, value: Option<u64>) -> Result<u64> {
+        match region.space {
+            SYSTEM_MEMORY => unsafe {
+                let address = address as usize;
+                Ok(match value {
+                    Some(value) => {
+                        match width {
+                            8 => volatile_store(address as *mut u8, value as u8),
+                            16 => volatile_store(address as *mut u16, value as u16),
+                            32 => volatile_store(address as *mut u32, value as u32),
+                            _ => volatile_store(address as *mut u64, value),
+                        }
+                        value
+                    },
+                    None => match width {
+                        8 => volatile_load(address as *const u8) as u64,
+                        16 => volatile_load(address as *const u16) as u64,
+                        32 => volatile_load(address as *const u32) as u64,
+                        _ => volatile_load(address as *const u64),
+                    },
+                })
+            },
+            SYSTEM_IO => {
+                let port = address as u16;
+                Ok(match value {
+                    Some(value) => {
+                        match width {
+                            8 => Pio::<u8>::new(port).write(value as u8),
+                            16 => Pio::<u16>::new(port).write(value as u16),
+                            32 => Pio::<u32>::new(port).write(value as u32),
+                            _ => {
+                                Pio::<u32>::new(port).write(value as u32);
+                                Pio::<u32>::new(port + 4).write((value >> 32) as u32);
+                            },
+                        }
+                        value
+                    },
+                    None => match width {
+                        8 => Pio::<u8>::new(port).read() as u64,
+                        16 => Pio::<u16>::new(port).read() as u64,
+                        32 => Pio::<u32>::new(port).read() as u64,
+                        _ => Pio::<u32>::new(port).read() as u64 | (Pio::<u32>::new(port + 4).read() as u64) << 32,
+                    },
+                })
+            },
+            PCI_CONFIG => {
+                let (bus, slot, func) = self.pci_address(&region.scope)?;
+                let mut pci = PciConfig::new(bus, slot, func);
+
+                // Config space is accessed in aligned dwords
+                let mut result = 0;
+                let mut done = 0;
+                while done < width {
+                    let offset = address + done / 8;
+                    let reg = (offset & 0xFC) as u8;
+                    let shift = (offset & 3) * 8;
+                    let count = cmp::min(32 - shift, width - done);
+
+                    let mut dword = unsafe { pci.read(reg) } as u64;
+                    match value {
+                        Some(value) => {
+                            dword &= !(mask(count) << shift);
+                            dword |= (value >> done & mask(count)) << shift;
+                            unsafe { pci.write(reg, dword as u32) };
+                        },
+                        None => result |= (dword >> shift & mask(count)) << done,
+                    }
+
+                    done += count;
+                }
+
+                Ok(value.unwrap_or(result))
+            },
+            space => {
+                syslog_debug!("AML: unsupported address space: {}", space);
+                Err(Error::new(ENOSYS))
+            },
+        }
+    }
+
+    /// The PCI function of a configuration region, from the `_ADR` of its device and the `_BBN`
+    /// of its bridge
+    fn pci_address(&mut self, scope: &str) -> Result<(u8, u8, u8)> {
+        let mut adr = None;
+        let mut bus = 0;
+
+        let mut path: String = scope.into();
+        loop {
+            if adr.is_none() {
+                if self.get(&join(&path, "_ADR")).is_some() {
+                    adr = Some(self.evaluate(&join(&path, "_ADR"), Vec::new())?.to_integer()?);
+                }
+            } else if self.get(&join(&path, "_BBN")).is_some() {
+                bus = self.evaluate(&join(&path, "_BBN"), Vec::new())?.to_integer()?;
+                break;
+            }
+
+            if path == "\\" {
+                break;
+            }
+            path = parent(&path);
+        }
+
+        match adr {
+            Some(adr) => Ok((bus as u8, (adr >> 16) as u8, adr as u8)),
+            None => Err(Error::new(EINVAL)),
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use collections::string::String;
+
+use core::mem::size_of;
+use core::ops::{BitOrAssign, ShlAssign};
+
+use system::error::{Error, Result, EINVAL};
+
+pub const ZERO_OP: u8 = 0x00;
+pub const ONE_OP: u8 = 0x01;
+pub const ALIAS_OP: u8 = 0x06;
+pub const NAME_OP: u8 = 0x08;
+pub const BYTE_PREFIX: u8 = 0x0A;
+pub const WORD_PREFIX: u8 = 0x0B;
+pub const DWORD_PREFIX: u8 = 0x0C;
+pub const STRING_PREFIX: u8 = 0x0D;
+pub const QWORD_PREFIX: u8 = 0x0E;
+pub const SCOPE_OP: u8 = 0x10;
+pub const BUFFER_OP: u8 = 0x11;
+pub const PACKAGE_OP: u8 = 0x12;
+pub const VAR_PACKAGE_OP: u8 = 0x13;
+pub const METHOD_OP: u8 = 0x14;
+pub const EXTERNAL_OP: u8 = 0x15;
+pub const DUAL_NAME_PREFIX: u8 = 0x2E;
+pub const MULTI_NAME_PREFIX: u8 = 0x2F;
+pub const EXT_OP_PREFIX: u8 = 0x5B;
+pub const ROOT_PREFIX: u8 = 0x5C;
+pub const PARENT_PREFIX: u8 = 0x5E;
+pub const LOCAL0_OP: u8 = 0x60;
+pub const LOCAL7_OP: u8 = 0x67;
+pub const ARG0_OP: u8 = 0x68;
+pub const ARG6_OP: u8 = 0x6E;
+pub const STORE_OP: u8 = 0x70;
+pub const REF_OF_OP: u8 = 0x71;
+pub const ADD_OP: u8 = 0x72;
+pub const CONCAT_OP: u8 = 0x73;
+pub const SUBTRACT_OP: u8 = 0x74;
+pub const INCREMENT_OP: u8 = 0x75;
+pub const DECREMENT_OP: u8 = 0x76;
+pub const MULTIPLY_OP: u8 = 0x77;
+pub const DIVIDE_OP: u8 = 0x78;
+pub const SHIFT_LEFT_OP: u8 = 0x79;
+pub const SHIFT_RIGHT_OP: u8 = 0x7A;
+pub const AND_OP: u8 = 0x7B;
+pub const NAND_OP: u8 = 0x7C;
+pub const OR_OP: u8 = 0x7D;
+pub const NOR_OP: u8 = 0x7E;
+pub const XOR_OP: u8 = 0x7F;
+pub const NOT_OP: u8 = 0x80;
+pub const FIND_SET_LEFT_BIT_OP: u8 = 0x81;
+pub const FIND_SET_RIGHT_BIT_OP: u8 = 0x82;
+pub const DEREF_OF_OP: u8 = 0x83;
+pub const CONCAT_RES_OP: u8 = 0x84;
+pub const MOD_OP: u8 = 0x85;
+pub const NOTIFY_OP: u8 = 0x86;
+pub const SIZE_OF_OP: u8 = 0x87;
+pub const INDEX_OP: u8 = 0x88;
+pub const CREATE_DWORD_FIELD_OP: u8 = 0x8A;
+pub const CREATE_WORD_FIELD_OP: u8 = 0x8B;
+pub const CREATE_BYTE_FIELD_OP: u8 = 0x8C;
+pub const CREATE_BIT_FIELD_OP: u8 = 0x8D;
+pub const OBJECT_TYPE_OP: u8 = 0x8E;
+pub const CREATE_QWORD_FIELD_OP: u8 = 0x8F;
+pub const LAND_OP: u8 = 0x90;
+pub const LOR_OP: u8 = 0x91;
+pub const LNOT_OP: u8 = 0x92;
+pub const LEQUAL_OP: u8 = 0x93;
+pub const LGREATER_OP: u8 = 0x94;
+pub const LLESS_OP: u8 = 0x95;
+pub const TO_BUFFER_OP: u8 = 0x96;
+pub const TO_HEX_STRING_OP: u8 = 0x98;
+pub const TO_INTEGER_OP: u8 = 0x99;
+pub const TO_STRING_OP: u8 = 0x9C;
+pub const COPY_OBJECT_OP: u8 = 0x9D;
+pub const MID_OP: u8 = 0x9E;
+pub const CONTINUE_OP: u8 = 0x9F;
+pub const IF_OP: u8 = 0xA0;
+pub const ELSE_OP: u8 = 0xA1;
+pub const WHILE_OP: u8 = 0xA2;
+pub const NOOP_OP: u8 = 0xA3;
+pub const RETURN_OP: u8 = 0xA4;
+pub const BREAK_OP: u8 = 0xA5;
+pub const BREAKPOINT_OP: u8 = 0xCC;
+pub const ONES_OP: u8 = 0xFF;
+
+// EXT
+pub const MUTEX_OP: u8 = 0x01;
+pub const EVENT_OP: u8 = 0x02;
+pub const COND_REF_OF_OP: u8 = 0x12;
+pub const CREATE_FIELD_OP: u8 = 0x13;
+pub const STALL_OP: u8 = 0x21;
+pub const SLEEP_OP: u8 = 0x22;
+pub const ACQUIRE_OP: u8 = 0x23;
+pub const SIGNAL_OP: u8 = 0x24;
+pub const WAIT_OP: u8 = 0x25;
+pub const RESET_OP: u8 = 0x26;
+pub const RELEASE_OP: u8 = 0x27;
+pub const REVISION_OP: u8 = 0x30;
+pub const DEBUG_OP: u8 = 0x31;
+pub const FATAL_OP: u8 = 0x32;
+pub const TIMER_OP: u8 = 0x33;
+pub const OP_REGION_OP: u8 = 0x80;
+pub const FIELD_OP: u8 = 0x81;
+pub const DEVICE_OP: u8 = 0x82;
+pub const PROCESSOR_OP: u8 = 0x83;
+pub const POWER_RES_OP: u8 = 0x84;
+pub const THERMAL_ZONE_OP: u8 = 0x85;
+pub const INDEX_FIELD_OP: u8 = 0x86;
+
+/// The byte at `i`, or an error at the end of the code
+pub fn peek(bytes: &[u8], i: usize) -> Result<u8> {
+    match bytes.get(i) {
+        Some(&b) => Ok(b),
+        None => Err(Error::new(EINVAL)),
+    }
+}
+
+/// Read the byte at `i` and advance past it
+pub fn next(bytes: &[u8], i: &mut usize) -> Result<u8> {
+    let b = peek(bytes, *i)?;
+    *i += 1;
+    Ok(b)
+}
+
+// This one function required three different unstable features and four trait requirements. Why is generic math so hard?
+pub fn parse_num<T: BitOrAssign + From<u8> + ShlAssign<usize>>(bytes: &[u8],
+                                                               i: &mut usize)
+                                                               -> T {
+    let mut num: T = T::from(0);
+
+    let mut shift = 0;
+    while *i < bytes.len() && shift < size_of::<T>() * 8 {
+        let mut b = T::from(bytes[*i]);
+        b <<= shift;
+        num |= b;
+        shift += 8;
+        *i += 1;
+    }
+
+    num
+}
+
+/// Parse a package length, which counts its own bytes
+pub fn parse_length(bytes: &[u8], i: &mut usize) -> usize {
+    let mut length = 0;
+
+    if *i < bytes.len() {
+        let b = bytes[*i] as usize;
+
+        let mut follow = (b & 0b11000000) >> 6;
+        if follow == 0 {
+            length += b & 0b111111;
+        } else {
+            length += b & 0b1111;
+        }
+
+        *i += 1;
+
+        let mut shift = 4;
+        while *i < bytes.len() && follow > 0 {
+            length += (bytes[*i] as usize) << shift;
+
+            shift += 8;
+            follow -= 1;
+            *i += 1;
+        }
+    }
+
+    length
+}
+
+/// Parse a package length, returning the end of the package
+pub fn parse_end(bytes: &[u8], i: &mut usize) -> Result<usize> {
+    let start = *i;
+    let end = start + parse_length(bytes, i);
+    if end <= bytes.len() && end >= *i {
+        Ok(end)
+    } else {
+        Err(Error::new(EINVAL))
+    }
+}
+
+fn is_lead_name_char(c: u8) -> bool {
+    (c >= b'A' && c <= b'Z') || c == b'_'
+}
+
+fn is_name_char(c: u8) -> bool {
+    is_lead_name_char(c) || (c >= b'0' && c <= b'9')
+}
+
+/// Can a name string start with `c`
+pub fn is_name_start(c: u8) -> bool {
+    is_lead_name_char(c) || c == ROOT_PREFIX || c == PARENT_PREFIX || c == DUAL_NAME_PREFIX ||
+    c == MULTI_NAME_PREFIX
+}
+
+/// Parse a name segment, which is always four characters, padded with underscores
+pub fn parse_name_seg(bytes: &[u8], i: &mut usize) -> Result<String> {
+    let mut seg = String::new();
+    for j in 0..4 {
+        let c = next(bytes, i)?;
+        if (j == 0 && is_lead_name_char(c)) || (j > 0 && is_name_char(c)) {
+            seg.push(c as char);
+        } else {
+            syslog_debug!("AML: invalid name character: {:02X}", c);
+            return Err(Error::new(EINVAL));
+        }
+    }
+    Ok(seg)
+}
+
+/// Parse a name string, as it is written: root and parent prefixes, then the segments joined
+/// by dots. The null name is empty.
+pub fn parse_name_string(bytes: &[u8], i: &mut usize) -> Result<String> {
+    let mut name = String::new();
+
+    loop {
+        match peek(bytes, *i)? {
+            ROOT_PREFIX => name.push('\\'),
+            PARENT_PREFIX => name.push('^'),
+            _ => break,
+        }
+        *i += 1;
+    }
+
+    let count = match next(bytes, i)? {
+        ZERO_OP => 0,
+        DUAL_NAME_PREFIX => 2,
+        MULTI_NAME_PREFIX => next(bytes, i)?,
+        _ => {
+            *i -= 1;
+            1
+        }
+    };
+
+    for j in 0..count {
+        if j > 0 {
+            name.push('.');
+        }
+        name.push_str(&parse_name_seg(bytes, i)?);
+    }
+
+    Ok(name)
+}
+
+/// Parse a null terminated string
+pub fn parse_string(bytes: &[u8], i: &mut usize) -> Result<String> {
+    let mut string = String::new();
+    loop {
+        match next(bytes, i)? {
+            0 => break,
+            c => string.push(c as char),
+        }
+    }
+    Ok(string)
+}
@@ -0,0 +1,74 @@
+//! Resource templates, the buffers that `_CRS` and `_PRS` return
+
+/// An interrupt in a resource template
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Interrupt {
+    /// The ISA IRQ or GSI
+    pub number: u32,
+    /// Level triggered instead of edge triggered
+    pub level: bool,
+    /// Active low instead of active high
+    pub active_low: bool,
+}
+
+/// The first interrupt in a resource template
+///
+/// Both IRQ descriptors, with a mask of ISA IRQs, and extended interrupt descriptors, with a list
+/// of GSIs, are used. Returns None if there is neither, or the IRQ mask is empty.
+pub fn interrupt(resources: &[u8]) -> Option<Interrupt> {
+    let mut i = 0;
+    while i < resources.len() {
+        let tag = resources[i];
+        if tag & 0x80 == 0 {
+            // A small item, with the name in bits 3 to 6 and the length in bits 0 to 2
+            let length = (tag & 0b111) as usize;
+            if i + 1 + length > resources.len() {
+                break;
+            }
+
+            match tag >> 3 {
+                0x04 if length >= 2 => {
+                    let mask = resources[i + 1] as u16 | (resources[i + 2] as u16) << 8;
+                    // Without the information byte, the IRQ is edge triggered and active high
+                    let info = if length >= 3 { resources[i + 3] } else { 1 };
+                    if mask == 0 {
+                        return None;
+                    }
+                    return Some(Interrupt {
+                        number: mask.trailing_zeros(),
+                        level: info & 1 == 0,
+                        active_low: info & 1 << 3 == 1 << 3,
+                    });
+                },
+                // The end tag
+                0x0F => break,
+                _ => (),
+            }
+
+            i += 1 + length;
+        } else {
+            // A large item, with a 16 bit length
+            if i + 3 > resources.len() {
+                break;
+            }
+            let length = resources[i + 1] as usize | (resources[i + 2] as usize) << 8;
+            if i + 3 + length > resources.len() {
+                break;
+            }
+
+            if tag == 0x89 && length >= 6 && resources[i + 4] > 0 {
+                let info = resources[i + 3];
+                return Some(Interrupt {
+                    number: resources[i + 5] as u32 | (resources[i + 6] as u32) << 8 |
+                            (resources[i + 7] as u32) << 16 | (resources[i + 8] as u32) << 24,
+                    level: info & 1 << 1 == 0,
+                    active_low: info & 1 << 2 == 1 << 2,
+                });
+            }
+
+            i += 3 + length;
+        }
+    }
+
+    None
+}
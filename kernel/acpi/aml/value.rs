@@ -0,0 +1,195 @@
+use collections::string::String;
+use collections::vec::Vec;
+
+use core::cmp;
+
+use system::error::{Error, Result, EINVAL};
+
+/// A method, with its code
+#[derive(Clone, Copy, Debug)]
+pub struct Method {
+    pub code: &'static [u8],
+    pub args: u8,
+}
+
+/// An operation region, the address space that fields are read from
+#[derive(Clone, Debug)]
+pub struct OperationRegion {
+    pub space: u8,
+    pub offset: u64,
+    pub length: u64,
+    /// The scope it was defined in, which is the device of a PCI configuration region
+    pub scope: String,
+}
+
+/// Where a field unit is accessed
+#[derive(Clone, Debug)]
+pub enum FieldKind {
+    /// Part of the operation region at this path
+    Region(String),
+    /// Accessed by writing the offset to the index field, then accessing the data field
+    Index {
+        index: String,
+        data: String,
+    },
+}
+
+/// A field unit, with its offset and length in bits
+#[derive(Clone, Debug)]
+pub struct FieldUnit {
+    pub kind: FieldKind,
+    pub offset: u64,
+    pub length: u64,
+    /// The access type in bits 0 to 3, and the update rule in bits 5 and 6
+    pub flags: u8,
+}
+
+impl FieldUnit {
+    /// The width of each access in bits
+    pub fn access_width(&self) -> u64 {
+        match self.flags & 0xF {
+            2 => 16,
+            3 => 32,
+            4 => 64,
+            // Any access and buffer access are done a byte at a time
+            _ => 8,
+        }
+    }
+
+    /// The update rule, for bits of a unit that are not written
+    pub fn update_rule(&self) -> u8 {
+        (self.flags >> 5) & 0b11
+    }
+}
+
+/// Bits of a named buffer, with the offset and length in bits
+#[derive(Clone, Debug)]
+pub struct BufferField {
+    pub buffer: String,
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// An object in the namespace, or the value of an expression
+#[derive(Clone, Debug)]
+pub enum AmlValue {
+    Uninitialized,
+    Integer(u64),
+    String(String),
+    Buffer(Vec<u8>),
+    Package(Vec<AmlValue>),
+    /// A reference to the named object at this path, from a package or `RefOf`
+    Reference(String),
+    Method(Method),
+    OperationRegion(OperationRegion),
+    FieldUnit(FieldUnit),
+    BufferField(BufferField),
+    Scope,
+    Device,
+    Processor,
+    PowerResource,
+    ThermalZone,
+    Mutex,
+    Event,
+}
+
+impl AmlValue {
+    pub fn to_integer(&self) -> Result<u64> {
+        match *self {
+            AmlValue::Integer(value) => Ok(value),
+            AmlValue::Buffer(ref bytes) => Ok(bytes.iter().take(8).enumerate().fold(0, |value, (i, &b)| {
+                value | (b as u64) << (i * 8)
+            })),
+            AmlValue::String(ref string) => {
+                let digits = string.trim_left_matches("0x").trim_left_matches("0X");
+                let mut value = 0;
+                for c in digits.chars() {
+                    match c.to_digit(16) {
+                        Some(digit) => value = value << 4 | digit as u64,
+                        None => break,
+                    }
+                }
+                Ok(value)
+            },
+            _ => Err(Error::new(EINVAL)),
+        }
+    }
+
+    /// The bytes of an integer, string or buffer. Strings do not get a null terminator.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        match *self {
+            AmlValue::Integer(value) => Ok((0..8).map(|i| (value >> (i * 8)) as u8).collect()),
+            AmlValue::String(ref string) => Ok(string.as_bytes().to_vec()),
+            AmlValue::Buffer(ref bytes) => Ok(bytes.clone()),
+            _ => Err(Error::new(EINVAL)),
+        }
+    }
+
+    pub fn to_buffer(&self) -> Result<Vec<u8>> {
+        let mut bytes = self.to_bytes()?;
+        if let AmlValue::String(_) = *self {
+            bytes.push(0);
+        }
+        Ok(bytes)
+    }
+
+    /// The object type, as returned by `ObjectType`
+    pub fn object_type(&self) -> u64 {
+        match *self {
+            AmlValue::Uninitialized | AmlValue::Scope => 0,
+            AmlValue::Integer(_) => 1,
+            AmlValue::String(_) => 2,
+            AmlValue::Buffer(_) => 3,
+            AmlValue::Package(_) => 4,
+            AmlValue::FieldUnit(_) => 5,
+            AmlValue::Device => 6,
+            AmlValue::Event => 7,
+            AmlValue::Method(_) => 8,
+            AmlValue::Mutex => 9,
+            AmlValue::OperationRegion(_) => 10,
+            AmlValue::PowerResource => 11,
+            AmlValue::Processor => 12,
+            AmlValue::ThermalZone => 13,
+            AmlValue::BufferField(_) => 14,
+            AmlValue::Reference(_) => 20,
+        }
+    }
+}
+
+/// A mask of the low `count` bits
+pub fn mask(count: u64) -> u64 {
+    if count >= 64 {
+        !0
+    } else {
+        (1 << count) - 1
+    }
+}
+
+/// Read `count` bits, at most 64, from `bytes` at bit `offset`. Missing bits are zero.
+pub fn get_bits(bytes: &[u8], offset: u64, count: u64) -> u64 {
+    let mut value = 0;
+    for bit in 0..cmp::min(count, 64) {
+        let position = offset + bit;
+        if let Some(&b) = bytes.get((position / 8) as usize) {
+            if b & 1 << (position % 8) != 0 {
+                value |= 1 << bit;
+            }
+        }
+    }
+    value
+}
+
+/// Write `count` bits, at most 64, of `value` into `bytes` at bit `offset`. Bits past the end
+/// are dropped.
+pub fn set_bits(bytes: &mut [u8], offset: u64, count: u64, value: u64) {
+    for bit in 0..cmp::min(count, 64) {
+        let position = offset + bit;
+        if let Some(b) = bytes.get_mut((position / 8) as usize) {
+            if value & 1 << bit != 0 {
+                *b |= 1 << (position % 8);
+            } else {
+                *b &= !(1 << (position % 8));
+            }
+        }
+    }
+}
@@ -0,0 +1,114 @@
+//! Copy the DSDT and SSDTs out of a memory dump of a QEMU machine, for `make aml_fixtures`
+//!
+//! The dump starts at physical address 0, and is taken with `pmemsave` in the QEMU monitor once
+//! the firmware has built the tables. The tables are found like `RSDT::new` finds them, and are
+//! written to `<prefix>-dsdt.aml`, `<prefix>-ssdt.aml`, `<prefix>-ssdt1.aml` and so on.
+
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::process;
+
+/// The size of a table header, which is followed by the data of the table
+const HEADER_SIZE: usize = 36;
+
+fn read_u32(memory: &[u8], address: usize) -> Option<usize> {
+    memory.get(address..address + 4).map(|bytes| {
+        bytes[0] as usize | (bytes[1] as usize) << 8 | (bytes[2] as usize) << 16 | (bytes[3] as usize) << 24
+    })
+}
+
+/// The bytes of the table at `address`, if its checksum is valid
+fn table(memory: &[u8], address: usize) -> Option<&[u8]> {
+    let length = match read_u32(memory, address + 4) {
+        Some(length) if length >= HEADER_SIZE => length,
+        _ => return None,
+    };
+    memory.get(address..address + length).and_then(|table| {
+        if table.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0 {
+            Some(table)
+        } else {
+            None
+        }
+    })
+}
+
+/// The address of the RSDT, from the RSDP in the BIOS area
+fn rsdt(memory: &[u8]) -> Option<usize> {
+    let mut address = 0xE0000;
+    while address + 20 <= 0x100000 {
+        if let Some(rsdp) = memory.get(address..address + 20) {
+            if &rsdp[..8] == b"RSD PTR " && rsdp.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0 {
+                return read_u32(memory, address + 16);
+            }
+        }
+        address += 16;
+    }
+    None
+}
+
+fn write(path: &str, table: &[u8]) {
+    match File::create(path).and_then(|mut file| file.write_all(table)) {
+        Ok(()) => println!("{}: {} bytes", path, table.len()),
+        Err(err) => {
+            println!("{}: {}", path, err);
+            process::exit(1);
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        println!("usage: {} <memory dump> <prefix>", args[0]);
+        process::exit(1);
+    }
+
+    let mut memory = Vec::new();
+    if let Err(err) = File::open(&args[1]).and_then(|mut file| file.read_to_end(&mut memory)) {
+        println!("{}: {}", args[1], err);
+        process::exit(1);
+    }
+
+    let rsdt = match rsdt(&memory).and_then(|address| table(&memory, address)) {
+        Some(rsdt) if &rsdt[..4] == b"RSDT" => rsdt,
+        _ => {
+            println!("{}: no valid RSDP and RSDT", args[1]);
+            process::exit(1);
+        }
+    };
+
+    let mut dsdt = false;
+    let mut ssdts = 0;
+    let mut entry = HEADER_SIZE;
+    while entry + 4 <= rsdt.len() {
+        let address = read_u32(rsdt, entry).unwrap();
+        match table(&memory, address) {
+            Some(header) if &header[..4] == b"FACP" => {
+                // The address of the DSDT follows the FACS in the FADT
+                match read_u32(header, 40).and_then(|address| table(&memory, address)) {
+                    Some(table) => {
+                        write(&format!("{}-dsdt.aml", args[2]), table);
+                        dsdt = true;
+                    },
+                    None => println!("FACP: no valid DSDT"),
+                }
+            },
+            Some(header) if &header[..4] == b"SSDT" => {
+                if ssdts == 0 {
+                    write(&format!("{}-ssdt.aml", args[2]), header);
+                } else {
+                    write(&format!("{}-ssdt{}.aml", args[2], ssdts), header);
+                }
+                ssdts += 1;
+            },
+            Some(_) => (),
+            None => println!("{:X}: invalid table", address),
+        }
+        entry += 4;
+    }
+
+    if ! dsdt {
+        process::exit(1);
+    }
+}
@@ -4,11 +4,11 @@ use core::ptr;
 #[repr(packed)]
 #[derive(Clone, Copy, Debug, Default)]
 pub struct GenericAddressStructure {
-    address_space: u8,
-    bit_width: u8,
-    bit_offset: u8,
-    access_size: u8,
-    address: u64,
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
 }
 
 #[repr(packed)]
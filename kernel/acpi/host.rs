@@ -0,0 +1,155 @@
+//! The AML interpreter and its tests, built for the host by `make test_aml`
+//!
+//! The kernel cannot run tests, so this builds the ACPI table headers and `aml` on their own, with
+//! stand-ins for the parts of the kernel that the interpreter uses. Port and PCI configuration
+//! accesses go to memory that each test thread has for itself, so a test can set up the registers
+//! that methods read. System memory is not emulated, so tests must not evaluate code that uses it.
+
+#![feature(alloc, collections, core_intrinsics, question_mark)]
+#![deny(warnings)]
+
+extern crate alloc;
+extern crate collections;
+extern crate core;
+
+pub use self::dsdt::DSDT;
+pub use self::sdt::SDTHeader;
+pub use self::ssdt::SSDT;
+
+macro_rules! syslog_debug {
+    ($($arg:tt)*) => ({
+        println!($($arg)*);
+    });
+}
+
+macro_rules! syslog_info {
+    ($($arg:tt)*) => ({
+        println!($($arg)*);
+    });
+}
+
+pub mod aml;
+pub mod dsdt;
+pub mod sdt;
+pub mod ssdt;
+
+#[path = "../../crates/system/error.rs"]
+pub mod error;
+
+#[cfg(test)]
+mod tests;
+
+/// The parts of the `system` crate that the interpreter uses
+mod system {
+    pub use super::error;
+}
+
+/// The parts of `arch` that the interpreter uses
+mod arch {
+    pub mod apic {
+        /// Sleeping is not needed without hardware
+        pub fn delay(_micros: usize) {}
+    }
+}
+
+/// The parts of `drivers` that the interpreter uses
+mod drivers {
+    pub mod io {
+        use std::cell::RefCell;
+        use std::collections::BTreeMap;
+        use std::marker::PhantomData;
+
+        thread_local!(static PORTS: RefCell<BTreeMap<u16, u8>> = RefCell::new(BTreeMap::new()));
+
+        pub trait Io {
+            type Value;
+
+            fn read(&self) -> Self::Value;
+            fn write(&mut self, value: Self::Value);
+        }
+
+        /// A port, which reads and writes the port memory of the thread, with zeros where nothing
+        /// was written
+        pub struct Pio<T> {
+            port: u16,
+            value: PhantomData<T>,
+        }
+
+        impl<T> Pio<T> {
+            pub fn new(port: u16) -> Self {
+                Pio {
+                    port: port,
+                    value: PhantomData,
+                }
+            }
+        }
+
+        macro_rules! pio {
+            ($type:ty, $bytes:expr) => {
+                impl Io for Pio<$type> {
+                    type Value = $type;
+
+                    fn read(&self) -> $type {
+                        PORTS.with(|ports| {
+                            let ports = ports.borrow();
+                            (0..$bytes).fold(0, |value, i| {
+                                value | (*ports.get(&(self.port + i)).unwrap_or(&0) as $type) << (i * 8)
+                            })
+                        })
+                    }
+
+                    fn write(&mut self, value: $type) {
+                        PORTS.with(|ports| {
+                            let mut ports = ports.borrow_mut();
+                            for i in 0..$bytes {
+                                ports.insert(self.port + i, (value >> (i * 8)) as u8);
+                            }
+                        });
+                    }
+                }
+            }
+        }
+
+        pio!(u8, 1);
+        pio!(u16, 2);
+        pio!(u32, 4);
+    }
+
+    pub mod pci {
+        pub mod config {
+            use std::cell::RefCell;
+            use std::collections::BTreeMap;
+
+            thread_local!(static SPACE: RefCell<BTreeMap<(u8, u8, u8, u8), u32>> = RefCell::new(BTreeMap::new()));
+
+            /// A PCI configuration, which reads and writes the configuration memory of the thread,
+            /// with zeros where nothing was written
+            #[derive(Copy, Clone)]
+            pub struct PciConfig {
+                bus: u8,
+                slot: u8,
+                func: u8,
+            }
+
+            impl PciConfig {
+                pub fn new(bus: u8, slot: u8, func: u8) -> Self {
+                    PciConfig {
+                        bus: bus,
+                        slot: slot,
+                        func: func,
+                    }
+                }
+
+                pub unsafe fn read(&mut self, offset: u8) -> u32 {
+                    let key = (self.bus, self.slot, self.func, offset & 0xFC);
+                    SPACE.with(|space| *space.borrow().get(&key).unwrap_or(&0))
+                }
+
+                pub unsafe fn write(&mut self, offset: u8, value: u32) {
+                    let key = (self.bus, self.slot, self.func, offset & 0xFC);
+                    SPACE.with(|space| space.borrow_mut().insert(key, value));
+                }
+            }
+        }
+    }
+}
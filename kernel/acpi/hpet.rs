@@ -0,0 +1,29 @@
+use super::SDTHeader;
+use super::fadt::GenericAddressStructure;
+
+use core::mem::size_of;
+use core::ptr;
+
+/// The HPET description table, which gives the address of a high precision event timer
+#[repr(packed)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HPET {
+    pub header: SDTHeader,
+    pub hardware_rev_id: u8,
+    pub comparator_descriptor: u8,
+    pub pci_vendor_id: u16,
+    pub base_address: GenericAddressStructure,
+    pub hpet_number: u8,
+    pub min_periodic_clock_tick: u16,
+    pub page_protection: u8,
+}
+
+impl HPET {
+    pub fn new(header: &'static SDTHeader) -> Option<Self> {
+        if header.valid("HPET") && header.length as usize >= size_of::<HPET>() {
+            Some(unsafe { ptr::read((header as *const SDTHeader) as *const HPET) })
+        } else {
+            None
+        }
+    }
+}
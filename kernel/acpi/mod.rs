@@ -1,12 +1,22 @@
 use alloc::boxed::Box;
 
+use arch::apic;
+use arch::idt::IdtDescriptor;
+
+use collections::{String, Vec};
+
+use core::intrinsics::volatile_store;
 use core::str;
 
-use fs::{KScheme, Resource};
-use system::error::{Error, Result, ENOENT};
-use system::syscall::O_CREAT;
+use drivers::io::{Io, Pio};
+use drivers::pci::config::PciConfig;
+use fs::{KScheme, Resource, VecResource};
+use system::error::{Error, Result, EACCES, ENOENT};
+use system::syscall::{MODE_DIR, MODE_FILE, O_CREAT};
+pub use self::aml::{AmlValue, Namespace};
 pub use self::dsdt::DSDT;
 pub use self::fadt::FADT;
+pub use self::hpet::HPET;
 pub use self::madt::MADT;
 pub use self::rsdt::RSDT;
 pub use self::sdt::SDTHeader;
@@ -15,6 +25,7 @@ pub use self::ssdt::SSDT;
 pub mod aml;
 pub mod dsdt;
 pub mod fadt;
+pub mod hpet;
 pub mod madt;
 pub mod rsdt;
 pub mod sdt;
@@ -27,8 +38,21 @@ pub struct Acpi {
     dsdt: Option<DSDT>,
     ssdt: Option<SSDT>,
     madt: Option<MADT>,
+    hpet: Option<HPET>,
+    namespace: Namespace,
+    /// The tables with valid checksums, by name, for `acpi:tables`
+    tables: Vec<(String, &'static SDTHeader)>,
 }
 
+/// The sleep type bits and the sleep enable bit of the PM1 control registers
+const SLP_TYP: u16 = 0b111 << 10;
+const SLP_EN: u16 = 1 << 13;
+
+/// The FADT flag for a supported reset register
+const RESET_REG_SUP: u32 = 1 << 10;
+/// The end of the reset value in the FADT, which tables before ACPI 2.0 are too short for
+const RESET_VALUE_END: u32 = 129;
+
 impl Acpi {
     pub fn new() -> Option<Box<Self>> {
         match RSDT::new() {
@@ -41,30 +65,66 @@ impl Acpi {
                     dsdt: None,
                     ssdt: None,
                     madt: None,
+                    hpet: None,
+                    namespace: Namespace::default(),
+                    tables: Vec::new(),
                 };
 
+                // SSDTs add to the namespace of the DSDT, so they are loaded after it
+                let mut ssdts = Vec::new();
+
                 for addr in acpi.rsdt.addrs.iter() {
                     let header = unsafe { &*(*addr as *const SDTHeader) };
+                    if ! header.valid_checksum() {
+                        syslog_info!("{}: Invalid checksum", unsafe { str::from_utf8_unchecked(&header.signature) });
+                        continue;
+                    }
+                    acpi.add_table(header);
+
                     if let Some(fadt) = FADT::new(header) {
                         //Can't do it debugln!("{:#?}", fadt);
                         if let Some(dsdt) = DSDT::new(unsafe { &*(fadt.dsdt as *const SDTHeader) }) {
-                            syslog_debug!("DSDT:");
-                            aml::parse(dsdt.data);
+                            acpi.add_table(dsdt.header);
                             acpi.dsdt = Some(dsdt);
                         }
                         acpi.fadt = Some(fadt);
                     } else if let Some(ssdt) = SSDT::new(header) {
-                        syslog_debug!("SSDT:");
-                        aml::parse(ssdt.data);
+                        ssdts.push(ssdt);
                         acpi.ssdt = Some(ssdt);
                     } else if let Some(madt) = MADT::new(header) {
                         syslog_debug!("{:#?}", madt);
                         acpi.madt = Some(madt);
+                    } else if let Some(hpet) = HPET::new(header) {
+                        syslog_debug!("{:#?}", hpet);
+                        acpi.hpet = Some(hpet);
                     } else {
                         syslog_debug!("{}: Unknown Table", unsafe { str::from_utf8_unchecked(&header.signature) });
                     }
                 }
 
+                if let Some(dsdt) = acpi.dsdt {
+                    acpi.namespace = Namespace::new(dsdt.header.revision);
+                    if let Err(err) = acpi.namespace.load(dsdt.data) {
+                        syslog_info!("DSDT: {}", err);
+                    }
+                }
+                for ssdt in ssdts.iter() {
+                    if let Err(err) = acpi.namespace.load(ssdt.data) {
+                        syslog_info!("SSDT: {}", err);
+                    }
+                }
+
+                for device in acpi.namespace.devices() {
+                    match acpi.namespace.device_status(&device) {
+                        Ok(status) if status & 1 == 1 => match acpi.namespace.device_resources(&device) {
+                            Ok(resources) => syslog_debug!("{}: status {:X}, {} bytes of resources", device, status, resources.len()),
+                            Err(_) => syslog_debug!("{}: status {:X}", device, status),
+                        },
+                        Ok(status) => syslog_debug!("{}: status {:X}", device, status),
+                        Err(err) => syslog_debug!("{}: _STA: {}", device, err),
+                    }
+                }
+
                 Some(acpi)
             }
             Err(e) => {
@@ -73,6 +133,190 @@ impl Acpi {
             }
         }
     }
+
+    /// The MADT, which lists the processors and interrupt controllers
+    pub fn madt(&self) -> Option<&MADT> {
+        self.madt.as_ref()
+    }
+
+    /// The HPET table, which gives the address of the high precision event timer
+    pub fn hpet(&self) -> Option<&HPET> {
+        self.hpet.as_ref()
+    }
+
+    /// Add a table to `acpi:tables`, under its signature. Later tables with the same signature
+    /// get a number after it.
+    fn add_table(&mut self, header: &'static SDTHeader) {
+        let signature = String::from_utf8_lossy(&header.signature).into_owned();
+        let count = self.tables.iter().filter(|&&(_, other)| other.signature == header.signature).count();
+        let name = if count == 0 {
+            signature
+        } else {
+            format!("{}{}", signature, count)
+        };
+        self.tables.push((name, header));
+    }
+
+    /// Route the interrupts of the PCI functions on bus 0 through the IOAPICs, with `_PRT`
+    ///
+    /// `\_PIC(1)` tells the firmware that the APIC is used, so that `_PRT` gives the GSIs that the
+    /// IOAPICs receive. Each interrupt is delivered as the IRQ in the interrupt line of the
+    /// function, which the firmware programmed and drivers read. This must be called after
+    /// `apic::init`.
+    pub fn route_pci(&mut self) {
+        if ! apic::io_apic_enabled() {
+            return;
+        }
+
+        if self.namespace.get("\\_PIC").is_some() {
+            if let Err(err) = self.namespace.evaluate("\\_PIC", vec![AmlValue::Integer(1)]) {
+                syslog_info!("\\_PIC: {}", err);
+            }
+        }
+
+        let routes = match self.namespace.pci_routes() {
+            Ok(routes) => routes,
+            Err(err) => {
+                syslog_info!("  * PCI: _PRT: {}, interrupts use the ISA routes", err);
+                return;
+            }
+        };
+
+        for slot in 0..32 {
+            for func in 0..8 {
+                let mut pci = PciConfig::new(0, slot, func);
+                if unsafe { pci.read(0x00) } & 0xFFFF == 0xFFFF {
+                    continue;
+                }
+
+                let interrupt = unsafe { pci.read(0x3C) };
+                let line = interrupt as u8;
+                let pin = (interrupt >> 8) as u8;
+                if pin >= 1 && pin <= 4 && line >= 1 && line < 16 {
+                    match routes.iter().find(|route| route.slot == slot && route.pin == pin - 1) {
+                        Some(route) => {
+                            let gsi = route.interrupt.number;
+                            let mut flags = 0;
+                            if route.interrupt.level {
+                                flags |= apic::REDIRECTION_LEVEL;
+                            }
+                            if route.interrupt.active_low {
+                                flags |= apic::REDIRECTION_ACTIVE_LOW;
+                            }
+
+                            if unsafe { apic::route_irq(line, gsi, flags) } {
+                                syslog_debug!("  * PCI {:02X}.{}: IRQ {} from GSI {}", slot, func, line, gsi);
+                            } else {
+                                syslog_info!("  * PCI {:02X}.{}: no IOAPIC for GSI {}", slot, func, gsi);
+                            }
+                        },
+                        None => syslog_info!("  * PCI {:02X}.{}: pin {} has no route", slot, func, pin),
+                    }
+                }
+
+                // Bit 7 of the header type is set for devices with more than one function
+                if func == 0 && unsafe { pci.read(0x0C) } & 1 << 23 == 0 {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Enter the soft off state, with the sleep types from `\_S5`
+    fn power_off(&mut self) {
+        let fadt = match self.fadt {
+            Some(fadt) => fadt,
+            None => {
+                debugln!("Unable to power off: No FADT");
+                return;
+            }
+        };
+
+        let (slp_typa, slp_typb) = match self.namespace.evaluate("\\_S5_", Vec::new()) {
+            Ok(AmlValue::Package(values)) => {
+                let slp_typ = |i: usize| values.get(i).and_then(|value| value.to_integer().ok()).unwrap_or(0) as u16;
+                (slp_typ(0), slp_typ(1))
+            },
+            _ => {
+                debugln!("No \\_S5, trying sleep type 0");
+                (0, 0)
+            }
+        };
+
+        // Firmware prepares for sleep in _PTS, with the sleep state as the argument
+        if self.namespace.get("\\_PTS").is_some() {
+            if let Err(err) = self.namespace.evaluate("\\_PTS", vec![AmlValue::Integer(5)]) {
+                debugln!("_PTS: {}", err);
+            }
+        }
+
+        debugln!("Powering Off");
+
+        let mut pm1a = Pio::<u16>::new(fadt.pm1a_control_block as u16);
+        let value = pm1a.read() & !(SLP_TYP | SLP_EN);
+        pm1a.write(value | (slp_typa << 10) & SLP_TYP | SLP_EN);
+
+        if fadt.pm1b_control_block != 0 {
+            let mut pm1b = Pio::<u16>::new(fadt.pm1b_control_block as u16);
+            let value = pm1b.read() & !(SLP_TYP | SLP_EN);
+            pm1b.write(value | (slp_typb << 10) & SLP_TYP | SLP_EN);
+        }
+    }
+
+    /// Reset the computer with the reset register of the FADT, falling back to the keyboard
+    /// controller and then a triple fault
+    fn reset(&mut self) {
+        debugln!("Resetting");
+
+        if let Some(fadt) = self.fadt {
+            if fadt.header.length >= RESET_VALUE_END && fadt.flags & RESET_REG_SUP == RESET_REG_SUP {
+                let reg = fadt.reset_reg;
+                match reg.address_space {
+                    0 => unsafe { volatile_store(reg.address as usize as *mut u8, fadt.reset_value) },
+                    1 => Pio::<u8>::new(reg.address as u16).write(fadt.reset_value),
+                    2 => {
+                        // The device is in bits 32 to 47, the function in bits 16 to 31 and the
+                        // register in bits 0 to 15, on bus 0
+                        let mut pci = PciConfig::new(0, (reg.address >> 32) as u8, (reg.address >> 16) as u8);
+                        let offset = reg.address as u8;
+                        let shift = (offset & 3) * 8;
+                        unsafe {
+                            let dword = pci.read(offset & 0xFC) & !(0xFF << shift);
+                            pci.write(offset & 0xFC, dword | (fadt.reset_value as u32) << shift);
+                        }
+                    },
+                    space => debugln!("Unsupported reset register address space: {}", space),
+                }
+                apic::delay(100000);
+            }
+        }
+
+        // Pulse the reset line of the keyboard controller, when its input buffer is empty
+        {
+            let mut port = Pio::<u8>::new(0x64);
+            for _ in 0..100000 {
+                if ! port.readf(0x02) {
+                    break;
+                }
+            }
+            port.write(0xFE);
+            apic::delay(100000);
+        }
+
+        // An exception without an IDT causes a triple fault, which resets the processor
+        let idt = IdtDescriptor {
+            size: 0,
+            ptr: 0
+        };
+        unsafe {
+            asm!("lidt [$0]
+                int3"
+                :
+                : "r"(&idt as *const IdtDescriptor)
+                : "memory"
+                : "intel", "volatile");
+        }
+    }
 }
 
 impl KScheme for Acpi {
@@ -81,18 +325,38 @@ impl KScheme for Acpi {
     }
 
     fn open(&mut self, url: &str, flags: usize) -> Result<Box<Resource>> {
-        if url.splitn(2, ":").nth(1).unwrap_or("") == "off" && flags & O_CREAT == O_CREAT {
-            match self.fadt {
-                Some(fadt) => {
-                    debugln!("Powering Off");
-                    unsafe {
-                        asm!("out dx, ax" : : "{edx}"(fadt.pm1a_control_block), "{ax}"(0 | 1 << 13) : : "intel", "volatile")
-                    };
+        let path = url.splitn(2, ":").nth(1).unwrap_or("").trim_matches('/');
+
+        // Only root may power off or reset the machine
+        if path == "off" || path == "reset" {
+            let contexts = unsafe { & *::env().contexts.get() };
+            if try!(contexts.current()).euid != 0 {
+                return Err(Error::new(EACCES));
+            }
+        }
+
+        match path {
+            "off" if flags & O_CREAT == O_CREAT => self.power_off(),
+            "reset" if flags & O_CREAT == O_CREAT => self.reset(),
+            "tables" => {
+                let mut list = String::new();
+                for &(ref name, _) in self.tables.iter() {
+                    if ! list.is_empty() {
+                        list.push('\n');
+                    }
+                    list.push_str(name);
                 }
-                None => {
-                    debugln!("Unable to power off: No FADT");
+
+                return Ok(box VecResource::new(String::from("acpi:tables/"), list.into_bytes(), MODE_DIR));
+            },
+            _ => if path.starts_with("tables/") {
+                let name = &path["tables/".len()..];
+                for &(ref table_name, header) in self.tables.iter() {
+                    if table_name == name {
+                        return Ok(box VecResource::new(format!("acpi:tables/{}", name), header.bytes().to_vec(), MODE_FILE));
+                    }
                 }
-            }
+            },
         }
 
         Err(Error::new(ENOENT))
@@ -29,7 +29,7 @@ impl RSDP {
         Err("Did not find RSDP")
     }
 
-    // TODO: Checksum validation
+    /// Check the signature, and the checksum of the ACPI 1.0 fields
     pub fn valid(&self) -> bool {
         if self.signature == SIGNATURE {
             let ptr = (self as *const Self) as *const u8;
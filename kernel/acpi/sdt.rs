@@ -18,15 +18,21 @@ pub struct SDTHeader {
 
 impl SDTHeader {
     pub fn valid(&self, signature: &str) -> bool {
-        if self.signature == signature.as_bytes() {
-            let ptr = (self as *const Self) as *const u8;
-            let sum: u8 = (0..self.length as isize)
-                .fold(0, |sum, i| sum + unsafe { ptr::read(ptr.offset(i)) });
+        self.signature == signature.as_bytes() && self.valid_checksum()
+    }
+
+    /// Do the bytes of the table, including the header, sum to zero
+    pub fn valid_checksum(&self) -> bool {
+        let ptr = (self as *const Self) as *const u8;
+        let sum: u8 = (0..self.length as isize)
+            .fold(0, |sum: u8, i| sum.wrapping_add(unsafe { ptr::read(ptr.offset(i)) }));
+
+        sum == 0
+    }
 
-            sum == 0
-        } else {
-            false
-        }
+    /// The bytes of the table, including the header
+    pub fn bytes(&self) -> &'static [u8] {
+        unsafe { slice::from_raw_parts((self as *const Self) as *const u8, self.length as usize) }
     }
 
     pub fn data<T>(&self) -> &'static [T] {
@@ -0,0 +1,231 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use super::aml::{resource, AmlValue, Interrupt, Namespace, PciRoute};
+use super::{SDTHeader, DSDT, SSDT};
+
+/// Parts of the tables of QEMU's i440FX machine, see `aml/fixtures/excerpt-dsdt.dsl` and
+/// `aml/fixtures/excerpt-ssdt.dsl`
+static EXCERPT_DSDT: &'static [u8] = include_bytes!("aml/fixtures/excerpt-dsdt.aml");
+static EXCERPT_SSDT: &'static [u8] = include_bytes!("aml/fixtures/excerpt-ssdt.aml");
+
+fn header(table: &'static [u8]) -> &'static SDTHeader {
+    unsafe { &*(table.as_ptr() as *const SDTHeader) }
+}
+
+/// Load a DSDT and SSDTs, like `Acpi::new` does
+fn load(dsdt: &'static [u8], ssdts: &[&'static [u8]]) -> Namespace {
+    let dsdt = DSDT::new(header(dsdt)).expect("invalid DSDT");
+
+    let mut namespace = Namespace::new(dsdt.header.revision);
+    namespace.load(dsdt.data).expect("failed to load DSDT");
+    for &ssdt in ssdts.iter() {
+        let ssdt = SSDT::new(header(ssdt)).expect("invalid SSDT");
+        namespace.load(ssdt.data).expect("failed to load SSDT");
+    }
+    namespace
+}
+
+fn excerpt() -> Namespace {
+    load(EXCERPT_DSDT, &[EXCERPT_SSDT])
+}
+
+/// Read a table from the fixtures, which is leaked so that it lives as long as the namespace
+fn read_fixture(path: &Path) -> Option<&'static [u8]> {
+    let mut data = Vec::new();
+    match File::open(path) {
+        Ok(mut file) => {
+            file.read_to_end(&mut data).expect("failed to read fixture");
+            Some(unsafe { &*Box::into_raw(data.into_boxed_slice()) })
+        },
+        Err(_) => None,
+    }
+}
+
+/// Load the tables that `make aml_fixtures` captured from a QEMU `machine`, or None if they were
+/// not captured
+fn captured(machine: &str) -> Option<Namespace> {
+    let fixtures = Path::new(file!()).parent().unwrap().join("aml/fixtures");
+    let fixture = |name: &str| -> PathBuf { fixtures.join(format!("qemu-{}-{}.aml", machine, name)) };
+
+    let dsdt = match read_fixture(&fixture("dsdt")) {
+        Some(dsdt) => dsdt,
+        None => {
+            println!("no tables were captured from QEMU's {} machine, run make aml_fixtures", machine);
+            return None;
+        }
+    };
+
+    // Later SSDTs get a number after their name, like in `acpi:tables`
+    let mut ssdts = Vec::new();
+    loop {
+        let name = if ssdts.is_empty() {
+            "ssdt".to_string()
+        } else {
+            format!("ssdt{}", ssdts.len())
+        };
+        match read_fixture(&fixture(&name)) {
+            Some(ssdt) => ssdts.push(ssdt),
+            None => break,
+        }
+    }
+
+    Some(load(dsdt, &ssdts))
+}
+
+fn integers(value: AmlValue) -> Vec<u64> {
+    match value {
+        AmlValue::Package(elements) => elements.iter().map(|element| element.to_integer().unwrap()).collect(),
+        value => panic!("not a package: {:?}", value),
+    }
+}
+
+#[test]
+fn sleep_states() {
+    let mut namespace = excerpt();
+    assert_eq!(integers(namespace.evaluate("\\_S3_", Vec::new()).unwrap()), vec![1, 1, 0, 0]);
+    assert_eq!(integers(namespace.evaluate("\\_S4_", Vec::new()).unwrap()), vec![2, 2, 0, 0]);
+    assert_eq!(integers(namespace.evaluate("\\_S5_", Vec::new()).unwrap()), vec![0, 0, 0, 0]);
+}
+
+#[test]
+fn pci_routing() {
+    let mut namespace = excerpt();
+    let routes = match namespace.evaluate("\\_SB_.PCI0._PRT", Vec::new()).unwrap() {
+        AmlValue::Package(routes) => routes,
+        value => panic!("not a package: {:?}", value),
+    };
+    assert_eq!(routes.len(), 128);
+
+    // The address, pin and link of the routes of a few slots and pins
+    let expected = [(0, 0xFFFF, 0, "\\_SB_.LNKD"),
+                    (4, 0x1FFFF, 0, "\\_SB_.LNKS"),
+                    (5, 0x1FFFF, 1, "\\_SB_.LNKB"),
+                    (127, 0x1FFFFF, 3, "\\_SB_.LNKB")];
+    for &(i, address, pin, link) in expected.iter() {
+        match routes[i] {
+            AmlValue::Package(ref route) => {
+                assert_eq!(route.len(), 4);
+                assert_eq!(route[0].to_integer().unwrap(), address);
+                assert_eq!(route[1].to_integer().unwrap(), pin);
+                match route[2] {
+                    AmlValue::Reference(ref path) => assert_eq!(path, link),
+                    ref value => panic!("route {}: not a reference: {:?}", i, value),
+                }
+                assert_eq!(route[3].to_integer().unwrap(), 0);
+            },
+            ref value => panic!("route {}: not a package: {:?}", i, value),
+        }
+    }
+
+    // The links of the excerpt have no _CRS, so every pin is left out
+    assert_eq!(namespace.pci_routes().unwrap().len(), 0);
+}
+
+#[test]
+fn devices() {
+    let mut namespace = excerpt();
+
+    let devices = namespace.devices();
+    for device in ["\\_SB_.PCI0", "\\_SB_.PCI0.ISA_", "\\_SB_.PCI0.ISA_.RTC_", "\\_SB_.LNKA",
+                   "\\_SB_.LNKS"].iter() {
+        assert!(devices.contains(&String::from(*device)), "{} is missing", device);
+    }
+
+    // Without a _STA, devices are present, enabled and functioning
+    assert_eq!(namespace.device_status("\\_SB_.PCI0.ISA_.RTC_").unwrap(), 0xF);
+    let resources = namespace.device_resources("\\_SB_.PCI0.ISA_.RTC_").unwrap();
+    assert_eq!(resources, vec![0x47, 0x01, 0x70, 0x00, 0x70, 0x00, 0x10, 0x02, 0x22, 0x00, 0x01, 0x79, 0x00]);
+    assert_eq!(resource::interrupt(&resources), Some(Interrupt {
+        number: 8,
+        level: false,
+        active_low: false,
+    }));
+
+    match namespace.evaluate("\\_SB_.PCI0._HID", Vec::new()).unwrap() {
+        AmlValue::Integer(id) => assert_eq!(id, 0x030AD041),
+        value => panic!("not an integer: {:?}", value),
+    }
+}
+
+#[test]
+fn resource_interrupts() {
+    // Interrupt (ResourceConsumer, Level, ActiveHigh, Shared) {0x10, 0x11}, like the GSI links of
+    // QEMU's Q35 machine
+    let extended = [0x89, 0x0A, 0x00, 0x09, 0x02, 0x10, 0x00, 0x00, 0x00, 0x11, 0x00, 0x00, 0x00, 0x79, 0x00];
+    assert_eq!(resource::interrupt(&extended), Some(Interrupt {
+        number: 0x10,
+        level: true,
+        active_low: false,
+    }));
+
+    // IRQ (Level, ActiveLow, Shared) {5, 10}
+    let irq = [0x23, 0x20, 0x04, 0x18, 0x79, 0x00];
+    assert_eq!(resource::interrupt(&irq), Some(Interrupt {
+        number: 5,
+        level: true,
+        active_low: true,
+    }));
+
+    // A disabled link has an empty mask, and a template may have no interrupt at all
+    assert_eq!(resource::interrupt(&[0x22, 0x00, 0x00, 0x79, 0x00]), None);
+    assert_eq!(resource::interrupt(&[0x47, 0x01, 0x70, 0x00, 0x70, 0x00, 0x10, 0x02, 0x79, 0x00]), None);
+
+    // Truncated templates are not read past their end
+    assert_eq!(resource::interrupt(&[0x89, 0x0A, 0x00, 0x09, 0x01, 0x10]), None);
+    assert_eq!(resource::interrupt(&[0x23, 0x20]), None);
+}
+
+/// The checks that hold for the tables of any QEMU machine
+fn check_captured(namespace: &mut Namespace) -> Vec<PciRoute> {
+    let s5 = integers(namespace.evaluate("\\_S5_", Vec::new()).expect("no \\_S5"));
+    assert!(s5.len() >= 2, "\\_S5 is too short: {:?}", s5);
+
+    assert!(namespace.devices().contains(&String::from("\\_SB_.PCI0")), "no \\_SB.PCI0");
+    assert_eq!(namespace.device_status("\\_SB_.PCI0").unwrap() & 1, 1);
+
+    if namespace.get("\\_PIC").is_some() {
+        namespace.evaluate("\\_PIC", vec![AmlValue::Integer(1)]).expect("\\_PIC failed");
+    }
+
+    let routes = namespace.pci_routes().expect("no PCI routes");
+    assert!(! routes.is_empty());
+    for route in routes.iter() {
+        assert!(route.slot < 32 && route.pin < 4, "invalid route: {:?}", route);
+    }
+    routes
+}
+
+#[test]
+fn captured_i440fx() {
+    let mut namespace = match captured("pc") {
+        Some(namespace) => namespace,
+        None => return,
+    };
+
+    // SeaBIOS routes PIRQA to PIRQD to IRQs 10, 10, 11 and 11 in the PIIX3 at 00:01.0
+    unsafe { ::drivers::pci::config::PciConfig::new(0, 1, 0).write(0x60, 0x0B0B0A0A) };
+
+    let routes = check_captured(&mut namespace);
+    for route in routes.iter() {
+        assert!(route.interrupt.number < 16, "not an ISA IRQ: {:?}", route);
+    }
+    assert!(routes.iter().any(|route| route.interrupt.number == 10));
+    assert!(routes.iter().any(|route| route.interrupt.number == 11));
+}
+
+#[test]
+fn captured_q35() {
+    let mut namespace = match captured("q35") {
+        Some(namespace) => namespace,
+        None => return,
+    };
+
+    // With the APIC, the pins are wired to the GSI links, which use GSIs 16 to 23
+    let routes = check_captured(&mut namespace);
+    for route in routes.iter() {
+        assert!(route.interrupt.number >= 16 && route.interrupt.number < 24, "not a PCI GSI: {:?}", route);
+        assert!(route.interrupt.level, "not level triggered: {:?}", route);
+    }
+}
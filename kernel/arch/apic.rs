@@ -0,0 +1,486 @@
+use acpi::MADT;
+
+use core::{cmp, u32};
+
+use drivers::io::{Io, Mmio, Pio};
+
+/// The local APIC registers
+const LAPIC_ID: usize = 0x20;
+const LAPIC_TPR: usize = 0x80;
+const LAPIC_EOI: usize = 0xB0;
+const LAPIC_SVR: usize = 0xF0;
+const LAPIC_ICR_LOW: usize = 0x300;
+const LAPIC_ICR_HIGH: usize = 0x310;
+const LAPIC_LVT_TIMER: usize = 0x320;
+const LAPIC_TIMER_INITIAL: usize = 0x380;
+const LAPIC_TIMER_CURRENT: usize = 0x390;
+const LAPIC_TIMER_DIVIDE: usize = 0x3E0;
+
+/// Interrupt command flags
+const ICR_INIT: u32 = 0b101 << 8;
+const ICR_STARTUP: u32 = 0b110 << 8;
+const ICR_PENDING: u32 = 1 << 12;
+const ICR_ASSERT: u32 = 1 << 14;
+const ICR_LEVEL: u32 = 1 << 15;
+const ICR_OTHERS: u32 = 0b11 << 18;
+
+/// Local vector table flags
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_PERIODIC: u32 = 1 << 17;
+
+/// Divide the bus clock by 16 for the timer
+const TIMER_DIVIDE_16: u32 = 0b0011;
+
+/// The IOAPIC registers, which are selected by writing to IOREGSEL and accessed through IOWIN
+const IOREGSEL: usize = 0x00;
+const IOWIN: usize = 0x10;
+const IOAPIC_VER: u32 = 0x01;
+const IOAPIC_REDIRECTION: u32 = 0x10;
+
+/// Redirection entry flags
+pub const REDIRECTION_ACTIVE_LOW: u32 = 1 << 13;
+pub const REDIRECTION_LEVEL: u32 = 1 << 15;
+pub const REDIRECTION_MASKED: u32 = 1 << 16;
+
+/// The PIT divider, as set up in `asm/initialize.asm`
+const PIT_DIVIDER: u16 = 5370;
+/// The PIT interval in nanoseconds, which the timer count is calibrated to
+const PIT_NANOS: u64 = 4500572;
+
+/// The vector of the local APIC timer
+pub const TIMER_VECTOR: u8 = 0x30;
+/// The vectors of message signalled interrupts, which are delivered as IRQ `vector - 0x20`
+pub const MSI_VECTOR_FIRST: u8 = 0x50;
+pub const MSI_VECTOR_LAST: u8 = 0x7F;
+/// The vector of spurious interrupts, which do not need an end of interrupt
+pub const SPURIOUS_VECTOR: u8 = 0x4F;
+
+/// The most IOAPICs that are used
+const MAX_IO_APICS: usize = 8;
+
+/// The local APIC, which is at the same address for every processor
+pub static mut LOCAL_APIC: Option<LocalApic> = None;
+
+/// The IOAPICs from the MADT
+static mut IO_APICS: [Option<IoApic>; MAX_IO_APICS] = [None; MAX_IO_APICS];
+
+/// True if IRQs are delivered by the IOAPICs instead of the PIC
+static mut IRQ_IO_APIC: bool = false;
+
+/// The GSI that each ISA IRQ is routed from, if it was routed through an IOAPIC
+static mut IRQ_GSI: [Option<u32>; 16] = [None; 16];
+
+/// The local APIC timer count for one PIT interval, or 0 if the PIT is the timer
+static mut TIMER_COUNT: u32 = 0;
+
+/// The allocated vectors for message signalled interrupts, bit `n` stands for
+/// `MSI_VECTOR_FIRST + n`
+static mut MSI_VECTORS: u64 = 0;
+
+/// Message signalled interrupts are written to this address, with the APIC ID in bits 12 to 19
+const MSI_ADDRESS: u32 = 0xFEE00000;
+
+/// The local APIC (advanced programmable interrupt controller) of a processor
+///
+/// Every processor accesses its own local APIC at the same address.
+pub struct LocalApic {
+    pub address: usize,
+}
+
+impl LocalApic {
+    pub fn new(address: usize) -> LocalApic {
+        LocalApic {
+            address: address
+        }
+    }
+
+    unsafe fn read(&self, reg: usize) -> u32 {
+        (&*((self.address + reg) as *const Mmio<u32>)).read()
+    }
+
+    unsafe fn write(&mut self, reg: usize, value: u32) {
+        (&mut *((self.address + reg) as *mut Mmio<u32>)).write(value);
+    }
+
+    /// Enable the local APIC of this processor, and accept all interrupts
+    pub unsafe fn enable(&mut self) {
+        let svr = self.read(LAPIC_SVR);
+        self.write(LAPIC_SVR, svr | 1 << 8 | SPURIOUS_VECTOR as u32);
+        self.write(LAPIC_TPR, 0);
+    }
+
+    /// The APIC ID of this processor
+    pub unsafe fn id(&self) -> u8 {
+        (self.read(LAPIC_ID) >> 24) as u8
+    }
+
+    /// Signal the end of an interrupt that was delivered by the local APIC
+    pub unsafe fn eoi(&mut self) {
+        self.write(LAPIC_EOI, 0);
+    }
+
+    /// Send an interprocessor interrupt, and wait for it to be accepted
+    unsafe fn ipi(&mut self, apic_id: u8, command: u32) {
+        self.write(LAPIC_ICR_HIGH, (apic_id as u32) << 24);
+        self.write(LAPIC_ICR_LOW, command);
+        while self.read(LAPIC_ICR_LOW) & ICR_PENDING == ICR_PENDING {}
+    }
+
+    /// Reset a processor, so that it waits for a startup IPI
+    pub unsafe fn ipi_init(&mut self, apic_id: u8) {
+        self.ipi(apic_id, ICR_INIT | ICR_ASSERT | ICR_LEVEL);
+        self.ipi(apic_id, ICR_INIT | ICR_LEVEL);
+    }
+
+    /// Start a processor in real mode at `page`, which must be 4 KiB aligned and below 1 MiB
+    pub unsafe fn ipi_startup(&mut self, apic_id: u8, page: usize) {
+        self.ipi(apic_id, ICR_STARTUP | ICR_ASSERT | (page >> 12) as u32);
+    }
+
+    /// Send `vector` to every processor but this one
+    pub unsafe fn ipi_others(&mut self, vector: u8) {
+        self.ipi(0, ICR_OTHERS | ICR_ASSERT | vector as u32);
+    }
+
+    /// Count the timer ticks in one PIT interval
+    ///
+    /// The interval is measured with channel 2 of the PIT, which is not connected to an IRQ.
+    pub unsafe fn calibrate_timer(&mut self) -> u32 {
+        let mut gate = Pio::<u8>::new(0x61);
+        let mut command = Pio::<u8>::new(0x43);
+        let mut data = Pio::<u8>::new(0x42);
+
+        // Stop channel 2, and keep the speaker off
+        let value = gate.read() & 0xFC;
+        gate.write(value);
+
+        // Channel 2, low byte then high byte, interrupt on terminal count
+        command.write(0xB0);
+        data.write(PIT_DIVIDER as u8);
+        data.write((PIT_DIVIDER >> 8) as u8);
+
+        self.write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_16);
+        self.write(LAPIC_LVT_TIMER, LVT_MASKED | TIMER_VECTOR as u32);
+
+        gate.write(value | 1);
+        self.write(LAPIC_TIMER_INITIAL, 0xFFFFFFFF);
+        while gate.read() & 1 << 5 == 0 {}
+        let current = self.read(LAPIC_TIMER_CURRENT);
+
+        self.write(LAPIC_TIMER_INITIAL, 0);
+        gate.write(value);
+
+        0xFFFFFFFF - current
+    }
+
+    /// Interrupt this processor with `TIMER_VECTOR` every `count` timer ticks
+    pub unsafe fn start_timer(&mut self, count: u32) {
+        self.write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_16);
+        self.write(LAPIC_LVT_TIMER, LVT_PERIODIC | TIMER_VECTOR as u32);
+        self.write(LAPIC_TIMER_INITIAL, count);
+    }
+
+    /// Interrupt this processor with `TIMER_VECTOR` once, after `count` timer ticks
+    pub unsafe fn oneshot_timer(&mut self, count: u32) {
+        self.write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_16);
+        self.write(LAPIC_LVT_TIMER, TIMER_VECTOR as u32);
+        self.write(LAPIC_TIMER_INITIAL, count);
+    }
+
+    /// Stop the timer of this processor
+    pub unsafe fn stop_timer(&mut self) {
+        self.write(LAPIC_LVT_TIMER, LVT_MASKED | TIMER_VECTOR as u32);
+        self.write(LAPIC_TIMER_INITIAL, 0);
+    }
+}
+
+/// An IOAPIC, which delivers the interrupts from a range of global system interrupts (GSIs)
+#[derive(Clone, Copy, Debug)]
+pub struct IoApic {
+    pub address: usize,
+    pub gsi_base: u32,
+    pub count: u32,
+}
+
+impl IoApic {
+    pub unsafe fn new(address: usize, gsi_base: u32) -> IoApic {
+        let mut io_apic = IoApic {
+            address: address,
+            gsi_base: gsi_base,
+            count: 0,
+        };
+        io_apic.count = (io_apic.read(IOAPIC_VER) >> 16 & 0xFF) + 1;
+        io_apic
+    }
+
+    unsafe fn read(&mut self, reg: u32) -> u32 {
+        (&mut *((self.address + IOREGSEL) as *mut Mmio<u32>)).write(reg);
+        (&*((self.address + IOWIN) as *const Mmio<u32>)).read()
+    }
+
+    unsafe fn write(&mut self, reg: u32, value: u32) {
+        (&mut *((self.address + IOREGSEL) as *mut Mmio<u32>)).write(reg);
+        (&mut *((self.address + IOWIN) as *mut Mmio<u32>)).write(value);
+    }
+
+    /// Does this IOAPIC deliver `gsi`
+    pub fn handles(&self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi < self.gsi_base + self.count
+    }
+
+    /// Deliver `gsi` to the processor with `apic_id` as `vector`, with the redirection `flags`
+    pub unsafe fn route(&mut self, gsi: u32, vector: u8, apic_id: u8, flags: u32) {
+        let reg = IOAPIC_REDIRECTION + (gsi - self.gsi_base) * 2;
+        self.write(reg, REDIRECTION_MASKED);
+        self.write(reg + 1, (apic_id as u32) << 24);
+        self.write(reg, flags | vector as u32);
+    }
+
+    /// The vector that `gsi` is delivered as
+    pub unsafe fn vector(&mut self, gsi: u32) -> u8 {
+        self.read(IOAPIC_REDIRECTION + (gsi - self.gsi_base) * 2) as u8
+    }
+
+    /// Mask `gsi` and clear its vector, so that it is not delivered as any IRQ
+    pub unsafe fn clear(&mut self, gsi: u32) {
+        self.write(IOAPIC_REDIRECTION + (gsi - self.gsi_base) * 2, REDIRECTION_MASKED);
+    }
+
+    /// Mask or unmask `gsi`, keeping the rest of its redirection entry
+    pub unsafe fn set_masked(&mut self, gsi: u32, masked: bool) {
+        let reg = IOAPIC_REDIRECTION + (gsi - self.gsi_base) * 2;
+        let value = self.read(reg);
+        if masked {
+            self.write(reg, value | REDIRECTION_MASKED);
+        } else {
+            self.write(reg, value & ! REDIRECTION_MASKED);
+        }
+    }
+}
+
+/// Enable the local APIC and its timer, and route the ISA IRQs through the IOAPICs in the MADT
+///
+/// IRQ `n` is still delivered as interrupt `0x20 + n`, and IRQs go to the bootstrap processor.
+/// The PIC is only used if there is no IOAPIC. PCI interrupts may be wired to other GSIs, which
+/// `route_irq` adds afterwards. The local APIC timer replaces IRQ 0, and is calibrated to tick as
+/// often as the PIT did.
+pub unsafe fn init(madt: &MADT) {
+    let mut local_apic = LocalApic::new(madt.local_apic_address as usize);
+    local_apic.enable();
+    let apic_id = local_apic.id();
+
+    for (entry, io_apic) in madt.io_apics.iter().zip(IO_APICS.iter_mut()) {
+        *io_apic = Some(IoApic::new(entry.address as usize, entry.gsi_base));
+    }
+
+    if IO_APICS[0].is_some() {
+        // IRQ 0 is the PIT and IRQ 2 is the cascade from the second PIC, so neither is routed
+        for irq in 1..16 {
+            if irq == 2 {
+                continue;
+            }
+
+            // ISA IRQs are identity mapped, edge triggered and active high, unless overridden
+            let mut gsi = irq as u32;
+            let mut flags = 0;
+            for source_override in madt.int_source_overrides.iter() {
+                if source_override.bus_source == 0 && source_override.irq_source == irq {
+                    gsi = source_override.gsi;
+                    if source_override.flags & 0b11 == 0b11 {
+                        flags |= REDIRECTION_ACTIVE_LOW;
+                    }
+                    if source_override.flags >> 2 & 0b11 == 0b11 {
+                        flags |= REDIRECTION_LEVEL;
+                    }
+                }
+            }
+
+            match IO_APICS.iter_mut().filter_map(|io_apic| io_apic.as_mut()).find(|io_apic| io_apic.handles(gsi)) {
+                Some(io_apic) => {
+                    io_apic.route(gsi, 0x20 + irq, apic_id, flags);
+                    IRQ_GSI[irq as usize] = Some(gsi);
+                },
+                None => syslog_info!("  * APIC: no IOAPIC for IRQ {} at GSI {}", irq, gsi),
+            }
+        }
+
+        // Mask every IRQ on the PIC
+        Pio::<u8>::new(0x21).write(0xFF);
+        Pio::<u8>::new(0xA1).write(0xFF);
+
+        IRQ_IO_APIC = true;
+    } else {
+        // Mask IRQ 0 on the PIC
+        let mut mask = Pio::<u8>::new(0x21);
+        let value = mask.read();
+        mask.write(value | 1);
+    }
+
+    TIMER_COUNT = local_apic.calibrate_timer();
+    local_apic.start_timer(TIMER_COUNT);
+
+    LOCAL_APIC = Some(local_apic);
+
+    syslog_info!("  * APIC: {} IOAPICs, timer count {}",
+                 IO_APICS.iter().filter(|io_apic| io_apic.is_some()).count(),
+                 TIMER_COUNT);
+}
+
+/// Enable the local APIC and its timer on an application processor, like `init` did on the
+/// bootstrap processor
+pub unsafe fn init_ap() {
+    if let Some(ref mut local_apic) = LOCAL_APIC {
+        local_apic.enable();
+        if TIMER_COUNT > 0 {
+            local_apic.start_timer(TIMER_COUNT);
+        }
+    }
+}
+
+/// Interrupt this processor with `TIMER_VECTOR` once, after `nanos` nanoseconds, instead of
+/// periodically. The timer is stopped if `nanos` is None.
+///
+/// Nothing changes if the local APIC timer is not used.
+pub unsafe fn oneshot_timer(nanos: Option<u64>) {
+    if TIMER_COUNT == 0 {
+        return;
+    }
+
+    if let Some(ref mut local_apic) = LOCAL_APIC {
+        match nanos {
+            Some(nanos) => {
+                let count = nanos.saturating_mul(TIMER_COUNT as u64) / PIT_NANOS;
+                local_apic.oneshot_timer(cmp::max(1, cmp::min(count, u32::MAX as u64)) as u32);
+            },
+            None => local_apic.stop_timer(),
+        }
+    }
+}
+
+/// True if IRQs are delivered by the IOAPICs, so that `route_irq` can be used
+pub fn io_apic_enabled() -> bool {
+    unsafe { IRQ_IO_APIC }
+}
+
+/// Deliver `gsi` as `irq` too, with the redirection `flags`
+///
+/// This is used for PCI interrupts, which are usually level triggered and active low, and may be
+/// wired to GSIs above 15. Several of them may share an IRQ. The firmware only gives PCI devices
+/// IRQs that no ISA device uses, so the ISA route of `irq` is cleared if it came from another
+/// GSI. Returns false if `gsi` could not be routed.
+pub unsafe fn route_irq(irq: u8, gsi: u32, flags: u32) -> bool {
+    if ! IRQ_IO_APIC || irq == 0 || irq >= 16 {
+        return false;
+    }
+
+    let apic_id = match LOCAL_APIC {
+        Some(ref local_apic) => local_apic.id(),
+        None => return false,
+    };
+
+    for isa_irq in 0..16 {
+        if let Some(isa_gsi) = IRQ_GSI[isa_irq] {
+            if (isa_irq == irq as usize && isa_gsi != gsi) || (isa_irq != irq as usize && isa_gsi == gsi) {
+                if let Some(io_apic) = IO_APICS.iter_mut().filter_map(|io_apic| io_apic.as_mut()).find(|io_apic| io_apic.handles(isa_gsi)) {
+                    io_apic.clear(isa_gsi);
+                }
+                IRQ_GSI[isa_irq] = None;
+            }
+        }
+    }
+
+    match IO_APICS.iter_mut().filter_map(|io_apic| io_apic.as_mut()).find(|io_apic| io_apic.handles(gsi)) {
+        Some(io_apic) => {
+            io_apic.route(gsi, 0x20 + irq, apic_id, flags);
+            true
+        },
+        None => false,
+    }
+}
+
+/// Allocate a vector for a message signalled interrupt to this processor
+///
+/// Returns the message address and vector, or None if the local APIC is not used or every vector
+/// was allocated. The vector is given back with `free_msi`.
+pub unsafe fn alloc_msi() -> Option<(u32, u8)> {
+    let apic_id = match LOCAL_APIC {
+        Some(ref local_apic) => local_apic.id(),
+        None => return None,
+    };
+
+    for i in 0..MSI_VECTOR_LAST - MSI_VECTOR_FIRST + 1 {
+        if MSI_VECTORS & 1 << i == 0 {
+            MSI_VECTORS |= 1 << i;
+            return Some((MSI_ADDRESS | (apic_id as u32) << 12, MSI_VECTOR_FIRST + i));
+        }
+    }
+
+    None
+}
+
+/// Free a vector that `alloc_msi` allocated, after the function no longer sends it
+pub unsafe fn free_msi(vector: u8) {
+    if vector >= MSI_VECTOR_FIRST && vector <= MSI_VECTOR_LAST {
+        MSI_VECTORS &= ! (1 << (vector - MSI_VECTOR_FIRST));
+    }
+}
+
+/// Signal the end of an interrupt that was delivered by the local APIC
+pub unsafe fn eoi() {
+    if let Some(ref mut local_apic) = LOCAL_APIC {
+        local_apic.eoi();
+    }
+}
+
+/// Signal the end of `irq`, to the PIC or the local APIC
+pub unsafe fn irq_eoi(irq: u8) {
+    if IRQ_IO_APIC {
+        eoi();
+    } else {
+        if irq >= 8 {
+            Pio::<u8>::new(0xA0).write(0x20);
+        }
+
+        Pio::<u8>::new(0x20).write(0x20);
+    }
+}
+
+/// Mask or unmask `irq`, on the IOAPICs that route it or on the PIC
+///
+/// Only IRQs below 16 can be masked. Message signalled interrupts are edge triggered, so they do
+/// not fire again while they are handled.
+pub unsafe fn set_irq_masked(irq: u8, masked: bool) {
+    if irq >= 16 {
+        return;
+    }
+
+    if IRQ_IO_APIC {
+        // Shared PCI interrupts route several GSIs to the same vector
+        for io_apic in IO_APICS.iter_mut().filter_map(|io_apic| io_apic.as_mut()) {
+            for gsi in io_apic.gsi_base..io_apic.gsi_base + io_apic.count {
+                if io_apic.vector(gsi) == 0x20 + irq {
+                    io_apic.set_masked(gsi, masked);
+                }
+            }
+        }
+    } else {
+        let mut mask = Pio::<u8>::new(if irq < 8 { 0x21 } else { 0xA1 });
+        let bit = 1 << (irq & 7);
+        let value = mask.read();
+        if masked {
+            mask.write(value | bit);
+        } else {
+            mask.write(value & ! bit);
+        }
+    }
+}
+
+/// Wait for about `micros` microseconds
+///
+/// Each write to the POST port takes about a microsecond. This is meant for the small delays
+/// that hardware needs before the timer runs.
+pub fn delay(micros: usize) {
+    for _ in 0..micros {
+        Pio::<u8>::new(0x80).write(0);
+    }
+}
@@ -0,0 +1,188 @@
+use acpi::HPET;
+
+use arch::apic;
+use arch::hpet::Hpet;
+use arch::smp::{self, MAX_CPUS};
+
+use common::time::{Duration, NANOS_PER_SEC};
+
+/// The longest a context runs before the timer interrupts it, when the timer is not periodic.
+/// This is the PIT interval, which the periodic timers tick at.
+const TIME_SLICE: Duration = Duration {
+    secs: 0,
+    nanos: 4500572,
+};
+
+/// The TSC is calibrated by counting its ticks in 1 / CALIBRATION_DIVIDER seconds of the HPET
+const CALIBRATION_DIVIDER: u64 = 100;
+
+/// The counter that the monotonic clock is read from
+#[derive(Clone, Copy)]
+enum Counter {
+    /// The time stamp counter of the processor, with its frequency in hertz
+    Tsc(u64),
+    /// The main counter of the HPET
+    Hpet,
+}
+
+/// The HPET from ACPI, if it can be used
+static mut HPET_TIMER: Option<Hpet> = None;
+
+/// The counter of the monotonic clock, with the counter value and the monotonic time when it
+/// took over from the timer ticks
+static mut COUNTER: Option<(Counter, u64, Duration)> = None;
+
+/// The last time the running context of each processor was charged for, or switched to
+static mut CHARGED: [Duration; MAX_CPUS] = [Duration { secs: 0, nanos: 0 }; MAX_CPUS];
+
+/// Run `cpuid` for `leaf`, returning EAX, EBX, ECX and EDX
+unsafe fn cpuid(leaf: u32) -> (u32, u32, u32, u32) {
+    let (a, b, c, d): (u32, u32, u32, u32);
+    asm!("cpuid"
+        : "={eax}"(a), "={ebx}"(b), "={ecx}"(c), "={edx}"(d)
+        : "{eax}"(leaf), "{ecx}"(0)
+        :
+        : "intel", "volatile");
+    (a, b, c, d)
+}
+
+/// Read the time stamp counter
+fn rdtsc() -> u64 {
+    let (low, high): (u32, u32);
+    unsafe { asm!("rdtsc" : "={eax}"(low), "={edx}"(high) : : : "intel", "volatile"); }
+    (high as u64) << 32 | low as u64
+}
+
+/// Does the time stamp counter run at a constant rate in every power state
+///
+/// Only then can it measure time, and it is synchronized between the processors.
+fn invariant_tsc() -> bool {
+    unsafe {
+        cpuid(0x80000000).0 >= 0x80000007 && cpuid(0x80000007).3 & 1 << 8 == 1 << 8
+    }
+}
+
+/// Convert `ticks` of a counter at `frequency` hertz to a duration
+fn ticks_duration(ticks: u64, frequency: u64) -> Duration {
+    let secs = ticks / frequency;
+    let nanos = (ticks % frequency) * NANOS_PER_SEC as u64 / frequency;
+    Duration::new(secs as i64, nanos as i32)
+}
+
+/// Find the frequency of the time stamp counter in hertz, by comparing it to the HPET
+unsafe fn calibrate_tsc(hpet: &Hpet) -> u64 {
+    let hpet_start = hpet.counter();
+    let tsc_start = rdtsc();
+
+    loop {
+        let hpet_ticks = hpet.ticks_since(hpet_start);
+        let tsc_ticks = rdtsc().wrapping_sub(tsc_start);
+        if hpet_ticks >= hpet.frequency / CALIBRATION_DIVIDER {
+            return tsc_ticks * hpet.frequency / hpet_ticks;
+        }
+    }
+}
+
+/// Start the high resolution monotonic clock, with the HPET from ACPI
+///
+/// The clock is read from the time stamp counter if it is invariant, after calibrating it
+/// against the HPET. Otherwise the HPET counter is read, if it is 64 bits wide. If neither can
+/// be used, the clock keeps advancing with the timer ticks.
+pub unsafe fn init(table: &HPET) {
+    let hpet = match Hpet::new(table) {
+        Some(hpet) => hpet,
+        None => {
+            syslog_info!("  * Clock: HPET is not usable");
+            return;
+        }
+    };
+
+    let counter = if invariant_tsc() {
+        Counter::Tsc(calibrate_tsc(&hpet))
+    } else if hpet.wide {
+        Counter::Hpet
+    } else {
+        syslog_info!("  * Clock: no invariant TSC, and the HPET counter is 32 bits");
+        return;
+    };
+
+    HPET_TIMER = Some(hpet);
+
+    let start = match counter {
+        Counter::Tsc(_) => rdtsc(),
+        Counter::Hpet => HPET_TIMER.as_ref().map_or(0, |hpet| hpet.counter()),
+    };
+    let base = *::env().clock_monotonic.get();
+    COUNTER = Some((counter, start, base));
+    for charged in CHARGED.iter_mut() {
+        *charged = base;
+    }
+
+    match counter {
+        Counter::Tsc(frequency) => syslog_info!("  * Clock: TSC at {} kHz", frequency / 1000),
+        Counter::Hpet => syslog_info!("  * Clock: HPET at {} kHz", HPET_TIMER.as_ref().map_or(0, |hpet| hpet.frequency) / 1000),
+    }
+}
+
+/// Read the high resolution monotonic clock, or None if it was not started
+pub fn monotonic() -> Option<Duration> {
+    unsafe {
+        match COUNTER {
+            Some((Counter::Tsc(frequency), start, base)) => {
+                Some(base + ticks_duration(rdtsc().saturating_sub(start), frequency))
+            },
+            Some((Counter::Hpet, start, base)) => match HPET_TIMER {
+                Some(ref hpet) => Some(base + ticks_duration(hpet.ticks_since(start), hpet.frequency)),
+                None => None,
+            },
+            None => None,
+        }
+    }
+}
+
+/// The time the running context of this processor ran since it was last charged or switched
+/// to, which is charged to it now
+///
+/// Returns None without the high resolution clock, when every tick is charged as one interval.
+pub unsafe fn charge() -> Option<Duration> {
+    monotonic().map(|now| {
+        let charged = &mut CHARGED[smp::cpu_id()];
+        let duration = now - *charged;
+        *charged = now;
+        duration
+    })
+}
+
+/// Set the timer of this processor for a context switch, when the clock does not need ticks
+///
+/// The timer fires at `wake`, the next time a sleeping context wakes up. If the processor is
+/// not idle, it fires at the end of the time slice instead, if that comes earlier. The timer
+/// does not fire when the idle context has nothing to wait for, as interrupts and `IPI_WAKE`
+/// end the halt.
+pub unsafe fn set_timer(wake: Option<Duration>, idle: bool) {
+    let now = match monotonic() {
+        Some(now) => now,
+        None => return,
+    };
+
+    CHARGED[smp::cpu_id()] = now;
+
+    let deadline = if idle {
+        wake
+    } else {
+        let end = now + TIME_SLICE;
+        match wake {
+            Some(wake) if wake < end => Some(wake),
+            _ => Some(end),
+        }
+    };
+
+    apic::oneshot_timer(deadline.map(|deadline| {
+        let duration = deadline - now;
+        if duration.secs < 0 || (duration.secs == 0 && duration.nanos <= 0) {
+            0
+        } else {
+            duration.secs as u64 * NANOS_PER_SEC as u64 + duration.nanos as u64
+        }
+    }));
+}
@@ -3,35 +3,43 @@ use common::slice::GetSlice;
 use alloc::arc::Arc;
 use alloc::boxed::{Box, FnBox};
 
+use arch::clock;
 use arch::gdt::GDT_USER_TLS;
 use arch::memory;
-use arch::paging::Page;
+use arch::paging::{Page, PF_WRITE};
 use arch::regs::Regs;
+use arch::smp::{self, MAX_CPUS};
 
+use collections::BTreeMap;
 use collections::borrow::Cow;
 use collections::string::{String, ToString};
 use collections::vec::Vec;
 
-use common::time::Duration;
+use common::time::{Duration, NANOS_PER_SEC};
 
-use core::cell::UnsafeCell;
+use core::cell::{Cell, UnsafeCell};
 use core::slice::{self, Iter, IterMut};
 use core::{mem, ptr};
-use core::ops::DerefMut;
+use core::ops::{Deref, DerefMut};
 
 use fs::Resource;
 
 use syscall;
 
-use system::error::{Error, Result, EBADF, EFAULT, ENOMEM, ESRCH, ENOENT, EINVAL};
+use system::error::{Error, Result, EAGAIN, EBADF, EFAULT, EMFILE, ENOMEM, ESRCH, ENOENT, EINVAL};
+use system::syscall::{RLimit, SigAction, NSIG, RLIMIT_AS, RLIMIT_CPU, RLIMIT_NLIMITS, RLIMIT_NOFILE,
+                      RLIMIT_NPROC, RLIM_INFINITY, SIGCONT, SIGKILL, SIGSTOP, SIGTSTP, SIGTTIN, SIGTTOU, SIGXCPU};
 
-use sync::WaitMap;
+use sync::{WaitCondition, WaitMap};
 
 pub const CONTEXT_FX_SIZE: usize = memory::CLUSTER_SIZE;
 
 pub const CONTEXT_IMAGE_ADDR: usize = 0x8048000;
 pub const CONTEXT_IMAGE_SIZE: usize = 0x10000000;
 
+/// The program interpreter of a dynamically linked executable is loaded in the upper half of the image
+pub const CONTEXT_INTERP_ADDR: usize = CONTEXT_IMAGE_ADDR + CONTEXT_IMAGE_SIZE / 2;
+
 pub const CONTEXT_HEAP_ADDR: usize = CONTEXT_IMAGE_ADDR + CONTEXT_IMAGE_SIZE + memory::CLUSTER_SIZE;
 pub const CONTEXT_HEAP_SIZE: usize = 0x40000000;
 
@@ -43,11 +51,55 @@ pub const CONTEXT_STACK_SIZE: usize = 0x100000;
 
 pub const CONTEXT_TLS_ADDR: usize = CONTEXT_STACK_ADDR + CONTEXT_STACK_SIZE + memory::CLUSTER_SIZE;
 
+/// The words that the interrupt handler keeps below the registers on the kernel stack: the return
+/// pointer, the arguments of `kernel`, and the interrupt number for the return path
+#[cfg(target_arch = "x86")]
+const INTERRUPT_EXTRA: usize = 4;
+
+/// The words that the interrupt handler keeps below the registers on the kernel stack: the return
+/// pointer, the reg pointer, and the interrupt number for the return path. The arguments of
+/// `kernel` are passed in registers
+#[cfg(target_arch = "x86_64")]
+const INTERRUPT_EXTRA: usize = 3;
+
+/// The lowest nice value, which has the highest priority
+pub const NICE_MIN: isize = -20;
+/// The highest nice value, which has the lowest priority
+pub const NICE_MAX: isize = 19;
+
+/// The weight of each nice value from `NICE_MIN` to `NICE_MAX`. Every step is worth about 10% of
+/// processor time when competing with another context.
+const NICE_WEIGHTS: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291,
+    29154, 23254, 18705, 14949, 11916,
+    9548, 7620, 6100, 4904, 3906,
+    3121, 2501, 1991, 1586, 1277,
+    1024, 820, 655, 526, 423,
+    335, 272, 215, 172, 137,
+    110, 87, 70, 56, 45,
+    36, 29, 23, 18, 15,
+];
+
+/// How far behind the run queue a context may fall while blocked, in nanoseconds of virtual time.
+/// Contexts that wake up are scheduled ahead of busy ones, but cannot make up for all of the time
+/// they were blocked.
+const SLEEPER_CREDIT: u64 = 20000000;
+
 pub struct ContextManager {
     pub inner: Vec<Box<Context>>,
     pub enabled: bool,
-    pub i: usize,
+    /// The index of the context that each processor is running
+    pub running: [usize; MAX_CPUS],
+    /// The idle context of each processor, null for processors that were not started
+    idle: [*mut Context; MAX_CPUS],
     pub next_pid: usize,
+    /// Runnable contexts, ordered by virtual time and PID. The running context is not queued
+    run_queue: BTreeMap<(u64, usize), *mut Context>,
+    /// Sleeping contexts, ordered by wake time and PID. Entries for contexts that were woken
+    /// early are removed lazily
+    sleepers: BTreeMap<(i64, i32, usize), *mut Context>,
+    /// The virtual time of the last context that was picked to run
+    min_vtime: u64,
 }
 
 impl ContextManager {
@@ -55,20 +107,32 @@ impl ContextManager {
         ContextManager {
             inner: Vec::new(),
             enabled: false,
-            i: 0,
+            running: [0; MAX_CPUS],
+            idle: [ptr::null_mut(); MAX_CPUS],
             next_pid: 1,
+            run_queue: BTreeMap::new(),
+            sleepers: BTreeMap::new(),
+            min_vtime: 0,
         }
     }
 
     pub fn current(&self) -> Result<&Box<Context>> {
-        self.get(self.i)
+        self.running(smp::cpu_id())
     }
 
     pub fn current_mut(&mut self) -> Result<&mut Box<Context>> {
-        let i = self.i;
+        let i = self.running[smp::cpu_id()];
         self.get_mut(i)
     }
 
+    /// The context that processor `cpu` is running
+    pub fn running(&self, cpu: usize) -> Result<&Box<Context>> {
+        if self.idle[cpu].is_null() {
+            return Err(Error::new(ESRCH));
+        }
+        self.get(self.running[cpu])
+    }
+
     pub fn iter(&self) -> Iter<Box<Context>> {
         self.inner.iter()
     }
@@ -109,33 +173,158 @@ impl ContextManager {
         self.inner.len()
     }
 
-    pub unsafe fn push(&mut self, context: Box<Context>) {
+    pub unsafe fn push(&mut self, mut context: Box<Context>) {
+        let context_ptr = context.deref_mut() as *mut Context;
+        context.vtime = self.min_vtime;
+        context.index = self.inner.len();
         self.inner.push(context);
+        self.enqueue(context_ptr);
+        smp::wake_idle();
+    }
+
+    /// Check if any context is waiting to run
+    pub fn runnable(&self) -> bool {
+        ! self.run_queue.is_empty()
     }
 
-    pub unsafe fn clean(&mut self) {
-        loop {
-            while self.i >= self.len() {
-                self.i -= self.len();
+    /// Add the idle context of processor `cpu`, which runs when nothing else can. It becomes
+    /// the running context of the processor
+    pub unsafe fn push_idle(&mut self, cpu: usize, mut context: Box<Context>) {
+        self.idle[cpu] = context.deref_mut() as *mut Context;
+        context.index = self.inner.len();
+        self.running[cpu] = self.inner.len();
+        self.inner.push(context);
+    }
+
+    /// Is processor `cpu` running its idle context
+    pub fn is_idle(&self, cpu: usize) -> bool {
+        ! self.idle[cpu].is_null() &&
+            self.inner.get(self.running[cpu]).map_or(false, |context| context.deref() as *const Context == self.idle[cpu])
+    }
+
+    /// Put a context on the run queue, if it can run and is not queued already
+    ///
+    /// Idle contexts are never queued.
+    pub unsafe fn enqueue(&mut self, context_ptr: *mut Context) {
+        if self.idle.contains(&context_ptr) {
+            return;
+        }
+
+        let context = &mut *context_ptr;
+        if context.queued || context.blocked > 0 || context.exited {
+            return;
+        }
+
+        if context.vtime + SLEEPER_CREDIT < self.min_vtime {
+            context.vtime = self.min_vtime - SLEEPER_CREDIT;
+        }
+
+        context.queued = true;
+        self.run_queue.insert((context.vtime, context.pid), context_ptr);
+    }
+
+    /// Take the context with the least virtual time off the run queue
+    ///
+    /// Contexts that were blocked or exited since they were queued are dropped.
+    unsafe fn dequeue(&mut self) -> Option<*mut Context> {
+        while let Some(key) = self.run_queue.keys().next().cloned() {
+            if let Some(context_ptr) = self.run_queue.remove(&key) {
+                let context = &mut *context_ptr;
+                context.queued = false;
+                if context.blocked == 0 && ! context.exited {
+                    if context.vtime > self.min_vtime {
+                        self.min_vtime = context.vtime;
+                    }
+                    return Some(context_ptr);
+                }
             }
+        }
 
-            let remove = self.current().map(|next| next.exited).unwrap_or(false);
+        None
+    }
 
-            if remove {
-                self.inner.remove(self.i);
-            } else {
+    /// Wake up a context at `wake`, unless it is unblocked earlier
+    pub unsafe fn sleep(&mut self, context_ptr: *mut Context, wake: Duration) {
+        let context = &mut *context_ptr;
+        context.wake = Some(wake);
+        self.sleepers.insert((wake.secs, wake.nanos, context.pid), context_ptr);
+    }
+
+    /// The earliest time a sleeping context wakes up
+    pub fn next_wake(&self) -> Option<Duration> {
+        self.sleepers.keys().next().map(|&(secs, nanos, _)| Duration::new(secs, nanos))
+    }
+
+    /// Unblock the sleeping contexts whose wake time has passed
+    unsafe fn wake_sleepers(&mut self, now: Duration) {
+        while let Some(key) = self.sleepers.keys().next().cloned() {
+            if Duration::new(key.0, key.1) > now {
                 break;
             }
+
+            if let Some(context_ptr) = self.sleepers.remove(&key) {
+                let context = &mut *context_ptr;
+                if context.blocked > 0 {
+                    if let Some(wake) = context.wake {
+                        if wake <= now {
+                            context.wake = None;
+                            context.unblock("context_switch wake");
+                        }
+                    }
+                }
+            }
         }
+    }
 
-        while self.i >= self.len() {
-            self.i -= self.len();
+    /// Remove exited contexts, except for those that are running, as they are still on their
+    /// kernel stacks. This includes `current`, which is switched away from
+    pub unsafe fn clean(&mut self, current: *mut Context) {
+        let mut i = 0;
+        while i < self.inner.len() {
+            let context_ptr = self.inner[i].deref_mut() as *mut Context;
+            let running = self.idle.iter().zip(self.running.iter())
+                                          .any(|(idle, &running)| ! idle.is_null() && running == i);
+            if self.inner[i].exited && context_ptr != current && ! running {
+                let queued: Vec<(u64, usize)> = self.run_queue.iter()
+                                                    .filter(|&(_, &queued)| queued == context_ptr)
+                                                    .map(|(key, _)| *key)
+                                                    .collect();
+                for key in queued.iter() {
+                    self.run_queue.remove(key);
+                }
+
+                let sleeping: Vec<(i64, i32, usize)> = self.sleepers.iter()
+                                                           .filter(|&(_, &sleeper)| sleeper == context_ptr)
+                                                           .map(|(key, _)| *key)
+                                                           .collect();
+                for key in sleeping.iter() {
+                    self.sleepers.remove(key);
+                }
+
+                self.inner.remove(i);
+                for context in self.inner[i..].iter_mut() {
+                    context.index -= 1;
+                }
+                for running in self.running.iter_mut() {
+                    if *running > i {
+                        *running -= 1;
+                    }
+                }
+            } else {
+                i += 1;
+            }
         }
     }
 }
 
 /// Switch context
 ///
+/// The current context goes back on the run queue if it can still run, and the context with the
+/// least virtual time runs next. The idle context of this processor runs if nothing else can.
+///
+/// The run queue is shared by all processors. The kernel lock is held across the switch, and
+/// the next context continues with it.
+///
 /// Unsafe due to interrupt disabling, raw pointers, and unsafe Context functions
 pub unsafe fn context_switch() {
     let mut current_ptr: *mut Context = 0 as *mut Context;
@@ -144,58 +333,55 @@ pub unsafe fn context_switch() {
     {
         let contexts = &mut *::env().contexts.get();
         if contexts.enabled {
-            let current_i = contexts.i;
-            'searching: loop {
-                contexts.i += 1;
-                contexts.clean();
-                if let Ok(mut next) = contexts.current_mut() {
-                    if next.blocked > 0 {
-                        if let Some(wake) = next.wake {
-                            if wake <= Duration::monotonic() {
-                                next.wake = None;
-                                next.unblock("context_switch wake");
-                                break 'searching;
-                            }
-                        }
-                    } else {
-                        break 'searching;
-                    }
-                }
-                if contexts.i == current_i {
-                    break 'searching;
-                }
-            }
+            let cpu = smp::cpu_id();
+
+            contexts.wake_sleepers(Duration::monotonic());
+
+            let current = if let Ok(mut current) = contexts.current_mut() {
+                current.deref_mut() as *mut Context
+            } else {
+                0 as *mut Context
+            };
+
+            contexts.enqueue(current);
+            let next = contexts.dequeue().unwrap_or(contexts.idle[cpu]);
+
+            contexts.clean(current);
+
+            clock::set_timer(contexts.next_wake(), next == contexts.idle[cpu]);
 
-            if contexts.i != current_i {
-                if let Ok(mut current) = contexts.get_mut(current_i) {
-                    current.unmap();
+            contexts.running[cpu] = (*next).index;
 
-                    current_ptr = current.deref_mut();
+            if next != current {
+                if ! current.is_null() {
+                    (*current).unmap();
+
+                    current_ptr = current;
                 }
 
-                if let Ok(mut next) = contexts.current_mut() {
-                    next.switch += 1;
+                let next = &mut *next;
 
-                    if let Some(ref mut tss) = ::TSS_PTR {
-                        if next.kernel_stack > 0 {
-                            tss.sp0 = next.kernel_stack + CONTEXT_STACK_SIZE - 128;
-                        } else {
-                            tss.sp0 = 0x800000 - 128;
-                        }
+                next.switch += 1;
+
+                if let Some(tss) = smp::tss() {
+                    if next.kernel_stack > 0 {
+                        tss.sp0 = next.kernel_stack + CONTEXT_STACK_SIZE - 128;
+                    } else {
+                        tss.sp0 = 0x800000 - 128;
                     }
+                }
 
-                    if let Some(ref mut gdt) = ::GDT_PTR {
-                        if let Some(ref tls) = next.tls {
-                            gdt[GDT_USER_TLS].set_base(tls.virtual_address);
-                        } else {
-                            gdt[GDT_USER_TLS].set_base(0);
-                        }
+                if let Some(gdt) = smp::gdt() {
+                    if let Some(ref tls) = next.tls {
+                        gdt[GDT_USER_TLS].set_base(tls.virtual_address);
+                    } else {
+                        gdt[GDT_USER_TLS].set_base(0);
                     }
+                }
 
-                    next.map();
+                next.map();
 
-                    next_ptr = next.deref_mut();
-                }
+                next_ptr = next;
             }
         }
     }
@@ -209,6 +395,17 @@ pub unsafe fn context_clone(regs: &Regs) -> Result<usize> {
     let contexts = &mut *::env().contexts.get();
     let flags = regs.bx;
 
+    {
+        let parent = try!(contexts.current());
+        let limit = parent.rlimits[RLIMIT_NPROC].rlim_cur;
+        if parent.uid != 0 && limit != RLIM_INFINITY {
+            let count = contexts.iter().filter(|context| context.uid == parent.uid && ! context.exited).count();
+            if count >= limit {
+                return Err(Error::new(EAGAIN));
+            }
+        }
+    }
+
     let kernel_stack = memory::alloc(CONTEXT_STACK_SIZE);
     let fx = memory::alloc(CONTEXT_FX_SIZE);
     if kernel_stack > 0 && fx > 0 {
@@ -220,7 +417,7 @@ pub unsafe fn context_clone(regs: &Regs) -> Result<usize> {
             //debugln!("{}: {}: clone to {}: {:X}", parent.pid, parent.name, clone_pid, flags);
 
             let regs_size = mem::size_of::<Regs>();
-            let extra_size = mem::size_of::<usize>() * 3; /* Return pointer, interrupt code, regs pointer */
+            let extra_size = mem::size_of::<usize>() * INTERRUPT_EXTRA;
             let parent_regs_addr = (regs as *const Regs) as usize;
             let child_regs_addr = parent_regs_addr - parent.kernel_stack + kernel_stack;
             ::memcpy((child_regs_addr - extra_size) as *mut u8,
@@ -230,26 +427,16 @@ pub unsafe fn context_clone(regs: &Regs) -> Result<usize> {
             let child_regs = &mut *(child_regs_addr as *mut Regs);
             child_regs.ax = 0;
 
+            // The clone returns through the interrupt handler, after releasing the kernel lock
+            // that it is handed by the context switch
             let mut kernel_regs = parent.regs;
-            kernel_regs.sp = child_regs_addr - extra_size;
+            kernel_regs.sp = child_regs_addr - extra_size - mem::size_of::<usize>();
+            ptr::write(kernel_regs.sp as *mut usize, context_unlock as usize);
 
             memory::copy_pages(fx as *mut u8, parent.fx as *const u8, CONTEXT_FX_SIZE);
 
-            let stack = if let Some(ref entry) = parent.stack {
-                let physical_address = memory::alloc(entry.virtual_size);
-                if physical_address > 0 {
-                    memory::copy_pages(physical_address as *mut u8, entry.physical_address as *const u8, entry.virtual_size);
-
-                    Some(ContextMemory {
-                        physical_address: physical_address,
-                        virtual_address: entry.virtual_address,
-                        virtual_size: entry.virtual_size,
-                        writeable: entry.writeable,
-                        allocated: true,
-                    })
-                } else {
-                    None
-                }
+            let stack = if let Some(ref mut entry) = parent.stack {
+                entry.share()
             } else {
                 None
             };
@@ -267,6 +454,9 @@ pub unsafe fn context_clone(regs: &Regs) -> Result<usize> {
                             virtual_size: entry.virtual_size,
                             writeable: entry.writeable,
                             allocated: true,
+                            cache: 0,
+                            shared: false,
+                            max_writeable: true,
                         })
                     } else {
                         None
@@ -302,6 +492,12 @@ pub unsafe fn context_clone(regs: &Regs) -> Result<usize> {
                 Arc::new(UnsafeCell::new((*parent.mmap.get()).dup()))
             };
 
+            // Sharing remapped the writeable memory of the parent read only on this processor.
+            // Its threads on other processors have to do the same before they write to it again.
+            if flags & syscall::CLONE_VM != syscall::CLONE_VM {
+                smp::shootdown(0, 0);
+            }
+
             let tls_master = if flags & syscall::CLONE_VM == syscall::CLONE_VM {
                 parent.tls_master.clone()
             } else {
@@ -314,6 +510,12 @@ pub unsafe fn context_clone(regs: &Regs) -> Result<usize> {
                 Arc::new(UnsafeCell::new((*parent.env_vars.get()).clone()))
             };
 
+            let sig_actions = if flags & syscall::CLONE_VM == syscall::CLONE_VM {
+                parent.sig_actions.clone()
+            } else {
+                Arc::new(UnsafeCell::new(*parent.sig_actions.get()))
+            };
+
             let cwd = if flags & syscall::CLONE_FS == syscall::CLONE_FS {
                 parent.cwd.clone()
             } else {
@@ -331,6 +533,8 @@ pub unsafe fn context_clone(regs: &Regs) -> Result<usize> {
                         if let Ok(resource) = file.resource.dup() {
                             Some(ContextFile {
                                 fd: file.fd,
+                                flags: file.flags.clone(),
+                                cloexec: file.cloexec,
                                 resource: resource,
                             })
                         } else {
@@ -352,14 +556,31 @@ pub unsafe fn context_clone(regs: &Regs) -> Result<usize> {
             box Context {
                 pid: clone_pid,
                 ppid: parent.pid,
+                pgid: parent.pgid,
+                sid: parent.sid,
+                uid: parent.uid,
+                gid: parent.gid,
+                euid: parent.euid,
+                egid: parent.egid,
+                rlimits: parent.rlimits,
                 name: parent.name.clone(),
                 iopl: parent.iopl,
                 blocked: 0,
                 exited: false,
                 switch: 0,
                 time: 0,
+                cpu_time: Duration::new(0, 0),
+                nice: parent.nice,
+                vtime: 0,
+                queued: false,
+                index: 0,
                 vfork: vfork,
                 wake: None,
+                sig_pending: 0,
+                sig_blocked: parent.sig_blocked,
+                stopped: false,
+                interruptible: false,
+                watching: Vec::new(),
 
                 supervised: flags & syscall::CLONE_SUPERVISE == syscall::CLONE_SUPERVISE,
                 blocked_syscall: false,
@@ -377,6 +598,7 @@ pub unsafe fn context_clone(regs: &Regs) -> Result<usize> {
                 mmap: mmap,
                 tls_master: tls_master,
                 env_vars: env_vars,
+                sig_actions: sig_actions,
 
                 cwd: cwd,
                 files: files,
@@ -440,6 +662,14 @@ pub unsafe extern "cdecl" fn context_userspace(/*Throw away extra params from AB
     loop {}
 }
 
+/// Releases the kernel lock, when a new context is switched to for the first time
+///
+/// The context switch hands the lock to the next context. New contexts that go to userspace
+/// return here first, and this returns to the next address on their stack.
+pub unsafe extern "cdecl" fn context_unlock() {
+    smp::unlock_kernel();
+}
+
 /// Reads a Boxed function and executes it
 /// # Safety
 /// Unsafe due to raw memory handling and FnBox
@@ -469,18 +699,33 @@ pub struct ContextMemory {
     pub virtual_size: usize,
     pub writeable: bool,
     pub allocated: bool,
+    /// The page flags for caching, such as `PF_CACHE_DISABLE`, or 0 for write back caching
+    pub cache: usize,
+    /// The allocated pages are shared directly with clones instead of copied on write, such as
+    /// memory borrowed from a resource or a DMA buffer. They are only referenced, not owned.
+    pub shared: bool,
+    /// The memory may be made writeable by `mprotect`
+    pub max_writeable: bool,
 }
 
 impl ContextMemory {
+    /// Check if a page can be mapped writeable
+    ///
+    /// Allocated pages that are shared with a clone are mapped read only, and copied on write,
+    /// unless the memory is shared.
+    unsafe fn page_writeable(&self, i: usize) -> bool {
+        self.writeable && (! self.allocated || self.shared || memory::page_refs(self.physical_address + i * 4096) <= 1)
+    }
+
     pub unsafe fn map(&mut self) {
         for i in 0..(self.virtual_size + 4095) / 4096 {
-            if self.writeable {
-                Page::new(self.virtual_address + i * 4096)
-                    .map_user_write(self.physical_address + i * 4096);
+            let flags = if self.page_writeable(i) {
+                PF_WRITE | self.cache
             } else {
-                Page::new(self.virtual_address + i * 4096)
-                    .map_user_read(self.physical_address + i * 4096);
-            }
+                self.cache
+            };
+            Page::new(self.virtual_address + i * 4096)
+                .map_user_flags(self.physical_address + i * 4096, flags);
         }
     }
 
@@ -490,21 +735,120 @@ impl ContextMemory {
                 .map_kernel_write(self.virtual_address + i * 4096);
         }
     }
+
+    /// Share the memory with a clone
+    ///
+    /// Allocated memory is shared copy on write, and is remapped read only if it is mapped.
+    /// Shared memory gets another reference, and other memory, such as a framebuffer, is shared
+    /// directly.
+    ///
+    /// Only this processor is remapped, so `smp::shootdown` has to follow for memory that threads
+    /// share.
+    pub unsafe fn share(&mut self) -> Option<ContextMemory> {
+        if self.allocated && self.shared {
+            if ! memory::ref_pages(self.physical_address, self.virtual_size) {
+                return None;
+            }
+        } else if self.allocated {
+            if ! memory::ref_pages(self.physical_address, self.virtual_size) {
+                // The pages have too many references, so make a copy instead
+                let physical_address = memory::alloc_aligned(self.virtual_size, 4096);
+                if physical_address == 0 {
+                    return None;
+                }
+
+                memory::copy_pages(physical_address as *mut u8, self.physical_address as *const u8, self.virtual_size);
+
+                return Some(ContextMemory {
+                    physical_address: physical_address,
+                    virtual_address: self.virtual_address,
+                    virtual_size: self.virtual_size,
+                    writeable: self.writeable,
+                    allocated: true,
+                    cache: self.cache,
+                    shared: false,
+                    max_writeable: self.max_writeable,
+                });
+            }
+
+            if self.writeable {
+                self.map();
+            }
+        }
+
+        Some(ContextMemory {
+            physical_address: self.physical_address,
+            virtual_address: self.virtual_address,
+            virtual_size: self.virtual_size,
+            writeable: self.writeable,
+            allocated: self.allocated,
+            cache: self.cache,
+            shared: self.shared,
+            max_writeable: self.max_writeable,
+        })
+    }
+
+    /// Copy the memory if any of its pages are shared, and map it writeable
+    ///
+    /// Returns false if the memory is not writeable, or if the copy could not be allocated.
+    pub unsafe fn unshare(&mut self) -> bool {
+        if ! self.writeable || ! self.allocated || self.shared {
+            return false;
+        }
+
+        let shared = (0..(self.virtual_size + 4095) / 4096).any(|i| ! self.page_writeable(i));
+        if shared {
+            let physical_address = memory::alloc_aligned(self.virtual_size, 4096);
+            if physical_address == 0 {
+                return false;
+            }
+
+            memory::copy_pages(physical_address as *mut u8, self.physical_address as *const u8, self.virtual_size);
+            memory::unalloc_pages(self.physical_address, self.virtual_size);
+            self.physical_address = physical_address;
+        }
+
+        self.map();
+
+        true
+    }
 }
 
 impl Drop for ContextMemory {
     fn drop(&mut self) {
-        if self.allocated {
-            unsafe { memory::unalloc(self.physical_address) };
+        if self.allocated && self.shared {
+            unsafe { memory::unref_pages(self.physical_address, self.virtual_size) };
+        } else if self.allocated {
+            unsafe { memory::unalloc_pages(self.physical_address, self.virtual_size) };
         }
     }
 }
 
 pub struct ContextFile {
     pub fd: usize,
+    /// The access mode and file status flags, shared by descriptors duplicated from this one and
+    /// by their resources
+    pub flags: Arc<Cell<usize>>,
+    /// Close the descriptor when a new program is executed
+    pub cloexec: bool,
     pub resource: Box<Resource>,
 }
 
+impl ContextFile {
+    /// Create a descriptor for a new resource, which is handed the access mode and file status
+    /// `flags`
+    pub fn new(fd: usize, flags: usize, cloexec: bool, mut resource: Box<Resource>) -> Result<ContextFile> {
+        let flags = Arc::new(Cell::new(flags));
+        try!(resource.set_flags(flags.clone()));
+        Ok(ContextFile {
+            fd: fd,
+            flags: flags,
+            cloexec: cloexec,
+            resource: resource,
+        })
+    }
+}
+
 pub struct ContextZone {
     pub address: usize,
     pub size: usize,
@@ -520,24 +864,16 @@ impl ContextZone {
         }
     }
 
-    pub fn dup(&self) -> ContextZone {
+    /// Duplicate the zone for a clone, sharing allocated memory copy on write
+    ///
+    /// Like `ContextMemory::share`, this has to be followed by `smp::shootdown`.
+    pub fn dup(&mut self) -> ContextZone {
         let mut mem: Vec<ContextMemory> = Vec::new();
-        for entry in self.memory.iter() {
-            let physical_address = unsafe { memory::alloc(entry.virtual_size) };
-            if physical_address > 0 {
-                unsafe { memory::copy_pages(physical_address as *mut u8, entry.physical_address as *const u8, entry.virtual_size) };
-
-                //debugln!("{}: {}: dup memory {:X}:{:X} for {}", parent.pid, parent.name, entry.virtual_address, entry.virtual_address + entry.virtual_size, clone_pid);
-
-                mem.push(ContextMemory {
-                    physical_address: physical_address,
-                    virtual_address: entry.virtual_address,
-                    virtual_size: entry.virtual_size,
-                    writeable: entry.writeable,
-                    allocated: true,
-                });
+        for entry in self.memory.iter_mut() {
+            if let Some(shared) = unsafe { entry.share() } {
+                mem.push(shared);
             } else {
-                //debugln!("{}: {}: failed to dup memory {:X}:{:X} for {}", parent.pid, parent.name, entry.virtual_address, entry.virtual_address + entry.virtual_size, clone_pid);
+                //debugln!("failed to dup memory {:X}:{:X}", entry.virtual_address, entry.virtual_address + entry.virtual_size);
             }
         }
 
@@ -567,6 +903,9 @@ impl ContextZone {
                             virtual_size: size,
                             writeable: writeable,
                             allocated: allocated,
+                            cache: 0,
+                            shared: false,
+                            max_writeable: writeable,
                         });
 
                         return Ok(virtual_address);
@@ -587,6 +926,9 @@ impl ContextZone {
                     virtual_size: size,
                     writeable: writeable,
                     allocated: allocated,
+                    cache: 0,
+                    shared: false,
+                    max_writeable: writeable,
                 });
 
                 return Ok(virtual_address);
@@ -664,6 +1006,21 @@ impl ContextZone {
         Err(Error::new(ENOMEM))
     }
 
+    /// Copy the shared memory containing `address`, so that it can be written
+    ///
+    /// The whole memory is copied, so that it stays physically contiguous.
+    /// Returns false if the address is not in writeable, allocated memory, or if the copy could
+    /// not be allocated.
+    pub unsafe fn unshare(&mut self, address: usize) -> bool {
+        for mem in self.memory.iter_mut() {
+            if address >= mem.virtual_address && address < mem.virtual_address + mem.virtual_size {
+                return mem.unshare();
+            }
+        }
+
+        false
+    }
+
     /// Cleanup empty memory
     pub unsafe fn clean_mem(&mut self) {
         self.memory.retain(|mem| mem.virtual_size > 0);
@@ -701,6 +1058,20 @@ pub struct Context {
     pub pid: usize,
     /// The PID of the parent
     pub ppid: usize,
+    /// The process group ID
+    pub pgid: usize,
+    /// The session ID
+    pub sid: usize,
+    /// The real user ID
+    pub uid: usize,
+    /// The real group ID
+    pub gid: usize,
+    /// The effective user ID, used for permission checks
+    pub euid: usize,
+    /// The effective group ID, used for permission checks
+    pub egid: usize,
+    /// Resource limits, indexed by `RLIMIT_*`
+    pub rlimits: [RLimit; RLIMIT_NLIMITS],
     /// The name of the context
     pub name: Cow<'static, str>,
     /// The I/O privilege level
@@ -713,10 +1084,33 @@ pub struct Context {
     pub switch: usize,
     /// The number of time slices used
     pub time: usize,
+    /// The processor time used, checked against `RLIMIT_CPU`
+    pub cpu_time: Duration,
+    /// The nice value, from `NICE_MIN` to `NICE_MAX`. Lower values get more processor time
+    pub nice: isize,
+    /// The processor time used in nanoseconds, weighted by the nice value. The runnable context
+    /// with the least virtual time runs next
+    pub vtime: u64,
+    /// Indicates that the context is on the run queue
+    pub queued: bool,
+    /// The index of the context in the context manager, kept up to date as contexts are removed
+    pub index: usize,
     /// Indicates that the context needs to unblock parent
     pub vfork: Option<*mut Context>,
     /// When to wake up
     pub wake: Option<Duration>,
+    /// Signals waiting to be delivered, bit `n` stands for signal `n`
+    pub sig_pending: u32,
+    /// Signals that will not be delivered until unblocked
+    pub sig_blocked: u32,
+    /// Indicates that the context was stopped by a signal, and is blocked until continued
+    pub stopped: bool,
+    /// Indicates that the context is blocked on a `WaitCondition`, and is unblocked early by a
+    /// signal that interrupts it
+    pub interruptible: bool,
+    /// The conditions that notify this context without blocking it, see `WaitCondition::watch`.
+    /// They remove themselves when they notify or are dropped, so these are still alive.
+    pub watching: Vec<*const WaitCondition>,
     // }
 
     /// Is this process supervised?
@@ -758,6 +1152,8 @@ pub struct Context {
     /// Environment variables, cloned for threads, copied or created for
     /// processes. Modified by set_env
     pub env_vars: Arc<UnsafeCell<Vec<EnvVar>>>,
+    /// Signal dispositions, cloned for threads, copied for processes. Reset by exec
+    pub sig_actions: Arc<UnsafeCell<[SigAction; NSIG]>>,
 
     /// Program working directory, cloned for threads, copied or created for processes. Modified by chdir
     pub cwd: Arc<UnsafeCell<String>>,
@@ -765,8 +1161,8 @@ pub struct Context {
     pub files: Arc<UnsafeCell<Vec<ContextFile>>>,
     // }
 
-    /// Exit statuses of children
-    pub statuses: WaitMap<usize, usize>,
+    /// Wait statuses of children, with their process group ID
+    pub statuses: WaitMap<usize, (usize, usize)>,
 }
 
 impl Context {
@@ -801,18 +1197,36 @@ impl Context {
 
     pub unsafe fn root() -> Box<Self> {
         let fx = memory::alloc(CONTEXT_FX_SIZE);
+        let pid = Context::next_pid();
 
         box Context {
-            pid: Context::next_pid(),
+            pid: pid,
             ppid: 0,
+            pgid: pid,
+            sid: pid,
+            uid: 0,
+            gid: 0,
+            euid: 0,
+            egid: 0,
+            rlimits: [RLimit::default(); RLIMIT_NLIMITS],
             name: "kidle".into(),
             iopl: 3,
             blocked: 0,
             exited: false,
             switch: 0,
             time: 0,
+            cpu_time: Duration::new(0, 0),
+            nice: 0,
+            vtime: 0,
+            queued: false,
+            index: 0,
             vfork: None,
             wake: None,
+            sig_pending: 0,
+            sig_blocked: 0,
+            stopped: false,
+            interruptible: false,
+            watching: Vec::new(),
 
             supervised: false,
             blocked_syscall: false,
@@ -830,6 +1244,7 @@ impl Context {
             mmap: Arc::new(UnsafeCell::new(ContextZone::new(CONTEXT_MMAP_ADDR, CONTEXT_MMAP_SIZE))),
             tls_master: Arc::new(UnsafeCell::new(None)),
             env_vars: Arc::new(UnsafeCell::new(Vec::new())),
+            sig_actions: Arc::new(UnsafeCell::new([SigAction::default(); NSIG])),
 
             cwd: Arc::new(UnsafeCell::new(String::new())),
             files: Arc::new(UnsafeCell::new(Vec::new())),
@@ -845,17 +1260,36 @@ impl Context {
         let mut regs = Regs::default();
         regs.sp = kernel_stack + CONTEXT_STACK_SIZE - 128;
 
+        let pid = Context::next_pid();
+
         let mut ret = box Context {
-            pid: Context::next_pid(),
+            pid: pid,
             ppid: 0,
+            pgid: pid,
+            sid: pid,
+            uid: 0,
+            gid: 0,
+            euid: 0,
+            egid: 0,
+            rlimits: [RLimit::default(); RLIMIT_NLIMITS],
             name: name,
             iopl: 3,
             blocked: 0,
             exited: false,
             switch: 0,
             time: 0,
+            cpu_time: Duration::new(0, 0),
+            nice: 0,
+            vtime: 0,
+            queued: false,
+            index: 0,
             vfork: None,
             wake: None,
+            sig_pending: 0,
+            sig_blocked: 0,
+            stopped: false,
+            interruptible: false,
+            watching: Vec::new(),
 
             supervised: false,
             blocked_syscall: false,
@@ -873,6 +1307,7 @@ impl Context {
             mmap: Arc::new(UnsafeCell::new(ContextZone::new(CONTEXT_MMAP_ADDR, CONTEXT_MMAP_SIZE))),
             tls_master: Arc::new(UnsafeCell::new(None)),
             env_vars: Arc::new(UnsafeCell::new(Vec::new())),
+            sig_actions: Arc::new(UnsafeCell::new([SigAction::default(); NSIG])),
 
             cwd: Arc::new(UnsafeCell::new(String::new())),
             files: Arc::new(UnsafeCell::new(Vec::new())),
@@ -910,6 +1345,14 @@ impl Context {
         ret
     }
 
+    /// Stop being notified by the conditions that were watched with `WaitCondition::watch`
+    pub fn unwatch_all(&mut self) {
+        let context_ptr = self as *mut Context;
+        for condition in mem::replace(&mut self.watching, Vec::new()) {
+            unsafe { (*condition).unwatch(context_ptr) };
+        }
+    }
+
     pub fn block(&mut self, _reason: &str) {
         self.blocked += 1;
         // debugln!("    BLOCK {}: {}: {} {}", self.pid, self.name, self.blocked, reason);
@@ -919,6 +1362,55 @@ impl Context {
         // debugln!("    UNBLOCK {}: {}: {} {}", self.pid, self.name, self.blocked, reason);
         if self.blocked > 0 {
             self.blocked -= 1;
+            if self.blocked == 0 {
+                unsafe {
+                    (*::env().contexts.get()).enqueue(self);
+                    smp::wake_idle();
+                }
+            }
+        }
+    }
+
+    /// Wake up at `wake`, unless unblocked earlier
+    pub fn sleep(&mut self, wake: Duration) {
+        unsafe { (*::env().contexts.get()).sleep(self, wake) };
+    }
+
+    /// Mark a signal as pending
+    ///
+    /// Stop and continue signals cancel each other out, and continuing (or killing) a stopped
+    /// context unblocks it right away, so that the signal can be delivered. A sleeping context is
+    /// woken early, so that delivery does not have to wait for its timeout, and a context waiting
+    /// on a `WaitCondition` is unblocked, so that its system call fails with `EINTR`.
+    pub fn signal(&mut self, sig: usize) {
+        if sig == 0 || sig >= NSIG {
+            return;
+        }
+
+        match sig {
+            SIGCONT => {
+                self.sig_pending &= !(1 << SIGSTOP | 1 << SIGTSTP | 1 << SIGTTIN | 1 << SIGTTOU);
+            },
+            SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => {
+                self.sig_pending &= !(1 << SIGCONT);
+            },
+            _ => ()
+        }
+
+        if (sig == SIGCONT || sig == SIGKILL) && self.stopped {
+            self.stopped = false;
+            self.unblock("Context::signal continue");
+        }
+
+        self.sig_pending |= 1 << sig;
+
+        if syscall::signal::interrupting(self) != 0 {
+            if self.wake.is_some() {
+                self.sleep(Duration::monotonic());
+            } else if self.interruptible {
+                self.interruptible = false;
+                self.unblock("Context::signal interrupt");
+            }
         }
     }
 
@@ -956,8 +1448,8 @@ impl Context {
         }
     }
 
-    /// Get the next available file descriptor
-    pub fn next_fd(&self) -> usize {
+    /// Get the next available file descriptor, which has to be below `RLIMIT_NOFILE`
+    pub fn next_fd(&self) -> Result<usize> {
         let mut next_fd = 0;
 
         let mut collision = true;
@@ -972,7 +1464,61 @@ impl Context {
             }
         }
 
-        return next_fd;
+        if next_fd >= self.rlimits[RLIMIT_NOFILE].rlim_cur {
+            return Err(Error::new(EMFILE));
+        }
+
+        Ok(next_fd)
+    }
+
+    /// The amount of memory used by the context
+    pub fn memory_size(&self) -> usize {
+        let mut memory = 0;
+        if self.kernel_stack > 0 {
+            memory += CONTEXT_STACK_SIZE;
+        }
+        if let Some(ref stack) = self.stack {
+            memory += stack.virtual_size;
+        }
+        memory += unsafe { (*self.image.get()).size() };
+        memory += unsafe { (*self.heap.get()).size() };
+        memory += unsafe { (*self.mmap.get()).size() };
+        memory
+    }
+
+    /// Check that `size` more bytes of memory would not exceed `RLIMIT_AS`
+    pub fn check_memory(&self, size: usize) -> Result<()> {
+        let limit = self.rlimits[RLIMIT_AS].rlim_cur;
+        if limit != RLIM_INFINITY && self.memory_size().saturating_add(size) > limit {
+            Err(Error::new(ENOMEM))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Account for a time slice that was used
+    ///
+    /// The virtual time grows slower for contexts with a lower nice value.
+    ///
+    /// Every second past the soft `RLIMIT_CPU` sends `SIGXCPU`, and reaching the hard limit
+    /// sends `SIGKILL`.
+    pub fn charge(&mut self, duration: Duration) {
+        let secs = self.cpu_time.secs;
+        self.time += 1;
+        self.cpu_time = self.cpu_time + duration;
+
+        let nanos = duration.secs as u64 * NANOS_PER_SEC as u64 + duration.nanos as u64;
+        self.vtime += nanos * NICE_WEIGHTS[(0 - NICE_MIN) as usize] / NICE_WEIGHTS[(self.nice - NICE_MIN) as usize];
+
+        if self.cpu_time.secs > secs {
+            let secs = self.cpu_time.secs as usize;
+            let limit = self.rlimits[RLIMIT_CPU];
+            if secs >= limit.rlim_max {
+                self.signal(SIGKILL);
+            } else if secs >= limit.rlim_cur {
+                self.signal(SIGXCPU);
+            }
+        }
     }
 
     /// Get a resource from a file descriptor
@@ -988,6 +1534,28 @@ impl Context {
         Err(Error::new(EBADF))
     }
 
+    /// Get the access mode and file status flags of a file descriptor
+    pub fn get_file_flags(&self, fd: usize) -> Result<usize> {
+        for file in unsafe { (*self.files.get()).iter() } {
+            if file.fd == fd {
+                return Ok(file.flags.get());
+            }
+        }
+
+        Err(Error::new(EBADF))
+    }
+
+    /// Get the shared access mode and file status flags of a file descriptor, for duplicating it
+    pub fn get_file_flags_shared(&self, fd: usize) -> Result<Arc<Cell<usize>>> {
+        for file in unsafe { (*self.files.get()).iter() } {
+            if file.fd == fd {
+                return Ok(file.flags.clone());
+            }
+        }
+
+        Err(Error::new(EBADF))
+    }
+
     /// Get a mutable resource from a file descriptor
     pub fn get_file_mut<'a>(&mut self, fd: usize) -> Result<&'a mut Box<Resource>> {
         for file in unsafe { (*self.files.get()).iter_mut() } {
@@ -1088,6 +1656,47 @@ impl Context {
         Err(Error::new(EFAULT))
     }
 
+    /// Copy the memory containing `address` if it is shared copy on write, and map it writeable
+    ///
+    /// Returns false if the address is not in writeable memory of the context.
+    pub unsafe fn unshare_page(&mut self, address: usize) -> bool {
+        if let Some(ref mut stack) = self.stack {
+            if address >= stack.virtual_address && address < stack.virtual_address + stack.virtual_size {
+                return stack.unshare();
+            }
+        }
+
+        if (*self.image.get()).unshare(address)
+            || (*self.heap.get()).unshare(address)
+            || (*self.mmap.get()).unshare(address) {
+            // Threads on other processors have to map the copy
+            smp::shootdown(0, 0);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Check if the context shares memory with another, as threads do
+    pub fn shares_memory(&self, other: &Context) -> bool {
+        self.image.get() == other.image.get()
+            || self.heap.get() == other.heap.get()
+            || self.mmap.get() == other.mmap.get()
+    }
+
+    /// Copy the memory in a range that is shared copy on write
+    ///
+    /// Writes through virtual addresses do this on the page fault, but memory that will be
+    /// written through its physical address has to be unshared first.
+    /// This maps memory, so it must only be called on the current context.
+    pub fn unshare(&mut self, ptr: usize, len: usize) {
+        let mut address = ptr / 4096 * 4096;
+        while address < ptr + len {
+            unsafe { self.unshare_page(address) };
+            address += 4096;
+        }
+    }
+
     /// Gets an environment variable. Returns `Err` if the variable is not
     /// defined
     pub fn get_env_var(&self, var_name: &str) -> Result<&str> {
@@ -1235,6 +1844,7 @@ impl Context {
 
 impl Drop for Context {
     fn drop(&mut self) {
+        self.unwatch_all();
         if let Some(vfork) = self.vfork.take() {
             unsafe { (*vfork).unblock("Context::drop vfork") };
         }
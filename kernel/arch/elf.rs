@@ -2,7 +2,7 @@
 
 use collections::{String, Vec};
 
-use core::{ptr, str};
+use core::{mem, ptr, str};
 
 use common::slice::GetSlice;
 
@@ -12,6 +12,41 @@ use goblin::elf32::{header, program_header};
 #[cfg(target_arch = "x86_64")]
 use goblin::elf64::{header, program_header};
 
+#[cfg(target_arch = "x86")]
+use goblin::elf32::dyn::{Dyn, DT_NULL, DT_REL, DT_RELA, DT_RELAENT, DT_RELASZ, DT_RELENT, DT_RELSZ};
+#[cfg(target_arch = "x86")]
+use goblin::elf32::rela::Rela;
+
+#[cfg(target_arch = "x86_64")]
+use goblin::elf64::dyn::{Dyn, DT_NULL, DT_REL, DT_RELA, DT_RELAENT, DT_RELASZ, DT_RELENT, DT_RELSZ};
+#[cfg(target_arch = "x86_64")]
+use goblin::elf64::rela::Rela;
+
+use system::error::{self, Error, ENOEXEC};
+
+/// A relocation by the load address, `R_386_RELATIVE` or `R_X86_64_RELATIVE`
+pub const R_RELATIVE: usize = 8;
+
+/// A relocation without addend, which goblin only has the `Rela` counterpart of
+#[derive(Copy, Clone)]
+#[repr(packed)]
+pub struct Rel {
+    pub r_offset: usize,
+    pub r_info: usize,
+}
+
+/// Get the type of a relocation from its info field
+#[cfg(target_arch = "x86")]
+pub fn r_type(info: usize) -> usize {
+    info & 0xFF
+}
+
+/// Get the type of a relocation from its info field
+#[cfg(target_arch = "x86_64")]
+pub fn r_type(info: usize) -> usize {
+    info & 0xFFFFFFFF
+}
+
 /// An ELF executable
 pub struct Elf<'a> {
     pub data: &'a [u8],
@@ -32,16 +67,18 @@ impl<'a> Elf<'a> {
     }
 
     pub unsafe fn load_segments(&self) -> Vec<program_header::ProgramHeader> {
+        self.segments().into_iter().filter(|segment| {
+            segment.p_type == program_header::PT_LOAD || segment.p_type == program_header::PT_TLS
+        }).collect()
+    }
+
+    pub unsafe fn segments(&self) -> Vec<program_header::ProgramHeader> {
         let mut segments = Vec::new();
 
         let header = &*(self.data.as_ptr() as usize as *const header::Header);
 
         for i in 0..header.e_phnum {
-            let segment = ptr::read((self.data.as_ptr() as usize + header.e_phoff as usize + i as usize * header.e_phentsize as usize) as *const program_header::ProgramHeader);
-
-            if segment.p_type == program_header::PT_LOAD || segment.p_type == program_header::PT_TLS {
-                segments.push(segment);
-            }
+            segments.push(ptr::read((self.data.as_ptr() as usize + header.e_phoff as usize + i as usize * header.e_phentsize as usize) as *const program_header::ProgramHeader));
         }
 
         segments
@@ -52,4 +89,142 @@ impl<'a> Elf<'a> {
         let header = &*(self.data.as_ptr() as usize as *const header::Header);
         header.e_entry as usize
     }
+
+    /// Check if this is a position independent executable or shared object, that has to be loaded
+    /// at a base address
+    pub unsafe fn is_dynamic(&self) -> bool {
+        let header = &*(self.data.as_ptr() as usize as *const header::Header);
+        header.e_type == header::ET_DYN
+    }
+
+    /// Get the number and size of the program headers
+    pub unsafe fn program_headers(&self) -> (usize, usize) {
+        let header = &*(self.data.as_ptr() as usize as *const header::Header);
+        (header.e_phnum as usize, header.e_phentsize as usize)
+    }
+
+    /// Get the address of the program headers in the loaded image, relative to the base address
+    pub unsafe fn phdr(&self) -> Option<usize> {
+        let header = &*(self.data.as_ptr() as usize as *const header::Header);
+        let phoff = header.e_phoff as usize;
+
+        for segment in self.segments().iter() {
+            if segment.p_type == program_header::PT_PHDR {
+                return Some(segment.p_vaddr as usize);
+            }
+        }
+
+        for segment in self.segments().iter() {
+            if segment.p_type == program_header::PT_LOAD
+               && phoff >= segment.p_offset as usize
+               && phoff < (segment.p_offset + segment.p_filesz) as usize {
+                return Some(segment.p_vaddr as usize + phoff - segment.p_offset as usize);
+            }
+        }
+
+        None
+    }
+
+    /// Get the path of the program interpreter, if there is one
+    pub unsafe fn interpreter(&self) -> Option<&'a str> {
+        for segment in self.segments().iter() {
+            if segment.p_type == program_header::PT_INTERP {
+                let start = segment.p_offset as usize;
+                let end = start + segment.p_filesz as usize;
+                if let Some(path) = self.data.get(start .. end) {
+                    return str::from_utf8(path).ok().map(|path| path.trim_matches('\0'));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Get the address of the dynamic section, relative to the base address
+    pub unsafe fn dynamic(&self) -> Option<usize> {
+        for segment in self.segments().iter() {
+            if segment.p_type == program_header::PT_DYNAMIC {
+                return Some(segment.p_vaddr as usize);
+            }
+        }
+
+        None
+    }
+}
+
+/// Apply the relative relocations of an image loaded at `base`, with the dynamic section at
+/// `dynamic`, relative to `base`
+///
+/// `pointer` gives where `size` bytes at an address relative to `base` can be accessed, or None if
+/// they are not inside one loaded segment, so the image can be relocated before it is mapped. If
+/// the image has a program interpreter, `interpreted`, relocations of other types are left to it,
+/// otherwise they fail with `ENOEXEC`. Fails with `ENOEXEC`, without touching memory outside of the
+/// loaded segments, if the dynamic section, a relocation table or a relocated address is not
+/// inside of them.
+pub unsafe fn relocate<F>(dynamic: usize, base: usize, interpreted: bool, pointer: F) -> error::Result<()>
+    where F: Fn(usize, usize) -> Option<usize>
+{
+    let mut rel = (0, 0, mem::size_of::<Rel>());
+    let mut rela = (0, 0, mem::size_of::<Rela>());
+
+    let mut address = dynamic;
+    loop {
+        let value = match pointer(address, mem::size_of::<Dyn>()) {
+            Some(value) => ptr::read(value as *const Dyn),
+            None => return Err(Error::new(ENOEXEC))
+        };
+        match value.d_tag {
+            DT_NULL => break,
+            DT_REL => rel.0 = value.d_val as usize,
+            DT_RELSZ => rel.1 = value.d_val as usize,
+            DT_RELENT => rel.2 = value.d_val as usize,
+            DT_RELA => rela.0 = value.d_val as usize,
+            DT_RELASZ => rela.1 = value.d_val as usize,
+            DT_RELAENT => rela.2 = value.d_val as usize,
+            _ => ()
+        }
+        address += mem::size_of::<Dyn>();
+    }
+
+    if rel.0 > 0 {
+        let table = match pointer(rel.0, rel.1) {
+            Some(table) if rel.2 >= mem::size_of::<Rel>() => table,
+            _ => return Err(Error::new(ENOEXEC))
+        };
+
+        for i in 0..rel.1/rel.2 {
+            let reloc = ptr::read((table + i * rel.2) as *const Rel);
+            if r_type(reloc.r_info) == R_RELATIVE {
+                let target = match pointer(reloc.r_offset, mem::size_of::<usize>()) {
+                    Some(target) => target as *mut usize,
+                    None => return Err(Error::new(ENOEXEC))
+                };
+                ptr::write(target, ptr::read(target) + base);
+            } else if ! interpreted {
+                return Err(Error::new(ENOEXEC));
+            }
+        }
+    }
+
+    if rela.0 > 0 {
+        let table = match pointer(rela.0, rela.1) {
+            Some(table) if rela.2 >= mem::size_of::<Rela>() => table,
+            _ => return Err(Error::new(ENOEXEC))
+        };
+
+        for i in 0..rela.1/rela.2 {
+            let reloc = ptr::read((table + i * rela.2) as *const Rela);
+            if r_type(reloc.r_info as usize) == R_RELATIVE {
+                let target = match pointer(reloc.r_offset as usize, mem::size_of::<usize>()) {
+                    Some(target) => target as *mut usize,
+                    None => return Err(Error::new(ENOEXEC))
+                };
+                ptr::write(target, (base as isize + reloc.r_addend as isize) as usize);
+            } else if ! interpreted {
+                return Err(Error::new(ENOEXEC));
+            }
+        }
+    }
+
+    Ok(())
 }
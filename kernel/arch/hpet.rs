@@ -0,0 +1,99 @@
+use acpi::HPET;
+
+use drivers::io::{Io, Mmio};
+
+/// The HPET registers
+const HPET_CAPABILITIES: usize = 0x00;
+const HPET_CONFIGURATION: usize = 0x10;
+const HPET_COUNTER: usize = 0xF0;
+
+/// Capability flags
+const CAPABILITY_64_BIT: u32 = 1 << 13;
+
+/// Configuration flags
+const CONFIGURATION_ENABLE: u32 = 1;
+const CONFIGURATION_LEGACY: u32 = 1 << 1;
+
+/// Femtoseconds per second, which the counter period is given in
+const FEMTOS_PER_SEC: u64 = 1000000000000000;
+
+/// The high precision event timer (HPET), which has a counter that runs at a fixed frequency
+///
+/// Only the main counter is used, to measure time. The comparators are left disabled.
+pub struct Hpet {
+    pub address: usize,
+    /// The counter frequency in hertz
+    pub frequency: u64,
+    /// True if the counter is 64 bits wide, false if it is 32 bits wide and wraps around
+    pub wide: bool,
+}
+
+impl Hpet {
+    /// Start the main counter of the HPET from the ACPI table
+    ///
+    /// Returns None if the HPET is not in memory, or reports an invalid period.
+    pub unsafe fn new(table: &HPET) -> Option<Hpet> {
+        let base_address = table.base_address;
+        if base_address.address_space != 0 || base_address.address == 0 {
+            return None;
+        }
+
+        let mut hpet = Hpet {
+            address: base_address.address as usize,
+            frequency: 0,
+            wide: false,
+        };
+
+        // The period is in femtoseconds, and may not be more than 100 nanoseconds
+        let period = hpet.read(HPET_CAPABILITIES + 4) as u64;
+        if period == 0 || period > 100000000 {
+            return None;
+        }
+        hpet.frequency = FEMTOS_PER_SEC / period;
+        hpet.wide = hpet.read(HPET_CAPABILITIES) & CAPABILITY_64_BIT == CAPABILITY_64_BIT;
+
+        let configuration = hpet.read(HPET_CONFIGURATION) & !CONFIGURATION_LEGACY;
+        hpet.write(HPET_CONFIGURATION, configuration | CONFIGURATION_ENABLE);
+
+        Some(hpet)
+    }
+
+    unsafe fn read(&self, reg: usize) -> u32 {
+        (&*((self.address + reg) as *const Mmio<u32>)).read()
+    }
+
+    unsafe fn write(&mut self, reg: usize, value: u32) {
+        (&mut *((self.address + reg) as *mut Mmio<u32>)).write(value);
+    }
+
+    /// Read the main counter
+    ///
+    /// The halves are read separately, so the high half is read again in case the low half
+    /// wrapped around in between.
+    pub fn counter(&self) -> u64 {
+        unsafe {
+            if self.wide {
+                loop {
+                    let high = self.read(HPET_COUNTER + 4);
+                    let low = self.read(HPET_COUNTER);
+                    if self.read(HPET_COUNTER + 4) == high {
+                        return (high as u64) << 32 | low as u64;
+                    }
+                }
+            } else {
+                self.read(HPET_COUNTER) as u64
+            }
+        }
+    }
+
+    /// The counter ticks since the counter read `start`, allowing a 32 bit counter to wrap
+    /// around once
+    pub fn ticks_since(&self, start: u64) -> u64 {
+        let ticks = self.counter().wrapping_sub(start);
+        if self.wide {
+            ticks
+        } else {
+            ticks & 0xFFFFFFFF
+        }
+    }
+}
@@ -14,6 +14,13 @@ pub const CLUSTER_SIZE: usize = 4096; // Of 4 K chunks
 
 pub const LOGICAL_OFFSET: usize = 0x80000000;
 
+/// The low bits of a cluster entry count the additional references to a shared cluster,
+/// the high bits hold the address of the allocation it belongs to
+pub const CLUSTER_REFS: usize = CLUSTER_SIZE - 1;
+
+/// The cluster entry of clusters that are not available
+const CLUSTER_NOT_PRESENT: usize = 0xFFFFFFFF;
+
 pub unsafe fn copy_pages(dst: *mut u8, src: *const u8, size: usize) {
     let read_cluster = address_to_cluster(src as usize);
     let write_cluster = address_to_cluster(dst as usize);
@@ -40,6 +47,24 @@ pub unsafe fn copy_pages(dst: *mut u8, src: *const u8, size: usize) {
     }
 }
 
+/// Copy `src` to the clusters starting at `dst`, which do not need to be mapped
+pub unsafe fn copy_to_pages(dst: *mut u8, src: &[u8]) {
+    let write_cluster = address_to_cluster(dst as usize);
+
+    for (i, chunk) in src.chunks(CLUSTER_SIZE).enumerate() {
+        let write_address = cluster_to_address(write_cluster + i);
+
+        let mut write_page = Page::new(write_address);
+        let write_old = write_page.entry_data();
+        write_page.map_kernel_write(write_address);
+
+        ::memmove(write_address as *mut u8, chunk.as_ptr(), chunk.len());
+
+        write_page.set_entry_data(write_old);
+        write_page.flush();
+    }
+}
+
 /// A wrapper around raw pointers
 pub struct Memory<T> {
     ptr: *mut T,
@@ -245,7 +270,7 @@ pub unsafe fn cluster_to_address(number: usize) -> usize {
 pub unsafe fn cluster_init() {
     // First, set all clusters to the not present value
     for cluster in 0..CLUSTER_COUNT {
-        set_cluster(cluster, 0xFFFFFFFF);
+        set_cluster(cluster, CLUSTER_NOT_PRESENT);
     }
 
     // Next, set all valid clusters to the free value
@@ -326,7 +351,7 @@ pub unsafe fn alloc_size(ptr: usize) -> usize {
 
     if ptr > 0 {
         for i in address_to_cluster(ptr)..CLUSTER_COUNT {
-            if cluster(i) == ptr {
+            if cluster(i) & !CLUSTER_REFS == ptr {
                 size += CLUSTER_SIZE;
             } else {
                 break;
@@ -340,7 +365,7 @@ pub unsafe fn alloc_size(ptr: usize) -> usize {
 pub unsafe fn unalloc(ptr: usize) {
     if ptr > 0 {
         for i in address_to_cluster(ptr)..CLUSTER_COUNT {
-            if cluster(i) == ptr {
+            if cluster(i) & !CLUSTER_REFS == ptr {
                 set_cluster(i, 0);
             } else {
                 break;
@@ -353,6 +378,80 @@ pub unsafe fn unalloc_type<T>(ptr: *mut T) {
     unalloc(ptr as usize);
 }
 
+/// Get the number of references to the cluster containing `address`
+///
+/// Returns 0 if the cluster is not allocated.
+pub unsafe fn page_refs(address: usize) -> usize {
+    let data = cluster(address_to_cluster(address));
+    if data == 0 || data == CLUSTER_NOT_PRESENT {
+        0
+    } else {
+        (data & CLUSTER_REFS) + 1
+    }
+}
+
+/// Add a reference to every cluster in a range, so that it can be shared
+///
+/// Returns false, without changing anything, if a cluster is not allocated or has too many
+/// references.
+pub unsafe fn ref_pages(address: usize, size: usize) -> bool {
+    let first = address_to_cluster(address);
+    let count = (size + CLUSTER_SIZE - 1)/CLUSTER_SIZE;
+
+    for i in first..first + count {
+        let data = cluster(i);
+        if data == 0 || data == CLUSTER_NOT_PRESENT || data & CLUSTER_REFS == CLUSTER_REFS - 1 {
+            return false;
+        }
+    }
+
+    for i in first..first + count {
+        set_cluster(i, cluster(i) + 1);
+    }
+
+    true
+}
+
+/// Drop a reference to every cluster in a range, freeing the clusters that are no longer referenced
+pub unsafe fn unref_pages(address: usize, size: usize) {
+    let first = address_to_cluster(address);
+    let count = (size + CLUSTER_SIZE - 1)/CLUSTER_SIZE;
+
+    for i in first..first + count {
+        let data = cluster(i);
+        if data == 0 || data == CLUSTER_NOT_PRESENT {
+            continue;
+        } else if data & CLUSTER_REFS > 0 {
+            set_cluster(i, data - 1);
+        } else {
+            set_cluster(i, 0);
+        }
+    }
+}
+
+/// Drop a reference to every cluster in a range, like `unref_pages`
+///
+/// If the range starts an allocation, the unreferenced clusters of the allocation that follow the
+/// range are freed as well.
+pub unsafe fn unalloc_pages(address: usize, size: usize) {
+    let first = address_to_cluster(address);
+    let count = (size + CLUSTER_SIZE - 1)/CLUSTER_SIZE;
+
+    let starts = cluster(first) & !CLUSTER_REFS == address;
+
+    unref_pages(address, size);
+
+    if starts {
+        for i in first + count..CLUSTER_COUNT {
+            if cluster(i) == address {
+                set_cluster(i, 0);
+            } else {
+                break;
+            }
+        }
+    }
+}
+
 
 pub unsafe fn realloc(ptr: usize, size: usize) -> usize {
     realloc_aligned(ptr, size, 1)
@@ -394,7 +493,7 @@ pub unsafe fn realloc_inplace(ptr: usize, size: usize) -> usize {
 
 pub fn memory_used() -> usize {
     (0..CLUSTER_COUNT).fold(0usize, |ret, i| unsafe {
-        if cluster(i) != 0 && cluster(i) != 0xFFFFFFFF {
+        if cluster(i) != 0 && cluster(i) != CLUSTER_NOT_PRESENT {
             ret + CLUSTER_SIZE
         } else {
             ret
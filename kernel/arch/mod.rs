@@ -1,8 +1,12 @@
+pub mod apic;
+pub mod clock;
 pub mod context;
 pub mod elf;
 pub mod gdt;
+pub mod hpet;
 pub mod idt;
 pub mod memory;
 pub mod paging;
 pub mod regs;
+pub mod smp;
 pub mod tss;
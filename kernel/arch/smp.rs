@@ -0,0 +1,391 @@
+//! Symmetric multiprocessing
+//!
+//! Application processors are started on i386 and x86_64. Each one has its own GDT and TSS, and
+//! maps context memory in its own page tables, see `paging::processor_directory`. Everything
+//! else, including the IDT and kernel memory, is shared, and only one processor runs kernel code
+//! at a time.
+
+use arch::apic::LOCAL_APIC;
+use arch::gdt::{GdtEntry, GDT_TSS, GDT_TSS_ENTRIES};
+use arch::paging::Page;
+use arch::tss::Tss;
+
+use core::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+use core::{mem, slice, usize};
+
+use sync::SpinLock;
+
+/// The most processors that are used
+pub const MAX_CPUS: usize = 16;
+
+/// Sent by `shootdown`, so the others reload their mappings
+pub const IPI_FLUSH: usize = 0x41;
+/// Sent by `wake_idle`, so halted processors look for a context to run
+pub const IPI_WAKE: usize = 0x42;
+
+/// The number of application processors that were started
+static AP_COUNT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The GDT of each application processor, with the TSS of processor `n` at
+/// `GDT_TSS + n * GDT_TSS_ENTRIES`
+static mut CPU_GDT: [usize; MAX_CPUS] = [0; MAX_CPUS];
+/// The TSS of each application processor
+static mut CPU_TSS: [usize; MAX_CPUS] = [0; MAX_CPUS];
+
+/// The number of entries in the GDT of an application processor
+const GDT_ENTRIES: usize = GDT_TSS + MAX_CPUS * GDT_TSS_ENTRIES;
+
+/// The big kernel lock
+///
+/// Kernel code runs with interrupts disabled and expects to have the environment to itself, so
+/// only one processor runs it at a time. Interrupts take the lock on entry and release it when
+/// they return. A context switch hands the lock to the next context, new contexts release it
+/// before they enter userspace, and the idle loop releases it while halted.
+static KERNEL_LOCK: SpinLock = SpinLock::new();
+/// The processor holding the kernel lock, or `usize::MAX`
+static KERNEL_LOCK_CPU: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The memory that `shootdown` asks other processors to reload
+static mut FLUSH_RANGE: (usize, usize) = (0, 0);
+/// The processors that have to reload their mappings, bit `n` stands for processor `n`
+static FLUSH_PENDING: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The number of the running processor, from 0 for the bootstrap processor
+///
+/// Each processor loads a different TSS selector, so this is read from the task register.
+pub fn cpu_id() -> usize {
+    let selector: u16;
+    unsafe { asm!("str $0" : "=r"(selector) : : : "intel", "volatile"); }
+    (selector as usize / mem::size_of::<GdtEntry>()).saturating_sub(GDT_TSS) / GDT_TSS_ENTRIES
+}
+
+/// The number of processors that are running contexts
+pub fn cpu_count() -> usize {
+    AP_COUNT.load(Ordering::SeqCst) + 1
+}
+
+/// The GDT of the running processor
+pub unsafe fn gdt() -> Option<&'static mut [GdtEntry]> {
+    match cpu_id() {
+        0 => match ::GDT_PTR {
+            Some(ref mut gdt) => Some(&mut **gdt),
+            None => None,
+        },
+        cpu => Some(slice::from_raw_parts_mut(CPU_GDT[cpu] as *mut GdtEntry, GDT_ENTRIES)),
+    }
+}
+
+/// The TSS of the running processor
+pub unsafe fn tss() -> Option<&'static mut Tss> {
+    match cpu_id() {
+        0 => match ::TSS_PTR {
+            Some(ref mut tss) => Some(&mut **tss),
+            None => None,
+        },
+        cpu => Some(&mut *(CPU_TSS[cpu] as *mut Tss)),
+    }
+}
+
+/// Take the kernel lock, waiting until it is free
+///
+/// Returns false if this processor holds it already, which happens on exceptions in kernel
+/// code, such as page faults on copy on write memory.
+pub fn lock_kernel() -> bool {
+    let cpu = cpu_id();
+    if KERNEL_LOCK.is_locked() && KERNEL_LOCK_CPU.load(Ordering::SeqCst) == cpu {
+        return false;
+    }
+
+    while ! KERNEL_LOCK.try_lock() {
+        // The holder may be waiting for this processor in `shootdown`
+        unsafe {
+            flush_pending(cpu);
+            asm!("pause" : : : "memory" : "intel", "volatile");
+        }
+    }
+    KERNEL_LOCK_CPU.store(cpu, Ordering::SeqCst);
+
+    true
+}
+
+/// Release the kernel lock
+pub fn unlock_kernel() {
+    KERNEL_LOCK_CPU.store(usize::MAX, Ordering::SeqCst);
+    KERNEL_LOCK.unlock();
+}
+
+/// Make the other processors that run a context sharing memory with the current one reload
+/// their mappings, after the memory from `address` to `address + size` was changed
+///
+/// Every processor maps context memory in its own page tables, so threads on other processors
+/// do not see a change until they reload. This waits until they did, and must be called with
+/// the kernel lock held.
+pub unsafe fn shootdown(address: usize, size: usize) {
+    if AP_COUNT.load(Ordering::SeqCst) == 0 {
+        return;
+    }
+
+    let cpu = cpu_id();
+    let contexts = & *::env().contexts.get();
+
+    let mut pending = 0;
+    if let Ok(current) = contexts.current() {
+        for other in 0..MAX_CPUS {
+            if other != cpu {
+                if let Ok(context) = contexts.running(other) {
+                    if context.shares_memory(current) {
+                        pending |= 1 << other;
+                    }
+                }
+            }
+        }
+    }
+
+    if pending == 0 {
+        return;
+    }
+
+    FLUSH_RANGE = (address, size);
+    FLUSH_PENDING.store(pending, Ordering::SeqCst);
+
+    if let Some(ref mut local_apic) = LOCAL_APIC {
+        local_apic.ipi_others(IPI_FLUSH as u8);
+    }
+
+    while FLUSH_PENDING.load(Ordering::SeqCst) != 0 {
+        asm!("pause" : : : "memory" : "intel", "volatile");
+    }
+}
+
+/// Wake the other processors that are running their idle context, after a context became
+/// runnable
+///
+/// Their timers may not fire until the next sleeping context wakes up, so they are sent
+/// `IPI_WAKE`. This must be called with the kernel lock held.
+pub unsafe fn wake_idle() {
+    let cpu = cpu_id();
+    let contexts = & *::env().contexts.get();
+    if (0..MAX_CPUS).any(|other| other != cpu && contexts.is_idle(other)) {
+        if let Some(ref mut local_apic) = LOCAL_APIC {
+            local_apic.ipi_others(IPI_WAKE as u8);
+        }
+    }
+}
+
+/// Reload the mappings of this processor, if `shootdown` asked for it
+///
+/// This runs without the kernel lock, while the processor that holds it waits.
+unsafe fn flush_pending(cpu: usize) {
+    let bit = 1 << cpu;
+    if FLUSH_PENDING.load(Ordering::SeqCst) & bit == bit {
+        let (address, size) = FLUSH_RANGE;
+        let mut page = address / 4096 * 4096;
+        while page < address + size {
+            Page::new(page).map_kernel_write(page);
+            page += 4096;
+        }
+
+        if let Ok(mut current) = (&mut *::env().contexts.get()).current_mut() {
+            current.map();
+        }
+
+        FLUSH_PENDING.fetch_and(! bit, Ordering::SeqCst);
+    }
+}
+
+pub use self::bringup::init;
+
+mod bringup {
+    use acpi::MADT;
+
+    use arch::apic::{self, LOCAL_APIC};
+    use arch::context::{Context, CONTEXT_STACK_SIZE};
+    use arch::gdt::{GdtDescriptor, GdtEntry, GDT_KERNEL_DATA, GDT_TSS, GDT_TSS_ENTRIES};
+    use arch::idt::IdtDescriptor;
+    use arch::memory;
+    use arch::paging;
+    use arch::tss::Tss;
+
+    use core::intrinsics::{volatile_load, volatile_store};
+    use core::sync::atomic::Ordering;
+    use core::{mem, ptr};
+
+    use super::{lock_kernel, AP_COUNT, CPU_GDT, CPU_TSS, GDT_ENTRIES, MAX_CPUS};
+
+    /// Where the application processor startup code is copied. It has to be page aligned and
+    /// below 1 MiB, and this page is free after the bootloader is done with its stack
+    const TRAMPOLINE: usize = 0x6000;
+
+    /// The startup code, assembled from `asm/trampoline.asm`
+    #[cfg(target_arch = "x86")]
+    static TRAMPOLINE_DATA: &'static [u8] = include_bytes!("../../build/trampoline-i386.bin");
+    #[cfg(target_arch = "x86_64")]
+    static TRAMPOLINE_DATA: &'static [u8] = include_bytes!("../../build/trampoline-x86_64.bin");
+
+    /// The arguments of the startup code, which follow its first jump. The page directory is
+    /// the page map level 4 on x86_64
+    const TRAMPOLINE_READY: usize = TRAMPOLINE + 8;
+    const TRAMPOLINE_CPU_ID: usize = TRAMPOLINE + 12;
+    const TRAMPOLINE_PAGE_DIRECTORY: usize = TRAMPOLINE + 16;
+    const TRAMPOLINE_STACK: usize = TRAMPOLINE + 20;
+    const TRAMPOLINE_CODE: usize = TRAMPOLINE + 24;
+
+    /// The IDT is shared by all processors
+    static mut IDTR: IdtDescriptor = IdtDescriptor {
+        size: 0,
+        ptr: 0
+    };
+
+    /// The stack of each application processor, used by its idle context
+    static mut CPU_STACK: [usize; MAX_CPUS] = [0; MAX_CPUS];
+
+    /// A loaded TSS descriptor is marked busy, and cannot be loaded again until it is cleared
+    const TSS_BUSY: u8 = 1 << 1;
+
+    /// Start the enabled processors in the MADT
+    ///
+    /// This must be called with the kernel lock held, by the bootstrap processor, after
+    /// `apic::init`. The started processors wait for the lock before they run contexts.
+    pub unsafe fn init(madt: &MADT) {
+        let bsp_id = match LOCAL_APIC {
+            Some(ref local_apic) => local_apic.id(),
+            None => return,
+        };
+
+        asm!("sidt [$0]" : : "r"(&mut IDTR as *mut IdtDescriptor) : "memory" : "intel", "volatile");
+
+        ::memcpy(TRAMPOLINE as *mut u8, TRAMPOLINE_DATA.as_ptr(), TRAMPOLINE_DATA.len());
+
+        let mut cpu = 1;
+        for entry in madt.local_apics.iter() {
+            if entry.flags & 1 == 1 && entry.id != bsp_id {
+                if cpu >= MAX_CPUS {
+                    syslog_info!("  * SMP: only {} processors are used", MAX_CPUS);
+                    break;
+                }
+
+                if start_ap(cpu, entry.id) {
+                    cpu += 1;
+                } else {
+                    syslog_info!("  * SMP: APIC {} did not start", entry.id);
+                }
+            }
+        }
+
+        syslog_info!("  * SMP: {} processors", cpu);
+    }
+
+    /// Start the processor with `apic_id`, as processor number `cpu`
+    unsafe fn start_ap(cpu: usize, apic_id: u8) -> bool {
+        let stack = memory::alloc(CONTEXT_STACK_SIZE);
+        let gdt = memory::alloc(GDT_ENTRIES * mem::size_of::<GdtEntry>());
+        let tss = memory::alloc_type::<Tss>();
+        let page_directory = paging::processor_directory();
+        if stack == 0 || gdt == 0 || tss.is_null() || page_directory == 0 {
+            memory::unalloc(stack);
+            memory::unalloc(gdt);
+            memory::unalloc(tss as usize);
+            return false;
+        }
+
+        // The GDT is a copy, with the TSS of this processor at its own selector
+        ::memset(gdt as *mut u8, 0, GDT_ENTRIES * mem::size_of::<GdtEntry>());
+        if let Some(ref bsp_gdt) = ::GDT_PTR {
+            ::memcpy(gdt as *mut u8, bsp_gdt.as_ptr() as *const u8, (GDT_TSS + GDT_TSS_ENTRIES) * mem::size_of::<GdtEntry>());
+        }
+        {
+            // The TSS is allocated below 4 GiB, so on x86_64 the upper half of the base in the
+            // second entry stays zero
+            let entries = gdt as *mut GdtEntry;
+            let index = GDT_TSS + cpu * GDT_TSS_ENTRIES;
+            ptr::copy(entries.offset(GDT_TSS as isize), entries.offset(index as isize), GDT_TSS_ENTRIES);
+
+            let entry = &mut *entries.offset(index as isize);
+            entry.set_base(tss as usize);
+            entry.attribute &= ! TSS_BUSY;
+        }
+
+        if let Some(ref bsp_tss) = ::TSS_PTR {
+            ptr::copy(&**bsp_tss as *const Tss, tss, 1);
+        }
+        (*tss).sp0 = stack + CONTEXT_STACK_SIZE - 128;
+
+        CPU_GDT[cpu] = gdt;
+        CPU_TSS[cpu] = tss as usize;
+        CPU_STACK[cpu] = stack;
+
+        volatile_store(TRAMPOLINE_READY as *mut u32, 0);
+        volatile_store(TRAMPOLINE_CPU_ID as *mut u32, cpu as u32);
+        volatile_store(TRAMPOLINE_PAGE_DIRECTORY as *mut u32, page_directory as u32);
+        volatile_store(TRAMPOLINE_STACK as *mut u32, (stack + CONTEXT_STACK_SIZE - 128) as u32);
+        volatile_store(TRAMPOLINE_CODE as *mut u32, kstart_ap as usize as u32);
+
+        if let Some(ref mut local_apic) = LOCAL_APIC {
+            local_apic.ipi_init(apic_id);
+            apic::delay(10000);
+
+            // A second startup IPI is sent if the first one was missed
+            for _ in 0..2 {
+                local_apic.ipi_startup(apic_id, TRAMPOLINE);
+                apic::delay(200);
+                if volatile_load(TRAMPOLINE_READY as *const u32) != 0 {
+                    break;
+                }
+            }
+        }
+
+        for _ in 0..1000 {
+            if volatile_load(TRAMPOLINE_READY as *const u32) != 0 {
+                return true;
+            }
+            apic::delay(100);
+        }
+
+        false
+    }
+
+    /// The kernel entry of application processors, called by the startup code
+    ///
+    /// It runs on the stack of the idle context of the processor, and never returns. The startup
+    /// code passes the processor number on the stack on i386, and in rdi on x86_64.
+    unsafe extern "cdecl" fn kstart_ap(cpu: usize) -> ! {
+        let gdtr = GdtDescriptor {
+            size: (GDT_ENTRIES * mem::size_of::<GdtEntry>() - 1) as u16,
+            ptr: CPU_GDT[cpu] as _
+        };
+        asm!("lgdt [$0]" : : "r"(&gdtr as *const GdtDescriptor) : "memory" : "intel", "volatile");
+        asm!("lidt [$0]" : : "r"(&IDTR as *const IdtDescriptor) : "memory" : "intel", "volatile");
+
+        asm!("mov ds, ax
+            mov es, ax
+            mov fs, ax
+            mov gs, ax
+            mov ss, ax"
+            :
+            : "{ax}"((GDT_KERNEL_DATA * mem::size_of::<GdtEntry>()) as u16)
+            : "memory"
+            : "intel", "volatile");
+        asm!("ltr ax"
+            :
+            : "{ax}"(((GDT_TSS + cpu * GDT_TSS_ENTRIES) * mem::size_of::<GdtEntry>()) as u16)
+            : "memory"
+            : "intel", "volatile");
+
+        apic::init_ap();
+
+        lock_kernel();
+
+        {
+            let mut idle = Context::root();
+            idle.name = format!("kidle{}", cpu).into();
+            idle.kernel_stack = CPU_STACK[cpu];
+            (&mut *::env().contexts.get()).push_idle(cpu, idle);
+        }
+
+        AP_COUNT.fetch_add(1, Ordering::SeqCst);
+
+        ::idle_loop();
+
+        loop {}
+    }
+}
@@ -1,3 +1,4 @@
+use arch::context::CONTEXT_IMAGE_ADDR;
 use arch::memory;
 
 use core::ptr;
@@ -303,9 +304,62 @@ impl Page {
         self.flush();
     }
 
+    /// Map the memory page to a given physical memory address, and allow userspace access with
+    /// extra page flags, such as `PF_WRITE` or `PF_CACHE_DISABLE`
+    pub unsafe fn map_user_flags(&mut self, physical_address: usize, flags: usize) {
+        self.set_entry_data((physical_address & PF_NONE) | (flags & PF_ALL) | PF_USER | PF_PRESENT);
+        self.flush();
+    }
+
     /// Unmap the memory page
     pub unsafe fn unmap(&mut self) {
         self.set_entry_data(0);
         self.flush();
     }
 }
+
+/// Create the page directory of an application processor
+///
+/// Kernel memory is shared, but context memory is mapped in page tables that are private to the
+/// processor, so that processors can run different contexts. The private tables are mapped where
+/// the shared ones are, so `Page` changes the tables of the processor it runs on.
+///
+/// Returns 0 if the memory could not be allocated.
+pub unsafe fn processor_directory() -> usize {
+    let first = CONTEXT_IMAGE_ADDR / (PAGE_TABLE_SIZE * PAGE_SIZE);
+    let last = memory::LOGICAL_OFFSET / (PAGE_TABLE_SIZE * PAGE_SIZE);
+
+    // The tables that map the private tables. They have to fit in one
+    let window = (PAGE_TABLES + first * PAGE_SIZE) / (PAGE_TABLE_SIZE * PAGE_SIZE);
+    debug_assert_eq!(window, (PAGE_TABLES + last * PAGE_SIZE - 1) / (PAGE_TABLE_SIZE * PAGE_SIZE));
+
+    let tables = memory::alloc_aligned((last - first) * PAGE_SIZE, PAGE_SIZE);
+    let window_table = memory::alloc_aligned(PAGE_SIZE, PAGE_SIZE);
+    let directory = memory::alloc_aligned(PAGE_SIZE, PAGE_SIZE);
+    if tables == 0 || window_table == 0 || directory == 0 {
+        memory::unalloc(tables);
+        memory::unalloc(window_table);
+        memory::unalloc(directory);
+        return 0;
+    }
+
+    // No context is mapped yet, so the private tables start as copies of the shared ones
+    ::memcpy(tables as *mut u8, (PAGE_TABLES + first * PAGE_SIZE) as *const u8, (last - first) * PAGE_SIZE);
+
+    ::memcpy(window_table as *mut u8, (PAGE_TABLES + window * PAGE_SIZE) as *const u8, PAGE_SIZE);
+    for table_i in first..last {
+        let virtual_address = PAGE_TABLES + table_i * PAGE_SIZE;
+        ptr::write((window_table + ((virtual_address / PAGE_SIZE) % PAGE_TABLE_SIZE) * PAGE_ENTRY_SIZE) as *mut usize,
+                   (tables + (table_i - first) * PAGE_SIZE) | PF_WRITE | PF_PRESENT);
+    }
+
+    ::memcpy(directory as *mut u8, PAGE_DIRECTORY as *const u8, PAGE_SIZE);
+    ptr::write((directory + window * PAGE_ENTRY_SIZE) as *mut usize,
+               window_table | PF_USER | PF_WRITE | PF_PRESENT);
+    for table_i in first..last {
+        ptr::write((directory + table_i * PAGE_ENTRY_SIZE) as *mut usize,
+                   (tables + (table_i - first) * PAGE_SIZE) | PF_USER | PF_WRITE | PF_PRESENT);
+    }
+
+    directory
+}
@@ -5,6 +5,8 @@ pub const GDT_USER_CODE: usize = 3;
 pub const GDT_USER_DATA: usize = 4;
 pub const GDT_USER_TLS: usize = 5;
 pub const GDT_TSS: usize = 6;
+/// The number of entries a TSS descriptor takes, as they are extended to 16 bytes
+pub const GDT_TSS_ENTRIES: usize = 2;
 
 #[repr(packed)]
 pub struct GdtDescriptor {
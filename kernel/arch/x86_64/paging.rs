@@ -1,3 +1,6 @@
+use arch::context::CONTEXT_IMAGE_ADDR;
+use arch::memory;
+
 use core::ptr;
 
 //Page flags
@@ -154,9 +157,80 @@ impl Page {
         self.flush();
     }
 
+    /// Map the memory page to a given physical memory address and allow userspace access with
+    /// extra page flags, such as `PF_WRITE` or `PF_CACHE_DISABLE`
+    pub unsafe fn map_user_flags(&mut self, physical_address: usize, flags: usize) {
+        ptr::write(self.entry_address() as *mut usize,
+                   (physical_address & PF_NONE) | (flags & PF_ALL) | PF_USER | PF_PRESENT);
+        self.flush();
+    }
+
     /// Unmap the memory page
     pub unsafe fn unmap(&mut self) {
         ptr::write(self.entry_address() as *mut usize, 0);
         self.flush();
     }
 }
+
+/// Create the page tables of an application processor, and return its page map level 4
+///
+/// Kernel memory is shared, but context memory is mapped in page tables that are private to the
+/// processor, so that processors can run different contexts. The private tables are mapped where
+/// the shared ones are, so `Page` changes the tables of the processor it runs on. The levels
+/// above them point to the private tables, so they are private copies as well.
+///
+/// Returns 0 if the memory could not be allocated.
+pub unsafe fn processor_directory() -> usize {
+    let first = CONTEXT_IMAGE_ADDR / (PAGE_TABLE_SIZE * PAGE_SIZE);
+    let last = memory::LOGICAL_OFFSET / (PAGE_TABLE_SIZE * PAGE_SIZE);
+
+    // The tables that map the private tables. They must not be private tables themselves
+    let window_first = (PAGE_TABLES + first * PAGE_SIZE) / (PAGE_TABLE_SIZE * PAGE_SIZE);
+    let window_last = (PAGE_TABLES + last * PAGE_SIZE - 1) / (PAGE_TABLE_SIZE * PAGE_SIZE) + 1;
+    debug_assert!(window_last <= first || window_first >= last);
+
+    let tables = memory::alloc_aligned((last - first) * PAGE_SIZE, PAGE_SIZE);
+    let window_tables = memory::alloc_aligned((window_last - window_first) * PAGE_SIZE, PAGE_SIZE);
+    let directories = memory::alloc_aligned(4 * PAGE_SIZE, PAGE_SIZE);
+    let dir_ptrs = memory::alloc_aligned(PAGE_SIZE, PAGE_SIZE);
+    let level_4 = memory::alloc_aligned(PAGE_SIZE, PAGE_SIZE);
+    if tables == 0 || window_tables == 0 || directories == 0 || dir_ptrs == 0 || level_4 == 0 {
+        memory::unalloc(tables);
+        memory::unalloc(window_tables);
+        memory::unalloc(directories);
+        memory::unalloc(dir_ptrs);
+        memory::unalloc(level_4);
+        return 0;
+    }
+
+    // No context is mapped yet, so the private tables start as copies of the shared ones
+    ::memcpy(tables as *mut u8, (PAGE_TABLES + first * PAGE_SIZE) as *const u8, (last - first) * PAGE_SIZE);
+
+    ::memcpy(window_tables as *mut u8, (PAGE_TABLES + window_first * PAGE_SIZE) as *const u8, (window_last - window_first) * PAGE_SIZE);
+    for table_i in first..last {
+        let virtual_address = PAGE_TABLES + table_i * PAGE_SIZE;
+        ptr::write((window_tables + (virtual_address / PAGE_SIZE - window_first * PAGE_TABLE_SIZE) * PAGE_ENTRY_SIZE) as *mut usize,
+                   (tables + (table_i - first) * PAGE_SIZE) | PF_WRITE | PF_PRESENT);
+    }
+
+    ::memcpy(directories as *mut u8, PAGE_DIRECTORIES as *const u8, 4 * PAGE_SIZE);
+    for table_i in window_first..window_last {
+        ptr::write((directories + table_i * PAGE_ENTRY_SIZE) as *mut usize,
+                   (window_tables + (table_i - window_first) * PAGE_SIZE) | PF_USER | PF_WRITE | PF_PRESENT);
+    }
+    for table_i in first..last {
+        ptr::write((directories + table_i * PAGE_ENTRY_SIZE) as *mut usize,
+                   (tables + (table_i - first) * PAGE_SIZE) | PF_USER | PF_WRITE | PF_PRESENT);
+    }
+
+    ::memcpy(dir_ptrs as *mut u8, PAGE_DIR_PTRS as *const u8, PAGE_SIZE);
+    for dp_i in 0..4 {
+        ptr::write((dir_ptrs + dp_i * PAGE_ENTRY_SIZE) as *mut usize,
+                   (directories + dp_i * PAGE_SIZE) | PF_USER | PF_WRITE | PF_PRESENT);
+    }
+
+    ::memcpy(level_4 as *mut u8, PAGE_LEVEL_4 as *const u8, PAGE_SIZE);
+    ptr::write(level_4 as *mut usize, dir_ptrs | PF_USER | PF_WRITE | PF_PRESENT);
+
+    level_4
+}
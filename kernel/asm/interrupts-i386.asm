@@ -11,17 +11,19 @@ USE32
 
 interrupts:
 .first:
-    mov [.entry], byte 0
+    push strict dword 0
     jmp dword .handle
 .second:
 %assign i 1
 %rep 255
-    mov [.entry], byte i
+    push strict dword i
     jmp dword .handle
 %assign i i+1
 %endrep
 .handle:
-    push ebp
+    ; The interrupt number is swapped into ebp, so its slot holds ebp like before.
+    ; It is kept on the stack, as a global could be overwritten by another processor
+    xchg [esp], ebp
     push esi
     push edi
     push edx
@@ -29,8 +31,12 @@ interrupts:
     push ebx
     push eax
 
-    push esp
-    push dword [.entry]
+    ; The interrupt number is kept in a slot of its own for the return path, as the handler
+    ; may change its argument slots
+    mov eax, esp
+    push ebp
+    push eax
+    push ebp
 
     mov eax, gdt.kernel_data
     mov ds, eax
@@ -40,6 +46,8 @@ interrupts:
 
     	call dword [.handler]
 
+    lea esp, [esp + 8] ; Skip the arguments
+
     mov eax, gdt.user_data | 3
     mov ds, eax
     mov es, eax
@@ -47,7 +55,10 @@ interrupts:
     mov eax, gdt.user_tls | 3
     mov gs, eax
 
-    add esp, 8 ; Skip interrupt code and reg pointer
+    ; A page fault that was handled returns here, with its error code still on the stack.
+    ; Neither lea nor pop change the flags, so the result is used after restoring the registers
+    cmp dword [esp], 0xE
+    lea esp, [esp + 4] ; Skip interrupt code
 
     pop eax
     pop ebx
@@ -57,10 +68,12 @@ interrupts:
     pop esi
     pop ebp
 
+    jne .return
+    lea esp, [esp + 4] ; Skip error code
+.return:
     iretd
 
 .handler: dd 0
-.entry: dd 0
 
 idtr:
     dw (idt.end - idt) + 1
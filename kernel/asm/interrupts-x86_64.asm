@@ -12,17 +12,19 @@ SECTION .text
 USE64
 interrupts:
 .first:
-	mov [.entry], byte 0
+	push strict qword 0
     jmp qword .handle
 .second:
 %assign i 1
 %rep 255
-	mov [.entry], byte i
+	push strict qword i
     jmp qword .handle
 %assign i i+1
 %endrep
 .handle:
-	push rbp
+	; The interrupt number is swapped into rbp, so its slot holds rbp like before.
+	; It is kept on the stack, as a global could be overwritten by another processor
+	xchg [rsp], rbp
 	push r15
 	push r14
 	push r13
@@ -38,10 +40,12 @@ interrupts:
 	push rbx
 	push rax
 
+	; The arguments are passed in rdi and rsi. The interrupt number is kept in a slot of its own
+	; for the return path, next to the reg pointer, which keeps the stack aligned
 	mov rsi, rsp
+	mov rdi, rbp
+	push rbp
 	push rsi
-	mov rdi, qword [.entry]
-	push rdi
 
     mov rax, gdt.kernel_data
     mov ds, rax
@@ -58,7 +62,10 @@ interrupts:
 	mov rax, gdt.user_tls | 3
     mov fs, rax
 
-	add rsp, 16 ; Skip interrupt code and reg pointer
+	; A page fault that was handled returns here, with its error code still on the stack.
+	; Neither lea nor pop change the flags, so the result is used after restoring the registers
+	cmp qword [rsp + 8], 0xE
+	lea rsp, [rsp + 16] ; Skip reg pointer and interrupt code
 
 	pop rax
 	pop rbx
@@ -76,10 +83,12 @@ interrupts:
 	pop r15
 	pop rbp
 
+	jne .return
+	lea rsp, [rsp + 8] ; Skip error code
+.return:
     iretq
 
 .handler: dq 0
-.entry: dq 0
 
 idtr:
     dw (idt.end - idt) + 1
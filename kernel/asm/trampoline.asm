@@ -0,0 +1,176 @@
+; Application processor startup
+;
+; The bootstrap processor copies this to 0x6000, fills in the arguments after the jump, and
+; sends the startup IPI with vector 0x06. The application processor starts here in real mode,
+; switches to protected mode with paging, or to long mode on x86_64, and calls the kernel with
+; its processor number. The arguments are below 4 GiB on both, so they take 32 bits.
+; The arguments are read before ready is set, so that the next processor can be started.
+ORG 0x6000
+SECTION .text
+USE16
+
+trampoline:
+    jmp short startup_ap
+    times 8 - ($ - trampoline) nop
+.ready: dd 0
+.cpu_id: dd 0
+.page_directory: dd 0
+.stack: dd 0
+.code: dd 0
+
+startup_ap:
+    cli
+
+    xor ax, ax
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+
+    ; enable fpu and sse, like initialize.fpu and initialize.sse do for the bootstrap processor
+    mov eax, cr0
+    and al, 11110011b
+    or al, 00100010b
+    mov cr0, eax
+    mov eax, cr4
+    or eax, 0000011000000000b
+    mov cr4, eax
+    fninit
+
+    lgdt [gdtr]
+
+%ifdef ARCH_x86_64
+    ; enable page address extension and page size extension, like startup_arch does
+    mov eax, cr4
+    or eax, 1 << 5 | 1 << 4
+    mov cr4, eax
+
+    ; the page map level 4 of this processor
+    mov eax, [trampoline.page_directory]
+    mov cr3, eax
+
+    ; set the long mode enable bit in the EFER MSR
+    mov ecx, 0xC0000080
+    rdmsr
+    or eax, 1 << 8
+    wrmsr
+
+    ; enable paging, write protection and protected mode at once
+    mov eax, cr0
+    or eax, 1 << 31 | 1 << 16 | 1
+    mov cr0, eax
+
+    ; far jump to enable long mode and load CS with 64 bit segment
+    jmp gdt.kernel_code:long_mode_ap
+
+USE64
+long_mode_ap:
+    mov rax, gdt.kernel_data
+    mov ds, rax
+    mov es, rax
+    mov fs, rax
+    mov gs, rax
+    mov ss, rax
+
+    ; writing the 32 bit registers clears the upper halves
+    mov edi, [trampoline.cpu_id]
+    mov esp, [trampoline.stack]
+    mov eax, [trampoline.code]
+    mov dword [trampoline.ready], 1
+
+    call rax
+.halt:
+    cli
+    hlt
+    jmp .halt
+%else
+
+    mov eax, cr0
+    or eax, 1
+    mov cr0, eax
+
+    ; far jump to load CS with 32 bit segment
+    jmp gdt.kernel_code:protected_mode_ap
+
+USE32
+protected_mode_ap:
+    mov eax, gdt.kernel_data
+    mov ds, eax
+    mov es, eax
+    mov fs, eax
+    mov gs, eax
+    mov ss, eax
+
+    ; enable paging and write protection, with the page directory of this processor
+    mov eax, [trampoline.page_directory]
+    mov cr3, eax
+    mov eax, cr0
+    or eax, 1 << 31 | 1 << 16
+    mov cr0, eax
+
+    mov ecx, [trampoline.cpu_id]
+    mov esp, [trampoline.stack]
+    mov eax, [trampoline.code]
+    mov dword [trampoline.ready], 1
+
+    push ecx
+    call eax
+.halt:
+    cli
+    hlt
+    jmp .halt
+%endif
+
+%include "asm/descriptor_flags.inc"
+%include "asm/gdt_entry.inc"
+
+gdtr:
+    dw gdt.end - 1  ; size
+    dd gdt          ; offset
+
+gdt:
+.null equ $ - gdt
+    dq 0
+
+%ifdef ARCH_x86_64
+.kernel_code equ $ - gdt
+    istruc GDTEntry
+        at GDTEntry.limitl, dw 0
+        at GDTEntry.basel, dw 0
+        at GDTEntry.basem, db 0
+        at GDTEntry.attribute, db attrib.present | attrib.user | attrib.code
+        at GDTEntry.flags__limith, db flags.long_mode
+        at GDTEntry.baseh, db 0
+    iend
+
+.kernel_data equ $ - gdt
+    istruc GDTEntry
+        at GDTEntry.limitl, dw 0
+        at GDTEntry.basel, dw 0
+        at GDTEntry.basem, db 0
+        at GDTEntry.attribute, db attrib.present | attrib.user | attrib.writable
+        at GDTEntry.flags__limith, db 0
+        at GDTEntry.baseh, db 0
+    iend
+%else
+
+.kernel_code equ $ - gdt
+    istruc GDTEntry
+        at GDTEntry.limitl, dw 0xFFFF
+        at GDTEntry.basel, dw 0
+        at GDTEntry.basem, db 0
+        at GDTEntry.attribute, db attrib.present | attrib.user | attrib.code | attrib.readable
+        at GDTEntry.flags__limith, db 0xFF | flags.granularity | flags.default_operand_size
+        at GDTEntry.baseh, db 0
+    iend
+
+.kernel_data equ $ - gdt
+    istruc GDTEntry
+        at GDTEntry.limitl, dw 0xFFFF
+        at GDTEntry.basel, dw 0
+        at GDTEntry.basem, db 0
+        at GDTEntry.attribute, db attrib.present | attrib.user | attrib.writable
+        at GDTEntry.flags__limith, db 0xFF | flags.granularity | flags.default_operand_size
+        at GDTEntry.baseh, db 0
+    iend
+%endif
+.end equ $ - gdt
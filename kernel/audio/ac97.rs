@@ -104,7 +104,7 @@ impl Resource for Ac97Resource {
                     {
                         let contexts = &mut *::env().contexts.get();
                         if let Ok(mut current) = contexts.current_mut() {
-                            current.wake = Some(Duration::monotonic() + Duration::new(0, 10 * time::NANOS_PER_MILLI));
+                            current.sleep(Duration::monotonic() + Duration::new(0, 10 * time::NANOS_PER_MILLI));
                             current.block("AC97 sleep 1");
                         }
                     }
@@ -166,7 +166,7 @@ impl Resource for Ac97Resource {
                 {
                     let contexts = &mut *::env().contexts.get();
                     if let Ok(mut current) = contexts.current_mut() {
-                        current.wake = Some(Duration::monotonic() + Duration::new(0, 10 * time::NANOS_PER_MILLI));
+                        current.sleep(Duration::monotonic() + Duration::new(0, 10 * time::NANOS_PER_MILLI));
                         current.block("AC97 sleep 2");
                     }
                 }
@@ -1,3 +1,5 @@
+use arch::clock;
+
 use core::cmp::Ordering;
 use core::ops::{Add, Sub};
 
@@ -34,13 +36,19 @@ impl Duration {
     }
 
     /// Get the current duration
+    ///
+    /// This is read from the high resolution clock if it was started, and otherwise advances
+    /// with the timer ticks.
     pub fn monotonic() -> Self {
-        unsafe { *::env().clock_monotonic.get() }
+        clock::monotonic().unwrap_or(unsafe { *::env().clock_monotonic.get() })
     }
 
     /// Get the realtime
+    ///
+    /// The real time clock is kept at the last tick, so the monotonic time since then is added.
     pub fn realtime() -> Self {
-        unsafe { *::env().clock_realtime.get() }
+        let (realtime, monotonic) = unsafe { (*::env().clock_realtime.get(), *::env().clock_monotonic.get()) };
+        realtime + (Duration::monotonic() - monotonic)
     }
 }
 
@@ -20,7 +20,7 @@ pub struct Ahci;
 impl Ahci {
     pub fn disks(mut pci: PciConfig) -> Vec<Box<Disk>> {
         let base = unsafe { (pci.read(0x24) & 0xFFFFFFF0) as usize };
-        let irq = unsafe { (pci.read(0x3C) & 0xF) as u8 };
+        let irq = unsafe { pci.irq() };
 
         syslog_info!(" + AHCI on: {:X} IRQ: {:X}", base as usize, irq);
 
@@ -45,6 +45,10 @@ impl Ahci {
                                       })
                                       .collect();
 
+        if ret.is_empty() {
+            unsafe { pci.free_irq(irq) };
+        }
+
         ret
     }
 }
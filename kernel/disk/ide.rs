@@ -532,8 +532,11 @@ impl IdeDisk {
         // debugln!("IDE DMA BLOCK: {} SECTORS: {} BUF: {:X} WRITE: {}", block, sectors, buf, write);
 
         if sectors > 0 {
-            let contexts = unsafe { & *::env().contexts.get() };
-            let current = try!(contexts.current());
+            let contexts = unsafe { &mut *::env().contexts.get() };
+            let current = try!(contexts.current_mut());
+            if ! write {
+                current.unshare(buf, sectors * 512);
+            }
             let physical_address = try!(current.translate(buf, sectors * 512));
 
             // debugln!("IDE DMA TRANSLATED {:X}", physical_address);
@@ -1,5 +1,7 @@
 use drivers::io::{Io, Pio};
 
+use super::msi;
+
 /// A PCI configuration
 #[derive(Copy, Clone)]
 pub struct PciConfig {
@@ -51,5 +53,47 @@ impl PciConfig {
         self.write(offset, value);
     }
 
+    /// The offset of the first capability with `id`, if the function has one
+    pub unsafe fn capability(&mut self, id: u8) -> Option<u8> {
+        // Bit 4 of the status register is set if there is a capability list
+        if self.read(0x04) & 1 << 20 == 0 {
+            return None;
+        }
+
+        // The list is walked at most 48 times, the most that fit in config space, in case of a loop
+        let mut offset = self.read(0x34) as u8 & 0xFC;
+        for _ in 0..48 {
+            if offset == 0 {
+                break;
+            }
+
+            let header = self.read(offset);
+            if header as u8 == id {
+                return Some(offset);
+            }
+            offset = (header >> 8) as u8 & 0xFC;
+        }
+
+        None
+    }
+
+    /// The IRQ that a driver of this function receives in `KScheme::on_irq`
+    ///
+    /// This is a message signalled interrupt if one could be enabled, or else the interrupt
+    /// line.
+    pub unsafe fn irq(&mut self) -> u8 {
+        match msi::enable(self) {
+            Some(irq) => irq,
+            None => self.read(0x3C) as u8 & 0xF,
+        }
+    }
+
+    /// Give back the IRQ from `irq`, when no driver uses the function
+    ///
+    /// A message signalled interrupt is disabled and its vector is freed.
+    pub unsafe fn free_irq(&mut self, irq: u8) {
+        msi::disable(self, irq);
+    }
+
     // TODO: Write functions to get data structures
 }
@@ -1,5 +1,6 @@
 pub mod config;
 pub mod common;
 mod init;
+pub mod msi;
 
 pub use drivers::pci::init::pci_init;
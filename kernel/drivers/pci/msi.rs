@@ -0,0 +1,129 @@
+use arch::apic;
+
+use drivers::io::{Io, Mmio};
+
+use super::config::PciConfig;
+
+/// The capability IDs
+const CAP_MSI: u8 = 0x05;
+const CAP_MSIX: u8 = 0x11;
+
+/// The message control flags, in the upper half of the first capability register
+const MSI_ENABLE: u32 = 1 << 16;
+const MSI_MULTIPLE_MESSAGE_ENABLE: u32 = 0b111 << 20;
+const MSI_64BIT: u32 = 1 << 23;
+const MSIX_FUNCTION_MASK: u32 = 1 << 30;
+const MSIX_ENABLE: u32 = 1 << 31;
+
+/// The type of a memory BAR, which is 64 bits wide if it takes two registers
+const BAR_TYPE: u32 = 0b110;
+const BAR_TYPE_64: u32 = 0b100;
+
+/// The interrupt disable flag of the command register, which turns off the interrupt line
+const COMMAND_INTERRUPT_DISABLE: u32 = 1 << 10;
+
+/// An entry of the MSI-X table
+#[repr(packed)]
+struct MsixEntry {
+    address_low: Mmio<u32>,
+    address_high: Mmio<u32>,
+    data: Mmio<u32>,
+    vector_control: Mmio<u32>,
+}
+
+/// Enable a message signalled interrupt for the function, preferring MSI-X to MSI
+///
+/// The message is a vector allocated from the local APIC, and the IRQ it is delivered as is
+/// returned. The interrupt line is disabled. Returns None if the function has neither a usable
+/// capability, or no vector could be allocated.
+pub unsafe fn enable(pci: &mut PciConfig) -> Option<u8> {
+    // The MSI-X table is in a memory BAR, at an offset with the BAR index in the low bits. A
+    // 64-bit BAR has the upper half of the address in the next register
+    let msix = match pci.capability(CAP_MSIX) {
+        Some(offset) => {
+            let table = pci.read(offset + 4);
+            let bar_offset = 0x10 + (table as u8 & 0b111) * 4;
+            let bar = pci.read(bar_offset);
+            if bar & 1 == 0 {
+                let mut base = (bar & 0xFFFFFFF0) as u64;
+                if bar & BAR_TYPE == BAR_TYPE_64 {
+                    base |= (pci.read(bar_offset + 4) as u64) << 32;
+                }
+
+                let table_address = base + (table & 0xFFFFFFF8) as u64;
+                if table_address <= usize::max_value() as u64 {
+                    Some((offset, table_address as usize))
+                } else {
+                    None
+                }
+            } else {
+                None
+            }
+        },
+        None => None
+    };
+    let msi = pci.capability(CAP_MSI);
+    if msix.is_none() && msi.is_none() {
+        return None;
+    }
+
+    let (address, vector) = match apic::alloc_msi() {
+        Some(message) => message,
+        None => return None,
+    };
+
+    // Fixed delivery and edge triggered
+    let data = vector as u32;
+
+    if let Some((offset, table_address)) = msix {
+        let control = pci.read(offset);
+        pci.write(offset, control | MSIX_ENABLE | MSIX_FUNCTION_MASK);
+
+        // Only the first entry is used, the others stay masked like they were after reset
+        let entry = &mut *(table_address as *mut MsixEntry);
+        entry.address_low.write(address);
+        entry.address_high.write(0);
+        entry.data.write(data);
+        entry.vector_control.write(0);
+
+        pci.write(offset, (control | MSIX_ENABLE) & ! MSIX_FUNCTION_MASK);
+    } else if let Some(offset) = msi {
+        let control = pci.read(offset);
+        pci.write(offset + 4, address);
+        if control & MSI_64BIT == MSI_64BIT {
+            pci.write(offset + 8, 0);
+            pci.write(offset + 12, data);
+        } else {
+            pci.write(offset + 8, data);
+        }
+
+        // A single message
+        pci.write(offset, control & ! MSI_MULTIPLE_MESSAGE_ENABLE | MSI_ENABLE);
+    }
+
+    pci.flag(0x04, COMMAND_INTERRUPT_DISABLE, true);
+
+    Some(vector - 0x20)
+}
+
+/// Disable the message signalled interrupt that `enable` returned `irq` for, and free its vector
+///
+/// The interrupt line is enabled again. Nothing changes if `irq` is not a message signalled
+/// interrupt.
+pub unsafe fn disable(pci: &mut PciConfig, irq: u8) {
+    let vector = irq + 0x20;
+    if vector < apic::MSI_VECTOR_FIRST || vector > apic::MSI_VECTOR_LAST {
+        return;
+    }
+
+    if let Some(offset) = pci.capability(CAP_MSIX) {
+        pci.flag(offset, MSIX_ENABLE, false);
+    }
+    if let Some(offset) = pci.capability(CAP_MSI) {
+        pci.flag(offset, MSI_ENABLE, false);
+    }
+
+    pci.flag(0x04, COMMAND_INTERRUPT_DISABLE, false);
+
+    apic::free_msi(vector);
+}
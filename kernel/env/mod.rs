@@ -14,8 +14,9 @@ use network::Nic;
 use fs::{KScheme, Resource, Scheme, VecResource};
 use sync::WaitQueue;
 
-use system::error::{Error, Result, ENOENT, EEXIST};
-use system::syscall::{MODE_DIR, O_CREAT};
+use system::error::{Error, Result, ENOENT, EEXIST, EXDEV};
+use system::scheme::check_access;
+use system::syscall::{MODE_DIR, MODE_PERM, O_ACCMODE, O_CREAT, O_EXCL, O_TRUNC, Stat};
 
 use self::console::Console;
 use self::log::Log;
@@ -122,13 +123,43 @@ impl Environment {
         } else {
             for mut scheme in unsafe { &mut *self.schemes.get() }.iter_mut() {
                 if scheme.scheme() == url_scheme {
-                    return scheme.open(url, flags);
+                    // Check an existing resource before an open that would create or truncate it
+                    if flags & (O_CREAT | O_TRUNC) != 0 {
+                        if let Ok(existing) = scheme.open(url, flags & !(O_ACCMODE | O_CREAT | O_TRUNC | O_EXCL)) {
+                            try!(Environment::check_access(&*existing, flags));
+                        }
+                    }
+
+                    let resource = try!(scheme.open(url, flags));
+                    try!(Environment::check_access(&*resource, flags));
+                    return Ok(resource);
                 }
             }
             Err(Error::new(ENOENT))
         }
     }
 
+    /// Check that the current context may access the resource with the given open flags
+    ///
+    /// This covers the schemes that only report permission bits in stat. For an open with
+    /// `O_CREAT` or `O_TRUNC`, the resource is first opened without them and checked, so that the
+    /// open is refused before it changes anything. Resources that do not support stat, or that
+    /// have no permission bits, can be accessed by anyone.
+    fn check_access(resource: &Resource, flags: usize) -> Result<()> {
+        let (uid, gid) = {
+            let contexts = unsafe { & *::env().contexts.get() };
+            let current = try!(contexts.current());
+            (current.euid, current.egid)
+        };
+
+        let mut stat = Stat::default();
+        if resource.stat(&mut stat).is_err() || stat.st_mode & MODE_PERM == 0 {
+            return Ok(());
+        }
+
+        check_access(stat.st_mode, stat.st_uid, stat.st_gid, uid, gid, flags)
+    }
+
     /// Makes a directory
     pub fn mkdir(&self, url: &str, flags: usize) -> Result<()> {
         if let Some(url_scheme) = url.splitn(2, ":").next() {
@@ -164,4 +195,30 @@ impl Environment {
         }
         Err(Error::new(ENOENT))
     }
+
+    /// Find the scheme shared by two URLs, or fail with `EXDEV` if they are on different schemes
+    fn scheme_pair(&self, old: &str, new: &str) -> Result<&mut Box<KScheme>> {
+        let old_scheme = old.splitn(2, ":").next().unwrap_or("");
+        let new_scheme = new.splitn(2, ":").next().unwrap_or("");
+        if old_scheme != new_scheme {
+            return Err(Error::new(EXDEV));
+        }
+
+        for scheme in unsafe { &mut *self.schemes.get() }.iter_mut() {
+            if scheme.scheme() == old_scheme {
+                return Ok(scheme);
+            }
+        }
+        Err(Error::new(ENOENT))
+    }
+
+    /// Rename a resource, within the same scheme
+    pub fn rename(&self, old: &str, new: &str) -> Result<()> {
+        try!(self.scheme_pair(old, new)).rename(old, new)
+    }
+
+    /// Create a hard link to a resource, within the same scheme
+    pub fn link(&self, old: &str, new: &str) -> Result<()> {
+        try!(self.scheme_pair(old, new)).link(old, new)
+    }
 }
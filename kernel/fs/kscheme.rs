@@ -2,7 +2,7 @@ use super::Resource;
 
 use alloc::boxed::Box;
 
-use system::error::{Error, Result, EPERM};
+use system::error::{Error, Result, ENOSYS, EPERM};
 
 #[allow(unused_variables)]
 pub trait KScheme {
@@ -29,4 +29,12 @@ pub trait KScheme {
     fn unlink(&mut self, path: &str) -> Result<()> {
         Err(Error::new(EPERM))
     }
+
+    fn rename(&mut self, old: &str, new: &str) -> Result<()> {
+        Err(Error::new(ENOSYS))
+    }
+
+    fn link(&mut self, old: &str, new: &str) -> Result<()> {
+        Err(Error::new(ENOSYS))
+    }
 }
@@ -1,7 +1,12 @@
+use alloc::arc::Arc;
 use alloc::boxed::Box;
 
-use system::error::{Error, Result, EPERM, ESPIPE};
-use system::syscall::Stat;
+use arch::context::Context;
+
+use core::cell::Cell;
+
+use system::error::{Error, Result, ENODEV, EPERM, ESPIPE};
+use system::syscall::{Stat, POLLIN, POLLOUT};
 
 /// Resource seek
 #[derive(Copy, Clone, Debug)]
@@ -59,6 +64,48 @@ pub trait Resource {
         Err(Error::new(EPERM))
     }
 
+    /// Get `size` bytes of memory at the page aligned `offset` of the resource, for mapping
+    /// Returns the physical address, and whether the memory was allocated and is owned by the mapping.
+    /// Returns `ENODEV` if the operation is not supported.
+    fn mmap(&mut self, offset: usize, size: usize, writeable: bool) -> Result<(usize, bool)> {
+        Err(Error::new(ENODEV))
+    }
+
+    /// The page flags for the caching of memory from `mmap`, such as `PF_CACHE_DISABLE`
+    /// Returns 0, for write back caching, by default.
+    fn mmap_cache(&self) -> usize {
+        0
+    }
+
+    /// Whether memory that `mmap` allocated is shared directly with clones of the context,
+    /// instead of copied on write, such as a DMA buffer that a device writes to
+    /// Returns false by default.
+    fn mmap_shared(&self) -> bool {
+        false
+    }
+
+    /// Check which of the requested `POLL*` events are ready, without blocking
+    /// `POLLERR` and `POLLHUP` may be returned even if not requested.
+    /// Returns reading and writing as ready by default, for resources that never block.
+    fn poll(&self, events: usize) -> Result<usize> {
+        Ok(events & (POLLIN | POLLOUT))
+    }
+
+    /// Notify `context` when the events returned by `poll` may have changed, by watching the
+    /// conditions that change them, until `Context::unwatch_all`
+    /// Returns false by default, for resources that cannot, which `poll` checks periodically.
+    fn poll_watch(&self, context: *mut Context) -> bool {
+        false
+    }
+
+    /// Use the access mode and file status flags in `flags`, such as `O_NONBLOCK`, which are shared
+    /// with the duplicates of the descriptor and changed by `F_SETFL`. Resources that keep them
+    /// should share them with their own duplicates too.
+    /// Resources that can block should return `EAGAIN` instead, while `O_NONBLOCK` is set.
+    fn set_flags(&mut self, flags: Arc<Cell<usize>>) -> Result<()> {
+        Ok(())
+    }
+
     /// Truncate to the given length
     /// Returns `EPERM` if the operation is not supported.
     fn truncate(&mut self, len: usize) -> Result<()> {
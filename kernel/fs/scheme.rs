@@ -1,30 +1,91 @@
 use alloc::arc::{Arc, Weak};
 use alloc::boxed::Box;
 
-use collections::String;
+use collections::{BTreeMap, BTreeSet, String};
 use collections::borrow::ToOwned;
 
-use core::cell::Cell;
-use core::mem::size_of;
+use core::cell::{Cell, UnsafeCell};
+use core::cmp;
+use core::mem::{self, size_of};
 use core::ops::DerefMut;
 use core::{ptr, slice};
 
 use arch::context::Context;
+use arch::memory;
 
-use sync::{WaitMap, WaitQueue};
+use common::time::Duration;
 
-use system::error::{Error, Result, EFAULT, EINVAL, ENODEV, ESPIPE};
-use system::scheme::Packet;
+use sync::{WaitCondition, WaitMap, WaitQueue};
+
+use syscall::signal;
+
+use system::error::{Error, Result, EFAULT, EINTR, EINVAL, ENODEV, ENOMEM, EPIPE, ESPIPE, ETIMEDOUT};
+use system::scheme::{Packet, SCHEME_CANCEL};
 use system::syscall::{SYS_CLOSE, SYS_DUP, SYS_FPATH, SYS_FSTAT, SYS_FSYNC, SYS_FTRUNCATE,
-                    SYS_OPEN, SYS_LSEEK, SEEK_SET, SEEK_CUR, SEEK_END, SYS_MKDIR,
-                    SYS_READ, SYS_WRITE, SYS_RMDIR, SYS_UNLINK, Stat};
+                    SYS_LINK, SYS_MMAP, SYS_OPEN, SYS_LSEEK, SEEK_SET, SEEK_CUR, SEEK_END,
+                    SYS_MKDIR, SYS_POLL, SYS_READ, SYS_RENAME, SYS_WRITE, SYS_RMDIR, SYS_UNLINK,
+                    POLLIN, POLLOUT, Stat};
 
 use super::{Resource, ResourceSeek, KScheme};
 
+/// How long a request may wait for the server to read it, in seconds, before the server is
+/// considered hung and the call fails with `ETIMEDOUT`
+const CALL_TIMEOUT: i64 = 30;
+
+/// How long to wait, in nanoseconds, before asking again about the events of a file that had none
+/// of them ready
+const POLL_RETRY: i32 = 10000000;
+
+/// The events of a file, as the server last answered `SYS_POLL`
+///
+/// `poll` does not wait for the server. It sends `SYS_POLL` and returns the last answer, and the
+/// answer notifies `condition` when it arrives.
+struct SchemePoll {
+    /// The muxed result of the last answer
+    result: Cell<usize>,
+    /// A request was sent and is not answered yet
+    asking: Cell<bool>,
+    /// The file was used after the request was sent, so its answer may be out of date
+    stale: Cell<bool>,
+    /// When the last answer arrived
+    answered: Cell<Duration>,
+    condition: WaitCondition,
+}
+
+impl SchemePoll {
+    fn new() -> SchemePoll {
+        SchemePoll {
+            result: Cell::new(0),
+            asking: Cell::new(false),
+            stale: Cell::new(false),
+            answered: Cell::new(Duration::new(0, 0)),
+            condition: WaitCondition::new(),
+        }
+    }
+
+    /// Forget the last answer, after a call that may have changed the events
+    fn reset(&self) {
+        self.result.set(0);
+        self.answered.set(Duration::new(0, 0));
+        if self.asking.get() {
+            self.stale.set(true);
+        }
+    }
+}
+
 struct SchemeInner {
     name: String,
     context: *mut Context,
     next_id: Cell<usize>,
+    /// The number of open server resources. The server is gone when this reaches 0.
+    servers: Cell<usize>,
+    /// The IDs of the requests that are waiting for a reply
+    pending: UnsafeCell<BTreeSet<usize>>,
+    /// The requests that nobody waits for anymore, by ID, with their call and the address of the
+    /// memory mapped into the server for them, which is released once the server is done
+    cancelled: UnsafeCell<BTreeMap<usize, (usize, Option<usize>)>>,
+    /// The `SYS_POLL` requests that are not answered yet, by ID
+    polls: UnsafeCell<BTreeMap<usize, Arc<SchemePoll>>>,
     todo: WaitQueue<Packet>,
     done: WaitMap<usize, (usize, usize, usize, usize)>,
 }
@@ -35,58 +96,297 @@ impl SchemeInner {
             name: name.to_owned(),
             context: context,
             next_id: Cell::new(1),
+            servers: Cell::new(0),
+            pending: UnsafeCell::new(BTreeSet::new()),
+            cancelled: UnsafeCell::new(BTreeMap::new()),
+            polls: UnsafeCell::new(BTreeMap::new()),
             todo: WaitQueue::new(),
             done: WaitMap::new(),
         }
     }
 
-    fn call(inner: &Weak<SchemeInner>, a: usize, b: usize, c: usize, d: usize) -> Result<usize> {
-        if let Some(scheme) = inner.upgrade() {
-            let id = scheme.next_id.get();
+    /// The scheme, if the server still has it open. Its context may be gone otherwise.
+    fn upgrade(inner: &Weak<SchemeInner>) -> Option<Arc<SchemeInner>> {
+        match inner.upgrade() {
+            Some(scheme) => if scheme.servers.get() > 0 {
+                Some(scheme)
+            } else {
+                None
+            },
+            None => None
+        }
+    }
 
-            //TODO: What should be done about collisions in self.todo or self.done?
-            let mut next_id = id + 1;
-            if next_id <= 0 {
-                next_id = 1;
-            }
-            scheme.next_id.set(next_id);
+    /// Allocate the ID of a request, skipping 0 and the IDs of requests that the server may still
+    /// reply to
+    fn alloc_id(&self) -> usize {
+        let pending = unsafe { & *self.pending.get() };
+        let cancelled = unsafe { & *self.cancelled.get() };
+        let polls = unsafe { & *self.polls.get() };
 
-            // debugln!("{} {}: {} {} {:X} {:X} {:X}", scheme.name, id, a, ::syscall::name(a), b, c, d);
+        let mut id = self.next_id.get();
+        while id == 0 || pending.contains(&id) || cancelled.contains_key(&id) || polls.contains_key(&id) {
+            id = id.wrapping_add(1);
+        }
+        self.next_id.set(id.wrapping_add(1));
+
+        id
+    }
 
-            scheme.todo.send(Packet {
-                id: id,
-                a: a,
-                b: b,
-                c: c,
-                d: d
-            }, "SchemeInner::call todo");
+    /// Stop waiting for the reply to a request, releasing `capture` once the server is done
+    ///
+    /// A request that the server has not read yet is taken out of the queue, unless it closes a
+    /// file, so the server still frees it. Otherwise the server is sent a `SCHEME_CANCEL` packet,
+    /// and a copy of `capture` stays mapped until the server replies to it.
+    fn cancel(&self, id: usize, a: usize, capture: Option<usize>) {
+        unsafe { &mut *self.pending.get() }.remove(&id);
 
-            let res = Error::demux(scheme.done.receive(&id, "SchemeInner::call done").0);
-            // debugln!("{} {}: {} {} {:X} {:X} {:X} = {:?}", scheme.name, id, a, ::syscall::name(a), b, c, d, res);
-            res
+        // The server replied in the meantime, so it is done with the request already
+        if let Some(reply) = self.done.try_receive(&id) {
+            self.orphan_reply(a, reply.0);
+            if let Some(capture) = capture {
+                self.release(capture);
+            }
+            return;
+        }
+
+        let todo = unsafe { self.todo.inner() };
+        if let Some(i) = todo.iter().position(|packet| packet.id == id) {
+            if todo[i].a == SYS_CLOSE {
+                unsafe { &mut *self.cancelled.get() }.insert(id, (a, capture));
+            } else {
+                todo.remove(i);
+                if let Some(capture) = capture {
+                    self.release(capture);
+                }
+            }
         } else {
-            Err(Error::new(ENODEV))
+            if let Some(capture) = capture {
+                self.detach(capture);
+            }
+            unsafe { &mut *self.cancelled.get() }.insert(id, (a, capture));
+            self.todo.send(Packet {
+                id: 0,
+                a: SCHEME_CANCEL,
+                b: id,
+                .. Packet::default()
+            }, "SchemeInner::cancel todo");
+        }
+    }
+
+    /// Handle the result of a request that nobody waits for
+    ///
+    /// A file opened or duplicated by it would never be closed, so it is closed here.
+    fn orphan_reply(&self, a: usize, result: usize) {
+        if a == SYS_OPEN || a == SYS_DUP {
+            if let Ok(file_id) = Error::demux(result) {
+                let id = self.alloc_id();
+                unsafe { &mut *self.cancelled.get() }.insert(id, (SYS_CLOSE, None));
+                self.todo.send(Packet {
+                    id: id,
+                    a: SYS_CLOSE,
+                    b: file_id,
+                    .. Packet::default()
+                }, "SchemeInner::orphan_reply todo");
+            }
         }
     }
 
+    /// Fail the pending requests with `EPIPE` and remove the scheme, once the server has closed
+    /// all of its resources
+    fn hang_up(&self) {
+        unsafe { self.todo.inner() }.clear();
+        unsafe { &mut *self.cancelled.get() }.clear();
+
+        for (_, poll) in mem::replace(unsafe { &mut *self.polls.get() }, BTreeMap::new()) {
+            poll.asking.set(false);
+            poll.result.set(Error::mux(Err(Error::new(EPIPE))));
+            poll.condition.notify("SchemeInner::hang_up poll");
+        }
+
+        for &id in unsafe { & *self.pending.get() }.iter() {
+            self.done.send(id, (Error::mux(Err(Error::new(EPIPE))), 0, 0, 0), "SchemeInner::hang_up done");
+        }
+
+        unsafe { &mut *::env().schemes.get() }.retain(|scheme| scheme.scheme() != self.name);
+    }
+
+    /// Send a request to the server and wait for its reply, then release `capture`, the address
+    /// of the memory mapped into the server for the request
+    ///
+    /// The call fails with `ENODEV` if the server is gone, with `EPIPE` if it goes away while
+    /// waiting, with `ETIMEDOUT` if it does not read the request within `CALL_TIMEOUT`, and with
+    /// `EINTR` if a signal interrupts the caller. An interrupted request is cancelled.
+    fn call(inner: &Weak<SchemeInner>, flags: usize, capture: Option<usize>, a: usize, b: usize, c: usize, d: usize) -> Result<usize> {
+        let scheme = match SchemeInner::upgrade(inner) {
+            Some(scheme) => scheme,
+            None => return Err(Error::new(ENODEV))
+        };
+
+        let id = scheme.alloc_id();
+
+        // debugln!("{} {}: {} {} {:X} {:X} {:X}", scheme.name, id, a, ::syscall::name(a), b, c, d);
+
+        let (uid, gid) = {
+            let contexts = unsafe { & *::env().contexts.get() };
+            let current = try!(contexts.current());
+            (current.euid, current.egid)
+        };
+
+        unsafe { &mut *scheme.pending.get() }.insert(id);
+        scheme.todo.send(Packet {
+            id: id,
+            a: a,
+            b: b,
+            c: c,
+            d: d,
+            uid: uid,
+            gid: gid,
+            flags: flags
+        }, "SchemeInner::call todo");
+
+        let timeout = Duration::monotonic() + Duration::new(CALL_TIMEOUT, 0);
+        loop {
+            if let Some(reply) = scheme.done.try_receive(&id) {
+                unsafe { &mut *scheme.pending.get() }.remove(&id);
+                if let Some(capture) = capture {
+                    scheme.release(capture);
+                }
+
+                let res = Error::demux(reply.0);
+                // debugln!("{} {}: {} {} {:X} {:X} {:X} = {:?}", scheme.name, id, a, ::syscall::name(a), b, c, d, res);
+                return res;
+            }
+
+            let interrupted = {
+                let contexts = unsafe { & *::env().contexts.get() };
+                contexts.current().map(|current| signal::interrupting(current) != 0).unwrap_or(false)
+            };
+            if interrupted {
+                scheme.cancel(id, a, capture);
+                return Err(Error::new(EINTR));
+            }
+
+            // Only requests that the server has not read yet time out
+            let queued = unsafe { scheme.todo.inner() }.iter().any(|packet| packet.id == id);
+            if queued {
+                let now = Duration::monotonic();
+                if now >= timeout {
+                    scheme.cancel(id, a, capture);
+                    return Err(Error::new(ETIMEDOUT));
+                }
+
+                scheme.done.condition.wait_for("SchemeInner::call done", timeout - now);
+            } else {
+                // Interruptions are handled above, on the next iteration
+                let _ = scheme.done.condition.wait("SchemeInner::call done");
+            }
+        }
+    }
+
+    /// Ask the server which of `events` are ready on `file_id`, without waiting for the answer
+    fn ask_poll(inner: &Weak<SchemeInner>, flags: usize, file_id: usize, events: usize, poll: &Arc<SchemePoll>) -> Result<()> {
+        let scheme = match SchemeInner::upgrade(inner) {
+            Some(scheme) => scheme,
+            None => return Err(Error::new(ENODEV))
+        };
+
+        let id = scheme.alloc_id();
+
+        let (uid, gid) = {
+            let contexts = unsafe { & *::env().contexts.get() };
+            let current = try!(contexts.current());
+            (current.euid, current.egid)
+        };
+
+        poll.asking.set(true);
+        poll.stale.set(false);
+        unsafe { &mut *scheme.polls.get() }.insert(id, poll.clone());
+        scheme.todo.send(Packet {
+            id: id,
+            a: SYS_POLL,
+            b: file_id,
+            c: events,
+            d: 0,
+            uid: uid,
+            gid: gid,
+            flags: flags
+        }, "SchemeInner::ask_poll todo");
+
+        Ok(())
+    }
+
+    /// Map memory of the caller into the server
     fn capture(inner: &Weak<SchemeInner>, mut physical_address: usize, size: usize, writeable: bool) -> Result<usize> {
-        if let Some(scheme) = inner.upgrade() {
+        if let Some(scheme) = SchemeInner::upgrade(inner) {
             if physical_address >= 0x80000000 {
                 physical_address -= 0x80000000;
             }
             unsafe {
                 let mmap = &mut *(*scheme.context).mmap.get();
-                return mmap.add_mem(physical_address, size, writeable, false);
+                mmap.add_mem(physical_address, size, writeable, false)
             }
         } else {
-            return Err(Error::new(ENODEV));
+            Err(Error::new(ENODEV))
         }
     }
 
-    fn release(inner: &Weak<SchemeInner>, virtual_address: usize) {
-        if let Some(scheme) = inner.upgrade() {
+    /// Map a read only copy of `data` into the server, so that it does not depend on the lifetime
+    /// of `data` if the request is cancelled
+    fn capture_copy(inner: &Weak<SchemeInner>, data: &[u8]) -> Result<usize> {
+        if let Some(scheme) = SchemeInner::upgrade(inner) {
+            let physical_address = unsafe { memory::alloc_aligned(cmp::max(data.len(), 1), 4096) };
+            if physical_address == 0 {
+                return Err(Error::new(ENOMEM));
+            }
+
             unsafe {
+                memory::copy_to_pages(physical_address as *mut u8, data);
+
                 let mmap = &mut *(*scheme.context).mmap.get();
+                match mmap.add_mem(physical_address, data.len(), false, true) {
+                    Ok(virtual_address) => Ok(virtual_address),
+                    Err(err) => {
+                        memory::unalloc(physical_address);
+                        Err(err)
+                    }
+                }
+            }
+        } else {
+            Err(Error::new(ENODEV))
+        }
+    }
+
+    /// Give the server a copy of memory captured from the caller, so that the caller can go on
+    /// while the server may still use it
+    ///
+    /// The memory is unmapped if the copy cannot be allocated.
+    fn detach(&self, virtual_address: usize) {
+        if self.servers.get() > 0 {
+            unsafe {
+                let mmap = &mut *(*self.context).mmap.get();
+                if let Ok(mut mem) = mmap.get_mem_mut(virtual_address) {
+                    if ! mem.allocated {
+                        let copy = memory::alloc_aligned(mem.virtual_size, 4096);
+                        if copy > 0 {
+                            memory::copy_pages(copy as *mut u8, mem.physical_address as *const u8, mem.virtual_size);
+                            mem.physical_address = copy;
+                            mem.allocated = true;
+                        } else {
+                            mem.virtual_size = 0;
+                        }
+                    }
+                }
+                mmap.clean_mem();
+            }
+        }
+    }
+
+    /// Unmap memory from the server, if it is still there
+    fn release(&self, virtual_address: usize) {
+        if self.servers.get() > 0 {
+            unsafe {
+                let mmap = &mut *(*self.context).mmap.get();
                 if let Ok(mut mem) = mmap.get_mem_mut(virtual_address) {
                     mem.virtual_size = 0;
                 }
@@ -94,30 +394,50 @@ impl SchemeInner {
             }
         }
     }
-}
 
-impl Drop for SchemeInner {
-    fn drop(&mut self) {
-        unsafe { &mut *::env().schemes.get() }.retain(|scheme| scheme.scheme() != self.name);
+    fn translate(inner: &Weak<SchemeInner>, virtual_address: usize, size: usize) -> Result<usize> {
+        if let Some(scheme) = SchemeInner::upgrade(inner) {
+            unsafe { (*scheme.context).translate(virtual_address, size) }
+        } else {
+            Err(Error::new(ENODEV))
+        }
     }
 }
 
 pub struct SchemeResource {
     inner: Weak<SchemeInner>,
     file_id: usize,
+    flags: Arc<Cell<usize>>,
+    poll: Arc<SchemePoll>,
 }
 
 impl SchemeResource {
+    fn new(inner: Weak<SchemeInner>, file_id: usize, flags: Arc<Cell<usize>>) -> SchemeResource {
+        SchemeResource {
+            inner: inner,
+            file_id: file_id,
+            flags: flags,
+            poll: Arc::new(SchemePoll::new()),
+        }
+    }
+
     fn call(&self, a: usize, b: usize, c: usize, d: usize) -> Result<usize> {
-        SchemeInner::call(&self.inner, a, b, c, d)
+        self.poll.reset();
+        SchemeInner::call(&self.inner, self.flags.get(), None, a, b, c, d)
     }
 
-    fn capture(&self, physical_address: usize, size: usize, writeable: bool) -> Result<usize> {
-        SchemeInner::capture(&self.inner, physical_address, size, writeable)
+    /// Send a call with memory of the caller, which is mapped into the server until it is done
+    fn call_captured(&self, physical_address: usize, size: usize, writeable: bool, a: usize, b: usize, c: usize) -> Result<usize> {
+        let offset = physical_address % 4096;
+
+        let virtual_address = try!(SchemeInner::capture(&self.inner, physical_address - offset, size + offset, writeable));
+
+        self.poll.reset();
+        SchemeInner::call(&self.inner, self.flags.get(), Some(virtual_address), a, b, virtual_address + offset, c)
     }
 
-    fn release(&self, virtual_address: usize){
-        SchemeInner::release(&self.inner, virtual_address);
+    fn translate(&self, virtual_address: usize, size: usize) -> Result<usize> {
+        SchemeInner::translate(&self.inner, virtual_address, size)
     }
 }
 
@@ -125,28 +445,16 @@ impl Resource for SchemeResource {
     /// Duplicate the resource
     fn dup(&self) -> Result<Box<Resource>> {
         let file_id = try!(self.call(SYS_DUP, self.file_id, 0, 0));
-        Ok(Box::new(SchemeResource {
-            inner: self.inner.clone(),
-            file_id: file_id
-        }))
+        Ok(Box::new(SchemeResource::new(self.inner.clone(), file_id, self.flags.clone())))
     }
 
     /// Return the URL of this resource
     fn path(&self, buf: &mut [u8]) -> Result <usize> {
-        let contexts = unsafe { & *::env().contexts.get() };
-        let current = try!(contexts.current());
+        let contexts = unsafe { &mut *::env().contexts.get() };
+        let current = try!(contexts.current_mut());
+        current.unshare(buf.as_mut_ptr() as usize, buf.len());
         if let Ok(physical_address) = current.translate(buf.as_mut_ptr() as usize, buf.len()) {
-            let offset = physical_address % 4096;
-
-            let virtual_address = try!(self.capture(physical_address - offset, buf.len() + offset, true));
-
-            let result = self.call(SYS_FPATH, self.file_id, virtual_address + offset, buf.len());
-
-            //debugln!("Read {:X} mapped from {:X} to {:X} offset {} length {} size {} result {:?}", physical_address, buf.as_ptr() as usize, virtual_address + offset, offset, buf.len(), virtual_size, result);
-
-            self.release(virtual_address);
-
-            result
+            self.call_captured(physical_address, buf.len(), true, SYS_FPATH, self.file_id, buf.len())
         } else {
             debugln!("{}:{} fault {:X} {}", file!(), line!(), buf.as_ptr() as usize, buf.len());
             Err(Error::new(EFAULT))
@@ -155,20 +463,11 @@ impl Resource for SchemeResource {
 
     /// Read data to buffer
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let contexts = unsafe { & *::env().contexts.get() };
-        let current = try!(contexts.current());
+        let contexts = unsafe { &mut *::env().contexts.get() };
+        let current = try!(contexts.current_mut());
+        current.unshare(buf.as_mut_ptr() as usize, buf.len());
         if let Ok(physical_address) = current.translate(buf.as_mut_ptr() as usize, buf.len()) {
-            let offset = physical_address % 4096;
-
-            let virtual_address = try!(self.capture(physical_address - offset, buf.len() + offset, true));
-
-            let result = self.call(SYS_READ, self.file_id, virtual_address + offset, buf.len());
-
-            //debugln!("Read {:X} mapped from {:X} to {:X} offset {} length {} size {} result {:?}", physical_address, buf.as_ptr() as usize, virtual_address + offset, offset, buf.len(), virtual_size, result);
-
-            self.release(virtual_address);
-
-            result
+            self.call_captured(physical_address, buf.len(), true, SYS_READ, self.file_id, buf.len())
         } else {
             debugln!("{}:{} fault {:X} {}", file!(), line!(), buf.as_ptr() as usize, buf.len());
             Err(Error::new(EFAULT))
@@ -180,17 +479,7 @@ impl Resource for SchemeResource {
         let contexts = unsafe { & *::env().contexts.get() };
         let current = try!(contexts.current());
         if let Ok(physical_address) = current.translate(buf.as_ptr() as usize, buf.len()) {
-            let offset = physical_address % 4096;
-
-            let virtual_address = try!(self.capture(physical_address - offset, buf.len() + offset, false));
-
-            let result = self.call(SYS_WRITE, self.file_id, virtual_address + offset, buf.len());
-
-            // debugln!("Write {:X} mapped from {:X} to {:X} offset {} length {} result {:?}", physical_address, buf.as_ptr() as usize, virtual_address + offset, offset, buf.len(), result);
-
-            self.release(virtual_address);
-
-            result
+            self.call_captured(physical_address, buf.len(), false, SYS_WRITE, self.file_id, buf.len())
         } else {
             debugln!("{}:{} fault {:X} {}", file!(), line!(), buf.as_ptr() as usize, buf.len());
             Err(Error::new(EFAULT))
@@ -212,18 +501,11 @@ impl Resource for SchemeResource {
     fn stat(&self, stat: &mut Stat) -> Result<()> {
         let buf = unsafe { slice::from_raw_parts_mut(stat as *mut Stat as *mut u8, size_of::<Stat>()) };
 
-        let contexts = unsafe { & *::env().contexts.get() };
-        let current = try!(contexts.current());
+        let contexts = unsafe { &mut *::env().contexts.get() };
+        let current = try!(contexts.current_mut());
+        current.unshare(buf.as_mut_ptr() as usize, buf.len());
         if let Ok(physical_address) = current.translate(buf.as_mut_ptr() as usize, buf.len()) {
-            let offset = physical_address % 4096;
-
-            let virtual_address = try!(self.capture(physical_address - offset, buf.len() + offset, true));
-
-            let result = self.call(SYS_FSTAT, self.file_id, virtual_address + offset, 0);
-
-            self.release(virtual_address);
-
-            result.and(Ok(()))
+            self.call_captured(physical_address, buf.len(), true, SYS_FSTAT, self.file_id, 0).and(Ok(()))
         } else {
             debugln!("{}:{} fault {:X} {}", file!(), line!(), buf.as_ptr() as usize, buf.len());
             Err(Error::new(EFAULT))
@@ -235,10 +517,46 @@ impl Resource for SchemeResource {
         self.call(SYS_FSYNC, self.file_id, 0, 0).and(Ok(()))
     }
 
+    /// Map memory of the resource, shared with the scheme
+    fn mmap(&mut self, offset: usize, size: usize, _writeable: bool) -> Result<(usize, bool)> {
+        let virtual_address = try!(self.call(SYS_MMAP, self.file_id, offset, size));
+        if virtual_address % 4096 != 0 {
+            return Err(Error::new(EINVAL));
+        }
+
+        let physical_address = try!(self.translate(virtual_address, size));
+        Ok((physical_address, false))
+    }
+
     /// Truncate the resource
     fn truncate(&mut self, len: usize) -> Result<()> {
         self.call(SYS_FTRUNCATE, self.file_id, len, 0).and(Ok(()))
     }
+
+    /// The events that the scheme reported ready, asking it again if none were
+    ///
+    /// A scheme that answers at once with none ready is asked again after `POLL_RETRY`.
+    fn poll(&self, events: usize) -> Result<usize> {
+        let poll = &self.poll;
+        let ready = try!(Error::demux(poll.result.get())) & events;
+        if ready == 0 && ! poll.asking.get() &&
+           Duration::monotonic() >= poll.answered.get() + Duration::new(0, POLL_RETRY) {
+            try!(SchemeInner::ask_poll(&self.inner, self.flags.get(), self.file_id, events, poll));
+        }
+        Ok(ready)
+    }
+
+    /// Notified by the answer to `SYS_POLL`, if one is expected
+    fn poll_watch(&self, context: *mut Context) -> bool {
+        self.poll.condition.watch(context);
+        self.poll.asking.get()
+    }
+
+    /// Use the file status flags, which are passed to the scheme with every call
+    fn set_flags(&mut self, flags: Arc<Cell<usize>>) -> Result<()> {
+        self.flags = flags;
+        Ok(())
+    }
 }
 
 impl Drop for SchemeResource {
@@ -249,14 +567,23 @@ impl Drop for SchemeResource {
 
 pub struct SchemeServerResource {
     inner: Arc<SchemeInner>,
+    flags: Arc<Cell<usize>>,
+}
+
+impl SchemeServerResource {
+    fn new(inner: Arc<SchemeInner>, flags: Arc<Cell<usize>>) -> SchemeServerResource {
+        inner.servers.set(inner.servers.get() + 1);
+        SchemeServerResource {
+            inner: inner,
+            flags: flags
+        }
+    }
 }
 
 impl Resource for SchemeServerResource {
     /// Duplicate the resource
     fn dup(&self) -> Result<Box<Resource>> {
-        Ok(box SchemeServerResource {
-            inner: self.inner.clone()
-        })
+        Ok(box SchemeServerResource::new(self.inner.clone(), self.flags.clone()))
     }
 
     /// Return the URL of this resource
@@ -284,7 +611,7 @@ impl Resource for SchemeServerResource {
         if buf.len() >= size_of::<Packet>() {
             let mut i = 0;
 
-            let packet = self.inner.todo.receive("SchemeServerResource::read todo");
+            let packet = try!(self.inner.todo.receive_flags(self.flags.get(), "SchemeServerResource::read todo"));
             unsafe { ptr::write(buf.as_mut_ptr().offset(i as isize) as *mut Packet, packet); }
             i += size_of::<Packet>();
 
@@ -303,14 +630,41 @@ impl Resource for SchemeServerResource {
         }
     }
 
-    /// Write to resource
+    /// Write replies
+    ///
+    /// Replies to cancelled requests are dropped, closing the files they opened. The reply to a
+    /// `SCHEME_CANCEL` packet releases the memory of the request it cancelled. Answers to `SYS_POLL`
+    /// notify the resources that asked.
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
         if buf.len() >= size_of::<Packet>() {
             let mut i = 0;
 
             while i <= buf.len() - size_of::<Packet>() {
                 let packet = unsafe { & *(buf.as_ptr().offset(i as isize) as *const Packet) };
-                self.inner.done.send(packet.id, (packet.a, packet.b, packet.c, packet.d), "SchemeServerResource::write done");
+                let cancelled = unsafe { &mut *self.inner.cancelled.get() };
+                let orphan = cancelled.get(&packet.id).map(|&(a, _)| a);
+                if packet.id == 0 {
+                    if let Some((_, Some(capture))) = cancelled.remove(&packet.b) {
+                        self.inner.release(capture);
+                    }
+                } else if unsafe { & *self.inner.pending.get() }.contains(&packet.id) {
+                    self.inner.done.send(packet.id, (packet.a, packet.b, packet.c, packet.d), "SchemeServerResource::write done");
+                } else if let Some(poll) = unsafe { &mut *self.inner.polls.get() }.remove(&packet.id) {
+                    // An answer that may be out of date is dropped, so the next poll asks again
+                    poll.asking.set(false);
+                    if ! poll.stale.get() {
+                        poll.result.set(packet.a);
+                        poll.answered.set(Duration::monotonic());
+                    }
+                    poll.condition.notify("SchemeServerResource::write poll");
+                } else if let Some(a) = orphan {
+                    // Closes are not sent a SCHEME_CANCEL packet, so nothing follows their reply
+                    if a == SYS_CLOSE {
+                        cancelled.remove(&packet.id);
+                    } else {
+                        self.inner.orphan_reply(a, packet.a);
+                    }
+                }
                 i += size_of::<Packet>();
             }
 
@@ -333,9 +687,42 @@ impl Resource for SchemeServerResource {
     fn truncate(&mut self, _len: usize) -> Result<()> {
         Err(Error::new(EINVAL))
     }
+
+    /// Readable when there are packets to handle
+    fn poll(&self, events: usize) -> Result<usize> {
+        let mut revents = events & POLLOUT;
+
+        if ! unsafe { self.inner.todo.inner() }.is_empty() {
+            revents |= events & POLLIN;
+        }
+
+        Ok(revents)
+    }
+
+    fn poll_watch(&self, context: *mut Context) -> bool {
+        self.inner.todo.condition.watch(context);
+        true
+    }
+
+    fn set_flags(&mut self, flags: Arc<Cell<usize>>) -> Result<()> {
+        self.flags = flags;
+        Ok(())
+    }
+}
+
+impl Drop for SchemeServerResource {
+    fn drop(&mut self) {
+        self.inner.servers.set(self.inner.servers.get() - 1);
+        if self.inner.servers.get() == 0 {
+            self.inner.hang_up();
+        }
+    }
 }
 
 /// Scheme has to be wrapped
+///
+/// It is removed from the schemes when the server goes away, which may happen while one of its
+/// calls waits. So its calls clone `inner` first, and do not use `self` after waiting.
 pub struct Scheme {
     name: String,
     inner: Weak<SchemeInner>
@@ -345,9 +732,7 @@ impl Scheme {
     pub fn new(name: &str) -> Result<(Box<Scheme>, Box<Resource>)> {
         let contexts = unsafe { &mut *::env().contexts.get() };
         let mut current = try!(contexts.current_mut());
-        let server = box SchemeServerResource {
-            inner: Arc::new(SchemeInner::new(name, current.deref_mut()))
-        };
+        let server = box SchemeServerResource::new(Arc::new(SchemeInner::new(name, current.deref_mut())), Arc::new(Cell::new(0)));
         let scheme = box Scheme {
             name: name.to_owned(),
             inner: Arc::downgrade(&server.inner)
@@ -355,16 +740,25 @@ impl Scheme {
         Ok((scheme, server))
     }
 
-    fn call(&self, a: usize, b: usize, c: usize, d: usize) -> Result<usize> {
-        SchemeInner::call(&self.inner, a, b, c, d)
-    }
+    /// Send a call with a path, which is passed to the scheme along with its length
+    fn call_path(&self, a: usize, path: &str, b: usize) -> Result<usize> {
+        let inner = self.inner.clone();
+
+        let virtual_address = try!(SchemeInner::capture_copy(&inner, path.as_bytes()));
 
-    fn capture(&self, physical_address: usize, size: usize, writeable: bool) -> Result<usize> {
-        SchemeInner::capture(&self.inner, physical_address, size, writeable)
+        SchemeInner::call(&inner, 0, Some(virtual_address), a, virtual_address, path.len(), b)
     }
 
-    fn release(&self, virtual_address: usize){
-        SchemeInner::release(&self.inner, virtual_address);
+    /// Send a call with two paths, which are passed to the scheme as one buffer containing the
+    /// first path followed by the second. The length of the first path and of the whole buffer are
+    /// passed along with it.
+    fn call_pair(&self, a: usize, first: &str, second: &str) -> Result<()> {
+        let inner = self.inner.clone();
+        let paths = first.to_owned() + second;
+
+        let virtual_address = try!(SchemeInner::capture_copy(&inner, paths.as_bytes()));
+
+        SchemeInner::call(&inner, 0, Some(virtual_address), a, virtual_address, first.len(), paths.len()).and(Ok(()))
     }
 }
 
@@ -378,48 +772,30 @@ impl KScheme for Scheme {
     }
 
     fn open(&mut self, path: &str, flags: usize) -> Result<Box<Resource>> {
-        let virtual_address = try!(self.capture(path.as_ptr() as usize, path.len(), false));
-
-        let result = self.call(SYS_OPEN, virtual_address, path.len(), flags);
+        let inner = self.inner.clone();
 
-        self.release(virtual_address);
+        let file_id = try!(self.call_path(SYS_OPEN, path, flags));
 
-        match result {
-            Ok(file_id) => Ok(box SchemeResource {
-                inner: self.inner.clone(),
-                file_id: file_id,
-            }),
-            Err(err) => Err(err)
-        }
+        Ok(box SchemeResource::new(inner, file_id, Arc::new(Cell::new(flags))))
     }
 
     fn mkdir(&mut self, path: &str, flags: usize) -> Result<()> {
-        let virtual_address = try!(self.capture(path.as_ptr() as usize, path.len(), false));
-
-        let result = self.call(SYS_MKDIR, virtual_address, path.len(), flags);
-
-        self.release(virtual_address);
-
-        result.and(Ok(()))
+        self.call_path(SYS_MKDIR, path, flags).and(Ok(()))
     }
 
     fn rmdir(&mut self, path: &str) -> Result<()> {
-        let virtual_address = try!(self.capture(path.as_ptr() as usize, path.len(), false));
-
-        let result = self.call(SYS_RMDIR, virtual_address, path.len(), 0);
-
-        self.release(virtual_address);
-
-        result.and(Ok(()))
+        self.call_path(SYS_RMDIR, path, 0).and(Ok(()))
     }
 
     fn unlink(&mut self, path: &str) -> Result<()> {
-        let virtual_address = try!(self.capture(path.as_ptr() as usize, path.len(), false));
-
-        let result = self.call(SYS_UNLINK, virtual_address, path.len(), 0);
+        self.call_path(SYS_UNLINK, path, 0).and(Ok(()))
+    }
 
-        self.release(virtual_address);
+    fn rename(&mut self, old: &str, new: &str) -> Result<()> {
+        self.call_pair(SYS_RENAME, old, new)
+    }
 
-        result.and(Ok(()))
+    fn link(&mut self, old: &str, new: &str) -> Result<()> {
+        self.call_pair(SYS_LINK, old, new)
     }
 }
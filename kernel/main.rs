@@ -25,12 +25,15 @@ use acpi::Acpi;
 
 use alloc::boxed::Box;
 
+use arch::apic;
+use arch::clock;
 use arch::context::{context_switch, Context, ContextFile};
 use arch::gdt::{GdtDescriptor, GdtEntry};
 use arch::idt::{IdtDescriptor, IdtEntry};
 use arch::memory;
 use arch::paging::Page;
 use arch::regs::Regs;
+use arch::smp;
 use arch::tss::Tss;
 
 use collections::{String, Vec};
@@ -41,7 +44,6 @@ use core::{mem, slice, usize};
 use common::time::Duration;
 
 use drivers::pci;
-use drivers::io::{Io, Pio};
 use drivers::ps2::*;
 use drivers::rtc::*;
 use drivers::serial::{self, Serial};
@@ -57,6 +59,8 @@ use schemes::disk::DiskScheme;
 use schemes::display::DisplayScheme;
 use schemes::env::EnvScheme;
 use schemes::initfs::InitFsScheme;
+use schemes::irq::IrqScheme;
+use schemes::memory::MemoryScheme;
 use schemes::pty::PtyScheme;
 use schemes::sys::SysScheme;
 
@@ -203,32 +207,52 @@ pub fn env() -> &'static Environment {
 /// The PIT (programmable interval timer) duration.
 ///
 /// This duration defines the PIT interval, which is added to the monotonic clock and the real time
-/// clock, when interrupt 0x20 is received.
+/// clock, when interrupt 0x20 is received. The local APIC timers are calibrated to tick at the
+/// same interval.
 static PIT_DURATION: Duration = Duration {
     secs: 0,
     nanos: 4500572,
 };
 
+/// Handle a timer tick.
+///
+/// Every processor has a timer and charges the current context, but only the bootstrap processor
+/// advances the clocks. With the high resolution clock, the clocks catch up to it, and the
+/// context is charged for the time it actually ran.
+fn tick() {
+    if smp::cpu_id() == 0 {
+        let clock_monotonic = unsafe { &mut *env().clock_monotonic.get() };
+        let clock_realtime = unsafe { &mut *env().clock_realtime.get() };
+        let now = clock::monotonic().unwrap_or(*clock_monotonic + PIT_DURATION);
+        *clock_realtime = *clock_realtime + (now - *clock_monotonic);
+        *clock_monotonic = now;
+    }
+
+    let duration = unsafe { clock::charge() }.unwrap_or(PIT_DURATION);
+    if let Ok(mut current) = unsafe { &mut *env().contexts.get() }.current_mut() {
+        current.charge(duration);
+    }
+}
+
 /// The idle loop.
 ///
-/// This loop runs while the system is idle.
+/// This loop runs while the system is idle. Every processor has one, which holds the kernel lock
+/// except while interrupts are enabled.
 fn idle_loop() {
     loop {
         unsafe { asm!("cli" : : : : "intel", "volatile"); }
 
-        let mut halt = true;
-
-        for context in unsafe { & *env().contexts.get() }.iter().skip(1) {
-            if context.blocked == 0 {
-                halt = false;
-                break;
-            }
-        }
+        let halt = ! unsafe { & *env().contexts.get() }.runnable();
 
+        smp::unlock_kernel();
         if halt {
-            unsafe { asm!("sti ; hlt" : : : : "intel", "volatile"); }
+            unsafe { asm!("sti ; hlt ; cli" : : : : "intel", "volatile"); }
         } else {
             unsafe { asm!("sti ; nop ; cli" : : : : "intel", "volatile"); }
+        }
+        smp::lock_kernel();
+
+        if ! halt {
             unsafe { context_switch(); }
         }
     }
@@ -283,6 +307,9 @@ unsafe fn init(gdt_ptr: *mut GdtDescriptor, idt_ptr: *mut IdtDescriptor, tss_ptr
         debug_assert_eq!(BSS_TEST_NONZERO, usize::MAX);
     }
 
+    // Interrupts take the kernel lock on entry, but the lock was in BSS
+    smp::lock_kernel();
+
     // Setup paging, this allows for memory allocation
     Page::init();
     memory::cluster_init();
@@ -352,7 +379,7 @@ unsafe fn init(gdt_ptr: *mut GdtDescriptor, idt_ptr: *mut IdtDescriptor, tss_ptr
 
     match ENV_PTR {
         Some(ref mut env) => {
-            (&mut *env.contexts.get()).push(Context::root());
+            (&mut *env.contexts.get()).push_idle(0, Context::root());
 
             let mut serial = Serial::new(0x3F8, 0x4);
 
@@ -408,10 +435,19 @@ unsafe fn init(gdt_ptr: *mut GdtDescriptor, idt_ptr: *mut IdtDescriptor, tss_ptr
                     & __data_start as *const u8 as usize, & __data_end as *const u8 as usize,
                     & __bss_start as *const u8 as usize, & __bss_end as *const u8 as usize);
 
-            if let Some(acpi) = Acpi::new() {
+            if let Some(mut acpi) = Acpi::new() {
+                if let Some(hpet) = acpi.hpet() {
+                    clock::init(hpet);
+                }
+                if let Some(madt) = acpi.madt() {
+                    apic::init(madt);
+                    smp::init(madt);
+                }
+                acpi.route_pci();
                 (&mut *env.schemes.get()).push(acpi);
             }
 
+            *env.clock_monotonic.get() = Duration::monotonic();
             *env.clock_realtime.get() = Rtc::new().time();
 
             (&mut *env.schemes.get()).push(Ps2::new());
@@ -428,6 +464,10 @@ unsafe fn init(gdt_ptr: *mut GdtDescriptor, idt_ptr: *mut IdtDescriptor, tss_ptr
 
             (&mut *env.schemes.get()).push(box EnvScheme);
 
+            (&mut *env.schemes.get()).push(IrqScheme::new());
+
+            (&mut *env.schemes.get()).push(MemoryScheme::new());
+
             (&mut *env.schemes.get()).push(PtyScheme::new());
 
             (&mut *env.schemes.get()).push(SysScheme::new());
@@ -468,18 +508,9 @@ unsafe fn init(gdt_ptr: *mut GdtDescriptor, idt_ptr: *mut IdtDescriptor, tss_ptr
 
                     *current.cwd.get() = "initfs:/".to_string();
 
-                    (*current.files.get()).push(ContextFile {
-                        fd: 0,
-                        resource: ::env().open("debug:", 0).unwrap(),
-                    });
-                    (*current.files.get()).push(ContextFile {
-                        fd: 1,
-                        resource: ::env().open("debug:", 0).unwrap(),
-                    });
-                    (*current.files.get()).push(ContextFile {
-                        fd: 2,
-                        resource: ::env().open("debug:", 0).unwrap(),
-                    });
+                    for fd in 0..3 {
+                        (*current.files.get()).push(ContextFile::new(fd, 0, false, ::env().open("debug:", 0).unwrap()).unwrap());
+                    }
 
                     current.set_env_var("PATH", "file:/bin").unwrap();
                     current.set_env_var("COLUMNS", &term_columns).unwrap();
@@ -488,7 +519,7 @@ unsafe fn init(gdt_ptr: *mut GdtDescriptor, idt_ptr: *mut IdtDescriptor, tss_ptr
                 }
 
                 syslog_info!("The kernel has finished booting. Running /bin/init");
-                if let Err(err) = execute(vec!["initfs:/bin/init".to_string()]) {
+                if let Err(err) = execute(vec!["initfs:/bin/init".to_string()], None) {
                     syslog_info!("kernel: init: failed to execute: {}", err);
                 }
             });
@@ -501,7 +532,21 @@ unsafe fn init(gdt_ptr: *mut GdtDescriptor, idt_ptr: *mut IdtDescriptor, tss_ptr
 #[inline(never)]
 #[no_mangle]
 /// Interrupt and exception handling.
-pub extern "cdecl" fn kernel(interrupt: usize, mut regs: &mut Regs) {
+///
+/// Processors take turns running the kernel, by taking the kernel lock. The init interrupt takes
+/// it in `init`, after zeroing BSS.
+pub extern "cdecl" fn kernel(interrupt: usize, regs: &mut Regs) {
+    let locked = interrupt < 0xFF && smp::lock_kernel();
+
+    handle_interrupt(interrupt, regs);
+
+    if locked {
+        smp::unlock_kernel();
+    }
+}
+
+/// Handle an interrupt or exception, with the kernel lock held.
+fn handle_interrupt(interrupt: usize, mut regs: &mut Regs) {
     macro_rules! exception_inner {
         ($name:expr) => ({
             {
@@ -645,25 +690,33 @@ pub extern "cdecl" fn kernel(interrupt: usize, mut regs: &mut Regs) {
 
     match interrupt {
         0x20 => {
-            {
-                let mut clock_monotonic = unsafe { &mut *env().clock_monotonic.get() };
-                *clock_monotonic = *clock_monotonic + PIT_DURATION;
-            }
-            {
-                let mut clock_realtime = unsafe { &mut *env().clock_realtime.get() };
-                *clock_realtime = *clock_realtime + PIT_DURATION;
-            }
-
-            if let Ok(mut current) = unsafe { &mut *env().contexts.get() }.current_mut() {
-                current.time += 1;
-            }
+            tick();
 
             unsafe { context_switch(); }
         }
         i @ 0x21 ... 0x2F => {
             env().on_irq(i as u8 - 0x20);
         },
+        i if i >= apic::MSI_VECTOR_FIRST as usize && i <= apic::MSI_VECTOR_LAST as usize => {
+            env().on_irq(i as u8 - 0x20);
+
+            unsafe { apic::eoi() };
+        },
         0x80 => syscall::handle(regs),
+        i if i == apic::TIMER_VECTOR as usize => {
+            // The end of interrupt is sent first, as this may switch to a context that does not
+            // return through here
+            unsafe { apic::eoi() };
+
+            tick();
+
+            unsafe { context_switch(); }
+        },
+        // The mappings were reloaded while waiting for the kernel lock
+        smp::IPI_FLUSH => unsafe { apic::eoi() },
+        // The idle loop looks for a context to run once this returns
+        smp::IPI_WAKE => unsafe { apic::eoi() },
+        i if i == apic::SPURIOUS_VECTOR as usize => (),
         0xFF => {
             unsafe {
                 init(regs.ax as *mut GdtDescriptor, regs.bx as *mut IdtDescriptor, regs.cx as *mut Tss);
@@ -684,7 +737,24 @@ pub extern "cdecl" fn kernel(interrupt: usize, mut regs: &mut Regs) {
         0xB => exception_error!("Segment not present exception"),
         0xC => exception_error!("Stack-segment fault"),
         0xD => exception_error!("General protection fault"),
-        0xE => exception_error!("Page fault"),
+        0xE => {
+            // The error code is in the place of IP, until exception_error shifts the registers
+            let error = regs.ip;
+
+            // Writes to present pages may be to memory that is shared copy on write
+            if error & 3 == 3 {
+                let address: usize;
+                unsafe { asm!("mov $0, cr2" : "=r"(address) : : : "intel", "volatile") };
+
+                if let Ok(mut current) = unsafe { &mut *env().contexts.get() }.current_mut() {
+                    if unsafe { current.unshare_page(address) } {
+                        return;
+                    }
+                }
+            }
+
+            exception_error!("Page fault")
+        },
         0x10 => exception!("x87 floating-point exception"),
         0x11 => exception_error!("Alignment check exception"),
         0x12 => exception!("Machine check exception"),
@@ -695,10 +765,9 @@ pub extern "cdecl" fn kernel(interrupt: usize, mut regs: &mut Regs) {
     }
 
     if interrupt >= 0x20 && interrupt < 0x30 {
-        if interrupt >= 0x28 {
-            Pio::<u8>::new(0xA0).write(0x20);
-        }
-
-        Pio::<u8>::new(0x20).write(0x20);
+        unsafe { apic::irq_eoi(interrupt as u8 - 0x20) };
     }
+
+    // Pending signals are handled on the way back to userspace
+    syscall::signal::deliver(regs);
 }
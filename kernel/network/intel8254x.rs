@@ -192,7 +192,7 @@ impl Intel8254x {
             pci: pci,
             base: base & 0xFFFFFFF0,
             memory_mapped: base & 1 == 0,
-            irq: pci.read(0x3C) as u8 & 0xF,
+            irq: pci.irq(),
             resources: UnsafeCell::new(Vec::new()),
             inbound: VecDeque::new(),
             outbound: VecDeque::new(),
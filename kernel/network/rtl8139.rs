@@ -123,7 +123,7 @@ pub struct Rtl8139 {
 impl Rtl8139 {
     pub fn new(mut pci: PciConfig) -> Box<Self> {
         let base = unsafe { pci.read(0x10) as usize };
-        let irq = unsafe { pci.read(0x3C) as u8 & 0xF };
+        let irq = unsafe { pci.irq() };
 
         let mut module = box Rtl8139 {
             pci: pci,
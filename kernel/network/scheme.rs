@@ -1,14 +1,16 @@
+use alloc::arc::Arc;
 use alloc::boxed::Box;
 
 use collections::vec::Vec;
 use collections::vec_deque::VecDeque;
 
-use core::cell::UnsafeCell;
+use core::cell::{Cell, UnsafeCell};
 use core::ops::DerefMut;
 
 use fs::Resource;
 
 use system::error::Result;
+use system::syscall::{POLLIN, POLLOUT};
 
 use sync::WaitQueue;
 
@@ -23,6 +25,7 @@ pub struct NetworkResource {
     pub ptr: *mut NetworkResource,
     pub inbound: WaitQueue<Vec<u8>>,
     pub outbound: UnsafeCell<VecDeque<Vec<u8>>>,
+    pub flags: Arc<Cell<usize>>,
 }
 
 impl NetworkResource {
@@ -32,6 +35,7 @@ impl NetworkResource {
             ptr: 0 as *mut NetworkResource,
             inbound: WaitQueue::new(),
             outbound: UnsafeCell::new(VecDeque::new()),
+            flags: Arc::new(Cell::new(0)),
         };
 
         unsafe {
@@ -51,6 +55,7 @@ impl Resource for NetworkResource {
             ptr: 0 as *mut NetworkResource,
             inbound: self.inbound.clone(),
             outbound: UnsafeCell::new(unsafe { & *self.outbound.get() }.clone()),
+            flags: self.flags.clone(),
         };
 
         unsafe {
@@ -77,7 +82,7 @@ impl Resource for NetworkResource {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         let bytes = unsafe {
             (*self.nic).sync();
-            (*self.ptr).inbound.receive("NetworkResource::read")
+            try!((*self.ptr).inbound.receive_flags(self.flags.get(), "NetworkResource::read"))
         };
 
         let mut i = 0;
@@ -105,6 +110,24 @@ impl Resource for NetworkResource {
         }
         Ok(())
     }
+
+    fn poll(&self, events: usize) -> Result<usize> {
+        let mut revents = events & POLLOUT;
+
+        unsafe {
+            (*self.nic).sync();
+            if ! (*self.ptr).inbound.inner().is_empty() {
+                revents |= events & POLLIN;
+            }
+        }
+
+        Ok(revents)
+    }
+
+    fn set_flags(&mut self, flags: Arc<Cell<usize>>) -> Result<()> {
+        self.flags = flags;
+        Ok(())
+    }
 }
 
 impl Drop for NetworkResource {
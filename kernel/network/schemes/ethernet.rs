@@ -1,8 +1,10 @@
+use alloc::arc::Arc;
 use alloc::boxed::Box;
 
 use collections::vec::Vec;
 
 use core::{cmp, mem};
+use core::cell::Cell;
 
 use common::to_num::ToNum;
 
@@ -12,7 +14,7 @@ use network::ethernet::*;
 use fs::{KScheme, Resource};
 
 use system::error::{Error, Result, ENOENT};
-use system::syscall::O_RDWR;
+use system::syscall::{O_RDWR, POLLIN};
 
 /// A ethernet resource
 pub struct EthernetResource {
@@ -101,6 +103,20 @@ impl Resource for EthernetResource {
     fn sync(&mut self) -> Result<()> {
         self.network.sync()
     }
+
+    fn poll(&self, events: usize) -> Result<usize> {
+        let mut revents = try!(self.network.poll(events));
+
+        if ! self.data.is_empty() {
+            revents |= events & POLLIN;
+        }
+
+        Ok(revents)
+    }
+
+    fn set_flags(&mut self, flags: Arc<Cell<usize>>) -> Result<()> {
+        self.network.set_flags(flags)
+    }
 }
 
 pub struct EthernetScheme;
@@ -1,8 +1,10 @@
+use alloc::arc::Arc;
 use alloc::boxed::Box;
 
 use collections::vec::Vec;
 
 use core::{cmp, mem};
+use core::cell::Cell;
 
 use network::common::*;
 use network::ipv4::*;
@@ -14,7 +16,7 @@ use super::arp::{Arp, ArpHeader};
 use fs::{KScheme, Resource};
 
 use system::error::{Error, Result, ENOENT};
-use system::syscall::O_RDWR;
+use system::syscall::{O_RDWR, POLLIN};
 
 /// A IP (internet protocole) resource
 pub struct IpResource {
@@ -117,6 +119,20 @@ impl Resource for IpResource {
     fn sync(&mut self) -> Result<()> {
         self.link.sync()
     }
+
+    fn poll(&self, events: usize) -> Result<usize> {
+        let mut revents = try!(self.link.poll(events));
+
+        if ! self.data.is_empty() {
+            revents |= events & POLLIN;
+        }
+
+        Ok(revents)
+    }
+
+    fn set_flags(&mut self, flags: Arc<Cell<usize>>) -> Result<()> {
+        self.link.set_flags(flags)
+    }
 }
 
 /// A ARP entry (MAC + IP)
@@ -6,14 +6,14 @@ use collections::Vec;
 use common::random::rand;
 
 use core::{cmp, mem, slice, str};
-use core::cell::UnsafeCell;
+use core::cell::{Cell, UnsafeCell};
 
 use fs::{KScheme, Resource};
 
 use network::common::{n16, n32, Checksum, Ipv4Addr, IP_ADDR, FromBytes, ToBytes};
 
-use system::error::{Error, Result, ENOENT, EPIPE};
-use system::syscall::O_RDWR;
+use system::error::{Error, Result, EAGAIN, ENOENT, EPIPE};
+use system::syscall::{O_NONBLOCK, O_RDWR, POLLHUP, POLLIN};
 
 #[derive(Copy, Clone)]
 #[repr(packed)]
@@ -111,12 +111,18 @@ impl TcpStream {
         Ok(cmp::min(buf.len(), path.len()))
     }
 
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+    /// Read data, failing with `EAGAIN` instead of waiting for a segment if `flags` has
+    /// `O_NONBLOCK`. The IP resource always blocks, as writing waits on it for the ACK.
+    fn read(&mut self, buf: &mut [u8], flags: usize) -> Result<usize> {
         if self.finished {
             return Ok(0);
         }
 
         loop {
+            if flags & O_NONBLOCK == O_NONBLOCK && try!(self.ip.poll(POLLIN)) & POLLIN == 0 {
+                return Err(Error::new(EAGAIN));
+            }
+
             let mut bytes = [0; 65536];
             let count = try!(self.ip.read(&mut bytes));
 
@@ -378,13 +384,15 @@ impl Drop for TcpStream {
 
 /// A TCP resource
 pub struct TcpResource {
-    stream: Arc<UnsafeCell<TcpStream>>
+    stream: Arc<UnsafeCell<TcpStream>>,
+    flags: Arc<Cell<usize>>
 }
 
 impl Resource for TcpResource {
     fn dup(&self) -> Result<Box<Resource>> {
         Ok(box TcpResource {
-            stream: self.stream.clone()
+            stream: self.stream.clone(),
+            flags: self.flags.clone()
         })
     }
 
@@ -393,7 +401,7 @@ impl Resource for TcpResource {
     }
 
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        unsafe { (*self.stream.get()).read(buf) }
+        unsafe { (*self.stream.get()).read(buf, self.flags.get()) }
     }
 
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
@@ -403,6 +411,20 @@ impl Resource for TcpResource {
     fn sync(&mut self) -> Result<()> {
         unsafe { (*self.stream.get()).sync() }
     }
+
+    fn set_flags(&mut self, flags: Arc<Cell<usize>>) -> Result<()> {
+        self.flags = flags;
+        Ok(())
+    }
+
+    fn poll(&self, events: usize) -> Result<usize> {
+        let stream = unsafe { &*self.stream.get() };
+        if stream.finished {
+            Ok(events & POLLIN | POLLHUP)
+        } else {
+            stream.ip.poll(events)
+        }
+    }
 }
 
 /// A TCP scheme
@@ -441,7 +463,8 @@ impl KScheme for TcpScheme {
 
                     if stream.client_establish() {
                         return Ok(box TcpResource {
-                            stream: Arc::new(UnsafeCell::new(stream))
+                            stream: Arc::new(UnsafeCell::new(stream)),
+                            flags: Arc::new(Cell::new(0))
                         });
                     }
                 }
@@ -474,7 +497,8 @@ impl KScheme for TcpScheme {
 
                                     if stream.server_establish(segment) {
                                         return Ok(box TcpResource {
-                                            stream: Arc::new(UnsafeCell::new(stream))
+                                            stream: Arc::new(UnsafeCell::new(stream)),
+                                            flags: Arc::new(Cell::new(0))
                                         });
                                     }
                                 }
@@ -1,3 +1,4 @@
+use alloc::arc::Arc;
 use alloc::boxed::Box;
 
 use collections::Vec;
@@ -5,13 +6,14 @@ use collections::Vec;
 use common::random::rand;
 
 use core::{cmp, mem, ptr, slice, str};
+use core::cell::Cell;
 
 use fs::{KScheme, Resource};
 
 use network::common::{n16, Checksum, Ipv4Addr, IP_ADDR, FromBytes, ToBytes};
 
 use system::error::{Error, Result, ENOENT};
-use system::syscall::O_RDWR;
+use system::syscall::{O_RDWR, POLLIN};
 
 #[derive(Copy, Clone)]
 #[repr(packed)]
@@ -159,6 +161,20 @@ impl Resource for UdpResource {
     fn sync(&mut self) -> Result<()> {
         self.ip.sync()
     }
+
+    fn poll(&self, events: usize) -> Result<usize> {
+        let mut revents = try!(self.ip.poll(events));
+
+        if ! self.data.is_empty() {
+            revents |= events & POLLIN;
+        }
+
+        Ok(revents)
+    }
+
+    fn set_flags(&mut self, flags: Arc<Cell<usize>>) -> Result<()> {
+        self.ip.set_flags(flags)
+    }
 }
 
 /// UDP UdpScheme
@@ -1,18 +1,24 @@
+use alloc::arc::Arc;
 use alloc::boxed::Box;
 
+use arch::context::Context;
+
 use collections::borrow::ToOwned;
 use collections::string::String;
 
+use core::cell::Cell;
 use core::cmp;
 
 use fs::{KScheme, Resource};
 
 use system::error::Result;
+use system::syscall::{POLLIN, POLLOUT};
 
 /// A debug resource
 pub struct DebugResource {
     pub path: String,
     pub command: String,
+    pub flags: Arc<Cell<usize>>,
 }
 
 impl Resource for DebugResource {
@@ -20,6 +26,7 @@ impl Resource for DebugResource {
         Ok(box DebugResource {
             path: self.path.clone(),
             command: self.command.clone(),
+            flags: self.flags.clone(),
         })
     }
 
@@ -35,7 +42,7 @@ impl Resource for DebugResource {
 
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         if self.command.is_empty() {
-            self.command = unsafe { &mut *::env().console.get() }.commands.receive("DebugResource::read");
+            self.command = try!(unsafe { &mut *::env().console.get() }.commands.receive_flags(self.flags.get(), "DebugResource::read"));
         }
 
         let mut i = 0;
@@ -60,6 +67,27 @@ impl Resource for DebugResource {
         console.write(&[]);
         Ok(())
     }
+
+    fn poll(&self, events: usize) -> Result<usize> {
+        let mut revents = events & POLLOUT;
+
+        if ! self.command.is_empty() || ! unsafe { (*::env().console.get()).commands.inner() }.is_empty() {
+            revents |= events & POLLIN;
+        }
+
+        Ok(revents)
+    }
+
+    fn poll_watch(&self, context: *mut Context) -> bool {
+        let console = unsafe { & *::env().console.get() };
+        console.commands.condition.watch(context);
+        true
+    }
+
+    fn set_flags(&mut self, flags: Arc<Cell<usize>>) -> Result<()> {
+        self.flags = flags;
+        Ok(())
+    }
 }
 
 pub struct DebugScheme;
@@ -80,12 +108,14 @@ impl KScheme for DebugScheme {
         if let Some(ref display) = console.display {
             Ok(box DebugResource {
                 path: format!("debug:{}/{}", display.width/8, display.height/16),
-                command: String::new()
+                command: String::new(),
+                flags: Arc::new(Cell::new(0))
             })
         } else {
             Ok(box DebugResource {
                 path: "debug:".to_owned(),
-                command: String::new()
+                command: String::new(),
+                flags: Arc::new(Cell::new(0))
             })
         }
     }
@@ -4,14 +4,16 @@ use alloc::boxed::Box;
 use collections::borrow::ToOwned;
 use collections::String;
 
+use arch::memory;
+
 use core::cell::UnsafeCell;
-use core::cmp;
+use core::{cmp, slice};
 use disk::Disk;
 use fs::{KScheme, Resource, ResourceSeek, VecResource};
 
 use syscall::{MODE_DIR, MODE_FILE, Stat};
 
-use system::error::{Error, Result, ENOENT};
+use system::error::{Error, Result, EINVAL, ENOENT, ENOMEM};
 
 /// A disk resource
 pub struct DiskResource {
@@ -69,6 +71,34 @@ impl Resource for DiskResource {
     fn sync(&mut self) -> Result<()> {
         Ok(())
     }
+
+    /// Map a private copy of the disk contents
+    fn mmap(&mut self, offset: usize, size: usize, _writeable: bool) -> Result<(usize, bool)> {
+        let disk = unsafe { &mut *self.disk.get() };
+        if offset as u64 >= disk.size() {
+            return Err(Error::new(EINVAL));
+        }
+
+        let physical_address = unsafe { memory::alloc_aligned(size, 4096) };
+        if physical_address == 0 {
+            return Err(Error::new(ENOMEM));
+        }
+
+        let buf = unsafe { slice::from_raw_parts_mut(physical_address as *mut u8, size) };
+        let mut i = 0;
+        while i < size {
+            match disk.read((offset + i) as u64/512, &mut buf[i..]) {
+                Ok(0) => break,
+                Ok(count) => i += count,
+                Err(err) => {
+                    unsafe { memory::unalloc(physical_address) };
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok((physical_address, true))
+    }
 }
 
 impl Drop for DiskResource {
@@ -1,16 +1,21 @@
+use alloc::arc::Arc;
 use alloc::boxed::Box;
 
+use arch::context::Context;
+
 use collections::String;
 
 use common::event::Event;
 
 use core::{cmp, ptr};
+use core::cell::Cell;
 use core::mem::size_of;
 
 use fs::{KScheme, Resource, ResourceSeek};
 
 use system::error::{Error, Result, EACCES, EBADF, ENOENT, EINVAL};
 use system::graphics::fast_copy;
+use system::syscall::{POLLIN, POLLOUT};
 
 /// A display resource
 pub struct DisplayResource {
@@ -18,13 +23,16 @@ pub struct DisplayResource {
     path: String,
     /// Seek
     seek: usize,
+    /// File status flags
+    flags: Arc<Cell<usize>>,
 }
 
 impl Resource for DisplayResource {
     fn dup(&self) -> Result<Box<Resource>> {
         Ok(Box::new(DisplayResource {
             path: self.path.clone(),
-            seek: self.seek
+            seek: self.seek,
+            flags: self.flags.clone()
         }))
     }
 
@@ -41,7 +49,7 @@ impl Resource for DisplayResource {
 
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         if buf.len() >= size_of::<Event>() {
-            let event = ::env().events.receive("DisplayResource::read");
+            let event = try!(::env().events.receive_flags(self.flags.get(), "DisplayResource::read"));
             unsafe { ptr::write(buf.as_mut_ptr().offset(0isize) as *mut Event, event) };
             let mut i = size_of::<Event>();
 
@@ -95,6 +103,40 @@ impl Resource for DisplayResource {
     fn sync(&mut self) -> Result<()> {
         Ok(())
     }
+
+    fn poll(&self, events: usize) -> Result<usize> {
+        let mut revents = events & POLLOUT;
+
+        if ! unsafe { ::env().events.inner() }.is_empty() {
+            revents |= events & POLLIN;
+        }
+
+        Ok(revents)
+    }
+
+    fn poll_watch(&self, context: *mut Context) -> bool {
+        ::env().events.condition.watch(context);
+        true
+    }
+
+    fn set_flags(&mut self, flags: Arc<Cell<usize>>) -> Result<()> {
+        self.flags = flags;
+        Ok(())
+    }
+
+    /// Map the framebuffer directly
+    fn mmap(&mut self, offset: usize, size: usize, _writeable: bool) -> Result<(usize, bool)> {
+        let console = unsafe { & *::env().console.get() };
+        if let Some(ref display) = console.display {
+            if offset + size <= display.size * 4 {
+                Ok((display.onscreen as usize + offset, false))
+            } else {
+                Err(Error::new(EINVAL))
+            }
+        } else {
+            Err(Error::new(EBADF))
+        }
+    }
 }
 
 pub struct DisplayScheme;
@@ -114,6 +156,7 @@ impl KScheme for DisplayScheme {
                     Ok(box DisplayResource {
                         path: format!("display:{}/{}", display.width, display.height),
                         seek: 0,
+                        flags: Arc::new(Cell::new(0)),
                     })
                 } else {
                     Err(Error::new(ENOENT))
@@ -127,6 +170,7 @@ impl KScheme for DisplayScheme {
                 Ok(box DisplayResource {
                     path: format!("display:{}/{}", display.width, display.height),
                     seek: 0,
+                    flags: Arc::new(Cell::new(0)),
                 })
             } else {
                 Err(Error::new(ENOENT))
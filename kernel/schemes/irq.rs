@@ -0,0 +1,214 @@
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+
+use arch::apic;
+use arch::context::Context;
+
+use collections::BTreeMap;
+
+use core::cell::{Cell, UnsafeCell};
+use core::{cmp, mem, ptr};
+
+use fs::{KScheme, Resource};
+
+use sync::WaitCondition;
+
+use system::error::{Error, Result, EACCES, EAGAIN, EINVAL, ENOENT};
+use system::syscall::{O_NONBLOCK, POLLIN, POLLOUT};
+
+/// An interrupt line that userspace drivers wait on
+struct IrqLine {
+    /// The IRQ number
+    irq: u8,
+    /// The number of times the line fired while it was open
+    count: UnsafeCell<usize>,
+    /// The count that was last acknowledged. The line is masked while this is behind `count`.
+    acknowledged: UnsafeCell<usize>,
+    /// The number of open resources for this line
+    handles: UnsafeCell<usize>,
+    /// Notified when the line fires
+    condition: WaitCondition,
+}
+
+impl IrqLine {
+    fn new(irq: u8) -> Self {
+        IrqLine {
+            irq: irq,
+            count: UnsafeCell::new(0),
+            acknowledged: UnsafeCell::new(0),
+            handles: UnsafeCell::new(0),
+            condition: WaitCondition::new(),
+        }
+    }
+
+    /// Record that the line fired, and mask it until it is acknowledged
+    unsafe fn fire(&self) {
+        *self.count.get() += 1;
+        apic::set_irq_masked(self.irq, true);
+        self.condition.notify("IrqLine::fire");
+    }
+
+    /// Acknowledge the interrupts up to `count`, unmasking the line if none are left
+    unsafe fn acknowledge(&self, count: usize) {
+        let acknowledged = &mut *self.acknowledged.get();
+        *acknowledged = cmp::max(*acknowledged, cmp::min(count, *self.count.get()));
+        if *acknowledged == *self.count.get() {
+            apic::set_irq_masked(self.irq, false);
+        }
+    }
+}
+
+/// An open interrupt line
+///
+/// Reading blocks until the line fires, and returns the number of times it fired as a `usize`.
+/// Writing that number back acknowledges the interrupts, which unmasks the line.
+pub struct IrqResource {
+    line: Arc<IrqLine>,
+    /// The count that was last read
+    seen: usize,
+    flags: Arc<Cell<usize>>,
+}
+
+impl IrqResource {
+    fn new(line: Arc<IrqLine>, seen: usize, flags: Arc<Cell<usize>>) -> Self {
+        unsafe { *line.handles.get() += 1 };
+        IrqResource {
+            line: line,
+            seen: seen,
+            flags: flags,
+        }
+    }
+}
+
+impl Resource for IrqResource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box IrqResource::new(self.line.clone(), self.seen, self.flags.clone()))
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path = format!("irq:{}", self.line.irq);
+
+        for (b, p) in buf.iter_mut().zip(path.bytes()) {
+            *b = p;
+        }
+
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.len() < mem::size_of::<usize>() {
+            return Err(Error::new(EINVAL));
+        }
+
+        loop {
+            let count = unsafe { *self.line.count.get() };
+            if count != self.seen {
+                self.seen = count;
+                unsafe { ptr::write(buf.as_mut_ptr() as *mut usize, count) };
+                return Ok(mem::size_of::<usize>());
+            }
+
+            if self.flags.get() & O_NONBLOCK == O_NONBLOCK {
+                return Err(Error::new(EAGAIN));
+            }
+
+            try!(self.line.condition.wait("IrqResource::read"));
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.len() < mem::size_of::<usize>() {
+            return Err(Error::new(EINVAL));
+        }
+
+        unsafe {
+            let count = ptr::read(buf.as_ptr() as *const usize);
+            self.line.acknowledge(count);
+        }
+
+        Ok(mem::size_of::<usize>())
+    }
+
+    fn poll(&self, events: usize) -> Result<usize> {
+        let mut revents = events & POLLOUT;
+
+        if unsafe { *self.line.count.get() } != self.seen {
+            revents |= events & POLLIN;
+        }
+
+        Ok(revents)
+    }
+
+    fn poll_watch(&self, context: *mut Context) -> bool {
+        self.line.condition.watch(context);
+        true
+    }
+
+    fn set_flags(&mut self, flags: Arc<Cell<usize>>) -> Result<()> {
+        self.flags = flags;
+        Ok(())
+    }
+}
+
+impl Drop for IrqResource {
+    /// Acknowledge everything when the last handle is closed, so the line is not left masked
+    fn drop(&mut self) {
+        unsafe {
+            *self.line.handles.get() -= 1;
+            if *self.line.handles.get() == 0 {
+                self.line.acknowledge(*self.line.count.get());
+            }
+        }
+    }
+}
+
+/// The interrupt scheme, which delivers interrupts to userspace drivers
+///
+/// `irq:N` is ISA IRQ `N`, from 1 to 15 except the cascade, or a message signalled interrupt
+/// from `apic::alloc_msi`, as vector `N + 0x20`. A line that fires is masked until it is
+/// acknowledged, which also holds back kernel drivers sharing it. Only root may open lines.
+pub struct IrqScheme {
+    lines: BTreeMap<u8, Arc<IrqLine>>,
+}
+
+impl IrqScheme {
+    pub fn new() -> Box<Self> {
+        box IrqScheme {
+            lines: BTreeMap::new(),
+        }
+    }
+}
+
+impl KScheme for IrqScheme {
+    fn on_irq(&mut self, irq: u8) {
+        if let Some(line) = self.lines.get(&irq) {
+            if unsafe { *line.handles.get() } > 0 {
+                unsafe { line.fire() };
+            }
+        }
+    }
+
+    fn scheme(&self) -> &str {
+        "irq"
+    }
+
+    fn open(&mut self, url: &str, flags: usize) -> Result<Box<Resource>> {
+        {
+            let contexts = unsafe { & *::env().contexts.get() };
+            if try!(contexts.current()).euid != 0 {
+                return Err(Error::new(EACCES));
+            }
+        }
+
+        let path = url.splitn(2, ":").nth(1).unwrap_or("").trim_matches('/');
+        let irq = match path.parse::<u8>() {
+            Ok(irq @ 1 ... 15) if irq != 2 => irq,
+            Ok(irq) if irq >= apic::MSI_VECTOR_FIRST - 0x20 && irq <= apic::MSI_VECTOR_LAST - 0x20 => irq,
+            _ => return Err(Error::new(ENOENT)),
+        };
+
+        let line = self.lines.entry(irq).or_insert_with(|| Arc::new(IrqLine::new(irq))).clone();
+        let seen = unsafe { *line.count.get() };
+        Ok(box IrqResource::new(line, seen, Arc::new(Cell::new(flags))))
+    }
+}
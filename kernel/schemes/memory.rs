@@ -0,0 +1,144 @@
+use alloc::boxed::Box;
+
+use arch::memory;
+use arch::paging::{PF_CACHE_DISABLE, PF_WRITE_THROUGH};
+
+use core::{cmp, mem, ptr};
+
+use fs::{KScheme, Resource};
+
+use system::error::{Error, Result, EACCES, EINVAL, ENOENT, ENOMEM};
+
+/// What the memory of a resource is
+#[derive(Clone, Copy)]
+enum MemoryKind {
+    /// Physical memory at the offset of the mapping, with the given caching page flags
+    Physical(usize),
+    /// A new buffer for each mapping, which is physically contiguous
+    Dma,
+}
+
+/// A resource of the memory scheme
+pub struct MemoryResource {
+    path: &'static str,
+    kind: MemoryKind,
+    /// The physical address of the last DMA buffer that was mapped
+    last: Option<usize>,
+}
+
+impl Resource for MemoryResource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box MemoryResource {
+            path: self.path,
+            kind: self.kind,
+            last: self.last,
+        })
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path = self.path.as_bytes();
+
+        for (b, p) in buf.iter_mut().zip(path.iter()) {
+            *b = *p;
+        }
+
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
+    /// Read the physical address of the last DMA buffer, as a `usize`, or fail with `ENOENT` if
+    /// none was mapped yet
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self.kind {
+            MemoryKind::Dma if buf.len() >= mem::size_of::<usize>() => match self.last {
+                Some(last) => {
+                    unsafe { ptr::write(buf.as_mut_ptr() as *mut usize, last) };
+                    Ok(mem::size_of::<usize>())
+                },
+                None => Err(Error::new(ENOENT)),
+            },
+            _ => Err(Error::new(EINVAL)),
+        }
+    }
+
+    /// Map the physical memory at `offset`, or allocate a DMA buffer, which needs an offset of 0
+    fn mmap(&mut self, offset: usize, size: usize, _writeable: bool) -> Result<(usize, bool)> {
+        match self.kind {
+            MemoryKind::Physical(_) => Ok((offset, false)),
+            MemoryKind::Dma => {
+                if offset != 0 {
+                    return Err(Error::new(EINVAL));
+                }
+
+                let physical_address = unsafe { memory::alloc_aligned(size, 4096) };
+                if physical_address == 0 {
+                    return Err(Error::new(ENOMEM));
+                }
+
+                self.last = Some(physical_address);
+                Ok((physical_address, true))
+            },
+        }
+    }
+
+    fn mmap_cache(&self) -> usize {
+        match self.kind {
+            MemoryKind::Physical(cache) => cache,
+            MemoryKind::Dma => 0,
+        }
+    }
+
+    /// DMA buffers are not copied on write, as the device keeps using the same physical pages
+    fn mmap_shared(&self) -> bool {
+        match self.kind {
+            MemoryKind::Physical(_) => false,
+            MemoryKind::Dma => true,
+        }
+    }
+}
+
+/// The memory scheme, for userspace drivers
+///
+/// - `memory:physical` maps the physical memory at the offset given to `mmap`, such as a BAR.
+///   `memory:physical/uncached` and `memory:physical/writethrough` map it with caching disabled
+///   or write through.
+/// - `memory:dma` allocates a zeroed, physically contiguous buffer for each `mmap`. Reading
+///   returns the physical address of the last one. Buffers are shared with clones of the driver,
+///   not copied on write, and are freed when the last mapping is removed.
+///
+/// Only root may open it.
+pub struct MemoryScheme;
+
+impl MemoryScheme {
+    pub fn new() -> Box<Self> {
+        box MemoryScheme
+    }
+}
+
+impl KScheme for MemoryScheme {
+    fn scheme(&self) -> &str {
+        "memory"
+    }
+
+    fn open(&mut self, url: &str, _: usize) -> Result<Box<Resource>> {
+        {
+            let contexts = unsafe { & *::env().contexts.get() };
+            if try!(contexts.current()).euid != 0 {
+                return Err(Error::new(EACCES));
+            }
+        }
+
+        let (path, kind) = match url.splitn(2, ":").nth(1).unwrap_or("").trim_matches('/') {
+            "physical" => ("memory:physical", MemoryKind::Physical(0)),
+            "physical/uncached" => ("memory:physical/uncached", MemoryKind::Physical(PF_CACHE_DISABLE)),
+            "physical/writethrough" => ("memory:physical/writethrough", MemoryKind::Physical(PF_WRITE_THROUGH)),
+            "dma" => ("memory:dma", MemoryKind::Dma),
+            _ => return Err(Error::new(ENOENT)),
+        };
+
+        Ok(box MemoryResource {
+            path: path,
+            kind: kind,
+            last: None,
+        })
+    }
+}
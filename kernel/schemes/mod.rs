@@ -8,6 +8,10 @@ pub mod display;
 pub mod env;
 /// Init Filesystem
 pub mod initfs;
+/// Interrupts for userspace drivers
+pub mod irq;
+/// Physical memory and DMA buffers for userspace drivers
+pub mod memory;
 /// Pipes
 pub mod pipe;
 /// Psuedoterminals
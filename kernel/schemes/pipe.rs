@@ -1,6 +1,9 @@
 use alloc::arc::{Arc, Weak};
 use alloc::boxed::Box;
 
+use arch::context::Context;
+
+use core::cell::Cell;
 use core::cmp;
 
 use fs::Resource;
@@ -8,16 +11,19 @@ use fs::Resource;
 use sync::WaitQueue;
 
 use system::error::{Error, Result, EPIPE};
+use system::syscall::{POLLERR, POLLHUP, POLLIN, POLLOUT};
 
 /// Read side of a pipe
 pub struct PipeRead {
-    vec: Arc<WaitQueue<u8>>
+    vec: Arc<WaitQueue<u8>>,
+    flags: Arc<Cell<usize>>
 }
 
 impl PipeRead {
     pub fn new() -> Self {
         PipeRead {
-            vec: Arc::new(WaitQueue::new())
+            vec: Arc::new(WaitQueue::new()),
+            flags: Arc::new(Cell::new(0))
         }
     }
 }
@@ -26,6 +32,7 @@ impl Resource for PipeRead {
     fn dup(&self) -> Result<Box<Resource>> {
         Ok(box PipeRead {
             vec: self.vec.clone(),
+            flags: self.flags.clone(),
         })
     }
 
@@ -44,7 +51,7 @@ impl Resource for PipeRead {
             Ok(0)
         } else {
             if !buf.is_empty() {
-                buf[0] = self.vec.receive("PipeRead::read");
+                buf[0] = try!(self.vec.receive_flags(self.flags.get(), "PipeRead::read"));
             }
 
             let mut i = 1;
@@ -62,6 +69,30 @@ impl Resource for PipeRead {
             Ok(i)
         }
     }
+
+    fn poll(&self, events: usize) -> Result<usize> {
+        let mut revents = 0;
+
+        if ! unsafe { self.vec.inner() }.is_empty() {
+            revents |= events & POLLIN;
+        }
+
+        if Arc::weak_count(&self.vec) == 0 {
+            revents |= POLLHUP;
+        }
+
+        Ok(revents)
+    }
+
+    fn poll_watch(&self, context: *mut Context) -> bool {
+        self.vec.condition.watch(context);
+        true
+    }
+
+    fn set_flags(&mut self, flags: Arc<Cell<usize>>) -> Result<()> {
+        self.flags = flags;
+        Ok(())
+    }
 }
 
 /// Read side of a pipe
@@ -111,4 +142,20 @@ impl Resource for PipeWrite {
         //TODO: Wait until empty
         Ok(())
     }
+
+    fn poll(&self, events: usize) -> Result<usize> {
+        match self.vec.upgrade() {
+            Some(_) => Ok(events & POLLOUT),
+            None => Ok(POLLERR)
+        }
+    }
+}
+
+impl Drop for PipeWrite {
+    /// Wake up pollers of the read side, which may see the pipe hung up
+    fn drop(&mut self) {
+        if let Some(vec) = self.vec.upgrade() {
+            vec.condition.notify("PipeWrite::drop");
+        }
+    }
 }
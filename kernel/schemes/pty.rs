@@ -1,20 +1,32 @@
 use alloc::arc::{Arc, Weak};
 use alloc::boxed::Box;
 
+use arch::context::Context;
+
 use collections::{Vec, VecDeque};
 
-use core::cmp;
+use core::cell::Cell;
+use core::{cmp, str};
 
 use fs::{KScheme, Resource};
 
 use sync::WaitQueue;
 
-use system::error::{Error, ENOENT, Result};
+use syscall::signal::kill_group;
+
+use system::error::{Error, EINTR, EINVAL, EIO, ENOENT, EPERM, Result};
+use system::syscall::{POLLHUP, POLLIN, POLLOUT, SIGINT, SIGQUIT, SIGTSTP, SIGTTIN, SIG_IGN};
 
 pub struct Pty {
     id: usize,
     input: WaitQueue<u8>,
-    output: WaitQueue<Vec<u8>>
+    output: WaitQueue<Vec<u8>>,
+    /// The session this is the controlling terminal of, or 0
+    session: Cell<usize>,
+    /// The foreground process group, or 0
+    pgrp: Cell<usize>,
+    /// Whether the interrupt, suspend and quit characters are turned into signals, like `ISIG`
+    isig: Cell<bool>
 }
 
 impl Pty {
@@ -22,8 +34,69 @@ impl Pty {
         Pty {
             id: id,
             input: WaitQueue::new(),
-            output: WaitQueue::new()
+            output: WaitQueue::new(),
+            session: Cell::new(0),
+            pgrp: Cell::new(0),
+            isig: Cell::new(true)
+        }
+    }
+
+    /// Make this the controlling terminal of the current context's session, if the current
+    /// context is a session leader and this terminal is not yet controlling a session
+    fn attach(&self) {
+        let contexts = unsafe { & *::env().contexts.get() };
+        if let Ok(current) = contexts.current() {
+            if current.sid == current.pid && self.session.get() == 0 {
+                self.session.set(current.sid);
+                self.pgrp.set(current.pgid);
+            }
+        }
+    }
+
+    /// Turn a byte written by the master into a signal for the foreground process group
+    ///
+    /// Every byte is passed through as input if signals are turned off.
+    fn signal(&self, b: u8) -> Option<usize> {
+        if ! self.isig.get() {
+            return None;
+        }
+
+        let sig = match b {
+            0x03 => SIGINT,
+            0x1A => SIGTSTP,
+            0x1C => SIGQUIT,
+            _ => return None
+        };
+
+        let pgrp = self.pgrp.get();
+        if pgrp > 0 {
+            let _ = kill_group(pgrp, sig);
         }
+
+        Some(sig)
+    }
+
+    /// Stop a background process group of the controlling session that tries to read
+    ///
+    /// Returns `EIO` instead if the reader ignores or blocks `SIGTTIN`, as it would not stop.
+    fn check_foreground(&self) -> Result<()> {
+        let pgid = {
+            let contexts = unsafe { & *::env().contexts.get() };
+            let current = try!(contexts.current());
+            if current.sid != self.session.get() || current.pgid == self.pgrp.get() {
+                return Ok(());
+            }
+
+            let ignored = unsafe { (*current.sig_actions.get())[SIGTTIN].sa_handler } == SIG_IGN;
+            if ignored || current.sig_blocked & 1 << SIGTTIN != 0 {
+                return Err(Error::new(EIO));
+            }
+
+            current.pgid
+        };
+
+        let _ = kill_group(pgid, SIGTTIN);
+        Err(Error::new(EINTR))
     }
 }
 
@@ -48,7 +121,10 @@ impl KScheme for PtyScheme {
     }
 
     fn open(&mut self, url: &str, _: usize) -> Result<Box<Resource>> {
-        let req_id = url.splitn(2, ":").nth(1).unwrap_or("").parse::<usize>().unwrap_or(0);
+        let path = url.splitn(2, ":").nth(1).unwrap_or("");
+        let mut parts = path.splitn(2, "/");
+        let req_id = parts.next().unwrap_or("").parse::<usize>().unwrap_or(0);
+        let req_file = parts.next().unwrap_or("");
 
         self.ptys.retain(|pty| {
             pty.upgrade().is_some()
@@ -67,7 +143,15 @@ impl KScheme for PtyScheme {
             for pty in self.ptys.iter() {
                 if let Some(pty_strong) = pty.upgrade() {
                     if pty_strong.id == req_id {
-                        return Ok(Box::new(PtySlave::new(&pty_strong)))
+                        return match req_file {
+                            "" => {
+                                pty_strong.attach();
+                                Ok(Box::new(PtySlave::new(&pty_strong)))
+                            },
+                            "pgrp" => Ok(Box::new(PtyPgrp::new(&pty_strong))),
+                            "isig" => Ok(Box::new(PtyIsig::new(&pty_strong))),
+                            _ => Err(Error::new(ENOENT))
+                        };
                     }
                 }
             }
@@ -79,13 +163,15 @@ impl KScheme for PtyScheme {
 
 /// Psuedoterminal master
 pub struct PtyMaster {
-    inner: Arc<Pty>
+    inner: Arc<Pty>,
+    flags: Arc<Cell<usize>>
 }
 
 impl PtyMaster {
     pub fn new(id: usize) -> Self {
         PtyMaster {
-            inner: Arc::new(Pty::new(id))
+            inner: Arc::new(Pty::new(id)),
+            flags: Arc::new(Cell::new(0))
         }
     }
 }
@@ -93,7 +179,8 @@ impl PtyMaster {
 impl Resource for PtyMaster {
     fn dup(&self) -> Result<Box<Resource>> {
         Ok(box PtyMaster {
-            inner: self.inner.clone()
+            inner: self.inner.clone(),
+            flags: self.flags.clone()
         })
     }
 
@@ -108,7 +195,7 @@ impl Resource for PtyMaster {
     }
 
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let packet = self.inner.output.receive("PtyMaster::read");
+        let packet = try!(self.inner.output.receive_flags(self.flags.get(), "PtyMaster::read"));
 
         let mut i = 0;
 
@@ -122,22 +209,46 @@ impl Resource for PtyMaster {
 
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
         for &b in buf.iter() {
-            self.inner.input.send(b, "PtyMaster::write");
+            if self.inner.signal(b).is_none() {
+                self.inner.input.send(b, "PtyMaster::write");
+            }
         }
 
         Ok(buf.len())
     }
+
+    fn poll(&self, events: usize) -> Result<usize> {
+        let mut revents = events & POLLOUT;
+
+        if ! unsafe { self.inner.output.inner() }.is_empty() {
+            revents |= events & POLLIN;
+        }
+
+        Ok(revents)
+    }
+
+    fn poll_watch(&self, context: *mut Context) -> bool {
+        self.inner.output.condition.watch(context);
+        true
+    }
+
+    fn set_flags(&mut self, flags: Arc<Cell<usize>>) -> Result<()> {
+        self.flags = flags;
+        Ok(())
+    }
 }
 
 /// Psuedoterminal slave
 pub struct PtySlave {
-    inner: Weak<Pty>
+    inner: Weak<Pty>,
+    flags: Arc<Cell<usize>>
 }
 
 impl PtySlave {
     pub fn new(pty: &Arc<Pty>) -> Self {
         PtySlave {
-            inner: Arc::downgrade(&pty)
+            inner: Arc::downgrade(&pty),
+            flags: Arc::new(Cell::new(0))
         }
     }
 }
@@ -145,7 +256,8 @@ impl PtySlave {
 impl Resource for PtySlave {
     fn dup(&self) -> Result<Box<Resource>> {
         Ok(box PtySlave {
-            inner: self.inner.clone()
+            inner: self.inner.clone(),
+            flags: self.flags.clone()
         })
     }
 
@@ -167,8 +279,10 @@ impl Resource for PtySlave {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         match self.inner.upgrade() {
             Some(inner) => {
+                try!(inner.check_foreground());
+
                 if ! buf.is_empty() {
-                    buf[0] = inner.input.receive("PtySlave::read");
+                    buf[0] = try!(inner.input.receive_flags(self.flags.get(), "PtySlave::read"));
                 }
 
                 let mut i = 1;
@@ -210,4 +324,179 @@ impl Resource for PtySlave {
         }
         Ok(())
     }
+
+    fn poll(&self, events: usize) -> Result<usize> {
+        match self.inner.upgrade() {
+            Some(inner) => {
+                let mut revents = events & POLLOUT;
+
+                if ! unsafe { inner.input.inner() }.is_empty() {
+                    revents |= events & POLLIN;
+                }
+
+                Ok(revents)
+            },
+            None => Ok(POLLHUP)
+        }
+    }
+
+    fn poll_watch(&self, context: *mut Context) -> bool {
+        match self.inner.upgrade() {
+            Some(inner) => {
+                inner.input.condition.watch(context);
+                true
+            },
+            None => false
+        }
+    }
+
+    fn set_flags(&mut self, flags: Arc<Cell<usize>>) -> Result<()> {
+        self.flags = flags;
+        Ok(())
+    }
+}
+
+/// The foreground process group of a psuedoterminal, read and written as a decimal number
+pub struct PtyPgrp {
+    inner: Weak<Pty>
+}
+
+impl PtyPgrp {
+    pub fn new(pty: &Arc<Pty>) -> Self {
+        PtyPgrp {
+            inner: Arc::downgrade(&pty)
+        }
+    }
+}
+
+impl Resource for PtyPgrp {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box PtyPgrp {
+            inner: self.inner.clone()
+        })
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        match self.inner.upgrade() {
+            Some(inner) => {
+                let path = format!("pty:{}/pgrp", inner.id);
+
+                for (b, p) in buf.iter_mut().zip(path.bytes()) {
+                    *b = p;
+                }
+
+                Ok(cmp::min(buf.len(), path.len()))
+            },
+            None => Ok(0)
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self.inner.upgrade() {
+            Some(inner) => {
+                let pgrp = format!("{}", inner.pgrp.get());
+
+                for (b, p) in buf.iter_mut().zip(pgrp.bytes()) {
+                    *b = p;
+                }
+
+                Ok(cmp::min(buf.len(), pgrp.len()))
+            },
+            None => Ok(0)
+        }
+    }
+
+    /// Set the foreground process group, which has to be in the session of the caller, and the
+    /// terminal has to be the controlling terminal of that session
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self.inner.upgrade() {
+            Some(inner) => {
+                let pgrp = try!(str::from_utf8(buf).ok().and_then(|pgrp| pgrp.trim().parse::<usize>().ok()).ok_or(Error::new(EINVAL)));
+
+                let contexts = unsafe { & *::env().contexts.get() };
+                let sid = try!(contexts.current()).sid;
+                if sid != inner.session.get() || ! contexts.iter().any(|context| context.pgid == pgrp && context.sid == sid && ! context.exited) {
+                    return Err(Error::new(EPERM));
+                }
+
+                inner.pgrp.set(pgrp);
+
+                Ok(buf.len())
+            },
+            None => Ok(0)
+        }
+    }
+}
+
+/// Whether a psuedoterminal turns the interrupt, suspend and quit characters into signals, read
+/// and written as `1` or `0`
+pub struct PtyIsig {
+    inner: Weak<Pty>
+}
+
+impl PtyIsig {
+    pub fn new(pty: &Arc<Pty>) -> Self {
+        PtyIsig {
+            inner: Arc::downgrade(&pty)
+        }
+    }
+}
+
+impl Resource for PtyIsig {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box PtyIsig {
+            inner: self.inner.clone()
+        })
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        match self.inner.upgrade() {
+            Some(inner) => {
+                let path = format!("pty:{}/isig", inner.id);
+
+                for (b, p) in buf.iter_mut().zip(path.bytes()) {
+                    *b = p;
+                }
+
+                Ok(cmp::min(buf.len(), path.len()))
+            },
+            None => Ok(0)
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self.inner.upgrade() {
+            Some(inner) => {
+                if ! buf.is_empty() {
+                    buf[0] = if inner.isig.get() { b'1' } else { b'0' };
+                }
+
+                Ok(cmp::min(buf.len(), 1))
+            },
+            None => Ok(0)
+        }
+    }
+
+    /// Turn the signals on or off, which only a context in the session of the terminal may do
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self.inner.upgrade() {
+            Some(inner) => {
+                let isig = match str::from_utf8(buf).map(|isig| isig.trim()) {
+                    Ok("1") => true,
+                    Ok("0") => false,
+                    _ => return Err(Error::new(EINVAL))
+                };
+
+                let contexts = unsafe { & *::env().contexts.get() };
+                if try!(contexts.current()).sid != inner.session.get() {
+                    return Err(Error::new(EPERM));
+                }
+
+                inner.isig.set(isig);
+
+                Ok(buf.len())
+            },
+            None => Ok(0)
+        }
+    }
 }
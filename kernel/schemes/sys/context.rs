@@ -2,47 +2,51 @@ use alloc::boxed::Box;
 
 use collections::string::{String, ToString};
 
-use arch::context;
-
 use fs::{Resource, VecResource};
 
 use system::error::Result;
-use system::syscall::MODE_FILE;
+use system::syscall::{MODE_FILE, RLIMIT_AS, RLIMIT_NOFILE, RLIM_INFINITY};
+
+fn format_memory(memory: usize) -> String {
+    if memory >= 1024 * 1024 * 1024 {
+        format!("{} GB", memory / 1024 / 1024 / 1024)
+    } else if memory >= 1024 * 1024 {
+        format!("{} MB", memory / 1024 / 1024)
+    } else if memory >= 1024 {
+        format!("{} KB", memory / 1024)
+    } else {
+        format!("{} B", memory)
+    }
+}
+
+fn format_limit<F: Fn(usize) -> String>(limit: usize, f: F) -> String {
+    if limit == RLIM_INFINITY {
+        "-".to_string()
+    } else {
+        f(limit)
+    }
+}
 
 pub fn resource() -> Result<Box<Resource>> {
-    let mut string = format!("{:<6}{:<6}{:<10}{:<10}{:<8}{:<6}{:<6}{:<6}{}\n",
+    let mut string = format!("{:<6}{:<6}{:<10}{:<10}{:<6}{:<8}{:<8}{:<6}{:<6}{:<6}{:<6}{}\n",
                              "PID",
                              "PPID",
                              "SWITCH",
                              "TIME",
+                             "NICE",
                              "MEM",
+                             "MAXMEM",
                              "FDS",
+                             "MAXFDS",
                              "FLAG",
                              "IOPL",
                              "NAME");
     {
         let contexts = unsafe { & *::env().contexts.get() };
         for context in contexts.iter() {
-            let mut memory = 0;
-            if context.kernel_stack > 0 {
-                memory += context::CONTEXT_STACK_SIZE;
-            }
-            if let Some(ref stack) = context.stack {
-                memory += stack.virtual_size;
-            }
-            memory += unsafe { (*context.image.get()).size() };
-            memory += unsafe { (*context.heap.get()).size() };
-            memory += unsafe { (*context.mmap.get()).size() };
-
-            let memory_string = if memory >= 1024 * 1024 * 1024 {
-                format!("{} GB", memory / 1024 / 1024 / 1024)
-            } else if memory >= 1024 * 1024 {
-                format!("{} MB", memory / 1024 / 1024)
-            } else if memory >= 1024 {
-                format!("{} KB", memory / 1024)
-            } else {
-                format!("{} B", memory)
-            };
+            let memory_string = format_memory(context.memory_size());
+            let max_memory_string = format_limit(context.rlimits[RLIMIT_AS].rlim_cur, format_memory);
+            let max_files_string = format_limit(context.rlimits[RLIMIT_NOFILE].rlim_cur, |limit| limit.to_string());
 
             let mut flags_string = String::new();
             if context.stack.is_some() {
@@ -53,6 +57,9 @@ pub fn resource() -> Result<Box<Resource>> {
             if context.blocked > 0 {
                 flags_string.push('B');
             }
+            if context.queued {
+                flags_string.push('Q');
+            }
             if context.exited {
                 flags_string.push('E');
             }
@@ -65,14 +72,20 @@ pub fn resource() -> Result<Box<Resource>> {
             if context.supervised {
                 flags_string.push('T');
             }
+            if context.stopped {
+                flags_string.push('H');
+            }
 
-            string.push_str(&format!("{:<6}{:<6}{:<10}{:<10}{:<8}{:<6}{:<6}{:<6}{}\n",
+            string.push_str(&format!("{:<6}{:<6}{:<10}{:<10}{:<6}{:<8}{:<8}{:<6}{:<6}{:<6}{:<6}{}\n",
                                context.pid,
                                context.ppid,
                                context.switch,
                                context.time,
+                               context.nice,
                                memory_string,
+                               max_memory_string,
                                unsafe { (*context.files.get()).len() },
+                               max_files_string,
                                flags_string,
                                context.iopl,
                                context.name));
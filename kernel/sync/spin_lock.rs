@@ -0,0 +1,40 @@
+use core::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+
+/// A lock that busy waits until it is free, for mutual exclusion between processors
+///
+/// It does not disable interrupts, and it is not tied to a context: it may be released by
+/// another context than the one that took it.
+pub struct SpinLock {
+    locked: AtomicBool,
+}
+
+impl SpinLock {
+    pub const fn new() -> SpinLock {
+        SpinLock {
+            locked: ATOMIC_BOOL_INIT
+        }
+    }
+
+    /// Take the lock if it is free, returning true if it was taken
+    pub fn try_lock(&self) -> bool {
+        ! self.locked.compare_and_swap(false, true, Ordering::Acquire)
+    }
+
+    /// Take the lock, waiting until it is free
+    pub fn lock(&self) {
+        while ! self.try_lock() {
+            while self.is_locked() {
+                unsafe { asm!("pause" : : : "memory" : "intel", "volatile"); }
+            }
+        }
+    }
+
+    /// Release the lock
+    pub fn unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+}
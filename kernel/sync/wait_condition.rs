@@ -8,6 +8,10 @@ use core::ops::DerefMut;
 
 use common::time::Duration;
 
+use syscall;
+
+use system::error::{Error, Result, EINTR};
+
 pub struct WaitCondition {
     contexts: UnsafeCell<Vec<*mut Context>>
 }
@@ -30,24 +34,71 @@ impl WaitCondition {
         }*/
         let mut contexts = Vec::new();
         mem::swap(unsafe { &mut *self.contexts.get() }, &mut contexts);
+        let condition = self as *const WaitCondition;
         for &context in contexts.iter() {
-            unsafe { (*context).unblock(reason) }
+            unsafe {
+                (*context).watching.retain(|&watched| watched != condition);
+                (*context).interruptible = false;
+                (*context).unblock(reason);
+            }
         }
         contexts.len()
     }
 
-    pub fn wait(&self, reason: &str) {
+    /// Notify `context` along with the waiting contexts, without blocking it, so that it can wait
+    /// on several conditions at once
+    ///
+    /// The condition is added to `Context::watching`, so that `Context::unwatch_all` removes the
+    /// context again without looking up what it watched.
+    pub fn watch(&self, context: *mut Context) {
+        unsafe {
+            (*context).watching.push(self as *const WaitCondition);
+            (&mut *self.contexts.get()).push(context);
+        }
+    }
+
+    /// Stop notifying `context`, if it was not notified yet
+    pub fn unwatch(&self, context: *mut Context) {
+        let condition = self as *const WaitCondition;
+        unsafe {
+            (*context).watching.retain(|&watched| watched != condition);
+            (&mut *self.contexts.get()).retain(|&waiting| waiting != context);
+        }
+    }
+
+    /// Wait until notified, or fail with `EINTR` if a signal interrupts the wait
+    pub fn wait(&self, reason: &str) -> Result<()> {
         {
             // debugln!("  WaitCondition::wait {:X} {}", self as *const _ as usize, reason);
             if let Ok(mut context) = unsafe { &mut *::env().contexts.get() }.current_mut() {
+                if syscall::signal::interrupting(&context) != 0 {
+                    return Err(Error::new(EINTR));
+                }
+
                 let mut contexts = unsafe { &mut *self.contexts.get() };
                 contexts.push(context.deref_mut() as *mut Context);
+                (*context).interruptible = true;
                 (*context).block(reason);
             } else {
                 // debugln!("    NOT FOUND {}/{}", unsafe { & *::env().contexts.get() }.i, unsafe { & *::env().contexts.get() }.len());
             }
         }
         unsafe { context_switch(); }
+        {
+            if let Ok(mut context) = unsafe { &mut *::env().contexts.get() }.current_mut() {
+                (*context).interruptible = false;
+
+                // Notified contexts were taken off the list, so still being on it means that a
+                // signal unblocked this one
+                let context_ptr = context.deref_mut() as *mut Context;
+                let mut contexts = unsafe { &mut *self.contexts.get() };
+                if contexts.contains(&context_ptr) {
+                    contexts.retain(|&waiting| waiting != context_ptr);
+                    return Err(Error::new(EINTR));
+                }
+            }
+        }
+        Ok(())
     }
 
     pub fn wait_for(&self, reason: &str, time: Duration) -> bool {
@@ -57,7 +108,7 @@ impl WaitCondition {
             if let Ok(mut context) = unsafe { &mut *::env().contexts.get() }.current_mut() {
                 let mut contexts = unsafe { &mut *self.contexts.get() };
                 contexts.push(context.deref_mut() as *mut Context);
-                (*context).wake = Some(Duration::monotonic() + time);
+                (*context).sleep(Duration::monotonic() + time);
                 (*context).block(reason);
             } else {
                 // debugln!("    NOT FOUND {}/{}", unsafe { & *::env().contexts.get() }.i, unsafe { & *::env().contexts.get() }.len());
@@ -68,6 +119,10 @@ impl WaitCondition {
             if let Ok(mut context) = unsafe { &mut *::env().contexts.get() }.current_mut() {
                 if (*context).wake.is_none() {
                     ret = false;
+
+                    // Woken up by the timeout, so stop waiting for a notification
+                    let context_ptr = context.deref_mut() as *mut Context;
+                    unsafe { &mut *self.contexts.get() }.retain(|&waiting| waiting != context_ptr);
                 } else {
                     (*context).wake = None;
                 }
@@ -1,10 +1,13 @@
 use collections::BTreeMap;
 use core::cell::UnsafeCell;
+
+use system::error::Result;
+
 use super::WaitCondition;
 
 pub struct WaitMap<K, V> {
     inner: UnsafeCell<BTreeMap<K, V>>,
-    condition: WaitCondition
+    pub condition: WaitCondition
 }
 
 impl<K, V> WaitMap<K, V> where K: Ord {
@@ -24,12 +27,41 @@ impl<K, V> WaitMap<K, V> where K: Ord {
         self.condition.notify(reason);
     }
 
-    pub fn receive(&self, key: &K, reason: &str) -> V {
+    pub fn receive(&self, key: &K, reason: &str) -> Result<V> {
         loop {
-            if let Some(value) = unsafe { self.inner() }.remove(key) {
-                return value;
+            if let Some(value) = self.try_receive(key) {
+                return Ok(value);
             }
-            self.condition.wait(reason);
+            try!(self.condition.wait(reason));
         }
     }
+
+    pub fn try_receive(&self, key: &K) -> Option<V> {
+        unsafe { self.inner() }.remove(key)
+    }
+
+    pub fn receive_any(&self, reason: &str) -> Result<(K, V)> where K: Clone {
+        self.receive_where(|_, _| true, reason)
+    }
+
+    pub fn try_receive_any(&self) -> Option<(K, V)> where K: Clone {
+        self.try_receive_where(|_, _| true)
+    }
+
+    /// Receive the first entry, in key order, that matches `f`
+    pub fn receive_where<F>(&self, f: F, reason: &str) -> Result<(K, V)> where K: Clone, F: Fn(&K, &V) -> bool {
+        loop {
+            if let Some(entry) = self.try_receive_where(&f) {
+                return Ok(entry);
+            }
+            try!(self.condition.wait(reason));
+        }
+    }
+
+    /// Receive the first entry, in key order, that matches `f`, without waiting
+    pub fn try_receive_where<F>(&self, f: F) -> Option<(K, V)> where K: Clone, F: Fn(&K, &V) -> bool {
+        let inner = unsafe { self.inner() };
+        let key = inner.iter().find(|&(key, value)| f(key, value)).map(|(key, _)| key.clone());
+        key.and_then(|key| inner.remove(&key).map(|value| (key, value)))
+    }
 }
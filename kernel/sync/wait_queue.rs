@@ -6,6 +6,9 @@ use core::ops::DerefMut;
 
 use common::time::Duration;
 
+use system::error::{Error, Result, EAGAIN};
+use system::syscall::O_NONBLOCK;
+
 use super::WaitCondition;
 
 pub struct WaitQueue<T> {
@@ -32,12 +35,21 @@ impl<T> WaitQueue<T> {
         }
     }
 
-    pub fn receive(&self, reason: &str) -> T {
+    pub fn receive(&self, reason: &str) -> Result<T> {
         loop {
             if let Some(value) = unsafe { self.inner() }.pop_front() {
-                return value;
+                return Ok(value);
             }
-            self.condition.wait(reason);
+            try!(self.condition.wait(reason));
+        }
+    }
+
+    /// Receive a value, or fail with `EAGAIN` instead of blocking if `flags` contains `O_NONBLOCK`
+    pub fn receive_flags(&self, flags: usize, reason: &str) -> Result<T> {
+        if flags & O_NONBLOCK == O_NONBLOCK {
+            unsafe { self.inner() }.pop_front().ok_or(Error::new(EAGAIN))
+        } else {
+            self.receive(reason)
         }
     }
 
@@ -52,17 +64,17 @@ impl<T> WaitQueue<T> {
         }
     }
 
-    pub fn receive_all(&self, reason: &str) -> VecDeque<T> {
+    pub fn receive_all(&self, reason: &str) -> Result<VecDeque<T>> {
         loop {
             {
                 let mut inner = unsafe { self.inner() };
                 if ! inner.is_empty() {
                     let mut swap_inner = VecDeque::new();
                     mem::swap(inner.deref_mut(), &mut swap_inner);
-                    return swap_inner;
+                    return Ok(swap_inner);
                 }
             }
-            self.condition.wait(reason);
+            try!(self.condition.wait(reason));
         }
     }
 
@@ -3,11 +3,12 @@
 use alloc::arc::Arc;
 
 use arch::context::{CONTEXT_IMAGE_ADDR, CONTEXT_IMAGE_SIZE, CONTEXT_HEAP_ADDR, CONTEXT_HEAP_SIZE,
-                    CONTEXT_MMAP_ADDR, CONTEXT_MMAP_SIZE, CONTEXT_STACK_SIZE, CONTEXT_STACK_ADDR,
-                    CONTEXT_TLS_ADDR,
-                    context_switch, context_userspace, Context, ContextMemory, ContextZone};
+                    CONTEXT_INTERP_ADDR, CONTEXT_MMAP_ADDR, CONTEXT_MMAP_SIZE, CONTEXT_STACK_SIZE,
+                    CONTEXT_STACK_ADDR, CONTEXT_TLS_ADDR,
+                    context_switch, context_unlock, context_userspace, Context, ContextFile, ContextMemory, ContextZone,
+                    EnvVar};
 use arch::gdt::{GDT_USER_CODE, GDT_USER_DATA, GDT_USER_TLS, GdtEntry};
-use arch::elf::Elf;
+use arch::elf::{self, Elf};
 use arch::memory;
 use arch::regs::Regs;
 
@@ -22,36 +23,77 @@ use core::ops::DerefMut;
 use core::{mem, ptr, slice, str};
 
 use system::error::{Error, Result, ENOEXEC, ENOMEM};
-use system::syscall::O_RDONLY;
+use system::syscall::{SigAction, AT_BASE, AT_ENTRY, AT_NULL, AT_PAGESZ, AT_PHDR, AT_PHENT, AT_PHNUM,
+                      O_RDONLY, RLIMIT_AS, SIG_IGN};
+
+/// The arguments and environment variables of a new program, packed into one allocation
+struct Strings {
+    physical_address: usize,
+    size: usize,
+    /// Offsets of the NUL terminated arguments
+    args: Vec<usize>,
+    /// Offsets of the NUL terminated `name=value` environment variables
+    env_vars: Vec<usize>,
+}
+
+impl Strings {
+    /// Copy the arguments and environment variables into one allocation
+    fn new(args: &[String], env_vars: &[EnvVar]) -> Result<Strings> {
+        let mut data: Vec<u8> = Vec::new();
+
+        let mut arg_offsets = Vec::with_capacity(args.len());
+        for arg in args.iter() {
+            arg_offsets.push(data.len());
+            data.extend_from_slice(arg.trim_right_matches('\0').as_bytes());
+            data.push(0);
+        }
+
+        let mut env_offsets = Vec::with_capacity(env_vars.len());
+        for &EnvVar(ref name, ref value) in env_vars.iter() {
+            env_offsets.push(data.len());
+            data.extend_from_slice(name.as_bytes());
+            data.push(b'=');
+            data.extend_from_slice(value.as_bytes());
+            data.push(0);
+        }
+
+        let physical_address = unsafe { memory::alloc_aligned(data.len(), 4096) };
+        if physical_address == 0 {
+            return Err(Error::new(ENOMEM));
+        }
+
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr(), physical_address as *mut u8, data.len()) };
+
+        Ok(Strings {
+            physical_address: physical_address,
+            size: data.len(),
+            args: arg_offsets,
+            env_vars: env_offsets,
+        })
+    }
+}
 
-pub fn execute_thread(context_ptr: *mut Context, entry: usize, mut args: Vec<String>) -> ! {
+pub fn execute_thread(context_ptr: *mut Context, entry: usize, args: Vec<usize>, env_vars: Vec<usize>, auxv: Vec<(usize, usize)>) -> ! {
     Context::spawn("kexec".into(),
                    box move || {
         let context = unsafe { &mut *context_ptr };
 
         let mut context_args: Vec<usize> = Vec::new();
-        context_args.push(0); // ENVP
+        context_args.push(0); // AUXV NULL
+        context_args.push(AT_NULL);
+        for &(key, value) in auxv.iter().rev() {
+            context_args.push(value);
+            context_args.push(key);
+        }
+        context_args.push(0); // ENVP NULL
+        for &env_var in env_vars.iter().rev() {
+            context_args.push(env_var);
+        }
         context_args.push(0); // ARGV NULL
-        let mut argc = 0;
-        while let Some(mut arg) = args.pop() {
-            if ! arg.ends_with('\0') {
-                arg.push('\0');
-            }
-
-            let mut physical_address = arg.as_ptr() as usize;
-            if physical_address >= 0x80000000 {
-                physical_address -= 0x80000000;
-            }
-
-            let virtual_size = arg.len();
-            let virtual_address = unsafe { (*context.image.get()).add_mem(physical_address, virtual_size, false, true) }.unwrap();
-
-            mem::forget(arg);
-
-            context_args.push(virtual_address as usize);
-            argc += 1;
+        for &arg in args.iter().rev() {
+            context_args.push(arg);
         }
-        context_args.push(argc);
+        context_args.push(args.len());
 
         context.iopl = 0;
 
@@ -64,6 +106,9 @@ pub fn execute_thread(context_ptr: *mut Context, entry: usize, mut args: Vec<Str
             virtual_size: CONTEXT_STACK_SIZE,
             writeable: true,
             allocated: true,
+            cache: 0,
+            shared: false,
+            max_writeable: true,
         });
 
         unsafe {
@@ -73,7 +118,10 @@ pub fn execute_thread(context_ptr: *mut Context, entry: usize, mut args: Vec<Str
                     virtual_address: CONTEXT_TLS_ADDR,
                     virtual_size: tls_master.virtual_size + 4096,
                     writeable: true,
-                    allocated: true
+                    allocated: true,
+                    cache: 0,
+                    shared: false,
+                    max_writeable: true
                 };
 
                 tls_master.map();
@@ -116,6 +164,7 @@ pub fn execute_thread(context_ptr: *mut Context, entry: usize, mut args: Vec<Str
             context.push(entry);
             context.push(0);
             context.push(context_userspace as usize);
+            context.push(context_unlock as usize);
         }
     });
 
@@ -124,61 +173,145 @@ pub fn execute_thread(context_ptr: *mut Context, entry: usize, mut args: Vec<Str
     }
 }
 
-/// Execute an executable
-pub fn execute(mut args: Vec<String>) -> Result<usize> {
-    let contexts = unsafe { &mut *::env().contexts.get() };
-    let current = try!(contexts.current_mut());
-
+/// Read a whole file into memory
+fn read_file(current: &mut Context, path: &str) -> Result<Vec<u8>> {
     let mut vec: Vec<u8> = Vec::new();
 
-    let path = current.canonicalize(args.get(0).map_or("", |p| &p));
-    {
-        let mut resource = try!(::env().open(&path, O_RDONLY));
+    let mut resource = try!(::env().open(path, O_RDONLY));
 
-        // Hack to allow file scheme to find memory in context's memory space
-        unsafe {
-            let mmap = &mut *current.mmap.get();
+    // Hack to allow file scheme to find memory in context's memory space
+    unsafe {
+        let mmap = &mut *current.mmap.get();
 
-            let virtual_size = 1024*1024;
+        let virtual_size = 1024*1024;
 
-            let physical_address = memory::alloc_aligned(virtual_size, 4096);
-            if physical_address == 0 {
-                return Err(Error::new(ENOMEM));
-            }
+        let physical_address = memory::alloc_aligned(virtual_size, 4096);
+        if physical_address == 0 {
+            return Err(Error::new(ENOMEM));
+        }
 
-            let virtual_address = try!(mmap.add_mem(physical_address, virtual_size, true, true));
+        let virtual_address = try!(mmap.add_mem(physical_address, virtual_size, true, true));
 
-            for i in 0..mmap.memory.len() {
-                if mmap.memory[i].virtual_address == virtual_address {
-                    mmap.memory[i].map();
-                    break;
-                }
+        for i in 0..mmap.memory.len() {
+            if mmap.memory[i].virtual_address == virtual_address {
+                mmap.memory[i].map();
+                break;
             }
+        }
 
-            let mut read_loop = || -> Result<usize> {
-                loop {
-                    let mut bytes = slice::from_raw_parts_mut(virtual_address as *mut u8, virtual_size);
-                    match resource.read(&mut bytes) {
-                        Ok(0) => return Ok(0),
-                        Ok(count) => vec.extend_from_slice(bytes.get_slice(.. count)),
-                        Err(err) => return Err(err)
-                    }
+        let mut read_loop = || -> Result<usize> {
+            loop {
+                let mut bytes = slice::from_raw_parts_mut(virtual_address as *mut u8, virtual_size);
+                match resource.read(&mut bytes) {
+                    Ok(0) => return Ok(0),
+                    Ok(count) => vec.extend_from_slice(bytes.get_slice(.. count)),
+                    Err(err) => return Err(err)
                 }
-            };
+            }
+        };
 
-            let res = read_loop();
+        let res = read_loop();
 
-            for i in 0..mmap.memory.len() {
-                if mmap.memory[i].virtual_address == virtual_address {
-                    mmap.memory.remove(i).unmap();
-                    break;
-                }
+        for i in 0..mmap.memory.len() {
+            if mmap.memory[i].virtual_address == virtual_address {
+                mmap.memory.remove(i).unmap();
+                break;
             }
+        }
+
+        try!(res);
+    }
 
-            try!(res);
+    Ok(vec)
+}
+
+/// The amount of memory needed to load the segments of an ELF image, with room for alignment
+unsafe fn image_size(elf: &Elf) -> usize {
+    elf.load_segments().iter().fold(0, |size, segment| size + segment.p_memsz as usize + 4096)
+}
+
+/// Load the segments of an ELF image at `base`, and apply its relative relocations
+///
+/// The segments are copied into their memory without mapping it, so that nothing of the current
+/// image has to be given up before loading succeeds. If the image has a program interpreter,
+/// `interpreted`, relocations of other types are left to it. Returns the memory of the image, and
+/// of its TLS master if it has one.
+unsafe fn load(elf: &Elf, base: usize, interpreted: bool) -> Result<(Vec<ContextMemory>, Option<ContextMemory>)> {
+    let mut loaded = Vec::new();
+
+    for segment in elf.load_segments().iter() {
+        let virtual_address = base + segment.p_vaddr as usize;
+        let virtual_size = segment.p_memsz as usize;
+
+        let file_start = segment.p_offset as usize;
+        let file_size = segment.p_filesz as usize;
+        if file_size > virtual_size || file_start.saturating_add(file_size) > elf.data.len() {
+            return Err(Error::new(ENOEXEC));
+        }
+
+        let offset = virtual_address % 4096;
+
+        let physical_address = memory::alloc_aligned(virtual_size + offset, 4096);
+        if physical_address == 0 {
+            return Err(Error::new(ENOMEM));
+        }
+
+        let memory = ContextMemory {
+            physical_address: physical_address,
+            virtual_address: virtual_address - offset,
+            virtual_size: virtual_size + offset,
+            writeable: segment.p_flags & 2 == 2,
+            allocated: true,
+            cache: 0,
+            shared: false,
+            max_writeable: true,
+        };
+
+        // Copy progbits, the rest was zeroed by the allocation
+        ::memcpy((physical_address + offset) as *mut u8,
+                 elf.data.as_ptr().offset(file_start as isize),
+                 file_size);
+
+        loaded.push((memory, segment.p_type == 7));
+    }
+
+    if base > 0 {
+        if let Some(dynamic) = elf.dynamic() {
+            try!(elf::relocate(dynamic, base, interpreted, |address, size| {
+                let start = base + address;
+                let end = match start.checked_add(size) {
+                    Some(end) => end,
+                    None => return None
+                };
+                loaded.iter().find(|&&(ref memory, tls)| {
+                    ! tls && start >= memory.virtual_address && end <= memory.virtual_address + memory.virtual_size
+                }).map(|&(ref memory, _)| memory.physical_address + start - memory.virtual_address)
+            }));
+        }
+    }
+
+    let mut image = Vec::new();
+    let mut tls_master = None;
+    for (memory, tls) in loaded {
+        if tls {
+            tls_master = Some(memory);
+        } else {
+            image.push(memory);
         }
     }
 
+    Ok((image, tls_master))
+}
+
+/// Execute an executable, with the environment variables `env_vars`, or those of the current
+/// context if None
+pub fn execute(mut args: Vec<String>, env_vars: Option<Vec<EnvVar>>) -> Result<usize> {
+    let contexts = unsafe { &mut *::env().contexts.get() };
+    let current = try!(contexts.current_mut());
+
+    let path = current.canonicalize(args.get(0).map_or("", |p| &p));
+    let vec = try!(read_file(current, &path));
+
     if vec.starts_with(b"#!") {
         if let Some(mut arg) = args.get_mut(0) {
             *arg = path.to_string();
@@ -195,7 +328,7 @@ pub fn execute(mut args: Vec<String>) -> Result<usize> {
         if i == 0 {
             args.insert(i, "/bin/sh".to_owned());
         }
-        execute(args)
+        execute(args, env_vars)
     } else {
         match Elf::from(&vec) {
             Ok(executable) => {
@@ -203,65 +336,142 @@ pub fn execute(mut args: Vec<String>) -> Result<usize> {
                 let segments = unsafe { executable.load_segments() };
 
                 if entry > 0 && ! segments.is_empty() {
+                    // Position independent executables are linked at 0
+                    let base = if unsafe { executable.is_dynamic() } {
+                        CONTEXT_IMAGE_ADDR
+                    } else {
+                        0
+                    };
+
+                    let interp_data = if let Some(interp_path) = unsafe { executable.interpreter() } {
+                        let interp_path = current.canonicalize(interp_path);
+                        Some(try!(read_file(current, &interp_path)))
+                    } else {
+                        None
+                    };
+
+                    let interp = if let Some(ref interp_data) = interp_data {
+                        match Elf::from(interp_data) {
+                            Ok(interp) => Some(interp),
+                            Err(msg) => {
+                                debugln!("execute: failed to load interpreter of '{:?}': {}", path, msg);
+                                return Err(Error::new(ENOEXEC));
+                            }
+                        }
+                    } else {
+                        None
+                    };
+
+                    let interp_base = if let Some(ref interp) = interp {
+                        if unsafe { interp.is_dynamic() } {
+                            CONTEXT_INTERP_ADDR
+                        } else {
+                            0
+                        }
+                    } else {
+                        0
+                    };
+
+                    let strings = try!(Strings::new(&args, match env_vars {
+                        Some(ref env_vars) => env_vars,
+                        None => unsafe { &*current.env_vars.get() }
+                    }));
+
+                    let size = unsafe { image_size(&executable) + interp.as_ref().map_or(0, |interp| image_size(interp)) } + strings.size;
+                    if size > current.rlimits[RLIMIT_AS].rlim_cur {
+                        unsafe { memory::unalloc_pages(strings.physical_address, strings.size) };
+                        return Err(Error::new(ENOMEM));
+                    }
+
+                    // Everything that can fail is done before the old image is given up
+                    let mut image = ContextZone::new(CONTEXT_IMAGE_ADDR, CONTEXT_IMAGE_SIZE);
+                    let mut tls_master = None;
+                    let loaded = unsafe { load(&executable, base, interp.is_some()) }.and_then(|(memory, tls)| {
+                        image.memory.extend(memory);
+                        tls_master = tls;
+                        if let Some(ref interp) = interp {
+                            let (memory, _) = try!(unsafe { load(interp, interp_base, false) });
+                            image.memory.extend(memory);
+                        }
+                        image.add_mem(strings.physical_address, strings.size, true, true)
+                    });
+                    let strings_address = match loaded {
+                        Ok(address) => address,
+                        Err(err) => {
+                            unsafe { memory::unalloc_pages(strings.physical_address, strings.size) };
+                            return Err(err);
+                        }
+                    };
+
                     unsafe { current.unmap() };
 
                     current.name = path.to_string().into();
                     current.cwd = Arc::new(UnsafeCell::new(unsafe { (*current.cwd.get()).clone() }));
 
-                    current.image = Arc::new(UnsafeCell::new(ContextZone::new(CONTEXT_IMAGE_ADDR, CONTEXT_IMAGE_SIZE)));
+                    current.image = Arc::new(UnsafeCell::new(image));
+                    if tls_master.is_some() {
+                        unsafe { *current.tls_master.get() = tls_master };
+                    }
                     current.heap = Arc::new(UnsafeCell::new(ContextZone::new(CONTEXT_HEAP_ADDR, CONTEXT_HEAP_SIZE)));
                     current.mmap = Arc::new(UnsafeCell::new(ContextZone::new(CONTEXT_MMAP_ADDR, CONTEXT_MMAP_SIZE)));
-                    current.env_vars = Arc::new(UnsafeCell::new(unsafe { (*current.env_vars.get()).clone() }));
-
-                    {
-                        let image = unsafe { &mut *current.image.get() };
-
-                        for segment in segments.iter() {
-                            let virtual_address = segment.p_vaddr as usize;
-                            let virtual_size = segment.p_memsz as usize;
-
-                            let offset = virtual_address % 4096;
-
-                            let physical_address = unsafe { memory::alloc_aligned(virtual_size + offset, 4096) };
-
-                            if physical_address == 0 {
-                                panic!("OOM in exec");
-                            }
-
-                            let mut memory = ContextMemory {
-                                physical_address: physical_address,
-                                virtual_address: virtual_address - offset,
-                                virtual_size: virtual_size + offset,
-                                writeable: true,
-                                allocated: true,
-                            };
-
-                            unsafe { memory.map() };
-
-                            // Copy progbits
-                            unsafe {
-                                ::memcpy(virtual_address as *mut u8,
-                                        executable.data.as_ptr().offset(segment.p_offset as isize),
-                                        segment.p_filesz as usize)
-                            };
-
-                            unsafe { memory.unmap() };
+                    let env_vars = match env_vars {
+                        Some(env_vars) => env_vars,
+                        None => unsafe { (*current.env_vars.get()).clone() }
+                    };
+                    current.env_vars = Arc::new(UnsafeCell::new(env_vars));
+
+                    // Handlers are lost with the old image, ignored signals stay ignored
+                    let mut sig_actions = unsafe { *current.sig_actions.get() };
+                    for action in sig_actions.iter_mut() {
+                        if action.sa_handler != SIG_IGN {
+                            *action = SigAction::default();
+                        }
+                    }
+                    current.sig_actions = Arc::new(UnsafeCell::new(sig_actions));
+
+                    // Descriptors marked close-on-exec are closed, the rest are kept
+                    if Arc::strong_count(&current.files) == 1 {
+                        unsafe { (*current.files.get()).retain(|file| ! file.cloexec) };
+                    } else {
+                        let files: Vec<ContextFile> = unsafe { (*current.files.get()).iter() }
+                            .filter(|file| ! file.cloexec)
+                            .filter_map(|file| {
+                                file.resource.dup().ok().map(|resource| ContextFile {
+                                    fd: file.fd,
+                                    flags: file.flags.clone(),
+                                    cloexec: false,
+                                    resource: resource,
+                                })
+                            })
+                            .collect();
+                        current.files = Arc::new(UnsafeCell::new(files));
+                    }
 
-                            memory.writeable = segment.p_flags & 2 == 2;
+                    let args: Vec<usize> = strings.args.iter().map(|offset| strings_address + offset).collect();
+                    let env_vars: Vec<usize> = strings.env_vars.iter().map(|offset| strings_address + offset).collect();
 
-                            if segment.p_type == 1 {
-                                image.memory.push(memory);
-                            } else if segment.p_type == 7 {
-                                unsafe { *current.tls_master.get() = Some(memory) };
-                            }
-                        }
+                    let (phnum, phent) = unsafe { executable.program_headers() };
+                    let mut auxv = Vec::new();
+                    if let Some(phdr) = unsafe { executable.phdr() } {
+                        auxv.push((AT_PHDR, base + phdr));
                     }
+                    auxv.push((AT_PHENT, phent));
+                    auxv.push((AT_PHNUM, phnum));
+                    auxv.push((AT_PAGESZ, 4096));
+                    auxv.push((AT_BASE, interp_base));
+                    auxv.push((AT_ENTRY, base + entry));
+
+                    let start = if let Some(ref interp) = interp {
+                        interp_base + unsafe { interp.entry() }
+                    } else {
+                        base + entry
+                    };
 
                     //debugln!("{}: {}: execute {}", context.pid, context.name, url.string);
 
                     unsafe { current.map() };
 
-                    execute_thread(current.deref_mut(), entry, args);
+                    execute_thread(current.deref_mut(), start, args, env_vars, auxv);
                 } else {
                     Err(Error::new(ENOEXEC))
                 }
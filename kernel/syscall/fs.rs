@@ -1,16 +1,30 @@
 //! System calls related to files and resource management.
 
-use arch::context::ContextFile;
+use arch::context::{context_switch, Context, ContextFile};
 
+use common::time::Duration;
+
+use core::ops::DerefMut;
 use core::str;
 
 use fs::ResourceSeek;
 
 use schemes::pipe::{PipeRead, PipeWrite};
 
-use syscall::{Stat, SEEK_CUR, SEEK_END, SEEK_SET};
+use syscall::{PollFd, Stat, FD_CLOEXEC, F_GETFD, F_GETFL, F_SETFD, F_SETFL, O_APPEND, O_CLOEXEC,
+              O_CREAT, O_EXCL, O_NONBLOCK, O_RDONLY, O_TRUNC, O_WRONLY, POLLERR, POLLNVAL,
+              RLIMIT_NOFILE, SEEK_CUR, SEEK_END, SEEK_SET};
+
+use system::error::{Error, Result, EBADF, EFAULT, EINTR, EINVAL};
+
+use super::signal;
+
+/// The file status flags that can be changed with `F_SETFL`
+const SETFL_MASK: usize = O_APPEND | O_NONBLOCK;
 
-use system::error::{Error, Result, EBADF, EFAULT, EINVAL};
+/// How often `poll` checks resources that cannot notify it again while waiting, such as files of
+/// userspace schemes, in nanoseconds
+const POLL_INTERVAL: i32 = 10000000;
 
 /** <!-- @MANSTART{sys_chdir} -->
 NAME
@@ -117,6 +131,8 @@ SYNOPSIS
 DESCRIPTION
     sys_dup creates a copy of fd, using the lowest unused descriptor for the new descriptor
 
+    The new descriptor does not have FD_CLOEXEC set
+
 RETURN VALUE
     On success, Ok(new_fd) is returned, where new_fd is the new file descriptor. On error, Err(err)
     is returned where err is one of the following errors
@@ -125,25 +141,144 @@ ERRORS
     EBADF
         fd is not a valid open file decriptor
 
+    EMFILE
+        No descriptor below RLIMIT_NOFILE is available
+
     ESRCH
         Currently not running in a process context (rare, would only happen during kernel init)
 <!-- @MANEND --> */
 pub fn dup(fd: usize) -> Result<usize> {
     let contexts = unsafe { & *::env().contexts.get() };
     let current = try!(contexts.current());
+    let flags = try!(current.get_file_flags_shared(fd));
     let resource = try!(current.get_file(fd));
     let new_resource = try!(resource.dup());
-    let new_fd = current.next_fd();
+    let new_fd = try!(current.next_fd());
 
     unsafe {
         (*current.files.get()).push(ContextFile {
             fd: new_fd,
+            flags: flags,
+            cloexec: false,
+            resource: new_resource,
+        });
+    }
+    Ok(new_fd)
+}
+
+/** <!-- @MANSTART{sys_dup2} -->
+NAME
+    sys_dup2 - duplicate a file descriptor onto a given descriptor
+
+SYNOPSIS
+    sys_dup2(fd: usize, new_fd: usize) -> Result<usize>;
+
+DESCRIPTION
+    sys_dup2 creates a copy of fd using new_fd for the new descriptor. If new_fd was open, it is
+    closed first. If fd and new_fd are the same, nothing is done
+
+    The new descriptor does not have FD_CLOEXEC set
+
+RETURN VALUE
+    On success, Ok(new_fd) is returned. On error, Err(err) is returned where err is one of the
+    following errors
+
+ERRORS
+    EBADF
+        fd is not a valid open file decriptor, or new_fd is not below RLIMIT_NOFILE
+
+    ESRCH
+        Currently not running in a process context (rare, would only happen during kernel init)
+<!-- @MANEND --> */
+pub fn dup2(fd: usize, new_fd: usize) -> Result<usize> {
+    let contexts = unsafe { & *::env().contexts.get() };
+    let current = try!(contexts.current());
+    let flags = try!(current.get_file_flags_shared(fd));
+    if fd == new_fd {
+        return Ok(new_fd);
+    }
+
+    if new_fd >= current.rlimits[RLIMIT_NOFILE].rlim_cur {
+        return Err(Error::new(EBADF));
+    }
+
+    let resource = try!(current.get_file(fd));
+    let new_resource = try!(resource.dup());
+
+    unsafe {
+        let files = &mut *current.files.get();
+        files.retain(|file| file.fd != new_fd);
+        files.push(ContextFile {
+            fd: new_fd,
+            flags: flags,
+            cloexec: false,
             resource: new_resource,
         });
     }
     Ok(new_fd)
 }
 
+/** <!-- @MANSTART{sys_fcntl} -->
+NAME
+    sys_fcntl - manipulate a file descriptor
+
+SYNOPSIS
+    sys_fcntl(fd: usize, cmd: usize, arg: usize) -> Result<usize>;
+
+DESCRIPTION
+    sys_fcntl performs the operation cmd on fd
+
+    F_GETFD returns the file descriptor flags of fd, which is FD_CLOEXEC or 0
+
+    F_SETFD sets the file descriptor flags of fd to arg
+
+    F_GETFL returns the access mode and file status flags of fd
+
+    F_SETFL sets the file status flags of fd to arg. Only O_APPEND and O_NONBLOCK can be changed,
+    other bits in arg are ignored
+
+    The file status flags are shared by fd and every descriptor duplicated from it, by sys_dup,
+    sys_dup2 or sys_clone, so F_SETFL on one of them changes them for all
+
+RETURN VALUE
+    On success, Ok(flags) is returned for F_GETFD and F_GETFL, and Ok(0) for F_SETFD and F_SETFL. On error, Err(err) is
+    returned where err is one of the following errors
+
+ERRORS
+    EBADF
+        fd is not a valid open file decriptor
+
+    EINVAL
+        cmd is not recognized
+
+    ESRCH
+        Currently not running in a process context (rare, would only happen during kernel init)
+<!-- @MANEND --> */
+pub fn fcntl(fd: usize, cmd: usize, arg: usize) -> Result<usize> {
+    let contexts = unsafe { & *::env().contexts.get() };
+    let current = try!(contexts.current());
+    for file in unsafe { (*current.files.get()).iter_mut() } {
+        if file.fd == fd {
+            return match cmd {
+                F_GETFD => Ok(if file.cloexec { FD_CLOEXEC } else { 0 }),
+                F_SETFD => {
+                    file.cloexec = arg & FD_CLOEXEC == FD_CLOEXEC;
+                    Ok(0)
+                },
+                F_GETFL => Ok(file.flags.get()),
+                F_SETFL => {
+                    // The flags are shared with descriptors duplicated from fd and their resources
+                    file.flags.set((file.flags.get() & !SETFL_MASK) | (arg & SETFL_MASK));
+                    Ok(0)
+                },
+                _ => Err(Error::new(EINVAL))
+            };
+        }
+    }
+
+    Err(Error::new(EBADF))
+}
+
 pub fn fpath(fd: usize, buf: *mut u8, count: usize) -> Result<usize> {
     let contexts = unsafe { & *::env().contexts.get() };
     let current = contexts.current()?;
@@ -339,6 +474,8 @@ SYNOPSIS
 DESCRIPTION
     sys_open returns a file descriptor referencing path, creating path if O_CREAT is provided
 
+    If O_CLOEXEC is provided, the descriptor is closed when a new program is executed
+
     TODO: Open is very complicated, and has a lot of flags
 
 RETURN VALUE
@@ -360,6 +497,9 @@ ERRORS
     EISDIR
         path refers to a directory and O_DIRECTORY was not provided
 
+    EMFILE
+        No descriptor below RLIMIT_NOFILE is available
+
     ENOENT
         A directory component in path does not exist
 
@@ -387,35 +527,45 @@ pub fn open(path_ptr: *const u8, path_len: usize, flags: usize) -> Result<usize>
     let path_safe = current.get_slice(path_ptr, path_len)?;
     let path = current.canonicalize(unsafe { str::from_utf8_unchecked(path_safe) });
     let resource = try!(::env().open(&path, flags));
-    let fd = current.next_fd();
-    unsafe {
-        (*current.files.get()).push(ContextFile {
-            fd: fd,
-            resource: resource,
-        });
-    }
+    let cloexec = flags & O_CLOEXEC == O_CLOEXEC;
+    let flags = flags & !(O_CREAT | O_TRUNC | O_EXCL | O_CLOEXEC);
+    let fd = try!(current.next_fd());
+    let file = try!(ContextFile::new(fd, flags, cloexec, resource));
+    unsafe { (*current.files.get()).push(file) };
     Ok(fd)
 }
 
-pub fn pipe2(fds: *mut usize, _flags: usize) -> Result<usize> {
+pub fn pipe2(fds: *mut usize, flags: usize) -> Result<usize> {
     let contexts = unsafe { & *::env().contexts.get() };
     let current = try!(contexts.current());
     if fds as usize > 0 {
+        let cloexec = flags & O_CLOEXEC == O_CLOEXEC;
+        let flags = flags & SETFL_MASK;
+
         let read = box PipeRead::new();
         let write = box PipeWrite::new(&read);
 
         unsafe {
-            *fds.offset(0) = current.next_fd();
-            (*current.files.get()).push(ContextFile {
-                fd: *fds.offset(0),
-                resource: read,
-            });
-
-            *fds.offset(1) = current.next_fd();
-            (*current.files.get()).push(ContextFile {
-                fd: *fds.offset(1),
-                resource: write,
-            });
+            let read_fd = try!(current.next_fd());
+            (*current.files.get()).push(try!(ContextFile::new(read_fd, O_RDONLY | flags, cloexec, read)));
+
+            let write_fd = match current.next_fd() {
+                Ok(fd) => fd,
+                Err(err) => {
+                    (*current.files.get()).retain(|file| file.fd != read_fd);
+                    return Err(err);
+                }
+            };
+            match ContextFile::new(write_fd, O_WRONLY | flags, cloexec, write) {
+                Ok(file) => (*current.files.get()).push(file),
+                Err(err) => {
+                    (*current.files.get()).retain(|file| file.fd != read_fd);
+                    return Err(err);
+                }
+            }
+
+            *fds.offset(0) = read_fd;
+            *fds.offset(1) = write_fd;
         }
 
         Ok(0)
@@ -424,6 +574,120 @@ pub fn pipe2(fds: *mut usize, _flags: usize) -> Result<usize> {
     }
 }
 
+/** <!-- @MANSTART{sys_poll} -->
+NAME
+    sys_poll - wait for one of a set of file descriptors to become ready
+
+SYNOPSIS
+    sys_poll(fds: &mut [PollFd], timeout: isize) -> Result<usize>;
+
+DESCRIPTION
+    sys_poll waits until one of the file descriptors in fds is ready for the events requested in
+    its events field, such as POLLIN and POLLOUT, and sets revents to the events that are ready
+
+    POLLERR and POLLHUP are reported even if they were not requested, and POLLNVAL is reported if
+    fd is not a valid open file descriptor
+
+    timeout is the maximum time to wait in milliseconds. A negative timeout waits forever, and a
+    timeout of 0 returns immediately
+
+RETURN VALUE
+    On success, Ok(count) is returned, where count is the number of file descriptors with a
+    nonzero revents, or 0 if the timeout expired. On error, Err(err) is returned where err is one
+    of the following errors
+
+ERRORS
+    EFAULT
+        fds is outside of the accessible address space of the process
+
+    EINTR
+        A signal was received before any file descriptor became ready
+
+    ESRCH
+        Currently not running in a process context (rare, would only happen during kernel init)
+<!-- @MANEND --> */
+pub fn poll(fds_ptr: *mut PollFd, nfds: usize, timeout: isize) -> Result<usize> {
+    let end = if timeout >= 0 {
+        Some(Duration::monotonic() + Duration::new(timeout as i64 / 1000, (timeout % 1000) as i32 * 1000000))
+    } else {
+        None
+    };
+
+    loop {
+        {
+            let contexts = unsafe { & *::env().contexts.get() };
+            let current = try!(contexts.current());
+            let fds = try!(current.get_slice_mut(fds_ptr, nfds));
+
+            let mut count = 0;
+            for fd in fds.iter_mut() {
+                fd.revents = match current.get_file(fd.fd) {
+                    Ok(resource) => resource.poll(fd.events).unwrap_or(POLLERR),
+                    Err(_) => POLLNVAL
+                };
+
+                if fd.revents != 0 {
+                    count += 1;
+                }
+            }
+
+            if count > 0 {
+                return Ok(count);
+            }
+
+            if signal::interrupting(current) != 0 {
+                return Err(Error::new(EINTR));
+            }
+        }
+
+        if let Some(end) = end {
+            if Duration::monotonic() >= end {
+                return Ok(0);
+            }
+        }
+
+        // Wait for any of the resources to notify a change, or for the timeout
+        {
+            let contexts = unsafe { &mut *::env().contexts.get() };
+            let mut current = try!(contexts.current_mut());
+            let context_ptr = current.deref_mut() as *mut Context;
+
+            let mut watched = true;
+            for fd in try!(current.get_slice(fds_ptr, nfds)).iter() {
+                if let Ok(resource) = current.get_file(fd.fd) {
+                    watched &= resource.poll_watch(context_ptr);
+                }
+            }
+
+            let wake = if watched {
+                end
+            } else {
+                let interval = Duration::monotonic() + Duration::new(0, POLL_INTERVAL);
+                Some(end.map_or(interval, |end| if end < interval { end } else { interval }))
+            };
+
+            current.interruptible = true;
+            if let Some(wake) = wake {
+                current.sleep(wake);
+            }
+            current.block("poll");
+        }
+
+        unsafe { context_switch(); }
+
+        {
+            let contexts = unsafe { &mut *::env().contexts.get() };
+            let mut current = try!(contexts.current_mut());
+            current.interruptible = false;
+            current.wake = None;
+
+            // The descriptors may have been closed or replaced meanwhile, so the watched
+            // conditions are not looked up through them again
+            current.unwatch_all();
+        }
+    }
+}
+
 /** <!-- @MANSTART{sys_read} -->
 NAME
     sys_read - read from a file descriptor
@@ -440,6 +704,9 @@ RETURN VALUE
     Err(err) is returned where err is one of the following errors
 
 ERRORS
+    EAGAIN
+        fd has O_NONBLOCK set and the read would block
+
     EBADF
         fd is not a valid open file decriptor
 
@@ -467,6 +734,26 @@ pub fn read(fd: usize, buf: *mut u8, count: usize) -> Result<usize> {
     }
 }
 
+pub fn link(old_ptr: *const u8, old_len: usize, new_ptr: *const u8, new_len: usize) -> Result<usize> {
+    let contexts = unsafe { & *::env().contexts.get() };
+    let current = try!(contexts.current());
+    let old_safe = current.get_slice(old_ptr, old_len)?;
+    let new_safe = current.get_slice(new_ptr, new_len)?;
+    let old_string = current.canonicalize(unsafe { str::from_utf8_unchecked(old_safe) });
+    let new_string = current.canonicalize(unsafe { str::from_utf8_unchecked(new_safe) });
+    ::env().link(&old_string, &new_string).and(Ok(0))
+}
+
+pub fn rename(old_ptr: *const u8, old_len: usize, new_ptr: *const u8, new_len: usize) -> Result<usize> {
+    let contexts = unsafe { & *::env().contexts.get() };
+    let current = try!(contexts.current());
+    let old_safe = current.get_slice(old_ptr, old_len)?;
+    let new_safe = current.get_slice(new_ptr, new_len)?;
+    let old_string = current.canonicalize(unsafe { str::from_utf8_unchecked(old_safe) });
+    let new_string = current.canonicalize(unsafe { str::from_utf8_unchecked(new_safe) });
+    ::env().rename(&old_string, &new_string).and(Ok(0))
+}
+
 pub fn rmdir(path_ptr: *const u8, path_len: usize) -> Result<usize> {
     let contexts = unsafe { & *::env().contexts.get() };
     let current = try!(contexts.current());
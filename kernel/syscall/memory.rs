@@ -1,9 +1,12 @@
 //! System calls for basic memory management.
 
-use arch::context::ContextMemory;
+use arch::context::{ContextMemory, ContextZone};
 use arch::memory;
+use arch::smp;
 
-use system::error::Result;
+use system::error::{Error, Result, EACCES, EINVAL, ENOMEM};
+use system::syscall::{MAP_ANONYMOUS, MAP_PRIVATE, O_ACCMODE, O_RDONLY, O_RDWR, O_WRONLY, PROT_NONE,
+                      PROT_WRITE};
 
 //TODO: Refactor file to propogate results
 
@@ -26,7 +29,11 @@ pub fn brk(addr: usize) -> Result<usize> {
             //Return current break
         } else if addr > ret {
             let size = addr - ret;
-            let physical_address = unsafe { memory::alloc_aligned(size, 4096) };
+            let physical_address = if current.check_memory(size).is_ok() {
+                unsafe { memory::alloc_aligned(size, 4096) }
+            } else {
+                0
+            };
             if physical_address > 0 {
                 // debugln!("BRK: Alloc {}", size);
                 let mut mem = ContextMemory {
@@ -34,30 +41,31 @@ pub fn brk(addr: usize) -> Result<usize> {
                     virtual_address: ret,
                     virtual_size: size,
                     writeable: true,
-                    allocated: true
+                    allocated: true,
+                    cache: 0,
+                    shared: false,
+                    max_writeable: true
                 };
                 ret = mem.virtual_address + mem.virtual_size;
 
                 unsafe {
                     mem.map();
                     (*current.heap.get()).memory.push(mem);
+                    smp::shootdown(ret - size, size);
                 }
             } else {
                 debugln!("BRK: Alloc failed {}", size);
             }
         } else if addr < ret {
             //TODO: Realloc
-            let mut clean = false;
-            for mut mem in unsafe { (*current.heap.get()).memory.iter_mut() } {
+            let heap = unsafe { &mut *current.heap.get() };
+            for mut mem in heap.memory.iter_mut() {
                 if addr <= mem.virtual_address {
                     unsafe { mem.unmap() };
-                    mem.virtual_size = 0;
-                    clean = true;
                 }
             }
-            if clean {
-                unsafe { (*current.heap.get()).clean_mem() };
-            }
+            heap.memory.retain(|mem| addr > mem.virtual_address);
+            unsafe { smp::shootdown(addr, ret - addr) };
         } else {
             //Already set to desired break
         }
@@ -67,3 +75,145 @@ pub fn brk(addr: usize) -> Result<usize> {
 
     Ok(ret)
 }
+
+/// Map memory into the mmap zone of the current context
+///
+/// Anonymous mappings are zeroed. Otherwise the memory is provided by the resource open at `fd`,
+/// which has to be open for reading, and for writing too if the mapping is shared and writeable.
+/// Private, writeable mappings of a resource are copied, so that writes are not visible to it,
+/// and private, read only ones cannot be made writeable. Memory that is borrowed from a resource
+/// is referenced, so it stays valid after the resource is closed or its scheme exits.
+pub fn mmap(fd: usize, offset: usize, size: usize, prot: usize, flags: usize) -> Result<usize> {
+    if size == 0 || offset % 4096 != 0 || prot == PROT_NONE {
+        return Err(Error::new(EINVAL));
+    }
+
+    let writeable = prot & PROT_WRITE == PROT_WRITE;
+    let private = flags & MAP_PRIVATE == MAP_PRIVATE;
+
+    let contexts = unsafe { &mut *::env().contexts.get() };
+    let mut current = try!(contexts.current_mut());
+    try!(current.check_memory(size));
+
+    let (mut physical_address, mut allocated, mut cache, mut shared, mut max_writeable) = if flags & MAP_ANONYMOUS == MAP_ANONYMOUS {
+        let physical_address = unsafe { memory::alloc_aligned(size, 4096) };
+        if physical_address == 0 {
+            return Err(Error::new(ENOMEM));
+        }
+        (physical_address, true, 0, false, true)
+    } else {
+        let access = try!(current.get_file_flags(fd)) & O_ACCMODE;
+        if access == O_WRONLY || (writeable && ! private && access == O_RDONLY) {
+            return Err(Error::new(EACCES));
+        }
+
+        let resource = try!(current.get_file_mut(fd));
+        let (physical_address, allocated) = try!(resource.mmap(offset, size, writeable));
+        let shared = allocated && resource.mmap_shared();
+        (physical_address, allocated, resource.mmap_cache(), shared, allocated || (! private && access == O_RDWR))
+    };
+
+    if ! allocated {
+        if private && writeable {
+            let copy = unsafe { memory::alloc_aligned(size, 4096) };
+            if copy == 0 {
+                return Err(Error::new(ENOMEM));
+            }
+            unsafe { memory::copy_pages(copy as *mut u8, physical_address as *const u8, size) };
+            physical_address = copy;
+            allocated = true;
+            cache = 0;
+            max_writeable = true;
+        } else if unsafe { memory::page_refs(physical_address) } > 0 {
+            if ! unsafe { memory::ref_pages(physical_address, size) } {
+                return Err(Error::new(ENOMEM));
+            }
+            allocated = true;
+            shared = true;
+        }
+    }
+
+    let mmap = unsafe { &mut *current.mmap.get() };
+    match mmap.add_mem(physical_address, size, writeable, allocated) {
+        Ok(virtual_address) => {
+            unsafe {
+                let mem = try!(mmap.get_mem_mut(virtual_address));
+                mem.cache = cache;
+                mem.shared = shared;
+                mem.max_writeable = max_writeable;
+                mem.map();
+                smp::shootdown(virtual_address, size);
+            }
+            Ok(virtual_address)
+        },
+        Err(err) => {
+            if shared {
+                unsafe { memory::unref_pages(physical_address, size) };
+            } else if allocated {
+                unsafe { memory::unalloc(physical_address) };
+            }
+            Err(err)
+        }
+    }
+}
+
+/// Find the mapping starting at `addr`, if it covers exactly `size` bytes
+fn find_mapping(mmap: &ContextZone, addr: usize, size: usize) -> Result<usize> {
+    for (i, mem) in mmap.memory.iter().enumerate() {
+        if mem.virtual_address == addr {
+            if (mem.virtual_size + 4095) / 4096 == (size + 4095) / 4096 {
+                return Ok(i);
+            } else {
+                return Err(Error::new(EINVAL));
+            }
+        }
+    }
+
+    Err(Error::new(EINVAL))
+}
+
+/// Remove a mapping created by `mmap`
+///
+/// Only whole mappings can be removed.
+pub fn munmap(addr: usize, size: usize) -> Result<usize> {
+    let contexts = unsafe { & *::env().contexts.get() };
+    let current = try!(contexts.current());
+    let mmap = unsafe { &mut *current.mmap.get() };
+
+    let i = try!(find_mapping(mmap, addr, size));
+    let mut mem = mmap.memory.remove(i);
+    unsafe {
+        mem.unmap();
+        smp::shootdown(addr, size);
+    }
+
+    Ok(0)
+}
+
+/// Change the protection of a mapping created by `mmap`
+///
+/// Only whole mappings can be changed. Mappings of a resource that could not be mapped writeable
+/// cannot be made writeable.
+pub fn mprotect(addr: usize, size: usize, prot: usize) -> Result<usize> {
+    if prot == PROT_NONE {
+        return Err(Error::new(EINVAL));
+    }
+
+    let contexts = unsafe { & *::env().contexts.get() };
+    let current = try!(contexts.current());
+    let mmap = unsafe { &mut *current.mmap.get() };
+
+    let i = try!(find_mapping(mmap, addr, size));
+    let mem = &mut mmap.memory[i];
+    let writeable = prot & PROT_WRITE == PROT_WRITE;
+    if writeable && ! mem.max_writeable {
+        return Err(Error::new(EACCES));
+    }
+    mem.writeable = writeable;
+    unsafe {
+        mem.map();
+        smp::shootdown(addr, size);
+    }
+
+    Ok(0)
+}
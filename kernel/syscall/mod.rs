@@ -8,6 +8,7 @@ pub mod execute;
 pub mod fs;
 pub mod memory;
 pub mod process;
+pub mod signal;
 pub mod time;
 
 pub fn name(number: usize) -> &'static str {
@@ -22,23 +23,47 @@ pub fn name(number: usize) -> &'static str {
         SYS_CLOSE => "close",
         SYS_CLOCK_GETTIME => "clock_gettime",
         SYS_DUP => "dup",
+        SYS_DUP2 => "dup2",
         SYS_EXECVE => "execve",
         SYS_EXIT => "exit",
+        SYS_FCNTL => "fcntl",
         SYS_FPATH => "fpath",
         SYS_FSTAT => "fstat",
         SYS_FSYNC => "fsync",
         SYS_FTRUNCATE => "ftruncate",
         SYS_FUTEX => "futex",
+        SYS_GETEGID => "getegid",
+        SYS_GETEUID => "geteuid",
+        SYS_GETGID => "getgid",
+        SYS_GETPGID => "getpgid",
         SYS_GETPID => "getpid",
+        SYS_GETPRIORITY => "getpriority",
+        SYS_GETRLIMIT => "getrlimit",
+        SYS_GETUID => "getuid",
         SYS_IOPL => "iopl",
-        // TODO: link
+        SYS_KILL => "kill",
+        SYS_LINK => "link",
         SYS_LSEEK => "lseek",
         SYS_MKDIR => "mkdir",
+        SYS_MMAP => "mmap",
+        SYS_MPROTECT => "mprotect",
+        SYS_MUNMAP => "munmap",
         SYS_NANOSLEEP => "nanosleep",
         SYS_OPEN => "open",
         SYS_PIPE2 => "pipe2",
+        SYS_POLL => "poll",
         SYS_READ => "read",
+        SYS_RENAME => "rename",
         SYS_RMDIR => "rmdir",
+        SYS_SETGID => "setgid",
+        SYS_SETPGID => "setpgid",
+        SYS_SETPRIORITY => "setpriority",
+        SYS_SETRLIMIT => "setrlimit",
+        SYS_SETSID => "setsid",
+        SYS_SETUID => "setuid",
+        SYS_SIGACTION => "sigaction",
+        SYS_SIGPROCMASK => "sigprocmask",
+        SYS_SIGRETURN => "sigreturn",
         SYS_UNLINK => "unlink",
         SYS_WAITPID => "waitpid",
         SYS_WRITE => "write",
@@ -97,18 +122,42 @@ pub fn handle(regs: &mut Regs) {
         SYS_FSYNC => fs::fsync(regs.bx),
         SYS_FTRUNCATE => fs::ftruncate(regs.bx, regs.cx),
         SYS_DUP => fs::dup(regs.bx),
+        SYS_DUP2 => fs::dup2(regs.bx, regs.cx),
+        SYS_FCNTL => fs::fcntl(regs.bx, regs.cx, regs.dx),
         SYS_IOPL => process::iopl(regs),
         SYS_CLOCK_GETTIME => time::clock_gettime(regs.bx, regs.cx as *mut TimeSpec),
-        SYS_EXECVE => process::execve(regs.bx as *const u8, regs.cx as *const *const u8),
+        SYS_EXECVE => process::execve(regs.bx as *const u8, regs.cx as *const *const u8, regs.dx as *const *const u8),
         SYS_EXIT => process::exit(regs.bx),
         SYS_GETPID => process::getpid(),
-        // TODO: link
+        SYS_GETPRIORITY => process::getpriority(regs.bx, regs.cx),
+        SYS_GETRLIMIT => process::getrlimit(regs.bx, regs.cx as *mut RLimit),
+        SYS_GETUID => process::getuid(),
+        SYS_GETGID => process::getgid(),
+        SYS_GETEUID => process::geteuid(),
+        SYS_GETEGID => process::getegid(),
+        SYS_SETUID => process::setuid(regs.bx),
+        SYS_SETGID => process::setgid(regs.bx),
+        SYS_GETPGID => process::getpgid(regs.bx),
+        SYS_SETPGID => process::setpgid(regs.bx, regs.cx),
+        SYS_SETPRIORITY => process::setpriority(regs.bx, regs.cx, regs.dx as isize),
+        SYS_SETRLIMIT => process::setrlimit(regs.bx, regs.cx as *const RLimit),
+        SYS_SETSID => process::setsid(),
+        SYS_LINK => fs::link(regs.bx as *const u8, regs.cx, regs.dx as *const u8, regs.si),
+        SYS_RENAME => fs::rename(regs.bx as *const u8, regs.cx, regs.dx as *const u8, regs.si),
         SYS_PIPE2 => fs::pipe2(regs.bx as *mut usize, regs.cx),
+        SYS_POLL => fs::poll(regs.bx as *mut PollFd, regs.cx, regs.dx as isize),
         SYS_RMDIR => fs::rmdir(regs.bx as *const u8, regs.cx),
         SYS_UNLINK => fs::unlink(regs.bx as *const u8, regs.cx),
         SYS_WAITPID => process::waitpid(regs.bx as isize, regs.cx as *mut usize, regs.dx),
         SYS_BRK => memory::brk(regs.bx),
+        SYS_MMAP => memory::mmap(regs.bx, regs.cx, regs.dx, regs.si, regs.di),
+        SYS_MUNMAP => memory::munmap(regs.bx, regs.cx),
+        SYS_MPROTECT => memory::mprotect(regs.bx, regs.cx, regs.dx),
         SYS_CHDIR => fs::chdir(regs.bx as *const u8, regs.cx),
+        SYS_KILL => signal::kill(regs.bx as isize, regs.cx),
+        SYS_SIGACTION => signal::sigaction(regs.bx, regs.cx as *const SigAction, regs.dx as *mut SigAction),
+        SYS_SIGPROCMASK => signal::sigprocmask(regs.bx, regs.cx as *const u32, regs.dx as *mut u32),
+        SYS_SIGRETURN => signal::sigreturn(regs),
         SYS_SUPERVISE => process::supervise(regs.bx),
         _ => Err(Error::new(ENOSYS)),
     };
@@ -1,16 +1,18 @@
 //! System calls related to process managment.
-use arch::context::{context_clone, context_switch, Context, ContextFile};
+use arch::context::{context_clone, context_switch, Context, ContextFile, EnvVar, NICE_MAX, NICE_MIN};
 use arch::regs::Regs;
 
 use collections::{BTreeMap, Vec};
 use collections::string::ToString;
 
-use core::{intrinsics, mem};
+use core::{cmp, intrinsics, mem};
 use core::ops::DerefMut;
 
 use system::{c_array_to_slice, c_string_to_str};
-use system::error::{Error, Result, EAGAIN, EACCES, ECHILD, EINVAL};
-use system::syscall::{FUTEX_WAKE, FUTEX_WAIT, FUTEX_REQUEUE};
+use system::error::{Error, Result, EAGAIN, EACCES, ECHILD, EINVAL, EPERM, ESRCH};
+use system::syscall::{RLimit, FUTEX_WAKE, FUTEX_WAIT, FUTEX_REQUEUE, PRIO_PGRP, PRIO_PROCESS, PRIO_USER,
+                      RLIMIT_NLIMITS, SIGCHLD, WCONTINUED, WNOHANG, WUNTRACED, wcontinued, wexited,
+                      wifcontinued, wifexited, wifsignaled, wifstopped, wsignaled};
 
 use super::execute::execute;
 
@@ -20,39 +22,61 @@ pub fn clone(regs: &Regs) -> Result<usize> {
     unsafe { context_clone(regs) }
 }
 
-pub fn execve(path: *const u8, args: *const *const u8) -> Result<usize> {
+/// Execute a program, with the `NAME=VALUE` environment variables `env_vars`, or those of the
+/// current context if `env_vars` is null
+pub fn execve(path: *const u8, args: *const *const u8, env_vars: *const *const u8) -> Result<usize> {
     let mut args_vec = Vec::new();
     args_vec.push(c_string_to_str(path).to_string());
     for arg in c_array_to_slice(args) {
         args_vec.push(c_string_to_str(*arg).to_string());
     }
 
-    execute(args_vec)
+    let env_vars_vec: Option<Vec<EnvVar>> = if env_vars.is_null() {
+        None
+    } else {
+        Some(c_array_to_slice(env_vars).iter().filter_map(|env_var| {
+            let env_var = c_string_to_str(*env_var);
+            env_var.find('=').map(|i| EnvVar(env_var[..i].to_string(), env_var[i + 1..].to_string()))
+        }).collect())
+    };
+
+    execute(args_vec, env_vars_vec)
+}
+
+/// Exit context with an exit code
+pub fn exit(code: usize) -> ! {
+    exit_status(wexited(code))
 }
 
-/// Exit context
-pub fn exit(status: usize) -> ! {
+/// Exit context because of a signal
+pub fn exit_signal(sig: usize) -> ! {
+    exit_status(wsignaled(sig))
+}
+
+/// Exit context, reporting the given wait status to the parent
+fn exit_status(status: usize) -> ! {
     {
         let contexts = unsafe { &mut *::env().contexts.get() };
 
         let mut statuses = BTreeMap::new();
-        let (pid, ppid) = {
+        let (pid, ppid, pgid) = {
             if let Ok(mut current) = contexts.current_mut() {
                 mem::swap(&mut statuses, &mut unsafe { current.statuses.inner() }.deref_mut());
                 current.exit();
-                (current.pid, current.ppid)
+                (current.pid, current.ppid, current.pgid)
             } else {
-                (0, 0)
+                (0, 0, 0)
             }
         };
 
         for mut context in contexts.iter_mut() {
             // Add exit status to parent
             if context.pid == ppid {
-                context.statuses.send(pid, status, "exit parent status");
+                context.statuses.send(pid, (pgid, status), "exit parent status");
                 for (pid, status) in statuses.iter() {
                     context.statuses.send(*pid, *status, "exit child status");
                 }
+                context.signal(SIGCHLD);
             }
 
             // Move children to parent
@@ -67,6 +91,15 @@ pub fn exit(status: usize) -> ! {
     }
 }
 
+/// Report a stopped or continued child to its parent
+pub fn notify_parent(pid: usize, ppid: usize, pgid: usize, status: usize) {
+    let contexts = unsafe { &mut *::env().contexts.get() };
+    if let Ok(mut parent) = contexts.find_mut(ppid) {
+        parent.statuses.send(pid, (pgid, status), "notify_parent status");
+        parent.signal(SIGCHLD);
+    }
+}
+
 pub fn futex(addr: *mut i32, op: usize, val: i32, val2: usize, addr2: *mut i32) -> Result<usize> {
     match op {
         FUTEX_WAIT => {
@@ -150,18 +183,113 @@ pub fn futex(addr: *mut i32, op: usize, val: i32, val2: usize, addr2: *mut i32)
     }
 }
 
+pub fn getegid() -> Result<usize> {
+    let contexts = unsafe { & *::env().contexts.get() };
+    let current = try!(contexts.current());
+    Ok(current.egid)
+}
+
+pub fn geteuid() -> Result<usize> {
+    let contexts = unsafe { & *::env().contexts.get() };
+    let current = try!(contexts.current());
+    Ok(current.euid)
+}
+
+pub fn getgid() -> Result<usize> {
+    let contexts = unsafe { & *::env().contexts.get() };
+    let current = try!(contexts.current());
+    Ok(current.gid)
+}
+
+/// Get the process group of a context, or of the caller if `pid` is 0
+pub fn getpgid(pid: usize) -> Result<usize> {
+    let contexts = unsafe { & *::env().contexts.get() };
+    if pid == 0 {
+        Ok(try!(contexts.current()).pgid)
+    } else {
+        Ok(try!(contexts.find(pid)).pgid)
+    }
+}
+
 pub fn getpid() -> Result<usize> {
     let contexts = unsafe { & *::env().contexts.get() };
     let current = try!(contexts.current());
     Ok(current.pid)
 }
 
+/// Check if a context is selected by `which` and `who`, as used by `getpriority` and `setpriority`
+fn priority_target(context: &Context, which: usize, who: usize) -> bool {
+    match which {
+        PRIO_PROCESS => context.pid == who,
+        PRIO_PGRP => context.pgid == who,
+        PRIO_USER => context.uid == who,
+        _ => false
+    }
+}
+
+/// Resolve a `who` of 0 to the caller for `which`
+fn priority_who(current: &Context, which: usize, who: usize) -> Result<usize> {
+    if who != 0 {
+        return Ok(who);
+    }
+
+    match which {
+        PRIO_PROCESS => Ok(current.pid),
+        PRIO_PGRP => Ok(current.pgid),
+        PRIO_USER => Ok(current.uid),
+        _ => Err(Error::new(EINVAL))
+    }
+}
+
+/// Get the lowest nice value of the selected contexts
+///
+/// Returns `20 - nice`, so that the result is never negative.
+pub fn getpriority(which: usize, who: usize) -> Result<usize> {
+    let contexts = unsafe { & *::env().contexts.get() };
+    let who = try!(priority_who(try!(contexts.current()), which, who));
+
+    let mut nice = None;
+    for context in contexts.iter() {
+        if priority_target(context, which, who) && ! context.exited {
+            if nice.map_or(true, |nice| context.nice < nice) {
+                nice = Some(context.nice);
+            }
+        }
+    }
+
+    match nice {
+        Some(nice) => Ok((20 - nice) as usize),
+        None => Err(Error::new(ESRCH))
+    }
+}
+
+/// Get a resource limit of the caller
+pub fn getrlimit(resource: usize, rlim: *mut RLimit) -> Result<usize> {
+    if resource >= RLIMIT_NLIMITS {
+        return Err(Error::new(EINVAL));
+    }
+
+    let contexts = unsafe { & *::env().contexts.get() };
+    let current = try!(contexts.current());
+    *try!(current.get_ref_mut(rlim)) = current.rlimits[resource];
+    Ok(0)
+}
+
+pub fn getuid() -> Result<usize> {
+    let contexts = unsafe { & *::env().contexts.get() };
+    let current = try!(contexts.current());
+    Ok(current.uid)
+}
+
 #[cfg(target_arch = "x86")]
 pub fn iopl(regs: &mut Regs) -> Result<usize> {
     let level = regs.bx;
     if level <= 3 {
         let contexts = unsafe { &mut *::env().contexts.get() };
         let mut current = try!(contexts.current_mut());
+        if current.euid != 0 {
+            return Err(Error::new(EPERM));
+        }
         current.iopl = level;
 
         regs.flags &= 0xFFFFFFFF - 0x3000;
@@ -179,6 +307,9 @@ pub fn iopl(regs: &mut Regs) -> Result<usize> {
     if level <= 3 {
         let contexts = unsafe { &mut *::env().contexts.get() };
         let mut current = try!(contexts.current_mut());
+        if current.euid != 0 {
+            return Err(Error::new(EPERM));
+        }
         current.iopl = level;
 
         regs.flags &= 0xFFFFFFFFFFFFFFFF - 0x3000;
@@ -190,22 +321,57 @@ pub fn iopl(regs: &mut Regs) -> Result<usize> {
     }
 }
 
-//TODO: Finish implementation, add more functions to WaitMap so that matching any or using WNOHANG works
-pub fn waitpid(pid: isize, status_ptr: *mut usize, _options: usize) -> Result<usize> {
+/// Wait for a child to exit, or with `WUNTRACED` and `WCONTINUED`, to stop or continue
+///
+/// See `sys_waitpid` for how `pid` selects children.
+pub fn waitpid(pid: isize, status_ptr: *mut usize, options: usize) -> Result<usize> {
     let contexts = unsafe { &mut *::env().contexts.get() };
-    let current = try!(contexts.current_mut());
+    let (current_pid, current_pgid) = {
+        let current = try!(contexts.current());
+        (current.pid, current.pgid)
+    };
+
+    let matches = |child_pid: usize, child_pgid: usize| -> bool {
+        if pid > 0 {
+            child_pid == pid as usize
+        } else if pid == -1 {
+            true
+        } else if pid == 0 {
+            child_pgid == current_pgid
+        } else {
+            child_pgid == (-pid) as usize
+        }
+    };
 
-    if pid > 0 {
-        let status = current.statuses.receive(&(pid as usize), "waitpid status");
+    let wanted = |child_pid: &usize, &(child_pgid, status): &(usize, usize)| -> bool {
+        matches(*child_pid, child_pgid) && (
+            wifexited(status) || wifsignaled(status)
+            || (options & WUNTRACED == WUNTRACED && wifstopped(status))
+            || (options & WCONTINUED == WCONTINUED && wifcontinued(status))
+        )
+    };
 
-        if let Ok(status_safe) = current.get_ref_mut(status_ptr) {
-            *status_safe = status;
-        }
+    let has_children = contexts.iter().any(|context| {
+        context.ppid == current_pid && ! context.exited && matches(context.pid, context.pgid)
+    });
 
-        Ok(pid as usize)
+    let current = try!(contexts.current());
+
+    let (child_pid, (_, status)) = if let Some(entry) = current.statuses.try_receive_where(&wanted) {
+        entry
+    } else if ! has_children {
+        return Err(Error::new(ECHILD));
+    } else if options & WNOHANG == WNOHANG {
+        return Ok(0);
     } else {
-        Err(Error::new(ECHILD))
+        try!(current.statuses.receive_where(&wanted, "waitpid status"))
+    };
+
+    if let Ok(status_safe) = current.get_ref_mut(status_ptr) {
+        *status_safe = status;
     }
+
+    Ok(child_pid)
 }
 
 pub fn sched_yield() -> Result<usize> {
@@ -215,6 +381,149 @@ pub fn sched_yield() -> Result<usize> {
     Ok(0)
 }
 
+/// Set the group ID
+///
+/// Root sets both the real and effective group ID, anyone else may only set the effective group
+/// ID back to the real one.
+pub fn setgid(gid: usize) -> Result<usize> {
+    let contexts = unsafe { &mut *::env().contexts.get() };
+    let mut current = try!(contexts.current_mut());
+    if current.euid == 0 {
+        current.gid = gid;
+        current.egid = gid;
+        Ok(0)
+    } else if gid == current.gid {
+        current.egid = gid;
+        Ok(0)
+    } else {
+        Err(Error::new(EPERM))
+    }
+}
+
+/// Move a context into a process group
+///
+/// The context must be the caller or one of its children, in the same session and not a session
+/// leader. A `pid` of 0 selects the caller, and a `pgid` of 0 creates a new group with the ID of
+/// the context. Joining an existing group is only allowed within the same session.
+pub fn setpgid(pid: usize, pgid: usize) -> Result<usize> {
+    let contexts = unsafe { &mut *::env().contexts.get() };
+
+    let (current_pid, current_sid) = {
+        let current = try!(contexts.current());
+        (current.pid, current.sid)
+    };
+
+    let pid = if pid == 0 { current_pid } else { pid };
+    let pgid = if pgid == 0 { pid } else { pgid };
+
+    if pgid != pid && ! contexts.iter().any(|context| context.pgid == pgid && context.sid == current_sid && ! context.exited) {
+        return Err(Error::new(EPERM));
+    }
+
+    let context = try!(contexts.find_mut(pid));
+    if context.pid != current_pid && context.ppid != current_pid {
+        return Err(Error::new(ESRCH));
+    }
+    if context.sid != current_sid || context.sid == context.pid {
+        return Err(Error::new(EPERM));
+    }
+
+    context.pgid = pgid;
+    Ok(0)
+}
+
+/// Set the nice value of the selected contexts
+///
+/// The value is clamped to `NICE_MIN` and `NICE_MAX`. Only root may lower the nice value, or change
+/// contexts of other users.
+pub fn setpriority(which: usize, who: usize, nice: isize) -> Result<usize> {
+    let contexts = unsafe { &mut *::env().contexts.get() };
+    let (euid, who) = {
+        let current = try!(contexts.current());
+        (current.euid, try!(priority_who(current, which, who)))
+    };
+
+    let nice = cmp::max(NICE_MIN, cmp::min(NICE_MAX, nice));
+
+    let mut found = false;
+    for context in contexts.iter() {
+        if priority_target(context, which, who) && ! context.exited {
+            found = true;
+            if euid != 0 && (euid != context.uid || nice < context.nice) {
+                return Err(Error::new(EPERM));
+            }
+        }
+    }
+
+    if ! found {
+        return Err(Error::new(ESRCH));
+    }
+
+    for mut context in contexts.iter_mut() {
+        if priority_target(context, which, who) && ! context.exited {
+            context.nice = nice;
+        }
+    }
+
+    Ok(0)
+}
+
+/// Set a resource limit of the caller
+///
+/// The soft limit may not exceed the hard limit, and only root may raise the hard limit.
+pub fn setrlimit(resource: usize, rlim: *const RLimit) -> Result<usize> {
+    if resource >= RLIMIT_NLIMITS {
+        return Err(Error::new(EINVAL));
+    }
+
+    let contexts = unsafe { &mut *::env().contexts.get() };
+    let mut current = try!(contexts.current_mut());
+    let new = *try!(current.get_ref(rlim));
+    if new.rlim_cur > new.rlim_max {
+        return Err(Error::new(EINVAL));
+    }
+    if new.rlim_max > current.rlimits[resource].rlim_max && current.euid != 0 {
+        return Err(Error::new(EPERM));
+    }
+
+    current.rlimits[resource] = new;
+    Ok(0)
+}
+
+/// Create a new session and process group, led by the caller
+///
+/// Fails if the caller already leads a process group. Returns the new session ID.
+pub fn setsid() -> Result<usize> {
+    let contexts = unsafe { &mut *::env().contexts.get() };
+    let mut current = try!(contexts.current_mut());
+    if current.pgid == current.pid {
+        return Err(Error::new(EPERM));
+    }
+
+    current.sid = current.pid;
+    current.pgid = current.pid;
+    Ok(current.sid)
+}
+
+/// Set the user ID
+///
+/// Root sets both the real and effective user ID, anyone else may only set the effective user ID
+/// back to the real one.
+pub fn setuid(uid: usize) -> Result<usize> {
+    let contexts = unsafe { &mut *::env().contexts.get() };
+    let mut current = try!(contexts.current_mut());
+    if current.euid == 0 {
+        current.uid = uid;
+        current.euid = uid;
+        Ok(0)
+    } else if uid == current.uid {
+        current.euid = uid;
+        Ok(0)
+    } else {
+        Err(Error::new(EPERM))
+    }
+}
+
 /// Supervise a child process of the current context.
 ///
 /// This will make all syscalls the given process makes mark the process as blocked, until it is
@@ -246,13 +555,10 @@ pub fn supervise(pid: usize) -> Result<usize> {
 
     let current = try!(contexts.current_mut());
 
-    let fd = current.next_fd();
+    let fd = try!(current.next_fd());
 
     unsafe {
-        (*current.files.get()).push(ContextFile {
-            fd: fd,
-            resource: box try!(SupervisorResource::new(procc)),
-        });
+        (*current.files.get()).push(try!(ContextFile::new(fd, 0, false, box try!(SupervisorResource::new(procc)))));
     }
 
     Ok(fd)
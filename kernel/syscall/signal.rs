@@ -0,0 +1,364 @@
+//! System calls related to signals, and delivery of signals to userspace.
+
+use arch::context::{context_switch, Context};
+use arch::regs::Regs;
+
+use collections::Vec;
+
+use core::mem;
+
+use system::error::{Error, Result, EINVAL, EPERM, ESRCH};
+use system::syscall::{SigAction, NSIG, SA_NODEFER, SA_RESETHAND, SIG_BLOCK, SIG_DFL, SIG_IGN,
+                      SIG_SETMASK, SIG_UNBLOCK, SIGCHLD, SIGCONT, SIGKILL, SIGSEGV, SIGSTOP,
+                      SIGTSTP, SIGTTIN, SIGTTOU, SIGURG, SIGWINCH, wcontinued, wstopped};
+
+use super::process::{exit_signal, notify_parent};
+
+/// Signals that can be neither caught, blocked nor ignored
+const UNBLOCKABLE: u32 = 1 << SIGKILL | 1 << SIGSTOP;
+
+/// The area below the user stack pointer that may be used without adjusting it
+#[cfg(target_arch = "x86")]
+const RED_ZONE: usize = 0;
+#[cfg(target_arch = "x86_64")]
+const RED_ZONE: usize = 128;
+
+/// The frame pushed to the user stack when running a signal handler.
+///
+/// The handler returns into `restorer`, which calls `SYS_SIGRETURN` with the stack pointer
+/// pointing at `sig`.
+#[derive(Copy, Clone)]
+#[repr(packed)]
+struct SignalFrame {
+    restorer: usize,
+    sig: usize,
+    blocked: usize,
+    regs: Regs,
+}
+
+/// What happens when a signal with the default disposition is delivered
+enum DefaultAction {
+    Terminate,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+fn default_action(sig: usize) -> DefaultAction {
+    match sig {
+        SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ignore,
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+        SIGCONT => DefaultAction::Continue,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+/// The pending signals that interrupt a blocking system call of `context`
+///
+/// These are the signals that are not blocked and not ignored, either explicitly or by default.
+pub fn interrupting(context: &Context) -> u32 {
+    let actions = unsafe { & *context.sig_actions.get() };
+
+    let mut sigs = context.sig_pending & !(context.sig_blocked & !UNBLOCKABLE);
+    for sig in 1..NSIG {
+        if sigs & 1 << sig != 0 {
+            let ignored = match actions[sig].sa_handler {
+                SIG_IGN => true,
+                SIG_DFL => match default_action(sig) {
+                    DefaultAction::Ignore | DefaultAction::Continue => true,
+                    DefaultAction::Terminate | DefaultAction::Stop => false,
+                },
+                _ => false
+            };
+
+            if ignored {
+                sigs &= !(1 << sig);
+            }
+        }
+    }
+    sigs
+}
+
+/// The credentials of the context that sends a signal
+#[derive(Clone, Copy)]
+struct Sender {
+    pid: usize,
+    uid: usize,
+    euid: usize,
+    sid: usize,
+}
+
+impl Sender {
+    fn current() -> Result<Sender> {
+        let contexts = unsafe { & *::env().contexts.get() };
+        let current = try!(contexts.current());
+        Ok(Sender {
+            pid: current.pid,
+            uid: current.uid,
+            euid: current.euid,
+            sid: current.sid,
+        })
+    }
+
+    /// Check if the sender may send `sig` to `context`
+    ///
+    /// Root may signal any context. Other users may signal contexts whose real or effective
+    /// user ID matches their own real or effective user ID, and may continue any context in
+    /// their session.
+    fn may_signal(&self, context: &Context, sig: usize) -> bool {
+        self.euid == 0
+            || self.uid == context.uid || self.uid == context.euid
+            || self.euid == context.uid || self.euid == context.euid
+            || (sig == SIGCONT && self.sid == context.sid)
+    }
+}
+
+/// Send a signal to a context, or to a process group
+///
+/// A positive `pid` selects a single context, 0 selects the process group of the caller, -1
+/// selects every context that the caller may signal, except itself and kernel contexts, and
+/// another negative `pid` selects the process group `-pid`. A signal of 0 only checks that the
+/// target exists. Returns `EPERM` if the caller may not signal any of the selected contexts.
+pub fn kill(pid: isize, sig: usize) -> Result<usize> {
+    if sig >= NSIG {
+        return Err(Error::new(EINVAL));
+    }
+
+    let sender = try!(Sender::current());
+
+    if pid > 0 {
+        kill_where(|context| context.pid == pid as usize, sig, Some(sender))
+    } else if pid == 0 {
+        let pgid = {
+            let contexts = unsafe { & *::env().contexts.get() };
+            try!(contexts.current()).pgid
+        };
+        kill_where(|context| context.pgid == pgid, sig, Some(sender))
+    } else if pid == -1 {
+        kill_where(|context| {
+            context.pid != sender.pid && unsafe { (*context.image.get()).size() } > 0
+        }, sig, Some(sender))
+    } else {
+        kill_where(|context| context.pgid == (-pid) as usize, sig, Some(sender))
+    }
+}
+
+/// Send a signal to every context in a process group, such as from a terminal
+pub fn kill_group(pgid: usize, sig: usize) -> Result<usize> {
+    if sig >= NSIG {
+        return Err(Error::new(EINVAL));
+    }
+
+    kill_where(|context| context.pgid == pgid, sig, None)
+}
+
+/// Send a signal to every context that matches `f`, and that `sender` may signal if it is given
+///
+/// Returns `ESRCH` if no context matches, and `EPERM` if none of them may be signalled.
+fn kill_where<F>(f: F, sig: usize, sender: Option<Sender>) -> Result<usize> where F: Fn(&Context) -> bool {
+    let contexts = unsafe { &mut *::env().contexts.get() };
+
+    let mut found = false;
+    let mut permitted = false;
+    let mut continued = Vec::new();
+    for mut context in contexts.iter_mut() {
+        if context.exited || ! f(context) {
+            continue;
+        }
+
+        found = true;
+        if let Some(sender) = sender {
+            if ! sender.may_signal(context, sig) {
+                continue;
+            }
+        }
+
+        permitted = true;
+        let was_stopped = context.stopped;
+        context.signal(sig);
+        if was_stopped && ! context.stopped {
+            continued.push((context.pid, context.ppid, context.pgid));
+        }
+    }
+
+    for (pid, ppid, pgid) in continued {
+        notify_parent(pid, ppid, pgid, wcontinued());
+    }
+
+    if permitted {
+        Ok(0)
+    } else if found {
+        Err(Error::new(EPERM))
+    } else {
+        Err(Error::new(ESRCH))
+    }
+}
+
+/// Get and set the disposition of a signal
+pub fn sigaction(sig: usize, act: *const SigAction, oldact: *mut SigAction) -> Result<usize> {
+    if sig == 0 || sig >= NSIG {
+        return Err(Error::new(EINVAL));
+    }
+
+    let contexts = unsafe { & *::env().contexts.get() };
+    let current = try!(contexts.current());
+    let actions = unsafe { &mut *current.sig_actions.get() };
+
+    if oldact as usize > 0 {
+        *try!(current.get_ref_mut(oldact)) = actions[sig];
+    }
+
+    if act as usize > 0 {
+        let act_safe = *try!(current.get_ref(act));
+        if UNBLOCKABLE & 1 << sig != 0 {
+            return Err(Error::new(EINVAL));
+        }
+        actions[sig] = act_safe;
+    }
+
+    Ok(0)
+}
+
+/// Get and change the blocked signals of the current context
+pub fn sigprocmask(how: usize, set: *const u32, oldset: *mut u32) -> Result<usize> {
+    let contexts = unsafe { &mut *::env().contexts.get() };
+    let mut current = try!(contexts.current_mut());
+
+    if oldset as usize > 0 {
+        *try!(current.get_ref_mut(oldset)) = current.sig_blocked;
+    }
+
+    if set as usize > 0 {
+        let set_safe = *try!(current.get_ref(set)) & !UNBLOCKABLE;
+        current.sig_blocked = match how {
+            SIG_BLOCK => current.sig_blocked | set_safe,
+            SIG_UNBLOCK => current.sig_blocked & !set_safe,
+            SIG_SETMASK => set_safe,
+            _ => return Err(Error::new(EINVAL)),
+        };
+    }
+
+    Ok(0)
+}
+
+/// Return from a signal handler, restoring the registers saved by `deliver`
+///
+/// The value returned is the restored AX, so that the syscall handler leaves it untouched.
+pub fn sigreturn(regs: &mut Regs) -> Result<usize> {
+    let contexts = unsafe { &mut *::env().contexts.get() };
+    let mut current = try!(contexts.current_mut());
+
+    let frame_ptr = (regs.sp - mem::size_of::<usize>()) as *const SignalFrame;
+    let frame = *try!(current.get_ref(frame_ptr));
+
+    // The privilege level, I/O privilege level and interrupt flag can not be changed by userspace
+    let cs = regs.cs;
+    let ss = regs.ss;
+    let flags = regs.flags;
+    *regs = frame.regs;
+    regs.cs = cs;
+    regs.ss = ss;
+    regs.flags = (frame.regs.flags & !0x3200) | (flags & 0x3200);
+
+    current.sig_blocked = frame.blocked as u32 & !UNBLOCKABLE;
+
+    Ok(regs.ax)
+}
+
+/// Deliver pending signals to the current context, before it returns to userspace
+///
+/// Default actions are run directly. If a handler is installed, a `SignalFrame` is pushed to the
+/// user stack and the registers are changed so that the handler runs on return.
+pub fn deliver(regs: &mut Regs) {
+    // Only interrupted userspace code can run handlers
+    if regs.cs & 3 != 3 {
+        return;
+    }
+
+    loop {
+        let (sig, action) = {
+            let contexts = unsafe { &mut *::env().contexts.get() };
+            let mut current = if let Ok(current) = contexts.current_mut() {
+                current
+            } else {
+                return;
+            };
+
+            let deliverable = current.sig_pending & !(current.sig_blocked & !UNBLOCKABLE);
+            if deliverable == 0 {
+                return;
+            }
+
+            let sig = deliverable.trailing_zeros() as usize;
+            current.sig_pending &= !(1 << sig);
+
+            (sig, unsafe { (*current.sig_actions.get())[sig] })
+        };
+
+        match action.sa_handler {
+            SIG_IGN => (),
+            SIG_DFL => match default_action(sig) {
+                DefaultAction::Terminate => exit_signal(sig),
+                DefaultAction::Ignore | DefaultAction::Continue => (),
+                DefaultAction::Stop => {
+                    {
+                        let contexts = unsafe { &mut *::env().contexts.get() };
+                        if let Ok(mut current) = contexts.current_mut() {
+                            current.stopped = true;
+                            current.block("signal::deliver stop");
+                            notify_parent(current.pid, current.ppid, current.pgid, wstopped(sig));
+                        }
+                    }
+
+                    unsafe { context_switch() };
+                },
+            },
+            handler => {
+                let contexts = unsafe { &mut *::env().contexts.get() };
+                let mut current = if let Ok(current) = contexts.current_mut() {
+                    current
+                } else {
+                    return;
+                };
+
+                let frame_addr = ((regs.sp - RED_ZONE - mem::size_of::<SignalFrame>()) & !15) - mem::size_of::<usize>();
+                match current.get_ref_mut(frame_addr as *mut SignalFrame) {
+                    Ok(frame) => *frame = SignalFrame {
+                        restorer: action.sa_restorer,
+                        sig: sig,
+                        blocked: current.sig_blocked as usize,
+                        regs: *regs,
+                    },
+                    Err(_) => {
+                        debugln!("PID {}: {}: bad stack for signal {}", current.pid, current.name, sig);
+                        exit_signal(SIGSEGV);
+                    }
+                }
+
+                regs.sp = frame_addr;
+                regs.ip = handler;
+                set_arg(regs, sig);
+
+                current.sig_blocked |= action.sa_mask & !UNBLOCKABLE;
+                if action.sa_flags & SA_NODEFER != SA_NODEFER {
+                    current.sig_blocked |= 1 << sig;
+                }
+                if action.sa_flags & SA_RESETHAND == SA_RESETHAND {
+                    unsafe { (*current.sig_actions.get())[sig] = SigAction::default() };
+                }
+
+                return;
+            }
+        }
+    }
+}
+
+/// The signal number is passed on the stack
+#[cfg(target_arch = "x86")]
+fn set_arg(_regs: &mut Regs, _sig: usize) {}
+
+/// The signal number is passed on the stack and in the first argument register
+#[cfg(target_arch = "x86_64")]
+fn set_arg(regs: &mut Regs, sig: usize) {
+    regs.di = sig;
+}
@@ -6,7 +6,9 @@ use common::time::Duration;
 
 use syscall::{CLOCK_MONOTONIC, CLOCK_REALTIME, TimeSpec};
 
-use system::error::{Error, Result, EINVAL};
+use system::error::{Error, Result, EINTR, EINVAL};
+
+use super::signal;
 
 /// Get the time of a given clock.
 pub fn clock_gettime(clock: usize, tp: *mut TimeSpec) -> Result<usize> {
@@ -32,17 +34,21 @@ pub fn clock_gettime(clock: usize, tp: *mut TimeSpec) -> Result<usize> {
 }
 
 /// Sleep in N nanoseconds.
+///
+/// Returns `EINTR` if woken early by a signal, with the remaining time in `rem`.
 pub fn nanosleep(req: *const TimeSpec, rem: *mut TimeSpec) -> Result<usize> {
-    {
+    let wake = {
         let contexts = unsafe { &mut *::env().contexts.get() };
         let mut current = try!(contexts.current_mut());
 
         // Copied with * to avoid borrow issue on current.blocked = true
         let req_safe = *current.get_ref(req)?;
 
+        let wake = Duration::monotonic() + Duration::new(req_safe.tv_sec, req_safe.tv_nsec);
         current.block("nanosleep");
-        current.wake = Some(Duration::monotonic() + Duration::new(req_safe.tv_sec, req_safe.tv_nsec));
-    }
+        current.sleep(wake);
+        wake
+    };
 
     unsafe { context_switch(); }
 
@@ -50,9 +56,22 @@ pub fn nanosleep(req: *const TimeSpec, rem: *mut TimeSpec) -> Result<usize> {
         let contexts = unsafe { & *::env().contexts.get() };
         let current = try!(contexts.current());
 
+        let now = Duration::monotonic();
+        let interrupted = now < wake && signal::interrupting(current) != 0;
+
         if let Ok(rem_safe) = current.get_ref_mut(rem) {
-            rem_safe.tv_sec = 0;
-            rem_safe.tv_nsec = 0;
+            if interrupted {
+                let remaining = wake - now;
+                rem_safe.tv_sec = remaining.secs;
+                rem_safe.tv_nsec = remaining.nanos;
+            } else {
+                rem_safe.tv_sec = 0;
+                rem_safe.tv_nsec = 0;
+            }
+        }
+
+        if interrupted {
+            return Err(Error::new(EINTR));
         }
     }
 
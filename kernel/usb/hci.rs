@@ -166,7 +166,7 @@ pub trait Hci {
                                         {
                                             let contexts = &mut *::env().contexts.get();
                                             if let Ok(mut current) = contexts.current_mut() {
-                                                current.wake = Some(Duration::monotonic() + Duration::new(0, 10 * time::NANOS_PER_MILLI));
+                                                current.sleep(Duration::monotonic() + Duration::new(0, 10 * time::NANOS_PER_MILLI));
                                                 current.block("HCI sleep");
                                             }
                                         }
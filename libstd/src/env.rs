@@ -5,7 +5,7 @@ use alloc::boxed::Box;
 use core_collections::borrow::ToOwned;
 
 use ffi::{OsString, OsStr};
-use fs::{self, File};
+use fs::File;
 use path::{Path, PathBuf};
 use string::{String, ToString};
 use sys_common::AsInner;
@@ -13,12 +13,16 @@ use vec::Vec;
 use error;
 use fmt;
 
+use system::{c_array_to_slice, c_string_to_str};
 use system::syscall::sys_chdir;
 
-use io::{Error, Result, Read, Write};
+use io::{Error, Result};
 
 static mut _args: *mut Vec<&'static str> = 0 as *mut Vec<&'static str>;
 
+/// The environment variables of this process, which `execve` passes on to a new program
+static mut _vars: *mut Vec<(String, String)> = 0 as *mut Vec<(String, String)>;
+
 /// An iterator over the arguments of a process, yielding a `String` value for each argument.
 pub struct Args {
     i: usize
@@ -68,6 +72,36 @@ pub unsafe fn args_destroy() {
     }
 }
 
+/// Initialize environment variables from the `NAME=VALUE` strings on the initial stack
+pub unsafe fn vars_init() {
+    let mut vars = Vec::new();
+    for var in c_array_to_slice(::externs::environ).iter() {
+        let var = c_string_to_str(*var);
+        if let Some(equal_sign) = var.find('=') {
+            vars.push((var[..equal_sign].to_owned(), var[equal_sign + 1..].to_owned()));
+        }
+    }
+    _vars = Box::into_raw(box vars);
+}
+
+/// Destroy environment variables
+pub unsafe fn vars_destroy() {
+    if _vars as usize > 0 {
+        drop(Box::from_raw(_vars));
+        _vars = 0 as *mut Vec<(String, String)>;
+    }
+}
+
+/// The environment variables, which are empty if they were not initialized
+fn vars_mut() -> &'static mut Vec<(String, String)> {
+    unsafe {
+        if _vars as usize == 0 {
+            _vars = Box::into_raw(box Vec::new());
+        }
+        &mut *_vars
+    }
+}
+
 /// Private function to get the path from a custom location
 /// If the custom directory cannot be found, None will be returned
 fn get_path_from(location : &str) -> Result<PathBuf> {
@@ -154,10 +188,9 @@ impl error::Error for VarError {
 /// or if the variable is not present then `Err` is returned
 pub fn var<K: AsRef<OsStr>>(key: K) -> ::core::result::Result<String, VarError> {
     if let Some(key_str) = key.as_ref().to_str() {
-        let mut file = try!(File::open(&("env:".to_owned() + key_str)).or(Err(VarError::NotPresent)));
-        let mut string = String::new();
-        try!(file.read_to_string(&mut string).or(Err(VarError::NotPresent)));
-        Ok(string)
+        vars_mut().iter().find(|&&(ref name, _)| name == key_str)
+                  .map(|&(_, ref value)| value.clone())
+                  .ok_or(VarError::NotPresent)
     } else {
         Err(VarError::NotUnicode(key.as_ref().to_owned()))
     }
@@ -176,16 +209,19 @@ pub fn var_os<K: AsRef<OsStr>>(key: K) -> Option<OsString> {
 /// Sets the environment variable `key` to the value `value` for the current process
 pub fn set_var<K: AsRef<OsStr>, V: AsRef<OsStr>>(key: K, value: V) {
     if let (Some(key_str), Some(value_str)) = (key.as_ref().to_str(), value.as_ref().to_str()) {
-        if let Ok(mut file) = File::open(&("env:".to_owned() + key_str)) {
-            let _ = file.write_all(value_str.as_bytes());
+        let vars = vars_mut();
+        if let Some(var) = vars.iter_mut().find(|&&mut (ref name, _)| name == key_str) {
+            var.1 = value_str.to_owned();
+            return;
         }
+        vars.push((key_str.to_owned(), value_str.to_owned()));
     }
 }
 
 /// Removes an environment variable from the environment of the current process
 pub fn remove_var<K: AsRef<OsStr>>(key: K) {
     if let Some(key_str) = key.as_ref().to_str() {
-        let _ = fs::remove_file(&("env:".to_owned() + key_str));
+        vars_mut().retain(|&(ref name, _)| name != key_str);
     }
 }
 
@@ -208,19 +244,5 @@ impl Iterator for Vars {
 
 /// Returns an iterator over the environment variables of the current process
 pub fn vars() -> Vars {
-    let mut variables: Vec<(String, String)> = Vec::new();
-    if let Ok(mut file) = File::open("env:") {
-        let mut string = String::new();
-        if file.read_to_string(&mut string).is_ok() {
-            for line in string.lines() {
-                if let Some(equal_sign) = line.chars().position(|c| c == '=') {
-                    let name = line.chars().take(equal_sign).collect::<String>();
-                    let value = line.chars().skip(equal_sign+1).collect::<String>();
-                    variables.push((name, value));
-                }
-            }
-            return Vars { vars: variables, pos: 0 };
-        }
-    }
-    Vars { vars: Vec::new(), pos: 0 }
+    Vars { vars: vars_mut().clone(), pos: 0 }
 }
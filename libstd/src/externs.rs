@@ -2,6 +2,10 @@
 #[no_mangle]
 pub static mut __errno: isize = 0;
 
+/// The environment given to the program, a null terminated array of `NAME=VALUE` strings
+#[no_mangle]
+pub static mut environ: *const *const u8 = 0 as *const *const u8;
+
 /// Memcpy
 ///
 /// Copy N bytes of memory from one location to another.
@@ -8,8 +8,9 @@ use sys_common::AsInner;
 use vec::Vec;
 
 use system::syscall::{sys_open, sys_dup, sys_close, sys_fpath, sys_fstat, sys_ftruncate, sys_read,
-              sys_write, sys_lseek, sys_fsync, sys_mkdir, sys_rmdir, sys_unlink};
-use system::syscall::{O_RDWR, O_RDONLY, O_WRONLY, O_APPEND, O_CREAT, O_TRUNC, MODE_DIR, MODE_FILE, SEEK_SET, SEEK_CUR, SEEK_END, Stat};
+              sys_write, sys_lseek, sys_fsync, sys_mkdir, sys_rmdir, sys_unlink, sys_link, sys_rename};
+use system::error::{ENOSYS, EXDEV};
+use system::syscall::{O_RDWR, O_RDONLY, O_WRONLY, O_APPEND, O_CLOEXEC, O_CREAT, O_TRUNC, MODE_DIR, MODE_FILE, SEEK_SET, SEEK_CUR, SEEK_END, Stat};
 
 /// A Unix-style file
 #[derive(Debug)]
@@ -22,13 +23,13 @@ impl File {
     /// Open a new file using a path
     pub fn open<P: AsRef<Path>>(path: P) -> Result<File> {
         let path_str = path.as_ref().as_os_str().as_inner();
-        sys_open(path_str, O_RDONLY).map(|fd| unsafe { File::from_raw_fd(fd) }).map_err(|x| Error::from_sys(x))
+        sys_open(path_str, O_RDONLY | O_CLOEXEC).map(|fd| unsafe { File::from_raw_fd(fd) }).map_err(|x| Error::from_sys(x))
     }
 
     /// Create a new file using a path
     pub fn create<P: AsRef<Path>>(path: P) -> Result<File> {
         let path_str = path.as_ref().as_os_str().as_inner();
-        sys_open(path_str, O_CREAT | O_RDWR | O_TRUNC).map(|fd| unsafe { File::from_raw_fd(fd) }).map_err(|x| Error::from_sys(x))
+        sys_open(path_str, O_CREAT | O_RDWR | O_TRUNC | O_CLOEXEC).map(|fd| unsafe { File::from_raw_fd(fd) }).map_err(|x| Error::from_sys(x))
     }
 
     /// Duplicate the file
@@ -192,7 +193,7 @@ impl OpenOptions {
     }
 
     pub fn open<P: AsRef<Path>>(&self, path: P) -> Result<File> {
-        let mut flags = 0;
+        let mut flags = O_CLOEXEC;
 
         if self.read && self.write {
             flags |= O_RDWR;
@@ -354,9 +355,28 @@ pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<u64> {
 }
 
 /// Rename a file or directory to a new name
+///
+/// Files are copied and removed instead if the scheme does not support renaming, or if they are
+/// moved to another scheme.
 pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<()> {
-    try!(copy(Path::new(from.as_ref()), to));
-    remove_file(from)
+    let from_str = from.as_ref().as_os_str().as_inner();
+    let to_str = to.as_ref().as_os_str().as_inner();
+    match sys_rename(from_str, to_str) {
+        Ok(_) => Ok(()),
+        // Schemes without rename, and renames across schemes, fall back to copying
+        Err(err) if err.errno == ENOSYS || err.errno == EXDEV => {
+            try!(copy(from.as_ref(), to.as_ref()));
+            remove_file(from.as_ref())
+        },
+        Err(err) => Err(Error::from_sys(err))
+    }
+}
+
+/// Create a new hard link `to` pointing at the file `from`
+pub fn hard_link<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<()> {
+    let from_str = from.as_ref().as_os_str().as_inner();
+    let to_str = to.as_ref().as_os_str().as_inner();
+    sys_link(from_str, to_str).and(Ok(())).map_err(|x| Error::from_sys(x))
 }
 
 /// Return an iterator over the entries within a directory
@@ -17,7 +17,7 @@ use option::Option::{self, None};
 use result;
 
 use system::error::Error as SysError;
-use system::error::{ENOENT, EACCES, EEXIST, EINVAL, STR_ERROR};
+use system::error::{ENOENT, EACCES, EAGAIN, EEXIST, EINVAL, STR_ERROR};
 
 /// A specialized [`Result`](../result/enum.Result.html) type for I/O
 /// operations.
@@ -103,6 +103,7 @@ impl Error {
                 EACCES => ErrorKind::PermissionDenied,
                 EEXIST => ErrorKind::AlreadyExists,
                 EINVAL => ErrorKind::InvalidInput,
+                EAGAIN => ErrorKind::WouldBlock,
                 _ => ErrorKind::Other
             },
             &Repr::Custom(ref c) => c.kind,
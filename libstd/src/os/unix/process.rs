@@ -0,0 +1,16 @@
+use process::Command;
+
+/// Unix-specific extensions to `process::Command`
+pub trait CommandExt {
+    /// Sets the user ID of the child process, applied before it is executed
+    fn uid(&mut self, id: u32) -> &mut Command;
+
+    /// Sets the group ID of the child process, applied before it is executed
+    fn gid(&mut self, id: u32) -> &mut Command;
+}
+
+/// Unix-specific extensions to `process::ExitStatus`
+pub trait ExitStatusExt {
+    /// If the process was terminated by a signal, returns that signal
+    fn signal(&self) -> Option<i32>;
+}
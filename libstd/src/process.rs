@@ -5,13 +5,15 @@ use fmt;
 use io::{Result, Read, Write};
 use mem;
 use os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use os::unix::process::{CommandExt, ExitStatusExt};
 use ops::DerefMut;
 use string::{String, ToString};
 use core_collections::borrow::ToOwned;
 use vec::Vec;
 
 use io::Error;
-use system::syscall::{sys_clone, sys_close, sys_dup, sys_execve, sys_exit, sys_pipe2, sys_read, sys_write, sys_waitpid, CLONE_VM, CLONE_VFORK, CLONE_SUPERVISE};
+use system::syscall::{sys_clone, sys_close, sys_dup2, sys_execve, sys_exit, sys_kill, sys_pipe2, sys_read, sys_setgid, sys_setuid, sys_write, sys_waitpid, CLONE_VM, CLONE_VFORK, CLONE_SUPERVISE, O_CLOEXEC, SIGKILL,
+                      wexitstatus, wifexited, wifsignaled, wtermsig};
 use system::error::Error as SysError;
 
 pub struct ExitStatus {
@@ -20,11 +22,38 @@ pub struct ExitStatus {
 
 impl ExitStatus {
     pub fn success(&self) -> bool {
-        self.status == 0
+        self.code() == Some(0)
     }
 
+    /// Returns the exit code, or `None` if the process was terminated by a signal
     pub fn code(&self) -> Option<i32> {
-        Some(self.status as i32)
+        if wifexited(self.status) {
+            Some(wexitstatus(self.status) as i32)
+        } else {
+            None
+        }
+    }
+}
+
+impl CommandExt for Command {
+    fn uid(&mut self, id: u32) -> &mut Command {
+        self.uid = Some(id as usize);
+        self
+    }
+
+    fn gid(&mut self, id: u32) -> &mut Command {
+        self.gid = Some(id as usize);
+        self
+    }
+}
+
+impl ExitStatusExt for ExitStatus {
+    fn signal(&self) -> Option<i32> {
+        if wifsignaled(self.status) {
+            Some(wtermsig(self.status) as i32)
+        } else {
+            None
+        }
     }
 }
 
@@ -95,6 +124,11 @@ impl Child {
         self.pid as u32
     }
 
+    /// Forces the child to exit, by sending it SIGKILL
+    pub fn kill(&mut self) -> Result<()> {
+        sys_kill(self.pid, SIGKILL).and(Ok(())).map_err(|x| Error::from_sys(x))
+    }
+
     pub fn wait(&mut self) -> Result<ExitStatus> {
         let mut status: usize = 0;
         sys_waitpid(self.pid, &mut status, 0).map(|_| ExitStatus { status: status }).map_err(|x| Error::from_sys(x))
@@ -108,6 +142,8 @@ pub struct Command {
     stdin: Stdio,
     stdout: Stdio,
     stderr: Stdio,
+    uid: Option<usize>,
+    gid: Option<usize>,
 }
 
 impl fmt::Debug for Command {
@@ -132,6 +168,8 @@ impl Command {
             stdin: Stdio::inherit(),
             stdout: Stdio::inherit(),
             stderr: Stdio::inherit(),
+            uid: None,
+            gid: None,
         }
     }
 
@@ -201,7 +239,29 @@ impl Command {
         }
         args_c.push(0 as *const u8);
 
-        let env = self.env.clone();
+        // The environment is built before cloning, as the child shares the memory of this process
+        let mut vars: Vec<(String, String)> = env::vars().collect();
+        for (key, val) in self.env.iter() {
+            if let Some(var) = vars.iter_mut().find(|&&mut (ref name, _)| name == key) {
+                var.1 = val.to_owned();
+                continue;
+            }
+            vars.push((key.to_owned(), val.to_owned()));
+        }
+
+        let mut vars_vec: Vec<String> = Vec::new();
+        for &(ref key, ref val) in vars.iter() {
+            vars_vec.push(format!("{}={}\0", key, val));
+        }
+
+        let mut vars_c: Vec<*const u8> = Vec::new();
+        for var_vec in vars_vec.iter() {
+            vars_c.push(var_vec.as_ptr());
+        }
+        vars_c.push(0 as *const u8);
+
+        let uid = self.uid;
+        let gid = self.gid;
 
         let child_res = res.deref_mut() as *mut usize;
         let child_stderr = self.stderr.inner;
@@ -211,15 +271,17 @@ impl Command {
             let child_stderr_res = match child_stderr {
                 StdioType::Piped(read, write) => {
                     let _ = sys_close(read);
-                    let _ = sys_close(2);
-                    let dup_res = sys_dup(write).map_err(|x| Error::from_sys(x));
-                    let _ = sys_close(write);
+                    let dup_res = sys_dup2(write, 2).map_err(|x| Error::from_sys(x));
+                    if write != 2 {
+                        let _ = sys_close(write);
+                    }
                     dup_res
                 },
                 StdioType::Raw(fd) => {
-                    let _ = sys_close(2);
-                    let dup_res = sys_dup(fd).map_err(|x| Error::from_sys(x));
-                    let _ = sys_close(fd);
+                    let dup_res = sys_dup2(fd, 2).map_err(|x| Error::from_sys(x));
+                    if fd != 2 {
+                        let _ = sys_close(fd);
+                    }
                     dup_res
                 },
                 StdioType::Null => {
@@ -232,15 +294,17 @@ impl Command {
             let child_stdout_res = match child_stdout {
                 StdioType::Piped(read, write) => {
                     let _ = sys_close(read);
-                    let _ = sys_close(1);
-                    let dup_res = sys_dup(write).map_err(|x| Error::from_sys(x));
-                    let _ = sys_close(write);
+                    let dup_res = sys_dup2(write, 1).map_err(|x| Error::from_sys(x));
+                    if write != 1 {
+                        let _ = sys_close(write);
+                    }
                     dup_res
                 },
                 StdioType::Raw(fd) => {
-                    let _ = sys_close(1);
-                    let dup_res = sys_dup(fd).map_err(|x| Error::from_sys(x));
-                    let _ = sys_close(fd);
+                    let dup_res = sys_dup2(fd, 1).map_err(|x| Error::from_sys(x));
+                    if fd != 1 {
+                        let _ = sys_close(fd);
+                    }
                     dup_res
                 },
                 StdioType::Null => {
@@ -253,15 +317,17 @@ impl Command {
             let child_stdin_res = match child_stdin {
                 StdioType::Piped(read, write) => {
                     let _ = sys_close(write);
-                    let _ = sys_close(0);
-                    let dup_res = sys_dup(read).map_err(|x| Error::from_sys(x));
-                    let _ = sys_close(read);
+                    let dup_res = sys_dup2(read, 0).map_err(|x| Error::from_sys(x));
+                    if read != 0 {
+                        let _ = sys_close(read);
+                    }
                     dup_res
                 },
                 StdioType::Raw(fd) => {
-                    let _ = sys_close(0);
-                    let dup_res = sys_dup(fd).map_err(|x| Error::from_sys(x));
-                    let _ = sys_close(fd);
+                    let dup_res = sys_dup2(fd, 0).map_err(|x| Error::from_sys(x));
+                    if fd != 0 {
+                        let _ = sys_close(fd);
+                    }
                     dup_res
                 },
                 StdioType::Null => {
@@ -275,11 +341,16 @@ impl Command {
             let _ = try!(child_stdout_res);
             let _ = try!(child_stdin_res);
 
-            for (key, val) in env.iter() {
-                env::set_var(key, val);
+            // The group has to be set first, as setting the user may drop the permission to do so
+            if let Some(gid) = gid {
+                try!(sys_setgid(gid).map_err(|x| Error::from_sys(x)));
+            }
+
+            if let Some(uid) = uid {
+                try!(sys_setuid(uid).map_err(|x| Error::from_sys(x)));
             }
 
-            unsafe { sys_execve(path_c.as_ptr(), args_c.as_ptr()) }.map_err(|x| Error::from_sys(x))
+            unsafe { sys_execve(path_c.as_ptr(), args_c.as_ptr(), vars_c.as_ptr()) }.map_err(|x| Error::from_sys(x))
         });
 
         match unsafe { sys_clone(flags) } {
@@ -395,7 +466,7 @@ pub struct Stdio {
 impl Stdio {
     pub fn piped() -> Stdio {
         let mut fds = [0; 2];
-        if sys_pipe2(&mut fds, 0).is_ok() {
+        if sys_pipe2(&mut fds, O_CLOEXEC).is_ok() {
             Stdio {
                 inner: StdioType::Piped(fds[0], fds[1])
             }
@@ -1,6 +1,6 @@
 use core::{fmt, mem, ptr, slice, str};
 use panic::panic_impl;
-use env::{args_init, args_destroy};
+use env::{args_init, args_destroy, vars_init, vars_destroy};
 use system::syscall::sys_exit;
 use vec::Vec;
 
@@ -62,6 +62,7 @@ pub unsafe extern "C" fn _start_stack(stack: *const usize){
 
     let argc = *stack;
     let argv = stack.offset(1) as *const *const u8;
+    ::externs::environ = argv.offset(argc as isize + 1);
     let _ = sys_exit(main(argc, argv));
 }
 
@@ -85,10 +86,12 @@ fn lang_start(main: *const u8, argc: usize, argv: *const *const u8) -> usize {
         }
 
         args_init(args);
+        vars_init();
 
         mem::transmute::<_, fn()>(main)();
 
         args_destroy();
+        vars_destroy();
     }
 
     0
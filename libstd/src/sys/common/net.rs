@@ -3,8 +3,10 @@ use fs::File;
 use io::{Error, ErrorKind, Result, Read, Write};
 use iter::Iterator;
 use net::{Ipv4Addr, SocketAddr, SocketAddrV4, Shutdown};
+use os::unix::io::AsRawFd;
 use string::ToString;
 use system::error::EINVAL;
+use system::syscall::{sys_fcntl, F_GETFL, F_SETFL, O_NONBLOCK};
 use time::{self, Duration};
 use vec::{IntoIter, Vec};
 
@@ -60,6 +62,21 @@ pub fn lookup_host(host: &str) -> Result<LookupHost> {
     }
 }
 
+fn nonblocking(file: &File) -> Result<bool> {
+    let flags = try!(sys_fcntl(file.as_raw_fd(), F_GETFL, 0).map_err(|x| Error::from_sys(x)));
+    Ok(flags & O_NONBLOCK == O_NONBLOCK)
+}
+
+fn set_nonblocking(file: &File, nonblocking: bool) -> Result<()> {
+    let flags = try!(sys_fcntl(file.as_raw_fd(), F_GETFL, 0).map_err(|x| Error::from_sys(x)));
+    let flags = if nonblocking {
+        flags | O_NONBLOCK
+    } else {
+        flags & !O_NONBLOCK
+    };
+    sys_fcntl(file.as_raw_fd(), F_SETFL, flags).and(Ok(())).map_err(|x| Error::from_sys(x))
+}
+
 #[derive(Debug)]
 pub struct TcpStream(UnsafeCell<File>);
 
@@ -106,7 +123,7 @@ impl TcpStream {
     }
 
     pub fn nonblocking(&self) -> Result<bool> {
-        Err(Error::new(ErrorKind::Other, "Not implemented"))
+        nonblocking(unsafe { &*self.0.get() })
     }
 
     pub fn only_v6(&self) -> Result<bool> {
@@ -129,8 +146,8 @@ impl TcpStream {
         Err(Error::new(ErrorKind::Other, "Not implemented"))
     }
 
-    pub fn set_nonblocking(&self, _nonblocking: bool) -> Result<()> {
-        Err(Error::new(ErrorKind::Other, "Not implemented"))
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        set_nonblocking(unsafe { &*self.0.get() }, nonblocking)
     }
 
     pub fn set_only_v6(&self, _only_v6: bool) -> Result<()> {
@@ -233,7 +250,7 @@ impl UdpSocket {
     }
 
     pub fn nonblocking(&self) -> Result<bool> {
-        Err(Error::new(ErrorKind::Other, "Not implemented"))
+        nonblocking(unsafe { &*self.0.get() })
     }
 
     pub fn only_v6(&self) -> Result<bool> {
@@ -256,8 +273,8 @@ impl UdpSocket {
         Err(Error::new(ErrorKind::Other, "Not implemented"))
     }
 
-    pub fn set_nonblocking(&self, _nonblocking: bool) -> Result<()> {
-        Err(Error::new(ErrorKind::Other, "Not implemented"))
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        set_nonblocking(unsafe { &*self.0.get() }, nonblocking)
     }
 
     pub fn set_only_v6(&self, _only_v6: bool) -> Result<()> {